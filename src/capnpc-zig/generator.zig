@@ -168,7 +168,7 @@ pub const Generator = struct {
         for (requested_file.imports) |imp| {
             if (!self.used_import_file_ids.contains(imp.id)) continue;
             const mod_name = self.import_modules.get(imp.id) orelse continue;
-            const import_path = try self.importPathFromCapnpName(imp.name);
+            const import_path = try self.importPathFromCapnpName(requested_file.filename, imp.name);
             defer self.allocator.free(import_path);
             try writer.print("const {s} = @import(\"{f}\");\n", .{ mod_name, std.zig.fmtString(import_path) });
         }
@@ -1440,15 +1440,24 @@ pub const Generator = struct {
         }
     }
 
-    /// Derive the .zig import path from a .capnp import name.
-    /// E.g., "other.capnp" → "other.zig", "path/to/types.capnp" → "path/to/types.zig"
-    fn importPathFromCapnpName(self: *Generator, capnp_name: []const u8) ![]const u8 {
-        const normalized = if (std.mem.startsWith(u8, capnp_name, "/")) capnp_name[1..] else capnp_name;
-        if (std.mem.endsWith(u8, normalized, ".capnp")) {
-            const base = normalized[0 .. normalized.len - 6];
-            return std.fmt.allocPrint(self.allocator, "{s}.zig", .{base});
-        }
-        return std.fmt.allocPrint(self.allocator, "{s}.zig", .{normalized});
+    /// Derive the .zig import path from a .capnp import name, as seen from the
+    /// file generated for `from_file`. Generated files mirror the source tree,
+    /// so an absolute import first climbs out of `from_file`'s directories.
+    /// E.g., "other.capnp" → "other.zig", "path/to/types.capnp" → "path/to/types.zig",
+    /// "/types.capnp" from "market/shop.capnp" → "../types.zig"
+    fn importPathFromCapnpName(self: *Generator, from_file: []const u8, capnp_name: []const u8) ![]const u8 {
+        const absolute = std.mem.startsWith(u8, capnp_name, "/");
+        const normalized = if (absolute) capnp_name[1..] else capnp_name;
+        const base = if (std.mem.endsWith(u8, normalized, ".capnp"))
+            normalized[0 .. normalized.len - 6]
+        else
+            normalized;
+
+        const depth = if (absolute) std.mem.count(u8, from_file, "/") else 0;
+        const up = try self.allocator.alloc(u8, depth * 3);
+        defer self.allocator.free(up);
+        for (0..depth) |i| @memcpy(up[i * 3 ..][0..3], "../");
+        return std.fmt.allocPrint(self.allocator, "{s}{s}.zig", .{ up, base });
     }
 
     /// Walk the scope chain from a node to find its owning file node ID.
@@ -1950,25 +1959,47 @@ test "Generator.importPathFromCapnpName replaces .capnp with .zig" {
     var gen = Generator.init(alloc, &.{}) catch unreachable;
     defer gen.deinit();
 
-    const r1 = try gen.importPathFromCapnpName("other.capnp");
+    const r1 = try gen.importPathFromCapnpName("root.capnp", "other.capnp");
     defer alloc.free(r1);
     try std.testing.expectEqualStrings("other.zig", r1);
 
-    const r2 = try gen.importPathFromCapnpName("path/to/types.capnp");
+    const r2 = try gen.importPathFromCapnpName("root.capnp", "path/to/types.capnp");
     defer alloc.free(r2);
     try std.testing.expectEqualStrings("path/to/types.zig", r2);
 
-    const r3 = try gen.importPathFromCapnpName("/capnp/stream.capnp");
+    const r3 = try gen.importPathFromCapnpName("root.capnp", "/capnp/stream.capnp");
     defer alloc.free(r3);
     try std.testing.expectEqualStrings("capnp/stream.zig", r3);
 }
 
+test "Generator.importPathFromCapnpName resolves absolute and relative imports alike from nested files" {
+    const alloc = std.testing.allocator;
+    var gen = Generator.init(alloc, &.{}) catch unreachable;
+    defer gen.deinit();
+
+    const absolute = try gen.importPathFromCapnpName("market/market.capnp", "/game_types.capnp");
+    defer alloc.free(absolute);
+    try std.testing.expectEqualStrings("../game_types.zig", absolute);
+
+    const relative = try gen.importPathFromCapnpName("market/market.capnp", "../game_types.capnp");
+    defer alloc.free(relative);
+    try std.testing.expectEqualStrings("../game_types.zig", relative);
+
+    const nested = try gen.importPathFromCapnpName("market/market.capnp", "shared/price.capnp");
+    defer alloc.free(nested);
+    try std.testing.expectEqualStrings("shared/price.zig", nested);
+
+    const deep = try gen.importPathFromCapnpName("market/shared/price.capnp", "/game_types.capnp");
+    defer alloc.free(deep);
+    try std.testing.expectEqualStrings("../../game_types.zig", deep);
+}
+
 test "Generator.importPathFromCapnpName appends .zig for non-.capnp" {
     const alloc = std.testing.allocator;
     var gen = Generator.init(alloc, &.{}) catch unreachable;
     defer gen.deinit();
 
-    const r = try gen.importPathFromCapnpName("something_else");
+    const r = try gen.importPathFromCapnpName("root.capnp", "something_else");
     defer alloc.free(r);
     try std.testing.expectEqualStrings("something_else.zig", r);
 }
@@ -1978,15 +2009,15 @@ test "Generator.importPathFromCapnpName handles empty and minimal names" {
     var gen = Generator.init(alloc, &.{}) catch unreachable;
     defer gen.deinit();
 
-    const empty = try gen.importPathFromCapnpName("");
+    const empty = try gen.importPathFromCapnpName("root.capnp", "");
     defer alloc.free(empty);
     try std.testing.expectEqualStrings(".zig", empty);
 
-    const root = try gen.importPathFromCapnpName("/");
+    const root = try gen.importPathFromCapnpName("root.capnp", "/");
     defer alloc.free(root);
     try std.testing.expectEqualStrings(".zig", root);
 
-    const minimal = try gen.importPathFromCapnpName("x");
+    const minimal = try gen.importPathFromCapnpName("root.capnp", "x");
     defer alloc.free(minimal);
     try std.testing.expectEqualStrings("x.zig", minimal);
 }
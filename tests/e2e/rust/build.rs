@@ -1,21 +1,207 @@
+use std::path::{Path, PathBuf};
+
+use capnp::schema_capnp::{code_generator_request, node};
+
 fn main() {
     let schema_dir = [
-        std::path::Path::new("../schemas"),
-        std::path::Path::new("schemas"),
-        std::path::Path::new("tests/e2e/schemas"),
+        Path::new("../schemas"),
+        Path::new("schemas"),
+        Path::new("tests/e2e/schemas"),
     ]
     .iter()
     .copied()
     .find(|path| path.join("game_world.capnp").exists())
     .expect("failed to locate e2e schema directory");
 
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").expect("OUT_DIR not set"));
+    let request_path = out_dir.join("schema.cgr");
+
     capnpc::CompilerCommand::new()
         .src_prefix(schema_dir)
+        .import_path(schema_dir)
+        .file(schema_dir.join("capnp/persistent.capnp"))
         .file(schema_dir.join("game_types.capnp"))
         .file(schema_dir.join("game_world.capnp"))
         .file(schema_dir.join("chat.capnp"))
         .file(schema_dir.join("inventory.capnp"))
         .file(schema_dir.join("matchmaking.capnp"))
+        .file(schema_dir.join("nesting.capnp"))
+        .file(schema_dir.join("defaults.capnp"))
+        .file(schema_dir.join("lists.capnp"))
+        .file(schema_dir.join("ordering.capnp"))
+        .file(schema_dir.join("telemetry.capnp"))
+        .file(schema_dir.join("registry.capnp"))
+        .file(schema_dir.join("debug.capnp"))
+        .file(schema_dir.join("linked.capnp"))
+        .file(schema_dir.join("generics.capnp"))
+        .file(schema_dir.join("envelope.capnp"))
+        .file(schema_dir.join("spells.capnp"))
+        .file(schema_dir.join("forecast.capnp"))
+        .file(schema_dir.join("evolution.capnp"))
+        .file(schema_dir.join("annotated.capnp"))
+        .file(schema_dir.join("widestruct.capnp"))
+        .file(schema_dir.join("commands.capnp"))
+        .file(schema_dir.join("market/market.capnp"))
+        .file(schema_dir.join("market/shared/price.capnp"))
+        .file(schema_dir.join("deep.capnp"))
+        .raw_code_generator_request_path(&request_path)
         .run()
         .expect("failed to compile Cap'n Proto schemas");
+
+    // The later revisions share their files' IDs, so they cannot go in the
+    // same request, and stay out of the fingerprint and method table.
+    capnpc::CompilerCommand::new()
+        .src_prefix(schema_dir)
+        .import_path(schema_dir)
+        .file(schema_dir.join("forecast_v2.capnp"))
+        .file(schema_dir.join("evolution_v2.capnp"))
+        .run()
+        .expect("failed to compile later schema revisions");
+
+    // The RPC protocol itself, for reading captured frames. Nothing serves
+    // it, so it too stays out of the fingerprint and method table.
+    capnpc::CompilerCommand::new()
+        .src_prefix(schema_dir)
+        .import_path(schema_dir)
+        .file(schema_dir.join("capnp/rpc.capnp"))
+        .run()
+        .expect("failed to compile rpc.capnp");
+
+    let file = std::fs::File::open(&request_path).expect("failed to open code generator request");
+    let message = capnp::serialize::read_message(
+        std::io::BufReader::new(file),
+        capnp::message::ReaderOptions::new(),
+    )
+    .expect("failed to read code generator request");
+    let request = message
+        .get_root::<code_generator_request::Reader>()
+        .expect("failed to read code generator request");
+
+    let nodes = node_shapes(request).expect("failed to read schema nodes");
+    let fingerprint = schema_fingerprint(&nodes);
+    std::fs::write(
+        out_dir.join("schema_fingerprint.rs"),
+        format!(
+            "/// Fingerprint of the compiled schema node IDs and their layouts.\n\
+             pub const SCHEMA_FINGERPRINT: u64 = {:#018x};\n",
+            fingerprint
+        ),
+    )
+    .expect("failed to write schema fingerprint");
+    std::fs::write(out_dir.join("schema_ids.rs"), schema_ids(&nodes))
+        .expect("failed to write schema ID manifest");
+
+    let methods = method_table(request).expect("failed to list interface methods");
+    std::fs::write(out_dir.join("schema_methods.rs"), methods)
+        .expect("failed to write method table");
+}
+
+/// Every node's ID, the file it is declared in and the layout facts a peer
+/// depends on (field/method/enumerant counts and struct section sizes).
+fn node_shapes(request: code_generator_request::Reader) -> capnp::Result<Vec<NodeShape>> {
+    let mut nodes = Vec::new();
+    for node in request.get_nodes()? {
+        let shape = match node.which()? {
+            node::Struct(s) => [
+                1,
+                s.get_data_word_count() as u64,
+                s.get_pointer_count() as u64,
+                s.get_fields()?.len() as u64,
+            ],
+            node::Interface(i) => [2, i.get_methods()?.len() as u64, 0, 0],
+            node::Enum(e) => [3, e.get_enumerants()?.len() as u64, 0, 0],
+            _ => [0; 4],
+        };
+        // `file.capnp:Outer.Inner`, or just `file.capnp` for the file itself.
+        let display_name = node.get_display_name()?.to_str()?;
+        let (file, name) = display_name.split_once(':').unwrap_or((display_name, ""));
+        nodes.push(NodeShape {
+            id: node.get_id(),
+            shape,
+            file: file.to_string(),
+            name: name.to_string(),
+        });
+    }
+    nodes.sort_unstable_by_key(|node| node.id);
+    Ok(nodes)
+}
+
+struct NodeShape {
+    id: u64,
+    shape: [u64; 4],
+    file: String,
+    /// Empty for the file node.
+    name: String,
+}
+
+/// FNV-1a over every node ID plus its layout facts, in ID order.
+fn schema_fingerprint<'a>(nodes: impl IntoIterator<Item = &'a NodeShape>) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for node in nodes {
+        for word in std::iter::once(node.id).chain(node.shape) {
+            for byte in word.to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            }
+        }
+    }
+    hash
+}
+
+/// Renders `SCHEMA_IDS`: per file, its ID, the fingerprint of its own nodes
+/// and every node it declares.
+fn schema_ids(nodes: &[NodeShape]) -> String {
+    let mut files: Vec<&NodeShape> = nodes.iter().filter(|node| node.name.is_empty()).collect();
+    files.sort_by(|a, b| a.file.cmp(&b.file));
+    let mut rows = String::new();
+    for file in files {
+        let declared: Vec<&NodeShape> = nodes
+            .iter()
+            .filter(|node| node.file == file.file && !node.name.is_empty())
+            .collect();
+        let fingerprint = schema_fingerprint(nodes.iter().filter(|node| node.file == file.file));
+        rows.push_str(&format!(
+            "    SchemaFile {{\n        file: {:?},\n        id: {:#018x},\n        \
+             fingerprint: {:#018x},\n        types: &[\n",
+            file.file, file.id, fingerprint
+        ));
+        for node in declared {
+            rows.push_str(&format!(
+                "            ({:#018x}, {:?}),\n",
+                node.id, node.name
+            ));
+        }
+        rows.push_str("        ],\n    },\n");
+    }
+    format!(
+        "/// Every schema file with its ID, the fingerprint of its own nodes and\n\
+         /// each node it declares, in file order.\n\
+         pub const SCHEMA_IDS: &[SchemaFile] = &[\n{}];\n",
+        rows
+    )
+}
+
+/// Renders `METHODS`: every interface method as (interface ID, ordinal, `Interface.method`).
+fn method_table(request: code_generator_request::Reader) -> capnp::Result<String> {
+    let mut rows = Vec::new();
+    for node in request.get_nodes()? {
+        if let node::Interface(interface) = node.which()? {
+            let display_name = node.get_display_name()?.to_str()?;
+            let interface_name = &display_name[node.get_display_name_prefix_length() as usize..];
+            for (ordinal, method) in interface.get_methods()?.iter().enumerate() {
+                let name = format!("{}.{}", interface_name, method.get_name()?.to_str()?);
+                rows.push((node.get_id(), ordinal, name));
+            }
+        }
+    }
+    rows.sort();
+    let rows: String = rows
+        .iter()
+        .map(|(id, ordinal, name)| format!("    ({:#018x}, {}, \"{}\"),\n", id, ordinal, name))
+        .collect();
+    Ok(format!(
+        "/// Every interface method as (interface ID, ordinal, `Interface.method`).\n\
+         pub const METHODS: &[(u64, u16, &str)] = &[\n{}];\n",
+        rows
+    ))
 }
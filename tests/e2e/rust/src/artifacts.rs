@@ -0,0 +1,230 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// ---------------------------------------------------------------------------
+// Wire recording
+// ---------------------------------------------------------------------------
+
+#[derive(Default)]
+struct WireBuffers {
+    sent: Vec<u8>,
+    received: Vec<u8>,
+    /// Length of `sent` after each write, with the length of `received` then.
+    writes: Vec<(usize, usize)>,
+}
+
+/// Shared record of every byte sent and received on one connection.
+#[derive(Clone, Default)]
+pub struct WireLog {
+    inner: Rc<RefCell<WireBuffers>>,
+}
+
+impl WireLog {
+    fn marks(&self) -> (usize, usize) {
+        let b = self.inner.borrow();
+        (b.sent.len(), b.received.len())
+    }
+
+    /// Copies of everything sent and received so far.
+    pub fn bytes(&self) -> (Vec<u8>, Vec<u8>) {
+        let b = self.inner.borrow();
+        (b.sent.clone(), b.received.clone())
+    }
+
+    /// How many bytes had been received when the first `sent` bytes went out.
+    pub fn received_when_sent(&self, sent: usize) -> usize {
+        let b = self.inner.borrow();
+        b.writes
+            .iter()
+            .find(|(end, _)| *end >= sent)
+            .map_or(b.received.len(), |(_, received)| *received)
+    }
+}
+
+/// Stream wrapper that copies all traffic into a `WireLog`.
+pub struct Recorded<S> {
+    inner: S,
+    log: WireLog,
+}
+
+impl<S> Recorded<S> {
+    pub fn new(inner: S, log: WireLog) -> Self {
+        Self { inner, log }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Recorded<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let this = &mut *self;
+        let res = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = res {
+            this.log
+                .inner
+                .borrow_mut()
+                .received
+                .extend_from_slice(&buf.filled()[before..]);
+        }
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Recorded<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = &mut *self;
+        let res = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            let mut b = this.log.inner.borrow_mut();
+            b.sent.extend_from_slice(&buf[..n]);
+            let marks = (b.sent.len(), b.received.len());
+            b.writes.push(marks);
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+fn describe_frames(out: &mut String, direction: &str, base: usize, bytes: &[u8]) {
+    let frames = match crate::frames::split(bytes) {
+        Ok(frames) => frames,
+        Err(e) => {
+            out.push_str(&format!("{} unreadable: {}\n", direction, e));
+            return;
+        }
+    };
+    for frame in frames {
+        let sizes = frame.segment_words();
+        let words: u32 = sizes.iter().sum();
+        out.push_str(&format!(
+            "{} offset={} segments={} words={} sizes={:?}",
+            direction,
+            base + frame.offset,
+            sizes.len(),
+            words,
+            sizes
+        ));
+        // Frames that do not read as RPC messages keep only their sizes.
+        let summary = frame.root().and_then(crate::frames::describe);
+        if let Ok(summary) = summary {
+            out.push_str(&format!(" {}", summary));
+        }
+        out.push('\n');
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Failure artifacts
+// ---------------------------------------------------------------------------
+
+/// Writes per-test failure artifacts under `<dir>/<schema>/<NN>-<slug>/`.
+pub struct Artifacts {
+    dir: PathBuf,
+    log: WireLog,
+    marks: (usize, usize),
+    /// The peer's DebugStats, taken after the test now ending failed.
+    server_stats: Option<String>,
+}
+
+impl Artifacts {
+    pub fn new(dir: &Path, schema: &str, log: WireLog) -> Self {
+        Self {
+            dir: dir.join(schema),
+            log,
+            marks: (0, 0),
+            server_stats: None,
+        }
+    }
+
+    /// Marks the end of a test; traffic up to here belongs to it.
+    pub fn advance(&mut self) {
+        self.marks = self.log.marks();
+        self.server_stats = None;
+    }
+
+    /// Writes `stats` to `server_stats.txt` with the next failure recorded.
+    pub fn set_server_stats(&mut self, stats: String) {
+        self.server_stats = Some(stats);
+    }
+
+    pub fn record_failure(&mut self, test_num: u32, desc: &str, fields: &[(&str, String)]) {
+        let dir = self.dir.join(format!("{:02}-{}", test_num, slug(desc)));
+        if let Err(e) = self.write(&dir, test_num, desc, fields) {
+            eprintln!("failed to write artifacts to {}: {}", dir.display(), e);
+        }
+        self.advance();
+    }
+
+    fn write(
+        &self,
+        dir: &Path,
+        test_num: u32,
+        desc: &str,
+        fields: &[(&str, String)],
+    ) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        let buffers = self.log.inner.borrow();
+        let sent = &buffers.sent[self.marks.0..];
+        let received = &buffers.received[self.marks.1..];
+
+        std::fs::write(dir.join("sent.bin"), sent)?;
+        std::fs::write(dir.join("received.bin"), received)?;
+
+        let mut wire = String::new();
+        describe_frames(&mut wire, "->", self.marks.0, sent);
+        describe_frames(&mut wire, "<-", self.marks.1, received);
+        std::fs::write(dir.join("wire.log"), wire)?;
+        if let Some(stats) = &self.server_stats {
+            std::fs::write(dir.join("server_stats.txt"), stats)?;
+        }
+
+        let mut record = std::fs::File::create(dir.join("failure.txt"))?;
+        writeln!(record, "test: {}", test_num)?;
+        writeln!(record, "description: {}", desc)?;
+        for (key, value) in fields {
+            if value.contains('\n') {
+                writeln!(record, "{}: |", key)?;
+                for line in value.lines() {
+                    writeln!(record, "  {}", line)?;
+                }
+            } else {
+                writeln!(record, "{}: {}", key, value)?;
+            }
+        }
+        writeln!(record, "bytes_sent: {}", sent.len())?;
+        writeln!(record, "bytes_received: {}", received.len())?;
+        Ok(())
+    }
+}
+
+fn slug(desc: &str) -> String {
+    let mut out = String::new();
+    for c in desc.chars() {
+        if c.is_ascii_alphanumeric() {
+            out.push(c.to_ascii_lowercase());
+        } else if !out.ends_with('-') {
+            out.push('-');
+        }
+    }
+    out.trim_matches('-').to_string()
+}
@@ -0,0 +1,159 @@
+//! Deliberately broken stream framing for the Zig decoder's negative tests.
+//!
+//! Every file is `<case>.bin`, a segment table that a conforming reader must
+//! reject before touching any pointer. `manifest.json` lists each file with the
+//! reason it must be rejected and the error the Rust reader gives for it.
+
+use std::path::Path;
+
+use capnp::message::ReaderOptions;
+use serde_json::json;
+
+use crate::error::{ErrorCode, TestError};
+
+/// Largest segment count readers accept, in both implementations.
+const MAX_SEGMENTS: u32 = 512;
+
+/// One malformed message.
+pub struct BadFrame {
+    /// File stem.
+    pub name: &'static str,
+    /// Why a reader must reject the file.
+    pub reason: &'static str,
+    bytes: fn() -> Vec<u8>,
+}
+
+/// Segment table for `count_minus_one + 1` segments with the given sizes, the
+/// padding word when `padded` (a well-formed table has it when the count is
+/// even), then `body` bytes of segment data.
+fn frame(count_minus_one: u32, sizes: &[u32], padded: bool, body: usize) -> Vec<u8> {
+    let mut bytes = count_minus_one.to_le_bytes().to_vec();
+    for size in sizes {
+        bytes.extend_from_slice(&size.to_le_bytes());
+    }
+    if padded {
+        bytes.extend_from_slice(&[0; 4]);
+    }
+    // A null root pointer followed by zeroes; the contents never matter.
+    bytes.resize(bytes.len() + body, 0);
+    bytes
+}
+
+pub const FRAMES: &[BadFrame] = &[
+    BadFrame {
+        name: "empty",
+        reason: "no segment table: the input is empty",
+        bytes: Vec::new,
+    },
+    BadFrame {
+        name: "truncated_count",
+        reason: "segment count word cut off after 2 of 4 bytes",
+        bytes: || vec![0, 0],
+    },
+    BadFrame {
+        name: "truncated_table",
+        reason: "segment table announces 3 segments but ends after the count",
+        bytes: || frame(2, &[], false, 0),
+    },
+    BadFrame {
+        name: "truncated_sizes",
+        reason: "segment table announces 4 segments but lists only 2 sizes",
+        bytes: || frame(3, &[1, 1], false, 0),
+    },
+    BadFrame {
+        name: "zero_segments",
+        reason: "segment count minus one is 0xffffffff, so the count wraps to zero",
+        bytes: || frame(u32::MAX, &[], false, 8),
+    },
+    BadFrame {
+        name: "too_many_segments",
+        reason: "513 segments, over the 512 segment limit; the table itself is complete",
+        bytes: || {
+            let sizes = vec![1; MAX_SEGMENTS as usize + 1];
+            frame(MAX_SEGMENTS, &sizes, false, sizes.len() * 8)
+        },
+    },
+    BadFrame {
+        name: "huge_segment",
+        reason: "single segment of 0x7fffffff words with one word of data present",
+        bytes: || frame(0, &[0x7fff_ffff], false, 8),
+    },
+    BadFrame {
+        name: "huge_second_segment",
+        reason: "second segment of 0xffffffff words; the sizes overflow a 32-bit total",
+        bytes: || frame(1, &[1, u32::MAX], true, 8),
+    },
+    BadFrame {
+        name: "truncated_segment",
+        reason: "segment of 4 words with only 3 words of data present",
+        bytes: || frame(0, &[4], false, 24),
+    },
+    BadFrame {
+        name: "misaligned_length",
+        reason: "segment of 1 word with 5 bytes of data: the length is not a whole number of words",
+        bytes: || frame(0, &[1], false, 5),
+    },
+    BadFrame {
+        name: "missing_padding",
+        reason:
+            "two segments without the padding word after the table, so the data runs 4 bytes short",
+        bytes: || frame(1, &[1, 1], false, 16),
+    },
+    BadFrame {
+        name: "truncated_padding",
+        reason: "table for two segments ends inside the padding word",
+        bytes: || {
+            let mut bytes = frame(1, &[1, 1], false, 0);
+            bytes.extend_from_slice(&[0, 0]);
+            bytes
+        },
+    },
+];
+
+impl BadFrame {
+    pub fn bytes(&self) -> Vec<u8> {
+        (self.bytes)()
+    }
+}
+
+/// How the Rust reader rejects `bytes`, or `None` if it accepts them.
+pub fn reference_error(bytes: &[u8]) -> Option<String> {
+    capnp::serialize::read_message(bytes, ReaderOptions::new())
+        .err()
+        .map(|e| e.to_string())
+}
+
+/// Writes every case and `manifest.json` to `out_dir`, creating it if needed,
+/// and prints each path.
+pub fn generate(out_dir: &Path) -> Result<(), TestError> {
+    let io_error = |path: &Path, e: std::io::Error| {
+        TestError::new(ErrorCode::ConfigError, format!("{}: {}", path.display(), e))
+    };
+    std::fs::create_dir_all(out_dir).map_err(|e| io_error(out_dir, e))?;
+    let mut manifest = Vec::new();
+    for frame in FRAMES {
+        let bytes = frame.bytes();
+        let Some(rust_error) = reference_error(&bytes) else {
+            return Err(TestError::new(
+                ErrorCode::Internal,
+                format!("{}: the Rust reader accepts this frame", frame.name),
+            ));
+        };
+        let file = format!("{}.bin", frame.name);
+        let path = out_dir.join(&file);
+        std::fs::write(&path, &bytes).map_err(|e| io_error(&path, e))?;
+        println!("{}", path.display());
+        manifest.push(json!({
+            "file": file,
+            "reason": frame.reason,
+            "rust_error": rust_error,
+        }));
+    }
+    let path = out_dir.join("manifest.json");
+    let mut text = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| TestError::new(ErrorCode::Internal, e.to_string()))?;
+    text.push('\n');
+    std::fs::write(&path, text).map_err(|e| io_error(&path, e))?;
+    println!("{}", path.display());
+    Ok(())
+}
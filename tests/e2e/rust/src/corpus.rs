@@ -0,0 +1,1635 @@
+//! Golden message corpus: fixed, representative messages for every schema,
+//! written as standard framed Cap'n Proto files for the Zig decoder tests,
+//! and checked field by field when the Zig encoder produces the same set.
+//!
+//! Each file is `<schema>.<message>.bin` and holds one message whose root is
+//! the struct named in the stem, framed with the standard stream framing or,
+//! with `--packed`, the packed encoding. Contents never change between runs,
+//! so the corpus can be regenerated and diffed. Next to a message,
+//! `<schema>.<message>.canonical.bin` holds another implementation's canonical
+//! form of it: the single canonical segment, without a segment table.
+//!
+//! The far-pointer group holds the same messages spread over many segments:
+//! `<schema>.<message>.far.bin` reaches every struct and list, the root
+//! included, through a far pointer to a landing pad in the object's own
+//! segment, and `<schema>.<message>.double_far.bin` puts each landing pad in a
+//! segment of its own, as a far pointer to the object plus a tag word.
+
+use std::path::Path;
+use std::time::Instant;
+
+use capnp::introspect::TypeVariant;
+use capnp::message::{self, Builder, HeapAllocator, ReaderOptions, ReaderSegments};
+use capnp::serialize::OwnedSegments;
+use capnp::traits::Owned;
+use capnp::{any_pointer, dynamic_list, dynamic_struct, dynamic_value};
+
+use crate::annotated_capnp::{entry, plain_entry, Currency};
+use crate::chat_capnp::{chat_message, room_info};
+use crate::defaults_capnp::{settings, Quality};
+use crate::error::{ErrorCode, TestError};
+use crate::game_types_capnp::{item, player_info, Faction, Rarity};
+use crate::game_world_capnp::{area_query, entity, spawn_request, EntityKind};
+use crate::inventory_capnp::{inventory_slot, inventory_view, trade_offer};
+use crate::lists_capnp::{blob, blob_set, bool_lists, nested_lists, point, sized_lists};
+use crate::market_capnp::listing;
+use crate::matchmaking_capnp::{match_info, match_result, queue_ticket, GameMode, MatchState};
+use crate::report::TapReporter;
+use crate::spells_capnp::{spell_effect, Element};
+use crate::widestruct_capnp::wide;
+
+/// One message in the corpus.
+pub struct Sample {
+    /// File stem, `<schema>.<message>`.
+    pub name: &'static str,
+    build: fn(&mut Builder<HeapAllocator>),
+    /// Reads the root as the sample's struct type.
+    root: fn(any_pointer::Reader<'_>) -> capnp::Result<dynamic_value::Reader<'_>>,
+    /// Initializes the root as the sample's struct type.
+    init: fn(any_pointer::Builder<'_>) -> dynamic_struct::Builder<'_>,
+}
+
+impl Sample {
+    /// The sample's message, built afresh.
+    pub(crate) fn message(&self) -> Builder<HeapAllocator> {
+        let mut message = Builder::new_default();
+        (self.build)(&mut message);
+        message
+    }
+}
+
+fn root<T: Owned>(root: any_pointer::Reader<'_>) -> capnp::Result<dynamic_value::Reader<'_>>
+where
+    for<'a> T::Reader<'a>: Into<dynamic_value::Reader<'a>>,
+{
+    Ok(root.get_as::<T::Reader<'_>>()?.into())
+}
+
+fn init<T: Owned>(root: any_pointer::Builder<'_>) -> dynamic_struct::Builder<'_>
+where
+    for<'a> T::Builder<'a>: Into<dynamic_value::Builder<'a>>,
+{
+    root.init_as::<T::Builder<'_>>().into().downcast()
+}
+
+pub const SAMPLES: &[Sample] = &[
+    Sample {
+        name: "game_world.entity",
+        build: build_entity,
+        root: root::<entity::Owned>,
+        init: init::<entity::Owned>,
+    },
+    Sample {
+        name: "game_world.spawn_request",
+        build: build_spawn_request,
+        root: root::<spawn_request::Owned>,
+        init: init::<spawn_request::Owned>,
+    },
+    Sample {
+        name: "game_world.area_query_all",
+        build: build_area_query_all,
+        root: root::<area_query::Owned>,
+        init: init::<area_query::Owned>,
+    },
+    Sample {
+        name: "game_world.area_query_by_kind",
+        build: build_area_query_by_kind,
+        root: root::<area_query::Owned>,
+        init: init::<area_query::Owned>,
+    },
+    Sample {
+        name: "game_world.area_query_by_faction",
+        build: build_area_query_by_faction,
+        root: root::<area_query::Owned>,
+        init: init::<area_query::Owned>,
+    },
+    Sample {
+        name: "game_world.entity_edge_floats",
+        build: build_entity_edge_floats,
+        root: root::<entity::Owned>,
+        init: init::<entity::Owned>,
+    },
+    Sample {
+        name: "game_world.area_query_nan_radius",
+        build: build_area_query_nan_radius,
+        root: root::<area_query::Owned>,
+        init: init::<area_query::Owned>,
+    },
+    Sample {
+        name: "chat.message_normal",
+        build: build_message_normal,
+        root: root::<chat_message::Owned>,
+        init: init::<chat_message::Owned>,
+    },
+    Sample {
+        name: "chat.message_emote",
+        build: build_message_emote,
+        root: root::<chat_message::Owned>,
+        init: init::<chat_message::Owned>,
+    },
+    Sample {
+        name: "chat.message_whisper",
+        build: build_message_whisper,
+        root: root::<chat_message::Owned>,
+        init: init::<chat_message::Owned>,
+    },
+    Sample {
+        name: "chat.room_info",
+        build: build_room_info,
+        root: root::<room_info::Owned>,
+        init: init::<room_info::Owned>,
+    },
+    Sample {
+        name: "inventory.view",
+        build: build_inventory_view,
+        root: root::<inventory_view::Owned>,
+        init: init::<inventory_view::Owned>,
+    },
+    Sample {
+        name: "inventory.trade_offer",
+        build: build_trade_offer,
+        root: root::<trade_offer::Owned>,
+        init: init::<trade_offer::Owned>,
+    },
+    Sample {
+        name: "matchmaking.queue_ticket",
+        build: build_queue_ticket,
+        root: root::<queue_ticket::Owned>,
+        init: init::<queue_ticket::Owned>,
+    },
+    Sample {
+        name: "matchmaking.match_info",
+        build: build_match_info,
+        root: root::<match_info::Owned>,
+        init: init::<match_info::Owned>,
+    },
+    Sample {
+        name: "matchmaking.match_result",
+        build: build_match_result,
+        root: root::<match_result::Owned>,
+        init: init::<match_result::Owned>,
+    },
+    Sample {
+        name: "defaults.settings_default",
+        build: build_settings_default,
+        root: root::<settings::Owned>,
+        init: init::<settings::Owned>,
+    },
+    Sample {
+        name: "defaults.settings_zero",
+        build: build_settings_zero,
+        root: root::<settings::Owned>,
+        init: init::<settings::Owned>,
+    },
+    Sample {
+        name: "defaults.settings_edge_floats",
+        build: build_settings_edge_floats,
+        root: root::<settings::Owned>,
+        init: init::<settings::Owned>,
+    },
+    Sample {
+        name: "lists.bools_alternating",
+        build: build_bools_alternating,
+        root: root::<bool_lists::Owned>,
+        init: init::<bool_lists::Owned>,
+    },
+    Sample {
+        name: "lists.bools_all",
+        build: build_bools_all,
+        root: root::<bool_lists::Owned>,
+        init: init::<bool_lists::Owned>,
+    },
+    Sample {
+        name: "lists.bools_sparse",
+        build: build_bools_sparse,
+        root: root::<bool_lists::Owned>,
+        init: init::<bool_lists::Owned>,
+    },
+    Sample {
+        name: "lists.empty",
+        build: build_lists_empty,
+        root: root::<sized_lists::Owned>,
+        init: init::<sized_lists::Owned>,
+    },
+    Sample {
+        name: "lists.voids",
+        build: build_lists_voids,
+        root: root::<sized_lists::Owned>,
+        init: init::<sized_lists::Owned>,
+    },
+    Sample {
+        name: "lists.nested",
+        build: build_lists_nested,
+        root: root::<nested_lists::Owned>,
+        init: init::<nested_lists::Owned>,
+    },
+    Sample {
+        name: "lists.text_invalid_utf8",
+        build: build_text_invalid_utf8,
+        root: root::<sized_lists::Owned>,
+        init: init::<sized_lists::Owned>,
+    },
+    Sample {
+        name: "lists.text_embedded_nul",
+        build: build_text_embedded_nul,
+        root: root::<sized_lists::Owned>,
+        init: init::<sized_lists::Owned>,
+    },
+    Sample {
+        name: "lists.blobs",
+        build: build_blobs,
+        root: root::<blob_set::Owned>,
+        init: init::<blob_set::Owned>,
+    },
+    Sample {
+        name: "spells.damage_single",
+        build: build_spell_damage_single,
+        root: root::<spell_effect::Owned>,
+        init: init::<spell_effect::Owned>,
+    },
+    Sample {
+        name: "spells.damage_splash",
+        build: build_spell_damage_splash,
+        root: root::<spell_effect::Owned>,
+        init: init::<spell_effect::Owned>,
+    },
+    Sample {
+        name: "spells.damage_chain",
+        build: build_spell_damage_chain,
+        root: root::<spell_effect::Owned>,
+        init: init::<spell_effect::Owned>,
+    },
+    Sample {
+        name: "spells.heal",
+        build: build_spell_heal,
+        root: root::<spell_effect::Owned>,
+        init: init::<spell_effect::Owned>,
+    },
+    Sample {
+        name: "spells.summon",
+        build: build_spell_summon,
+        root: root::<spell_effect::Owned>,
+        init: init::<spell_effect::Owned>,
+    },
+    Sample {
+        name: "spells.dispel",
+        build: build_spell_dispel,
+        root: root::<spell_effect::Owned>,
+        init: init::<spell_effect::Owned>,
+    },
+    Sample {
+        name: "annotated.entry",
+        build: build_annotated_entry,
+        root: root::<entry::Owned>,
+        init: init::<entry::Owned>,
+    },
+    Sample {
+        name: "annotated.plain_entry",
+        build: build_plain_entry,
+        root: root::<plain_entry::Owned>,
+        init: init::<plain_entry::Owned>,
+    },
+    Sample {
+        name: "widestruct.full",
+        build: build_wide_full,
+        root: root::<wide::Owned>,
+        init: init::<wide::Owned>,
+    },
+    Sample {
+        name: "widestruct.last_round",
+        build: build_wide_last_round,
+        root: root::<wide::Owned>,
+        init: init::<wide::Owned>,
+    },
+    Sample {
+        name: "market.listing",
+        build: build_market_listing,
+        root: root::<listing::Owned>,
+        init: init::<listing::Owned>,
+    },
+];
+
+/// Samples the JSON conversion leaves out: JSON strings are Unicode, so Text
+/// that is not UTF-8 has no JSON form.
+pub const NOT_JSON: &[&str] = &["lists.text_invalid_utf8"];
+
+/// Samples too large for every run, written and checked only with `--big`: a
+/// million-element List(UInt64) and a hundred-thousand-element struct list,
+/// big enough that a 32-bit size or a quadratic copy shows.
+pub const BIG_SAMPLES: &[Sample] = &[
+    Sample {
+        name: "lists.big_longs",
+        build: build_big_longs,
+        root: root::<sized_lists::Owned>,
+        init: init::<sized_lists::Owned>,
+    },
+    Sample {
+        name: "lists.big_points",
+        build: build_big_points,
+        root: root::<sized_lists::Owned>,
+        init: init::<sized_lists::Owned>,
+    },
+];
+
+/// Elements in `lists.big_longs`.
+pub const BIG_LONGS: u32 = 1_000_000;
+
+/// Elements in `lists.big_points`.
+pub const BIG_POINTS: u32 = 100_000;
+
+/// How [`encode`] spreads each sample over segments, and which file it goes to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Layout {
+    /// `<name>.bin`, as the library's builder lays it out.
+    Flat,
+    /// `<name>.far.bin`, every object behind a single-far pointer.
+    Far,
+    /// `<name>.double_far.bin`, every object behind a double-far pointer.
+    DoubleFar,
+}
+
+impl Layout {
+    /// File name of the sample `name` in this layout.
+    pub fn file(self, name: &str) -> String {
+        match self {
+            Layout::Flat => format!("{}.bin", name),
+            Layout::Far => format!("{}.far.bin", name),
+            Layout::DoubleFar => format!("{}.double_far.bin", name),
+        }
+    }
+}
+
+/// Writes every sample to `out_dir` in `layout`, creating it if needed, and
+/// prints each path.
+pub fn encode(out_dir: &Path, packed: bool, layout: Layout) -> Result<(), TestError> {
+    if packed && layout != Layout::Flat {
+        return Err(TestError::new(
+            ErrorCode::ConfigError,
+            "--packed is only supported for the flat layout",
+        ));
+    }
+    create_dir(out_dir)?;
+    for sample in SAMPLES {
+        let message = sample.message();
+        let path = out_dir.join(layout.file(sample.name));
+        if layout == Layout::Flat {
+            write_message(&path, &message, packed)?;
+        } else {
+            let segments = scatter(&message, layout);
+            let segments: Vec<&[u8]> = segments.iter().map(Vec::as_slice).collect();
+            let mut bytes = Vec::new();
+            capnp::serialize::write_message_segments(&mut bytes, &&segments[..])?;
+            write_file(&path, &bytes)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the [`BIG_SAMPLES`] to `out_dir`, printing each path and, on
+/// stderr, how long the message took to build and write.
+pub fn encode_big(out_dir: &Path, packed: bool, layout: Layout) -> Result<(), TestError> {
+    if layout != Layout::Flat {
+        return Err(TestError::new(
+            ErrorCode::ConfigError,
+            "--big is only supported for the flat layout",
+        ));
+    }
+    create_dir(out_dir)?;
+    for sample in BIG_SAMPLES {
+        let started = Instant::now();
+        let message = sample.message();
+        write_message(&out_dir.join(layout.file(sample.name)), &message, packed)?;
+        eprintln!(
+            "{}: built and written in {} ms",
+            sample.name,
+            started.elapsed().as_millis()
+        );
+    }
+    Ok(())
+}
+
+/// Re-lays a single-segment message out as `layout`: segment 0 holds only the
+/// root pointer, and every struct and list moves to a segment of its own.
+fn scatter(message: &Builder<HeapAllocator>, layout: Layout) -> Vec<Vec<u8>> {
+    let segments = message.get_segments_for_output();
+    assert_eq!(segments.len(), 1, "sample spilled into a second segment");
+    let words: Vec<u64> = segments[0]
+        .chunks_exact(8)
+        .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+    let mut out = vec![vec![0]];
+    copy_pointer(&words, 0, &mut out, (0, 0), layout);
+    out.into_iter()
+        .map(|segment| segment.iter().flat_map(|word| word.to_le_bytes()).collect())
+        .collect()
+}
+
+/// Copies the object the pointer at `at` in `src` points to into new
+/// segments of `out`, writes a far pointer to it at `to`, and recurses into
+/// the pointers it holds.
+fn copy_pointer(
+    src: &[u64],
+    at: usize,
+    out: &mut Vec<Vec<u64>>,
+    to: (usize, usize),
+    layout: Layout,
+) {
+    let pointer = src[at];
+    if pointer == 0 {
+        return;
+    }
+    let start = (at as i64 + 1 + i64::from((pointer as u32 as i32) >> 2)) as usize;
+    let section = |word: u64| ((word >> 32 & 0xffff) as usize, (word >> 48) as usize);
+    // Length of the object and the indices of the pointers in it.
+    let (len, slots): (usize, Vec<usize>) = match pointer & 3 {
+        0 => {
+            let (data, pointers) = section(pointer);
+            (data + pointers, (data..data + pointers).collect())
+        }
+        1 => {
+            let count = (pointer >> 35) as usize;
+            match pointer >> 32 & 7 {
+                // Composite: a tag word, then `count` words of elements.
+                7 => {
+                    let (data, pointers) = section(src[start]);
+                    let elements = (src[start] as u32 >> 2) as usize;
+                    let slots = (0..elements)
+                        .flat_map(|i| {
+                            let first = 1 + i * (data + pointers) + data;
+                            first..first + pointers
+                        })
+                        .collect();
+                    (count + 1, slots)
+                }
+                6 => (count, (0..count).collect()),
+                size => {
+                    let bits = [0, 1, 8, 16, 32, 64][size as usize];
+                    ((count * bits).div_ceil(64), Vec::new())
+                }
+            }
+        }
+        _ => unreachable!("corpus samples hold no far or capability pointers"),
+    };
+    // The pointer again, with the offset cleared for a landing pad.
+    let tag = pointer & !0xffff_fffc;
+    let far = |segment: usize, double: bool| 2 | u64::from(double) << 2 | (segment as u64) << 32;
+    let object = src[start..start + len].to_vec();
+    let (segment, offset) = match layout {
+        Layout::Flat => unreachable!("flat samples are written as built"),
+        Layout::Far => {
+            out.push([vec![tag], object].concat());
+            (out.len() - 1, 1)
+        }
+        Layout::DoubleFar => {
+            out.push(object);
+            let segment = out.len() - 1;
+            out.push(vec![far(segment, false), tag]);
+            (segment, 0)
+        }
+    };
+    out[to.0][to.1] = far(out.len() - 1, layout == Layout::DoubleFar);
+    for slot in slots {
+        copy_pointer(src, start + slot, out, (segment, offset + slot), layout);
+    }
+}
+
+fn create_dir(dir: &Path) -> Result<(), TestError> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| TestError::new(ErrorCode::ConfigError, format!("{}: {}", dir.display(), e)))
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<(), TestError> {
+    std::fs::write(path, contents).map_err(|e| {
+        TestError::new(ErrorCode::ConfigError, format!("{}: {}", path.display(), e))
+    })?;
+    println!("{}", path.display());
+    Ok(())
+}
+
+fn write_message(
+    path: &Path,
+    message: &Builder<HeapAllocator>,
+    packed: bool,
+) -> Result<(), TestError> {
+    let mut bytes = Vec::new();
+    if packed {
+        capnp::serialize_packed::write_message(&mut bytes, message)?;
+    } else {
+        capnp::serialize::write_message(&mut bytes, message)?;
+    }
+    write_file(path, &bytes)
+}
+
+fn check_dir(dir: &Path) -> Result<(), TestError> {
+    if dir.is_dir() {
+        Ok(())
+    } else {
+        Err(TestError::new(
+            ErrorCode::ConfigError,
+            format!("{}: not a directory", dir.display()),
+        ))
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, TestError> {
+    std::fs::read(path)
+        .map_err(|e| TestError::new(ErrorCode::DecodeError, format!("{}: {}", path.display(), e)))
+}
+
+fn read_message(path: &Path, packed: bool) -> Result<message::Reader<OwnedSegments>, TestError> {
+    let bytes = read_file(path)?;
+    Ok(if packed {
+        capnp::serialize_packed::read_message(bytes.as_slice(), ReaderOptions::new())?
+    } else {
+        capnp::serialize::read_message(bytes.as_slice(), ReaderOptions::new())?
+    })
+}
+
+/// Checks every sample file of `layout` in `in_dir` against the corpus, one
+/// TAP test each. For the far layouts the root must also be reached through a
+/// far pointer of that kind.
+pub fn verify(in_dir: &Path, packed: bool, layout: Layout) -> Result<(), TestError> {
+    check_dir(in_dir)?;
+    let mut tap = TapReporter::new(SAMPLES.len() as u32);
+    for sample in SAMPLES {
+        tap.pass_or_fail(sample.name, verify_sample(sample, in_dir, packed, layout));
+    }
+    tap.done()
+}
+
+/// Checks the [`BIG_SAMPLES`] files in `in_dir` like [`verify`], with a TAP
+/// comment giving how long each took to read and compare.
+pub fn verify_big(in_dir: &Path, packed: bool, layout: Layout) -> Result<(), TestError> {
+    if layout != Layout::Flat {
+        return Err(TestError::new(
+            ErrorCode::ConfigError,
+            "--big is only supported for the flat layout",
+        ));
+    }
+    check_dir(in_dir)?;
+    let mut tap = TapReporter::new(BIG_SAMPLES.len() as u32);
+    for sample in BIG_SAMPLES {
+        let started = Instant::now();
+        let result = verify_sample(sample, in_dir, packed, layout);
+        tap.comment(&format!(
+            "{}: read and compared in {} ms",
+            sample.name,
+            started.elapsed().as_millis()
+        ));
+        tap.pass_or_fail(sample.name, result);
+    }
+    tap.done()
+}
+
+fn verify_sample(
+    sample: &Sample,
+    in_dir: &Path,
+    packed: bool,
+    layout: Layout,
+) -> Result<(), TestError> {
+    let actual = read_message(&in_dir.join(layout.file(sample.name)), packed)?;
+
+    let expected = sample.message().into_reader();
+    let mut mismatches = diff(
+        (sample.root)(expected.get_root()?)?,
+        (sample.root)(actual.get_root()?)?,
+    )?;
+    if layout != Layout::Flat {
+        let segments = actual.into_segments();
+        let root = segments
+            .get_segment(0)
+            .and_then(|segment| segment.get(..8))
+            .map_or(0, |word| u64::from_le_bytes(word.try_into().unwrap()));
+        let double = layout == Layout::DoubleFar;
+        if root & 3 != 2 || (root & 4 != 0) != double {
+            mismatches.push(format!(
+                "(root): expected a {} pointer, got {:#018x}",
+                if double { "double-far" } else { "single-far" },
+                root
+            ));
+        }
+    }
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(TestError::new(
+            ErrorCode::AssertionFailed,
+            mismatches.join("; "),
+        ))
+    }
+}
+
+/// Re-emits each sample message in `in_dir` in canonical form and
+/// byte-compares it with the `.canonical.bin` file beside it, one TAP test each.
+pub fn canonicalize(in_dir: &Path, packed: bool) -> Result<(), TestError> {
+    check_dir(in_dir)?;
+    let mut tap = TapReporter::new(SAMPLES.len() as u32);
+    for sample in SAMPLES {
+        tap.pass_or_fail(sample.name, canonicalize_sample(sample, in_dir, packed));
+    }
+    tap.done()
+}
+
+fn canonicalize_sample(sample: &Sample, in_dir: &Path, packed: bool) -> Result<(), TestError> {
+    let message = read_message(&in_dir.join(format!("{}.bin", sample.name)), packed)?;
+    let root: any_pointer::Reader = message.get_root()?;
+    let words = root.target_size()?.word_count + 1;
+    let mut canonical = Builder::new(HeapAllocator::new().first_segment_words(words as u32));
+    canonical.set_root_canonical(root)?;
+    let expected = canonical.get_segments_for_output()[0];
+
+    let actual = read_file(&in_dir.join(format!("{}.canonical.bin", sample.name)))?;
+    let differs_at = expected
+        .iter()
+        .zip(&actual)
+        .position(|(e, a)| e != a)
+        .or((expected.len() != actual.len()).then(|| expected.len().min(actual.len())));
+    match differs_at {
+        None => Ok(()),
+        Some(offset) => Err(TestError::new(
+            ErrorCode::AssertionFailed,
+            format!(
+                "canonical form differs at byte {} (word {}); expected {} bytes, got {}",
+                offset,
+                offset / 8,
+                expected.len(),
+                actual.len()
+            ),
+        )),
+    }
+}
+
+/// Which way [`convert_json`] goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// `<name>.bin` to `<name>.json`.
+    Json,
+    /// `<name>.json` to `<name>.bin`.
+    Binary,
+}
+
+/// Converts every sample in `in_dir` to `format`, writing the results to
+/// `out_dir` in the conventions of [`crate::json`] and printing each path.
+/// `packed` applies to the binary side either way.
+pub fn convert_json(
+    in_dir: &Path,
+    out_dir: &Path,
+    format: Format,
+    packed: bool,
+) -> Result<(), TestError> {
+    check_dir(in_dir)?;
+    create_dir(out_dir)?;
+    for sample in SAMPLES
+        .iter()
+        .filter(|sample| !NOT_JSON.contains(&sample.name))
+    {
+        match format {
+            Format::Json => {
+                let message = read_message(&in_dir.join(format!("{}.bin", sample.name)), packed)?;
+                let json = crate::json::encode((sample.root)(message.get_root()?)?.downcast())?;
+                let mut text = serde_json::to_string_pretty(&json)
+                    .map_err(|e| TestError::new(ErrorCode::Internal, e.to_string()))?;
+                text.push('\n');
+                write_file(
+                    &out_dir.join(format!("{}.json", sample.name)),
+                    text.as_bytes(),
+                )?;
+            }
+            Format::Binary => {
+                let path = in_dir.join(format!("{}.json", sample.name));
+                let decode_error = |e: String| {
+                    TestError::new(ErrorCode::DecodeError, format!("{}: {}", path.display(), e))
+                };
+                let json: serde_json::Value = serde_json::from_slice(&read_file(&path)?)
+                    .map_err(|e| decode_error(e.to_string()))?;
+                let mut message = Builder::new_default();
+                crate::json::decode(&json, (sample.init)(message.init_root()))
+                    .map_err(decode_error)?;
+                write_message(
+                    &out_dir.join(format!("{}.bin", sample.name)),
+                    &message,
+                    packed,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Every differing leaf of `expected` and `actual`, as `path: expected X,
+/// got Y`.
+pub(crate) fn diff(
+    expected: dynamic_value::Reader<'_>,
+    actual: dynamic_value::Reader<'_>,
+) -> capnp::Result<Vec<String>> {
+    let mut mismatches = Vec::new();
+    compare("", expected, actual, &mut mismatches)?;
+    Ok(mismatches)
+}
+
+/// Walks `expected` and `actual` together, recording `path: expected X, got Y`
+/// for every differing leaf.
+fn compare(
+    path: &str,
+    expected: dynamic_value::Reader<'_>,
+    actual: dynamic_value::Reader<'_>,
+    mismatches: &mut Vec<String>,
+) -> capnp::Result<()> {
+    match (expected, actual) {
+        (dynamic_value::Reader::Struct(expected), dynamic_value::Reader::Struct(actual)) => {
+            compare_struct(path, expected, actual, mismatches)
+        }
+        (dynamic_value::Reader::List(expected), dynamic_value::Reader::List(actual)) => {
+            if expected.len() != actual.len() {
+                mismatches.push(format!(
+                    "{}: expected {} elements, got {}",
+                    path,
+                    expected.len(),
+                    actual.len()
+                ));
+                return Ok(());
+            }
+            for i in 0..expected.len() {
+                compare(
+                    &format!("{}[{}]", path, i),
+                    expected.get(i)?,
+                    actual.get(i)?,
+                    mismatches,
+                )?;
+            }
+            Ok(())
+        }
+        // Floats compare bit for bit, so NaN payloads and signed zeros count.
+        (dynamic_value::Reader::Float32(e), dynamic_value::Reader::Float32(a)) => {
+            if e.to_bits() != a.to_bits() {
+                mismatches.push(format!(
+                    "{}: expected {:?} ({:#010x}), got {:?} ({:#010x})",
+                    path,
+                    e,
+                    e.to_bits(),
+                    a,
+                    a.to_bits()
+                ));
+            }
+            Ok(())
+        }
+        (dynamic_value::Reader::Float64(e), dynamic_value::Reader::Float64(a)) => {
+            if e.to_bits() != a.to_bits() {
+                mismatches.push(format!(
+                    "{}: expected {:?} ({:#018x}), got {:?} ({:#018x})",
+                    path,
+                    e,
+                    e.to_bits(),
+                    a,
+                    a.to_bits()
+                ));
+            }
+            Ok(())
+        }
+        (expected, actual) => {
+            let (expected, actual) = (format!("{:?}", expected), format!("{:?}", actual));
+            if expected != actual {
+                mismatches.push(format!("{}: expected {}, got {}", path, expected, actual));
+            }
+            Ok(())
+        }
+    }
+}
+
+fn compare_struct(
+    path: &str,
+    expected: dynamic_struct::Reader<'_>,
+    actual: dynamic_struct::Reader<'_>,
+    mismatches: &mut Vec<String>,
+) -> capnp::Result<()> {
+    let name = |field: capnp::schema::Field| -> capnp::Result<String> {
+        Ok(field.get_proto().get_name()?.to_string()?)
+    };
+    let mut fields: Vec<_> = expected
+        .get_schema()
+        .get_non_union_fields()?
+        .iter()
+        .collect();
+    match (expected.which()?, actual.which()?) {
+        (Some(e), Some(a)) if e.get_index() == a.get_index() => fields.push(e),
+        (Some(e), a) => {
+            let actual = match a {
+                Some(a) => name(a)?,
+                None => "an unknown member".to_string(),
+            };
+            mismatches.push(format!(
+                "{}: expected union member {}, got {}",
+                if path.is_empty() { "(root)" } else { path },
+                name(e)?,
+                actual
+            ));
+        }
+        (None, _) => {}
+    }
+    for field in fields {
+        // Null on both sides reads the same default, and following it into a
+        // recursive struct's default would never end.
+        if !expected.has(field)? && !actual.has(field)? {
+            continue;
+        }
+        let field_path = match path {
+            "" => name(field)?,
+            _ => format!("{}.{}", path, name(field)?),
+        };
+        compare(
+            &field_path,
+            expected.get(field)?,
+            actual.get(field)?,
+            mismatches,
+        )?;
+    }
+    Ok(())
+}
+
+// -- Shared types --
+
+fn set_player(mut builder: player_info::Builder<'_>, id: u64, name: &str, faction: Faction) {
+    builder.reborrow().init_id().set_id(id);
+    builder.reborrow().set_name(name);
+    builder.reborrow().set_faction(faction);
+    builder.set_level((id % 60) as u16 + 1);
+}
+
+fn set_item(
+    mut builder: item::Builder<'_>,
+    id: u64,
+    name: &str,
+    rarity: Rarity,
+    attributes: &[(&str, i32)],
+) {
+    builder.reborrow().init_id().set_id(id);
+    builder.reborrow().set_name(name);
+    builder.reborrow().set_rarity(rarity);
+    builder.reborrow().set_level(42);
+    builder.reborrow().set_stack_size(1);
+    let mut list = builder.init_attributes(attributes.len() as u32);
+    for (i, (name, value)) in attributes.iter().enumerate() {
+        let mut attribute = list.reborrow().get(i as u32);
+        attribute.set_name(*name);
+        attribute.set_value(*value);
+    }
+}
+
+fn set_slot(
+    mut builder: inventory_slot::Builder<'_>,
+    slot_index: u16,
+    quantity: u32,
+    item: impl FnOnce(item::Builder<'_>),
+) {
+    builder.set_slot_index(slot_index);
+    builder.set_quantity(quantity);
+    item(builder.init_item());
+}
+
+// -- GameWorld --
+
+fn build_entity(message: &mut Builder<HeapAllocator>) {
+    let mut entity = message.init_root::<entity::Builder>();
+    entity.reborrow().init_id().set_id(1001);
+    entity.set_kind(EntityKind::Monster);
+    entity.set_name("Cave Troll");
+    let mut position = entity.reborrow().init_position();
+    position.set_x(12.5);
+    position.set_y(-3.25);
+    position.set_z(0.0);
+    entity.set_health(-15);
+    entity.set_max_health(2500);
+    entity.set_faction(Faction::Pirates);
+    entity.set_alive(false);
+}
+
+fn build_spawn_request(message: &mut Builder<HeapAllocator>) {
+    let mut request = message.init_root::<spawn_request::Builder>();
+    request.set_kind(EntityKind::Npc);
+    request.set_name("Innkeeper \u{00c6}lfwine");
+    let mut position = request.reborrow().init_position();
+    position.set_x(-100.0);
+    position.set_y(64.0);
+    position.set_z(7.75);
+    request.set_faction(Faction::Alliance);
+    request.set_max_health(100);
+}
+
+fn init_area_query(message: &mut Builder<HeapAllocator>) -> area_query::Builder<'_> {
+    let mut query = message.init_root::<area_query::Builder>();
+    let mut center = query.reborrow().init_center();
+    center.set_x(1.0);
+    center.set_y(2.0);
+    center.set_z(3.0);
+    query.set_radius(50.0);
+    query
+}
+
+fn build_area_query_all(message: &mut Builder<HeapAllocator>) {
+    init_area_query(message).init_filter().set_all(());
+}
+
+fn build_area_query_by_kind(message: &mut Builder<HeapAllocator>) {
+    init_area_query(message)
+        .init_filter()
+        .set_by_kind(EntityKind::Projectile);
+}
+
+fn build_area_query_by_faction(message: &mut Builder<HeapAllocator>) {
+    init_area_query(message)
+        .init_filter()
+        .set_by_faction(Faction::Horde);
+}
+
+/// Infinities and the ends of the Float32 range. NaNs here are the plain
+/// quiet NaN, the only one the JSON form can carry.
+fn build_entity_edge_floats(message: &mut Builder<HeapAllocator>) {
+    let mut entity = message.init_root::<entity::Builder>();
+    entity.reborrow().init_id().set_id(1002);
+    entity.set_kind(EntityKind::Projectile);
+    entity.set_name("Stray Bolt");
+    let mut position = entity.init_position();
+    position.set_x(f32::NEG_INFINITY);
+    position.set_y(f32::MAX);
+    position.set_z(f32::from_bits(0x0000_0001));
+}
+
+/// A NaN radius around a center of signed zero and subnormals.
+fn build_area_query_nan_radius(message: &mut Builder<HeapAllocator>) {
+    let mut query = message.init_root::<area_query::Builder>();
+    let mut center = query.reborrow().init_center();
+    center.set_x(-0.0);
+    center.set_y(f32::from_bits(0x807f_ffff));
+    center.set_z(f32::MIN_POSITIVE);
+    query.set_radius(f32::NAN);
+    query.init_filter().set_all(());
+}
+
+// -- Chat --
+
+fn init_chat_message<'a>(
+    message: &'a mut Builder<HeapAllocator>,
+    content: &str,
+) -> chat_message::Builder<'a> {
+    let mut chat = message.init_root::<chat_message::Builder>();
+    set_player(chat.reborrow().init_sender(), 7, "Alice", Faction::Alliance);
+    chat.set_content(content);
+    chat.reborrow()
+        .init_timestamp()
+        .set_unix_millis(1_700_000_000_123);
+    chat
+}
+
+fn build_message_normal(message: &mut Builder<HeapAllocator>) {
+    init_chat_message(message, "Hello, world!")
+        .init_kind()
+        .set_normal(());
+}
+
+fn build_message_emote(message: &mut Builder<HeapAllocator>) {
+    init_chat_message(message, "waves \u{1f44b}")
+        .init_kind()
+        .set_emote(());
+}
+
+fn build_message_whisper(message: &mut Builder<HeapAllocator>) {
+    init_chat_message(message, "meet at the docks")
+        .init_kind()
+        .init_whisper()
+        .set_id(8);
+}
+
+fn build_room_info(message: &mut Builder<HeapAllocator>) {
+    let mut room = message.init_root::<room_info::Builder>();
+    room.reborrow().init_id().set_id(u64::MAX);
+    room.set_name("general");
+    room.set_member_count(3);
+    room.set_topic("");
+}
+
+// -- Inventory --
+
+fn build_inventory_view(message: &mut Builder<HeapAllocator>) {
+    let mut view = message.init_root::<inventory_view::Builder>();
+    view.reborrow().init_owner().set_id(42);
+    let mut slots = view.reborrow().init_slots(3);
+    set_slot(slots.reborrow().get(0), 0, 1, |item| {
+        set_item(
+            item,
+            500,
+            "Sword of Testing",
+            Rarity::Legendary,
+            &[("strength", 25), ("agility", -3)],
+        )
+    });
+    set_slot(slots.reborrow().get(1), 1, 20, |item| {
+        set_item(item, 501, "Health Potion", Rarity::Common, &[])
+    });
+    set_slot(slots.reborrow().get(2), 7, 3, |item| {
+        set_item(
+            item,
+            502,
+            "Dragon Scale",
+            Rarity::Epic,
+            &[("fire_resist", 40)],
+        )
+    });
+    view.set_capacity(40);
+    view.set_used_slots(3);
+}
+
+fn build_trade_offer(message: &mut Builder<HeapAllocator>) {
+    let mut offer = message.init_root::<trade_offer::Builder>();
+    let mut items = offer.reborrow().init_offered_items(2);
+    set_slot(items.reborrow().get(0), 4, 5, |item| {
+        set_item(item, 600, "Iron Ore", Rarity::Common, &[])
+    });
+    set_slot(items.reborrow().get(1), 9, 1, |item| {
+        set_item(item, 601, "Mithril Ring", Rarity::Rare, &[("mana", 15)])
+    });
+    offer.set_accepted(true);
+}
+
+// -- Matchmaking --
+
+fn build_queue_ticket(message: &mut Builder<HeapAllocator>) {
+    let mut ticket = message.init_root::<queue_ticket::Builder>();
+    ticket.set_ticket_id(0x0123_4567_89ab_cdef);
+    set_player(ticket.reborrow().init_player(), 11, "Bob", Faction::Horde);
+    ticket.set_mode(GameMode::Arena3v3);
+    ticket
+        .reborrow()
+        .init_enqueued_at()
+        .set_unix_millis(1_700_000_100_000);
+    ticket.set_estimated_wait_secs(90);
+}
+
+fn build_match_info(message: &mut Builder<HeapAllocator>) {
+    let mut info = message.init_root::<match_info::Builder>();
+    info.reborrow().init_id().set_id(77);
+    info.set_mode(GameMode::Arena3v3);
+    info.set_state(MatchState::InProgress);
+    let mut team_a = info.reborrow().init_team_a(3);
+    for (i, name) in ["Alice", "Carol", "Erin"].iter().enumerate() {
+        set_player(
+            team_a.reborrow().get(i as u32),
+            20 + i as u64,
+            name,
+            Faction::Alliance,
+        );
+    }
+    let mut team_b = info.reborrow().init_team_b(3);
+    for (i, name) in ["Bob", "Dave", "Frank"].iter().enumerate() {
+        set_player(
+            team_b.reborrow().get(i as u32),
+            30 + i as u64,
+            name,
+            Faction::Horde,
+        );
+    }
+    info.init_created_at().set_unix_millis(1_700_000_200_000);
+}
+
+fn build_match_result(message: &mut Builder<HeapAllocator>) {
+    let mut result = message.init_root::<match_result::Builder>();
+    result.reborrow().init_match_id().set_id(77);
+    result.set_winning_team(1);
+    result.set_duration(754);
+    let mut stats = result.init_player_stats(2);
+    for (i, (name, kills, deaths, assists, score)) in
+        [("Alice", 4, 6, 2, -150), ("Bob", 9, 1, 5, 2300)]
+            .iter()
+            .enumerate()
+    {
+        let mut entry = stats.reborrow().get(i as u32);
+        set_player(
+            entry.reborrow().init_player(),
+            20 + i as u64 * 10,
+            name,
+            Faction::Neutral,
+        );
+        entry.set_kills(*kills);
+        entry.set_deaths(*deaths);
+        entry.set_assists(*assists);
+        entry.set_score(*score);
+    }
+}
+
+// -- Defaults --
+
+/// Every data field explicitly at its default, so the data section is all
+/// zero; the pointer fields stay null and read as their defaults.
+fn build_settings_default(message: &mut Builder<HeapAllocator>) {
+    let mut settings = message.init_root::<settings::Builder>();
+    settings.set_count(42);
+    settings.set_offset(-1_000_000);
+    settings.set_retries(3);
+    settings.set_port(8080);
+    settings.set_seed(0xdead_beef_cafe);
+    settings.set_ratio(1.5);
+    settings.set_scale(-0.25);
+    settings.set_enabled(true);
+    settings.set_quality(Quality::High);
+}
+
+/// Every field zero, empty or false, none of which is its default.
+fn build_settings_zero(message: &mut Builder<HeapAllocator>) {
+    let mut settings = message.init_root::<settings::Builder>();
+    settings.set_count(0);
+    settings.set_offset(0);
+    settings.set_retries(0);
+    settings.set_port(0);
+    settings.set_seed(0);
+    settings.set_ratio(0.0);
+    settings.set_scale(0.0);
+    settings.set_enabled(false);
+    settings.set_quality(Quality::Low);
+    settings.set_label("");
+    let mut bounds = settings.reborrow().init_bounds();
+    bounds.set_min(0);
+    bounds.set_max(0);
+    settings.reborrow().init_spawn();
+    settings.reborrow().init_starter_item();
+    settings.set_salt(&[]);
+    settings.reborrow().init_ports(0);
+    settings.reborrow().init_flags(0);
+    settings.reborrow().init_tags(0);
+    settings.reborrow().init_waypoints(0);
+    settings.init_matrix(0);
+}
+
+/// Non-finite and signed-zero values in the Float fields, which are stored
+/// XORed with their defaults like every other field.
+fn build_settings_edge_floats(message: &mut Builder<HeapAllocator>) {
+    let mut settings = message.init_root::<settings::Builder>();
+    settings.set_ratio(-0.0);
+    settings.set_scale(f32::NAN);
+}
+
+// -- Lists --
+
+/// Fills every list of a BoolLists root with `bit(index, len)`.
+fn init_bool_lists(message: &mut Builder<HeapAllocator>, bit: fn(u32, u32) -> bool) {
+    let mut lists = message.init_root::<bool_lists::Builder>();
+    let fill = |mut list: capnp::primitive_list::Builder<'_, bool>| {
+        let len = list.len();
+        for i in 0..len {
+            list.set(i, bit(i, len));
+        }
+    };
+    fill(lists.reborrow().init_empty(0));
+    fill(lists.reborrow().init_single(1));
+    fill(lists.reborrow().init_byte(8));
+    fill(lists.reborrow().init_past_byte(9));
+    fill(lists.reborrow().init_word(64));
+    fill(lists.init_past_word(65));
+}
+
+fn build_bools_alternating(message: &mut Builder<HeapAllocator>) {
+    init_bool_lists(message, |i, _| i % 2 == 0);
+}
+
+fn build_bools_all(message: &mut Builder<HeapAllocator>) {
+    init_bool_lists(message, |_, _| true);
+}
+
+/// Every seventh element and the last one, so set bits fall at a different
+/// position in each byte and right at each list's end.
+fn build_bools_sparse(message: &mut Builder<HeapAllocator>) {
+    init_bool_lists(message, |i, len| i % 7 == 0 || i + 1 == len);
+}
+
+/// Every list empty, and the text and data empty rather than null: each
+/// pointer carries an element size and a zero count and points at nothing.
+fn build_lists_empty(message: &mut Builder<HeapAllocator>) {
+    let mut lists = message.init_root::<sized_lists::Builder>();
+    lists.reborrow().init_voids(0);
+    lists.reborrow().init_bools(0);
+    lists.reborrow().init_bytes(0);
+    lists.reborrow().init_shorts(0);
+    lists.reborrow().init_ints(0);
+    lists.reborrow().init_longs(0);
+    lists.reborrow().init_texts(0);
+    lists.reborrow().init_points(0);
+    lists.set_text("");
+    lists.set_data(&[]);
+}
+
+/// A long List(Void), which is only its count; every other field is null.
+fn build_lists_voids(message: &mut Builder<HeapAllocator>) {
+    message
+        .init_root::<sized_lists::Builder>()
+        .init_voids(100_000);
+}
+
+/// Byte sequences that are not UTF-8, one way of going wrong each.
+pub const INVALID_UTF8: [&[u8]; 6] = [
+    b"\xff",             // a byte UTF-8 never uses
+    b"\x80",             // a continuation byte with no lead
+    b"\xc3",             // a two-byte sequence cut short
+    b"\xc0\xaf",         // an overlong '/'
+    b"\xed\xa0\x80",     // an encoded UTF-16 surrogate
+    b"\xf4\x90\x80\x80", // past U+10FFFF
+];
+
+/// The `text` and `data` of `lists.text_invalid_utf8`: valid and invalid
+/// runs mixed, so a reader that replaces or drops bad bytes changes the length.
+pub const INVALID_UTF8_TEXT: &[u8] = b"ok \xff then \xc3( and \xed\xa0\x80 end";
+
+/// The `texts` of `lists.text_embedded_nul`, NULs first, last and alone.
+pub const EMBEDDED_NUL_TEXTS: [&[u8]; 4] = [b"\0", b"\0\0", b"\0lead", b"tail\0"];
+
+/// The `text` and `data` of `lists.text_embedded_nul`. A reader that stops at
+/// the first NUL, as a C string would, sees only `before`.
+pub const EMBEDDED_NUL_TEXT: &[u8] = b"before\0after";
+
+/// Writes `bytes` into a fresh Text of their length, unchecked: the setters
+/// that take a `&str` cannot hold bytes that are not UTF-8.
+fn set_raw_text(builder: capnp::text::Builder<'_>, bytes: &[u8]) {
+    builder.as_bytes_mut().copy_from_slice(bytes);
+}
+
+/// Text that is not UTF-8, carried byte for byte; the same bytes go in `data`
+/// too. Readers that check Text must reject it and ones that don't must pass
+/// it through unchanged, but none may repair it.
+fn build_text_invalid_utf8(message: &mut Builder<HeapAllocator>) {
+    let mut lists = message.init_root::<sized_lists::Builder>();
+    let mut texts = lists.reborrow().init_texts(INVALID_UTF8.len() as u32);
+    for (i, bytes) in INVALID_UTF8.iter().enumerate() {
+        set_raw_text(texts.reborrow().init(i as u32, bytes.len() as u32), bytes);
+    }
+    set_raw_text(
+        lists.reborrow().init_text(INVALID_UTF8_TEXT.len() as u32),
+        INVALID_UTF8_TEXT,
+    );
+    lists.set_data(INVALID_UTF8_TEXT);
+}
+
+/// Text with NULs inside it, ahead of the terminating one; the same bytes go
+/// in `data` too.
+fn build_text_embedded_nul(message: &mut Builder<HeapAllocator>) {
+    let mut lists = message.init_root::<sized_lists::Builder>();
+    let mut texts = lists.reborrow().init_texts(EMBEDDED_NUL_TEXTS.len() as u32);
+    for (i, bytes) in EMBEDDED_NUL_TEXTS.iter().enumerate() {
+        set_raw_text(texts.reborrow().init(i as u32, bytes.len() as u32), bytes);
+    }
+    set_raw_text(
+        lists.reborrow().init_text(EMBEDDED_NUL_TEXT.len() as u32),
+        EMBEDDED_NUL_TEXT,
+    );
+    lists.set_data(EMBEDDED_NUL_TEXT);
+}
+
+/// The bytes of each blob in `lists.blobs`, carried as both its text and its
+/// data. The lengths put the text's NUL terminator last in a word, first in
+/// the next one and in between; one already ends in a NUL of its own.
+pub const BLOBS: [&[u8]; 7] = [
+    b"",
+    b"a",
+    b"seven b",
+    b"eight by",
+    b"nine byte",
+    b"nul\0",
+    "t\u{e9}l\u{e9}phone \u{260e}".as_bytes(),
+];
+
+/// Each of [`BLOBS`] as a blob whose text and data are the same bytes.
+fn build_blobs(message: &mut Builder<HeapAllocator>) {
+    let mut blobs = message
+        .init_root::<blob_set::Builder>()
+        .init_blobs(BLOBS.len() as u32);
+    for (i, bytes) in BLOBS.iter().enumerate() {
+        let mut blob = blobs.reborrow().get(i as u32);
+        set_raw_text(blob.reborrow().init_text(bytes.len() as u32), bytes);
+        blob.set_data(*bytes);
+    }
+}
+
+/// Every way `blob` differs from a blob carrying `bytes` as both text and
+/// data. Both pointers are also read as Data, which shows them as they are
+/// on the wire: the text must be `bytes` and a NUL, the data `bytes` alone.
+pub fn check_blob(blob: blob::Reader<'_>, bytes: &[u8]) -> capnp::Result<Vec<String>> {
+    use capnp::traits::IntoInternalStructReader;
+
+    let mut mismatches = Vec::new();
+    let mut check = |what: &str, actual: &[u8], expected: &[u8]| {
+        if actual != expected {
+            mismatches.push(format!(
+                "{}: expected {:02x?}, got {:02x?}",
+                what, expected, actual
+            ));
+        }
+    };
+    check("text", blob.get_text()?.as_bytes(), bytes);
+    check("data", blob.get_data()?, bytes);
+    let reader = blob.into_internal_struct_reader();
+    let mut terminated = bytes.to_vec();
+    terminated.push(0);
+    check(
+        "text on the wire",
+        reader.get_pointer_field(0).get_data(None)?,
+        &terminated,
+    );
+    check(
+        "data on the wire",
+        reader.get_pointer_field(1).get_data(None)?,
+        bytes,
+    );
+    Ok(mismatches)
+}
+
+/// Element `i` of `lists.big_longs`: every bit of the word varies along the
+/// list, so a copy that drops the high half or shifts elements shows.
+pub fn big_long(i: u32) -> u64 {
+    u64::from(i).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+}
+
+/// `x` of element `i` of `lists.big_points`; its label is `p<i>`.
+pub fn big_point_x(i: u32) -> i32 {
+    i as i32 - (BIG_POINTS / 2) as i32
+}
+
+fn build_big_longs(message: &mut Builder<HeapAllocator>) {
+    let mut longs = message
+        .init_root::<sized_lists::Builder>()
+        .init_longs(BIG_LONGS);
+    for i in 0..BIG_LONGS {
+        longs.set(i, big_long(i));
+    }
+}
+
+fn build_big_points(message: &mut Builder<HeapAllocator>) {
+    let mut points = message
+        .init_root::<sized_lists::Builder>()
+        .init_points(BIG_POINTS);
+    for i in 0..BIG_POINTS {
+        set_point(
+            points.reborrow().get(i),
+            big_point_x(i),
+            Some(&format!("p{}", i)),
+        );
+    }
+}
+
+fn set_point(mut builder: point::Builder<'_>, x: i32, label: Option<&str>) {
+    builder.set_x(x);
+    if let Some(label) = label {
+        builder.set_label(label);
+    }
+}
+
+/// Ragged lists at every level, with empty inner lists first, in the middle
+/// and last.
+fn build_lists_nested(message: &mut Builder<HeapAllocator>) {
+    let mut lists = message.init_root::<nested_lists::Builder>();
+
+    let rows: [&[u32]; 5] = [&[], &[1, 2, 3], &[], &[u32::MAX], &[4, 5]];
+    let mut grid = lists.reborrow().init_grid(rows.len() as u32);
+    for (i, row) in rows.iter().enumerate() {
+        let mut out = grid.reborrow().init(i as u32, row.len() as u32);
+        for (j, value) in row.iter().enumerate() {
+            out.set(j as u32, *value);
+        }
+    }
+
+    let sentences: [&[&str]; 3] = [&["a", "bc"], &[""], &[]];
+    let mut words = lists.reborrow().init_words(sentences.len() as u32);
+    for (i, sentence) in sentences.iter().enumerate() {
+        let mut out = words.reborrow().init(i as u32, sentence.len() as u32);
+        for (j, word) in sentence.iter().enumerate() {
+            out.set(j as u32, *word);
+        }
+    }
+
+    let shapes: [&[(i32, Option<&str>)]; 3] = [
+        &[(1, Some("a")), (2, None), (3, Some("corner"))],
+        &[],
+        &[(-3, Some(""))],
+    ];
+    let mut polygons = lists.reborrow().init_polygons(shapes.len() as u32);
+    for (i, shape) in shapes.iter().enumerate() {
+        let mut out = polygons.reborrow().init(i as u32, shape.len() as u32);
+        for (j, (x, label)) in shape.iter().enumerate() {
+            set_point(out.reborrow().get(j as u32), *x, *label);
+        }
+    }
+
+    let mut inventories = lists.init_inventories(3);
+    let mut full = inventories.reborrow().get(0);
+    full.reborrow().init_owner().set_id(1);
+    let mut slots = full.reborrow().init_slots(2);
+    set_slot(slots.reborrow().get(0), 0, 1, |item| {
+        set_item(item, 700, "Quiver", Rarity::Uncommon, &[("capacity", 30)])
+    });
+    set_slot(slots.reborrow().get(1), 5, 99, |item| {
+        set_item(item, 701, "Arrow", Rarity::Common, &[])
+    });
+    full.set_capacity(10);
+    full.set_used_slots(2);
+    let mut empty = inventories.reborrow().get(1);
+    empty.reborrow().init_owner().set_id(2);
+    empty.reborrow().init_slots(0);
+    empty.set_capacity(10);
+    let mut single = inventories.get(2);
+    single.reborrow().init_owner().set_id(3);
+    set_slot(single.reborrow().init_slots(1).get(0), 9, 1, |item| {
+        set_item(
+            item,
+            702,
+            "Cracked Orb",
+            Rarity::Rare,
+            &[("mana", 5), ("luck", -1), ("light", 2)],
+        )
+    });
+    single.set_capacity(1);
+    single.set_used_slots(1);
+}
+
+// -- Spells --
+
+/// Which reagent a spell costs.
+enum Reagent {
+    None,
+    Item(u64),
+    Health(u16),
+}
+
+fn init_spell<'a>(
+    message: &'a mut Builder<HeapAllocator>,
+    name: &str,
+    mana: u32,
+    reagent: Reagent,
+) -> spell_effect::Builder<'a> {
+    let mut spell = message.init_root::<spell_effect::Builder>();
+    spell.set_name(name);
+    let mut cost = spell.reborrow().init_cost();
+    cost.set_mana(mana);
+    cost.set_cooldown_millis(mana * 10);
+    let mut slot = cost.init_reagent();
+    match reagent {
+        Reagent::None => slot.set_none(()),
+        Reagent::Item(id) => slot.init_item().set_id(id),
+        Reagent::Health(health) => slot.set_health(health),
+    }
+    spell
+}
+
+/// The first member of every union, so each discriminant is zero.
+fn build_spell_damage_single(message: &mut Builder<HeapAllocator>) {
+    let mut damage = init_spell(message, "Arcane Bolt", 20, Reagent::None).init_damage();
+    damage.set_amount(45);
+    damage.set_element(Element::Arcane);
+    damage.init_spread().set_single(());
+}
+
+fn build_spell_damage_splash(message: &mut Builder<HeapAllocator>) {
+    let mut damage = init_spell(message, "Frost Nova", 55, Reagent::Health(15)).init_damage();
+    damage.set_amount(80);
+    damage.set_element(Element::Frost);
+    damage.init_spread().set_splash_radius(4.5);
+}
+
+/// A group inside a union inside a group inside a union member.
+fn build_spell_damage_chain(message: &mut Builder<HeapAllocator>) {
+    let mut damage = init_spell(message, "Chain Lightning", 70, Reagent::Item(900)).init_damage();
+    damage.set_amount(120);
+    damage.set_element(Element::Nature);
+    let mut chain = damage.init_spread().init_chain();
+    chain.set_jumps(3);
+    chain.set_falloff(0.5);
+}
+
+fn build_spell_heal(message: &mut Builder<HeapAllocator>) {
+    let mut heal = init_spell(message, "Renew", 30, Reagent::None).init_heal();
+    heal.set_amount(300);
+    heal.set_over_time_secs(12);
+}
+
+fn build_spell_summon(message: &mut Builder<HeapAllocator>) {
+    let mut summon = init_spell(message, "Summon Imps", 90, Reagent::Item(u64::MAX)).init_summon();
+    summon.set_creature("Imp");
+    summon.set_count(2);
+}
+
+/// The last member of both top-level unions, with every other field zero.
+fn build_spell_dispel(message: &mut Builder<HeapAllocator>) {
+    init_spell(message, "Dispel", 0, Reagent::Health(u16::MAX)).set_dispel(());
+}
+
+/// Every field set, `currency` and `meta.verified` away from their defaults;
+/// [`build_plain_entry`] sets the same values.
+fn build_annotated_entry(message: &mut Builder<HeapAllocator>) {
+    let mut entry = message.init_root::<entry::Builder>();
+    entry.set_id(42);
+    entry.set_amount(-1500);
+    entry.set_currency(Currency::Silver);
+    entry.set_memo("rent");
+    let mut tags = entry.reborrow().init_tags(2);
+    tags.set(0, "monthly");
+    tags.set(1, "inn");
+    entry.reborrow().init_source().set_quest("The Lost Ledger");
+    let mut meta = entry.init_meta();
+    meta.set_recorded_at(1_700_000_000_000);
+    meta.set_verified(false);
+}
+
+fn build_plain_entry(message: &mut Builder<HeapAllocator>) {
+    let mut entry = message.init_root::<plain_entry::Builder>();
+    entry.set_id(42);
+    entry.set_amount(-1500);
+    entry.set_currency(Currency::Silver);
+    entry.set_memo("rent");
+    let mut tags = entry.reborrow().init_tags(2);
+    tags.set(0, "monthly");
+    tags.set(1, "inn");
+    entry.reborrow().init_source().set_quest("The Lost Ledger");
+    let mut meta = entry.init_meta();
+    meta.set_recorded_at(1_700_000_000_000);
+    meta.set_verified(false);
+}
+
+/// Sets every field of a `Wide` from ordinal `first` on, from its ordinal
+/// `n` alone: each byte of a data field is `n + 1` (a `Bool` is true), text
+/// reads `text n`, data is `n % 4 + 1` bytes of `n + 1`, a `List(UInt16)`
+/// three elements of `n + 1` in each byte, a `List(Bool)` `n % 7 + 1`
+/// alternating bits from true, and a `WideInner` has ID `n` and name
+/// `inner n`.
+fn fill_wide(message: &mut Builder<HeapAllocator>, first: u16) -> capnp::Result<()> {
+    let mut wide = init::<wide::Owned>(message.init_root());
+    for field in wide.get_schema().get_fields()? {
+        // Fields are declared in ordinal order.
+        let n = field.get_index();
+        if n < first {
+            continue;
+        }
+        let bits = u64::from_le_bytes([n as u8 + 1; 8]);
+        match field.get_type().which() {
+            TypeVariant::Bool => wide.set(field, true.into())?,
+            TypeVariant::Int8 => wide.set(field, (bits as i8).into())?,
+            TypeVariant::Int16 => wide.set(field, (bits as i16).into())?,
+            TypeVariant::Int32 => wide.set(field, (bits as i32).into())?,
+            TypeVariant::UInt8 => wide.set(field, (bits as u8).into())?,
+            TypeVariant::UInt16 => wide.set(field, (bits as u16).into())?,
+            TypeVariant::UInt64 => wide.set(field, bits.into())?,
+            TypeVariant::Float32 => wide.set(field, f32::from_bits(bits as u32).into())?,
+            TypeVariant::Float64 => wide.set(field, f64::from_bits(bits).into())?,
+            TypeVariant::Text => wide.set(field, format!("text {}", n).as_str().into())?,
+            TypeVariant::Data => {
+                let data = vec![n as u8 + 1; n as usize % 4 + 1];
+                wide.set(field, dynamic_value::Reader::Data(&data))?
+            }
+            TypeVariant::List(element) => {
+                let bools = matches!(element.which(), TypeVariant::Bool);
+                let len = if bools { n % 7 + 1 } else { 3 };
+                let mut list: dynamic_list::Builder =
+                    wide.reborrow().initn(field, len.into())?.downcast();
+                for i in 0..len {
+                    if bools {
+                        list.set(i.into(), (i % 2 == 0).into())?;
+                    } else {
+                        list.set(i.into(), (bits as u16).into())?;
+                    }
+                }
+            }
+            TypeVariant::Struct(_) => {
+                let mut inner: dynamic_struct::Builder = wide.reborrow().init(field)?.downcast();
+                inner.set_named("id", u32::from(n).into())?;
+                inner.set_named("name", format!("inner {}", n).as_str().into())?;
+            }
+            _ => unreachable!(
+                "Wide has no {} field",
+                field.get_proto().get_name()?.to_str()?
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn build_wide_full(message: &mut Builder<HeapAllocator>) {
+    fill_wide(message, 0).unwrap();
+}
+
+/// Only the last round's fields, so the rest of both sections is zero.
+fn build_wide_last_round(message: &mut Builder<HeapAllocator>) {
+    fill_wide(message, 96).unwrap();
+}
+
+// -- Market --
+
+/// `item` and `seller` come from imports spelled relative and absolute;
+/// `price` from a schema two directories down.
+fn build_market_listing(message: &mut Builder<HeapAllocator>) {
+    let mut listing = message.init_root::<listing::Builder>();
+    set_item(
+        listing.reborrow().init_item(),
+        7001,
+        "Sunblade",
+        Rarity::Epic,
+        &[("fire", 12)],
+    );
+    listing.reborrow().init_seller().set_id(314);
+    let mut price = listing.init_price();
+    price.set_amount(420);
+    price.reborrow().init_currency().set_id(1);
+    price.set_tier(Rarity::Epic);
+}
@@ -0,0 +1,56 @@
+//! Message echo for round-trip checks: every framed message read is deep-copied
+//! into a fresh builder and written back, so the bytes out are what this
+//! implementation produces for the same content.
+
+use std::io::{BufRead, Write};
+
+use capnp::any_pointer;
+use capnp::message::{Builder, ReaderOptions};
+
+use crate::error::{ErrorCode, TestError};
+
+/// Echoes messages from `input` to `output` until a clean end of input, in the
+/// packed encoding when `packed`, and returns how many were copied. Output is
+/// flushed after every message so a peer can work in lockstep.
+pub fn run(
+    mut input: impl BufRead,
+    mut output: impl Write,
+    packed: bool,
+) -> Result<u64, TestError> {
+    let mut count = 0;
+    loop {
+        let read = if packed {
+            capnp::serialize_packed::try_read_message(&mut input, ReaderOptions::new())
+        } else {
+            capnp::serialize::try_read_message(&mut input, ReaderOptions::new())
+        };
+        let message = match read.map_err(|e| at(count, e.into()))? {
+            Some(message) => message,
+            None => return Ok(count),
+        };
+
+        let mut copy = Builder::new_default();
+        let root: any_pointer::Reader = message.get_root().map_err(|e| at(count, e.into()))?;
+        copy.set_root(root).map_err(|e| at(count, e.into()))?;
+
+        let written = if packed {
+            capnp::serialize_packed::write_message(&mut output, &copy)
+        } else {
+            capnp::serialize::write_message(&mut output, &copy)
+        };
+        written
+            .and_then(|()| output.flush().map_err(capnp::Error::from))
+            .map_err(|e| {
+                TestError::new(
+                    ErrorCode::Internal,
+                    format!("writing message {}: {}", count, e),
+                )
+            })?;
+        count += 1;
+    }
+}
+
+/// Names the message an error belongs to, counting from 0.
+fn at(index: u64, e: TestError) -> TestError {
+    TestError::new(e.code, format!("message {}: {}", index, e.message))
+}
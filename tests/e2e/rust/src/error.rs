@@ -0,0 +1,126 @@
+use std::fmt;
+
+/// Stable failure classification surfaced in TAP diagnostics and exit codes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    ConnectFailed,
+    BootstrapFailed,
+    RpcException,
+    /// The peer does not implement a method the test called.
+    Unimplemented,
+    AssertionFailed,
+    Timeout,
+    DecodeError,
+    VersionMismatch,
+    /// Bad flags, unknown schema, missing binaries: the harness itself is misconfigured.
+    ConfigError,
+    /// Harness bug or environment failure, including panics.
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::ConnectFailed => "connect-failed",
+            ErrorCode::BootstrapFailed => "bootstrap-failed",
+            ErrorCode::RpcException => "rpc-exception",
+            ErrorCode::Unimplemented => "unimplemented",
+            ErrorCode::AssertionFailed => "assertion-failed",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::DecodeError => "decode-error",
+            ErrorCode::VersionMismatch => "version-mismatch",
+            ErrorCode::ConfigError => "config-error",
+            ErrorCode::Internal => "internal-error",
+        }
+    }
+
+    /// Process exit code used when a run ends with this kind of failure.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorCode::RpcException
+            | ErrorCode::Unimplemented
+            | ErrorCode::AssertionFailed
+            | ErrorCode::DecodeError => exit::TEST_FAILURES,
+            ErrorCode::ConnectFailed | ErrorCode::BootstrapFailed | ErrorCode::VersionMismatch => {
+                exit::CONNECTION
+            }
+            ErrorCode::ConfigError => exit::CONFIG,
+            ErrorCode::Internal => exit::INTERNAL,
+            ErrorCode::Timeout => exit::TIMEOUT,
+        }
+    }
+}
+
+/// Process exit codes shared by every subcommand.
+pub mod exit {
+    pub const PASS: i32 = 0;
+    pub const TEST_FAILURES: i32 = 2;
+    pub const CONNECTION: i32 = 3;
+    pub const CONFIG: i32 = 4;
+    pub const INTERNAL: i32 = 5;
+    pub const TIMEOUT: i32 = 6;
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A classified failure, used both for individual tests and whole runs.
+#[derive(Clone, Debug)]
+pub struct TestError {
+    pub code: ErrorCode,
+    pub message: String,
+    /// The struct the failure was found in, in capnp text format.
+    pub dump: Option<String>,
+}
+
+impl TestError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            dump: None,
+        }
+    }
+
+    /// Attaches `reader` printed as `capnp convert` would print it.
+    pub fn with_dump<'a>(mut self, reader: impl Into<capnp::dynamic_value::Reader<'a>>) -> Self {
+        self.dump = Some(format!("{:#?}", reader.into()));
+        self
+    }
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}]", self.message, self.code)
+    }
+}
+
+impl std::error::Error for TestError {}
+
+impl From<capnp::Error> for TestError {
+    fn from(e: capnp::Error) -> Self {
+        let code = match e.kind {
+            capnp::ErrorKind::Failed
+            | capnp::ErrorKind::Overloaded
+            | capnp::ErrorKind::Disconnected => ErrorCode::RpcException,
+            capnp::ErrorKind::Unimplemented => ErrorCode::Unimplemented,
+            _ => ErrorCode::DecodeError,
+        };
+        Self::new(code, e.to_string())
+    }
+}
+
+impl From<capnp::NotInSchema> for TestError {
+    fn from(e: capnp::NotInSchema) -> Self {
+        Self::new(ErrorCode::DecodeError, e.to_string())
+    }
+}
+
+impl From<std::str::Utf8Error> for TestError {
+    fn from(e: std::str::Utf8Error) -> Self {
+        Self::new(ErrorCode::DecodeError, e.to_string())
+    }
+}
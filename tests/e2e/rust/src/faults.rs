@@ -0,0 +1,163 @@
+//! Injected failures for exception kind propagation tests.
+//!
+//! `--fail Interface.method=kind` makes the server answer that method of its
+//! bootstrap interface with an exception of the given kind instead of running
+//! it; every other call goes through to the real implementation. Clients run
+//! with the same `--expect-fail` flags and check the kind they receive.
+
+use capnp::any_pointer;
+use capnp::capability::{DispatchCallResult, FromClientHook, FromServer, Params, Promise, Results};
+use capnp::private::capability::ClientHook;
+use capnp::ErrorKind;
+
+use crate::error::{ErrorCode, TestError};
+
+/// Exception kinds an injected failure can carry, in the order the Cap'n
+/// Proto `Exception.Type` enum lists them.
+pub const KINDS: [(&str, ErrorKind); 4] = [
+    ("failed", ErrorKind::Failed),
+    ("overloaded", ErrorKind::Overloaded),
+    ("disconnected", ErrorKind::Disconnected),
+    ("unimplemented", ErrorKind::Unimplemented),
+];
+
+/// One method made to fail with `kind`.
+#[derive(Clone, Debug)]
+pub struct Fault {
+    pub interface_id: u64,
+    pub method_id: u16,
+    /// `Interface.method`, as listed in `METHODS`.
+    pub method: &'static str,
+    pub kind: ErrorKind,
+}
+
+/// Parses `Interface.method=kind`.
+pub fn parse_fault(s: &str) -> Result<Fault, String> {
+    let (method, kind) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected Interface.method=kind, got {:?}", s))?;
+    let &(interface_id, method_id, method) = crate::METHODS
+        .iter()
+        .find(|(_, _, name)| *name == method)
+        .ok_or_else(|| format!("no such interface method: {}", method))?;
+    let &(_, kind) = KINDS.iter().find(|(name, _)| *name == kind).ok_or_else(|| {
+        format!(
+            "unknown exception kind {:?}; expected failed, overloaded, disconnected or unimplemented",
+            kind
+        )
+    })?;
+    Ok(Fault {
+        interface_id,
+        method_id,
+        method,
+        kind,
+    })
+}
+
+/// Wraps `bootstrap`, an `interface_id` capability, so the methods in
+/// `faults` fail. Returns it untouched when there are none, so ordinary runs
+/// keep direct dispatch and pipelining.
+pub(crate) fn wrap(
+    bootstrap: capnp::capability::Client,
+    interface_id: u64,
+    faults: &[Fault],
+) -> Result<capnp::capability::Client, TestError> {
+    if faults.is_empty() {
+        return Ok(bootstrap);
+    }
+    if let Some(fault) = faults.iter().find(|f| f.interface_id != interface_id) {
+        return Err(TestError::new(
+            ErrorCode::ConfigError,
+            format!(
+                "cannot fail {}: only bootstrap interface methods can fail",
+                fault.method
+            ),
+        ));
+    }
+    let client: AnyClient = capnp_rpc::new_client(FaultServer {
+        inner: bootstrap,
+        faults: faults.to_vec(),
+    });
+    Ok(client.client)
+}
+
+struct FaultServer {
+    inner: capnp::capability::Client,
+    faults: Vec<Fault>,
+}
+
+/// Untyped client, for capabilities whose interface is only known at run time.
+pub(crate) struct AnyClient {
+    pub client: capnp::capability::Client,
+}
+
+impl FromClientHook for AnyClient {
+    fn new(hook: Box<dyn ClientHook>) -> Self {
+        Self {
+            client: capnp::capability::Client::new(hook),
+        }
+    }
+
+    fn into_client_hook(self) -> Box<dyn ClientHook> {
+        self.client.hook
+    }
+
+    fn as_client_hook(&self) -> &dyn ClientHook {
+        &*self.client.hook
+    }
+}
+
+struct FaultDispatch(FaultServer);
+
+impl std::ops::Deref for FaultDispatch {
+    type Target = FaultServer;
+
+    fn deref(&self) -> &FaultServer {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for FaultDispatch {
+    fn deref_mut(&mut self) -> &mut FaultServer {
+        &mut self.0
+    }
+}
+
+impl FromServer<FaultServer> for AnyClient {
+    type Dispatch = FaultDispatch;
+
+    fn from_server(server: FaultServer) -> FaultDispatch {
+        FaultDispatch(server)
+    }
+}
+
+impl capnp::capability::Server for FaultDispatch {
+    fn dispatch_call(
+        &mut self,
+        interface_id: u64,
+        method_id: u16,
+        params: Params<any_pointer::Owned>,
+        mut results: Results<any_pointer::Owned>,
+    ) -> DispatchCallResult {
+        if let Some(fault) = self
+            .faults
+            .iter()
+            .find(|f| f.interface_id == interface_id && f.method_id == method_id)
+        {
+            let error = capnp::Error {
+                kind: fault.kind,
+                extra: format!("injected failure in {}", fault.method),
+            };
+            return DispatchCallResult::new(Promise::err(error), false);
+        }
+        let mut request = self
+            .inner
+            .new_call::<any_pointer::Owned, any_pointer::Owned>(interface_id, method_id, None);
+        let promise = Promise::from_future(async move {
+            request.get().set_as(params.get()?)?;
+            let response = request.send().promise.await?;
+            results.get().set_as(response.get()?)
+        });
+        DispatchCallResult::new(promise, false)
+    }
+}
@@ -0,0 +1,156 @@
+//! Captured RPC traffic read back as typed `rpc.capnp` messages, for checks
+//! and logs that need what a frame says rather than only its size.
+//!
+//! The schema is compiled from `schemas/capnp/rpc.capnp`, not taken from the
+//! RPC stack, so frames can be read in builds without one and with the
+//! protocol revision the Zig peer speaks.
+
+use capnp::message::{self, ReaderOptions, ReaderSegments};
+use capnp::serialize::OwnedSegments;
+
+use crate::rpc_capnp::{disembargo, exception, message_target, resolve, return_};
+
+/// One framed message of a captured byte stream.
+pub struct Frame {
+    /// Offset of the frame's segment table in the stream.
+    pub offset: usize,
+    /// Offset just past the frame's last segment.
+    pub end: usize,
+    pub message: message::Reader<OwnedSegments>,
+}
+
+impl Frame {
+    /// The frame's root as an RPC message.
+    pub fn root(&self) -> capnp::Result<crate::rpc_capnp::message::Reader<'_>> {
+        self.message.get_root()
+    }
+
+    /// The size of each of the frame's segments, in words.
+    pub fn segment_words(&self) -> Vec<u32> {
+        let segments = self.message.get_segments();
+        (0..segments.len() as u32)
+            .filter_map(|i| segments.get_segment(i))
+            .map(|s| (s.len() / 8) as u32)
+            .collect()
+    }
+}
+
+/// Reads every whole frame in `bytes`, in order. A frame cut off by the end
+/// of the capture is left out; anything else unreadable is an error.
+pub fn split(bytes: &[u8]) -> capnp::Result<Vec<Frame>> {
+    // Captures are our own traffic, already read once under the real limits.
+    let mut options = ReaderOptions::new();
+    options.traversal_limit_in_words(None);
+    let mut frames = Vec::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let offset = bytes.len() - rest.len();
+        match capnp::serialize::read_message(&mut rest, options) {
+            Ok(message) => frames.push(Frame {
+                offset,
+                end: bytes.len() - rest.len(),
+                message,
+            }),
+            Err(e) if e.kind == capnp::ErrorKind::PrematureEndOfFile => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(frames)
+}
+
+/// One line naming `msg`'s kind and the IDs it carries, e.g.
+/// `Call question=3 target=import(1) method=0x…@2`.
+pub fn describe(msg: crate::rpc_capnp::message::Reader<'_>) -> capnp::Result<String> {
+    use crate::rpc_capnp::message::Which;
+
+    Ok(match msg.which()? {
+        Which::Unimplemented(inner) => {
+            format!("Unimplemented ({})", describe(inner?)?)
+        }
+        Which::Abort(e) => format!("Abort {}", describe_exception(e?)?),
+        Which::Bootstrap(b) => format!("Bootstrap question={}", b?.get_question_id()),
+        Which::Call(c) => {
+            let c = c?;
+            format!(
+                "Call question={} target={} method={:#018x}@{}",
+                c.get_question_id(),
+                describe_target(c.get_target()?)?,
+                c.get_interface_id(),
+                c.get_method_id()
+            )
+        }
+        Which::Return(r) => {
+            let r = r?;
+            let outcome = match r.which()? {
+                return_::Results(_) => "results".to_string(),
+                return_::Exception(e) => format!("exception {}", describe_exception(e?)?),
+                return_::Canceled(()) => "canceled".to_string(),
+                return_::ResultsSentElsewhere(()) => "resultsSentElsewhere".to_string(),
+                return_::TakeFromOtherQuestion(question) => {
+                    format!("takeFromOtherQuestion={}", question)
+                }
+                return_::AwaitFromThirdParty(_) => "awaitFromThirdParty".to_string(),
+            };
+            format!("Return answer={} {}", r.get_answer_id(), outcome)
+        }
+        Which::Finish(f) => format!("Finish question={}", f?.get_question_id()),
+        Which::Resolve(r) => {
+            let r = r?;
+            let outcome = match r.which()? {
+                resolve::Cap(_) => "cap",
+                resolve::Exception(_) => "exception",
+            };
+            format!("Resolve promise={} {}", r.get_promise_id(), outcome)
+        }
+        Which::Release(r) => {
+            let r = r?;
+            format!(
+                "Release id={} count={}",
+                r.get_id(),
+                r.get_reference_count()
+            )
+        }
+        Which::Disembargo(d) => {
+            let d = d?;
+            let context = match d.get_context().which()? {
+                disembargo::context::SenderLoopback(id) => format!("senderLoopback={}", id),
+                disembargo::context::ReceiverLoopback(id) => format!("receiverLoopback={}", id),
+                disembargo::context::Accept(_) => "accept".to_string(),
+            };
+            format!(
+                "Disembargo target={} {}",
+                describe_target(d.get_target()?)?,
+                context
+            )
+        }
+        Which::ObsoleteSave(_) => "ObsoleteSave".to_string(),
+        Which::ObsoleteDelete(_) => "ObsoleteDelete".to_string(),
+        Which::Provide(p) => format!("Provide question={}", p?.get_question_id()),
+        Which::Accept(a) => format!("Accept question={}", a?.get_question_id()),
+        Which::ThirdPartyAnswer(t) => format!("ThirdPartyAnswer answer={}", t?.get_answer_id()),
+        Which::Join(j) => format!("Join question={}", j?.get_question_id()),
+    })
+}
+
+fn describe_target(target: message_target::Reader<'_>) -> capnp::Result<String> {
+    Ok(match target.which()? {
+        message_target::ImportedCap(id) => format!("import({})", id),
+        message_target::PromisedAnswer(answer) => {
+            format!("answer({})", answer?.get_question_id())
+        }
+    })
+}
+
+fn describe_exception(e: exception::Reader<'_>) -> capnp::Result<String> {
+    let kind = match e.get_type()? {
+        exception::Type::Failed => "failed",
+        exception::Type::Overloaded => "overloaded",
+        exception::Type::Disconnected => "disconnected",
+        exception::Type::Unimplemented => "unimplemented",
+    };
+    Ok(format!(
+        "{}: {}",
+        kind,
+        String::from_utf8_lossy(e.get_reason()?.as_bytes())
+    ))
+}
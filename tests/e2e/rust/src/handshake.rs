@@ -0,0 +1,85 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::error::{ErrorCode, TestError};
+
+// ---------------------------------------------------------------------------
+// Version preamble
+// ---------------------------------------------------------------------------
+//
+// When both peers are started with `--protocol-version`, the connection opens
+// with an 8-byte hello in each direction before any RPC traffic:
+//
+//     bytes 0..4  magic "CPRV"
+//     bytes 4..8  two-party protocol version, little-endian u32
+//
+// The client speaks first. The server always answers with its own version and
+// then closes the connection if the versions differ.
+
+const MAGIC: [u8; 4] = *b"CPRV";
+
+fn hello(version: u32) -> [u8; 8] {
+    let mut buf = [0u8; 8];
+    buf[..4].copy_from_slice(&MAGIC);
+    buf[4..].copy_from_slice(&version.to_le_bytes());
+    buf
+}
+
+/// Reads a peer hello, returning its advertised version or `None` on a bad magic.
+async fn read_hello<S: AsyncRead + Unpin>(stream: &mut S) -> std::io::Result<Option<u32>> {
+    let mut buf = [0u8; 8];
+    stream.read_exact(&mut buf).await?;
+    if buf[..4] != MAGIC {
+        return Ok(None);
+    }
+    Ok(Some(u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]])))
+}
+
+/// Client side: advertises `version` and waits for the server to agree.
+pub async fn offer<S>(stream: &mut S, version: u32) -> Result<(), TestError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let io_failed =
+        |e: std::io::Error| TestError::new(ErrorCode::ConnectFailed, format!("handshake: {}", e));
+    stream.write_all(&hello(version)).await.map_err(io_failed)?;
+    stream.flush().await.map_err(io_failed)?;
+    match read_hello(stream).await.map_err(io_failed)? {
+        Some(peer) if peer == version => Ok(()),
+        Some(peer) => Err(TestError::new(
+            ErrorCode::VersionMismatch,
+            format!(
+                "protocol version mismatch: advertised {}, server speaks {}",
+                version, peer
+            ),
+        )),
+        None => Err(TestError::new(
+            ErrorCode::DecodeError,
+            "handshake: server reply is not a version hello",
+        )),
+    }
+}
+
+/// Server side: answers the client hello with `version`.
+/// Returns `Ok(false)` when the client must be disconnected.
+pub async fn accept<S>(stream: &mut S, version: u32) -> std::io::Result<bool>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let peer = read_hello(stream).await?;
+    stream.write_all(&hello(version)).await?;
+    stream.flush().await?;
+    match peer {
+        Some(peer) if peer == version => Ok(true),
+        Some(peer) => {
+            eprintln!(
+                "rejecting client: protocol version {} (server speaks {})",
+                peer, version
+            );
+            Ok(false)
+        }
+        None => {
+            eprintln!("rejecting client: missing protocol version hello");
+            Ok(false)
+        }
+    }
+}
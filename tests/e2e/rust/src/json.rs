@@ -0,0 +1,289 @@
+//! Cap'n Proto ⇄ JSON, following the conventions of the reference C++
+//! `JsonCodec`, so the Zig codec has something to diff against.
+//!
+//! - Fields use their schema names; null pointers and inactive union members
+//!   are left out, everything else is written even when it is the default.
+//! - Enums are their enumerant names, Data is standard padded base64, and
+//!   Void is `null`.
+//! - `Int64`/`UInt64` are decimal strings, since JSON numbers can't hold them
+//!   exactly; non-finite floats are `"NaN"`, `"Infinity"` and `"-Infinity"`.
+//! - Groups are nested objects.
+//!
+//! Decoding accepts the same shapes, plus plain numbers for 64-bit integers.
+
+use base64::Engine;
+use capnp::dynamic_value::{self, Enum};
+use capnp::introspect::{Type, TypeVariant};
+use capnp::schema::{EnumSchema, Field};
+use capnp::{dynamic_list, dynamic_struct};
+use serde_json::{Map, Number, Value};
+
+/// Writes `reader` as a JSON object.
+pub fn encode(reader: dynamic_struct::Reader<'_>) -> capnp::Result<Value> {
+    let mut object = Map::new();
+    for field in reader.get_schema().get_fields()? {
+        if !reader.has(field)? {
+            continue;
+        }
+        let name = field.get_proto().get_name()?.to_str()?;
+        object.insert(name.to_string(), encode_value(reader.get(field)?)?);
+    }
+    Ok(Value::Object(object))
+}
+
+fn encode_value(value: dynamic_value::Reader<'_>) -> capnp::Result<Value> {
+    use dynamic_value::Reader as V;
+    Ok(match value {
+        V::Void => Value::Null,
+        V::Bool(b) => b.into(),
+        V::Int8(n) => n.into(),
+        V::Int16(n) => n.into(),
+        V::Int32(n) => n.into(),
+        V::Int64(n) => n.to_string().into(),
+        V::UInt8(n) => n.into(),
+        V::UInt16(n) => n.into(),
+        V::UInt32(n) => n.into(),
+        V::UInt64(n) => n.to_string().into(),
+        V::Float32(n) => encode_float(n.into()),
+        V::Float64(n) => encode_float(n),
+        V::Enum(e) => match e.get_enumerant()? {
+            Some(enumerant) => enumerant.get_proto().get_name()?.to_str()?.into(),
+            None => e.get_value().into(),
+        },
+        V::Text(t) => t.to_str()?.into(),
+        V::Data(d) => base64::engine::general_purpose::STANDARD.encode(d).into(),
+        V::Struct(s) => encode(s)?,
+        V::List(list) => Value::Array(
+            list.iter()
+                .map(|element| encode_value(element?))
+                .collect::<capnp::Result<_>>()?,
+        ),
+        V::AnyPointer(_) | V::Capability(_) => {
+            return Err(capnp::Error::unimplemented(
+                "JSON has no form for AnyPointer or capabilities".into(),
+            ))
+        }
+    })
+}
+
+fn encode_float(n: f64) -> Value {
+    match Number::from_f64(n) {
+        Some(n) => Value::Number(n),
+        None if n.is_nan() => "NaN".into(),
+        None if n > 0.0 => "Infinity".into(),
+        None => "-Infinity".into(),
+    }
+}
+
+/// Fills `builder` from a JSON object written by [`encode`]. Errors name the
+/// path of the offending value.
+pub fn decode(value: &Value, builder: dynamic_struct::Builder<'_>) -> Result<(), String> {
+    decode_struct("", value, builder)
+}
+
+fn decode_struct(
+    path: &str,
+    value: &Value,
+    mut builder: dynamic_struct::Builder<'_>,
+) -> Result<(), String> {
+    let Value::Object(object) = value else {
+        let path = if path.is_empty() { "(root)" } else { path };
+        return Err(format!("{}: expected an object, got {}", path, value));
+    };
+    let schema = builder.get_schema();
+    for (name, value) in object {
+        let path = match path {
+            "" => name.clone(),
+            _ => format!("{}.{}", path, name),
+        };
+        let field = schema
+            .find_field_by_name(name)
+            .map_err(|e| format!("{}: {}", path, e))?
+            .ok_or_else(|| format!("{}: no such field", path))?;
+        decode_field(&path, value, builder.reborrow(), field)?;
+    }
+    Ok(())
+}
+
+fn decode_field(
+    path: &str,
+    value: &Value,
+    mut builder: dynamic_struct::Builder<'_>,
+    field: Field,
+) -> Result<(), String> {
+    let at = |e: capnp::Error| format!("{}: {}", path, e);
+    match field.get_type().which() {
+        TypeVariant::Struct(_) => {
+            let child = builder.init(field).map_err(at)?.downcast();
+            decode_struct(path, value, child)
+        }
+        TypeVariant::List(element) => {
+            let elements = array(path, value)?;
+            let list = builder
+                .initn(field, elements.len() as u32)
+                .map_err(at)?
+                .downcast();
+            decode_list(path, elements, list, element)
+        }
+        TypeVariant::Data => {
+            let bytes = data(path, value)?;
+            builder
+                .set(field, dynamic_value::Reader::Data(&bytes))
+                .map_err(at)
+        }
+        TypeVariant::Text => {
+            let text = value
+                .as_str()
+                .ok_or_else(|| format!("{}: expected a string, got {}", path, value))?;
+            builder.set(field, text.into()).map_err(at)
+        }
+        ty => {
+            let scalar = scalar(path, value, ty)?;
+            builder.set(field, scalar).map_err(at)
+        }
+    }
+}
+
+fn decode_list(
+    path: &str,
+    elements: &[Value],
+    mut list: dynamic_list::Builder<'_>,
+    element: Type,
+) -> Result<(), String> {
+    for (i, value) in elements.iter().enumerate() {
+        let path = format!("{}[{}]", path, i);
+        let at = |e: capnp::Error| format!("{}: {}", path, e);
+        let index = i as u32;
+        match element.which() {
+            TypeVariant::Struct(_) => {
+                let child = list.reborrow().get(index).map_err(at)?.downcast();
+                decode_struct(&path, value, child)?;
+            }
+            TypeVariant::List(inner) => {
+                let values = array(&path, value)?;
+                let child = list
+                    .reborrow()
+                    .init(index, values.len() as u32)
+                    .map_err(at)?
+                    .downcast();
+                decode_list(&path, values, child, inner)?;
+            }
+            TypeVariant::Data => {
+                let bytes = data(&path, value)?;
+                list.set(index, dynamic_value::Reader::Data(&bytes))
+                    .map_err(at)?;
+            }
+            TypeVariant::Text => {
+                let text = value
+                    .as_str()
+                    .ok_or_else(|| format!("{}: expected a string, got {}", path, value))?;
+                list.set(index, text.into()).map_err(at)?;
+            }
+            ty => {
+                let scalar = scalar(&path, value, ty)?;
+                list.set(index, scalar).map_err(at)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn array<'v>(path: &str, value: &'v Value) -> Result<&'v [Value], String> {
+    value
+        .as_array()
+        .map(Vec::as_slice)
+        .ok_or_else(|| format!("{}: expected an array, got {}", path, value))
+}
+
+fn data(path: &str, value: &Value) -> Result<Vec<u8>, String> {
+    let text = value
+        .as_str()
+        .ok_or_else(|| format!("{}: expected a base64 string, got {}", path, value))?;
+    base64::engine::general_purpose::STANDARD
+        .decode(text)
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Reads a non-pointer value of type `ty`.
+fn scalar(
+    path: &str,
+    value: &Value,
+    ty: TypeVariant,
+) -> Result<dynamic_value::Reader<'static>, String> {
+    use dynamic_value::Reader as V;
+    let kind = kind(&ty);
+    let mismatch = || format!("{}: expected {}, got {}", path, kind, value);
+    let int = || -> Result<i128, String> {
+        let n = match value {
+            Value::Number(n) => n.as_i64().map(i128::from).or(n.as_u64().map(i128::from)),
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        };
+        n.ok_or_else(mismatch)
+    };
+    let float = || -> Result<f64, String> {
+        match value {
+            Value::Number(n) => n.as_f64(),
+            Value::String(s) => match s.as_str() {
+                "NaN" => Some(f64::NAN),
+                "Infinity" => Some(f64::INFINITY),
+                "-Infinity" => Some(f64::NEG_INFINITY),
+                _ => None,
+            },
+            _ => None,
+        }
+        .ok_or_else(mismatch)
+    };
+    let out_of_range = |_| format!("{}: {} is out of range for {}", path, value, kind);
+    Ok(match ty {
+        TypeVariant::Void if value.is_null() => V::Void,
+        TypeVariant::Bool => V::Bool(value.as_bool().ok_or_else(mismatch)?),
+        TypeVariant::Int8 => V::Int8(int()?.try_into().map_err(out_of_range)?),
+        TypeVariant::Int16 => V::Int16(int()?.try_into().map_err(out_of_range)?),
+        TypeVariant::Int32 => V::Int32(int()?.try_into().map_err(out_of_range)?),
+        TypeVariant::Int64 => V::Int64(int()?.try_into().map_err(out_of_range)?),
+        TypeVariant::UInt8 => V::UInt8(int()?.try_into().map_err(out_of_range)?),
+        TypeVariant::UInt16 => V::UInt16(int()?.try_into().map_err(out_of_range)?),
+        TypeVariant::UInt32 => V::UInt32(int()?.try_into().map_err(out_of_range)?),
+        TypeVariant::UInt64 => V::UInt64(int()?.try_into().map_err(out_of_range)?),
+        TypeVariant::Float32 => V::Float32(float()? as f32),
+        TypeVariant::Float64 => V::Float64(float()?),
+        TypeVariant::Enum(raw) => V::Enum(enumerant(path, value, raw.into())?),
+        _ => return Err(mismatch()),
+    })
+}
+
+fn enumerant(path: &str, value: &Value, schema: EnumSchema) -> Result<Enum, String> {
+    let at = |e: capnp::Error| format!("{}: {}", path, e);
+    if let Some(n) = value.as_u64().and_then(|n| u16::try_from(n).ok()) {
+        return Ok(Enum::new(n, schema));
+    }
+    let name = value
+        .as_str()
+        .ok_or_else(|| format!("{}: expected an enumerant name, got {}", path, value))?;
+    for enumerant in schema.get_enumerants().map_err(at)? {
+        if enumerant.get_proto().get_name().map_err(at)?.as_bytes() == name.as_bytes() {
+            return Ok(Enum::new(enumerant.get_ordinal(), schema));
+        }
+    }
+    Err(format!("{}: no enumerant named {:?}", path, name))
+}
+
+fn kind(ty: &TypeVariant) -> &'static str {
+    match ty {
+        TypeVariant::Void => "null",
+        TypeVariant::Bool => "a bool",
+        TypeVariant::Int8 => "an Int8",
+        TypeVariant::Int16 => "an Int16",
+        TypeVariant::Int32 => "an Int32",
+        TypeVariant::Int64 => "an Int64",
+        TypeVariant::UInt8 => "a UInt8",
+        TypeVariant::UInt16 => "a UInt16",
+        TypeVariant::UInt32 => "a UInt32",
+        TypeVariant::UInt64 => "a UInt64",
+        TypeVariant::Float32 => "a Float32",
+        TypeVariant::Float64 => "a Float64",
+        TypeVariant::Enum(_) => "an enumerant",
+        _ => "a scalar",
+    }
+}
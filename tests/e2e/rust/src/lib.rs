@@ -0,0 +1,243 @@
+//! Cap'n Proto RPC e2e test harness: reference server, client suites and helpers.
+
+#[allow(unused_parens, clippy::extra_unused_type_parameters)]
+pub mod persistent_capnp {
+    include!(concat!(env!("OUT_DIR"), "/capnp/persistent_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod game_types_capnp {
+    include!(concat!(env!("OUT_DIR"), "/game_types_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod game_world_capnp {
+    include!(concat!(env!("OUT_DIR"), "/game_world_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod chat_capnp {
+    include!(concat!(env!("OUT_DIR"), "/chat_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod inventory_capnp {
+    include!(concat!(env!("OUT_DIR"), "/inventory_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod matchmaking_capnp {
+    include!(concat!(env!("OUT_DIR"), "/matchmaking_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod nesting_capnp {
+    include!(concat!(env!("OUT_DIR"), "/nesting_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod defaults_capnp {
+    include!(concat!(env!("OUT_DIR"), "/defaults_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod lists_capnp {
+    include!(concat!(env!("OUT_DIR"), "/lists_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod ordering_capnp {
+    include!(concat!(env!("OUT_DIR"), "/ordering_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod telemetry_capnp {
+    include!(concat!(env!("OUT_DIR"), "/telemetry_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod registry_capnp {
+    include!(concat!(env!("OUT_DIR"), "/registry_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod debug_capnp {
+    include!(concat!(env!("OUT_DIR"), "/debug_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod linked_capnp {
+    include!(concat!(env!("OUT_DIR"), "/linked_capnp.rs"));
+}
+// Generated introspection keeps every brand parameter, used or not.
+#[allow(unused_parens, clippy::extra_unused_type_parameters)]
+pub mod generics_capnp {
+    include!(concat!(env!("OUT_DIR"), "/generics_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod envelope_capnp {
+    include!(concat!(env!("OUT_DIR"), "/envelope_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod spells_capnp {
+    include!(concat!(env!("OUT_DIR"), "/spells_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod forecast_capnp {
+    include!(concat!(env!("OUT_DIR"), "/forecast_capnp.rs"));
+}
+/// The next revision of `forecast_capnp`, which only the Rust harness knows.
+#[allow(unused_parens)]
+pub mod forecast_v2_capnp {
+    include!(concat!(env!("OUT_DIR"), "/forecast_v2_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod evolution_capnp {
+    include!(concat!(env!("OUT_DIR"), "/evolution_capnp.rs"));
+}
+/// The next revision of `evolution_capnp`, which only the Rust harness knows.
+#[allow(unused_parens)]
+pub mod evolution_v2_capnp {
+    include!(concat!(env!("OUT_DIR"), "/evolution_v2_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod annotated_capnp {
+    include!(concat!(env!("OUT_DIR"), "/annotated_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod widestruct_capnp {
+    include!(concat!(env!("OUT_DIR"), "/widestruct_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod commands_capnp {
+    include!(concat!(env!("OUT_DIR"), "/commands_capnp.rs"));
+}
+// Generated code names a file's module after its stem, wherever it sits.
+#[allow(unused_parens)]
+pub mod market_capnp {
+    include!(concat!(env!("OUT_DIR"), "/market/market_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod price_capnp {
+    include!(concat!(env!("OUT_DIR"), "/market/shared/price_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod deep_capnp {
+    include!(concat!(env!("OUT_DIR"), "/deep_capnp.rs"));
+}
+#[allow(unused_parens)]
+pub mod rpc_capnp {
+    include!(concat!(env!("OUT_DIR"), "/capnp/rpc_capnp.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/schema_fingerprint.rs"));
+include!(concat!(env!("OUT_DIR"), "/schema_methods.rs"));
+include!(concat!(env!("OUT_DIR"), "/schema_ids.rs"));
+
+/// One schema file of the build, as listed in `SCHEMA_IDS`.
+pub struct SchemaFile {
+    /// Path under the schema directory, e.g. `market/market.capnp`.
+    pub file: &'static str,
+    pub id: u64,
+    /// `SCHEMA_FINGERPRINT` over this file's nodes alone.
+    pub fingerprint: u64,
+    /// (type ID, name) of every node declared in the file, in ID order;
+    /// nested names are dotted, e.g. `Entry.source`.
+    pub types: &'static [(u64, &'static str)],
+}
+
+#[cfg(feature = "net")]
+mod artifacts;
+pub mod bad_frames;
+#[cfg(feature = "net")]
+pub mod client;
+pub mod corpus;
+pub mod echo;
+pub mod error;
+#[cfg(feature = "net")]
+pub mod faults;
+pub mod frames;
+#[cfg(feature = "net")]
+mod handshake;
+pub mod json;
+#[cfg(feature = "net")]
+pub mod mock;
+#[cfg(feature = "net")]
+pub mod orchestrate;
+#[cfg(feature = "net")]
+pub mod quic;
+#[cfg(feature = "net")]
+pub mod repl;
+pub mod report;
+pub mod schema_dump;
+#[cfg(feature = "net")]
+pub mod server;
+#[cfg(feature = "net")]
+pub mod tls;
+#[cfg(feature = "net")]
+pub mod transport;
+#[cfg(feature = "net")]
+pub mod traversal;
+#[cfg(feature = "net")]
+pub mod vat;
+
+/// Schemas the harness has suites and server implementations for.
+pub const SCHEMAS: [&str; 21] = [
+    "game_world",
+    "chat",
+    "inventory",
+    "matchmaking",
+    "nesting",
+    "defaults",
+    "lists",
+    "ordering",
+    "telemetry",
+    "registry",
+    "debug",
+    "linked",
+    "generics",
+    "envelope",
+    "spells",
+    "forecast",
+    "evolution",
+    "annotated",
+    "commands",
+    "market",
+    "deep",
+];
+
+/// Schemas the Zig client and server implement, and so the ones `orchestrate`
+/// crosses by default; the rest are served only by the Rust backend.
+pub const MATRIX_SCHEMAS: [&str; 4] = ["game_world", "chat", "inventory", "matchmaking"];
+
+/// Rejects schema names the harness does not know; `gameworld` is accepted as an alias.
+pub fn check_schema(schema: &str) -> Result<(), error::TestError> {
+    if schema == "gameworld" || SCHEMAS.contains(&schema) {
+        Ok(())
+    } else {
+        Err(error::TestError::new(
+            error::ErrorCode::ConfigError,
+            format!("unknown schema: {}", schema),
+        ))
+    }
+}
+
+/// Limits applied to every incoming message; unset ones keep the library
+/// defaults. Set them to the peer's so both reject the same messages.
+#[derive(Clone, Copy, Debug, Default, clap::Args)]
+pub struct ReaderLimits {
+    /// Traversal limit for incoming messages, in words (default 8 Mi).
+    #[arg(
+        long = "reader-traversal-limit",
+        visible_alias = "traversal-limit-words",
+        value_name = "WORDS"
+    )]
+    pub traversal_limit_words: Option<u64>,
+    /// Nesting limit for incoming messages, in pointer levels (default 64).
+    #[arg(
+        long = "reader-nesting-limit",
+        value_name = "DEPTH",
+        value_parser = clap::value_parser!(i32).range(1..)
+    )]
+    pub nesting_limit: Option<i32>,
+}
+
+impl ReaderLimits {
+    pub fn options(&self) -> capnp::message::ReaderOptions {
+        let mut options = capnp::message::ReaderOptions::new();
+        if let Some(words) = self.traversal_limit_words {
+            options.traversal_limit_in_words(Some(words as usize));
+        }
+        if let Some(depth) = self.nesting_limit {
+            options.nesting_limit(depth);
+        }
+        options
+    }
+}
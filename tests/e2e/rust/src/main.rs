@@ -1,27 +1,17 @@
-pub mod game_types_capnp {
-    include!(concat!(env!("OUT_DIR"), "/game_types_capnp.rs"));
-}
-pub mod game_world_capnp {
-    include!(concat!(env!("OUT_DIR"), "/game_world_capnp.rs"));
-}
-pub mod chat_capnp {
-    include!(concat!(env!("OUT_DIR"), "/chat_capnp.rs"));
-}
-pub mod inventory_capnp {
-    include!(concat!(env!("OUT_DIR"), "/inventory_capnp.rs"));
-}
-pub mod matchmaking_capnp {
-    include!(concat!(env!("OUT_DIR"), "/matchmaking_capnp.rs"));
-}
-
-mod client;
-mod server;
+use e2e_rpc_test::error::{self, ErrorCode, TestError};
+use e2e_rpc_test::{bad_frames, corpus, echo, schema_dump};
+#[cfg(feature = "net")]
+use e2e_rpc_test::{client, faults, mock, orchestrate, repl, report, server, tls, transport, vat};
 
 use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(name = "e2e-rpc-test")]
 #[command(about = "Cap'n Proto RPC e2e test - Rust implementation")]
+#[command(
+    after_help = "Exit codes: 0 pass, 2 test failures, 3 connection/bootstrap failure, \
+                  4 configuration error, 5 internal error or panic, 6 timeout"
+)]
 struct Cli {
     #[command(subcommand)]
     mode: Mode,
@@ -29,6 +19,7 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Mode {
+    #[cfg(feature = "net")]
     Server {
         #[arg(long, default_value = "127.0.0.1")]
         host: String,
@@ -36,7 +27,97 @@ enum Mode {
         port: u16,
         #[arg(long, default_value = "game_world")]
         schema: String,
+        #[arg(long, value_enum, default_value_t = transport::Transport::Tcp)]
+        transport: transport::Transport,
+        /// Pipe name for `--transport named-pipe`, e.g. `\\.\pipe\capnp-e2e`.
+        #[arg(long)]
+        name: Option<String>,
+        /// Socket path for `--transport unix`, e.g. `/tmp/e2e.sock`.
+        #[arg(long)]
+        path: Option<std::path::PathBuf>,
+        /// Exchange a version hello before RPC traffic; both peers must agree.
+        #[arg(long)]
+        protocol_version: Option<u32>,
+        /// Start every outgoing message with a first segment of N words, so
+        /// larger responses span several segments joined by far pointers.
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        first_segment_words: Option<u32>,
+        /// Answer a bootstrap method with an exception of this kind instead
+        /// of running it, e.g. `GameWorld.spawnEntity=overloaded`. Repeatable.
+        #[arg(long = "fail", value_name = "METHOD=KIND", value_parser = faults::parse_fault)]
+        faults: Vec<faults::Fault>,
+        /// Serve no bootstrap capability; calls on the one a client asks for
+        /// fail with an exception.
+        #[arg(long)]
+        no_bootstrap: bool,
+        /// Flow control window for streaming calls, in bytes (default 65536).
+        /// Match the peer's so both sides hold back the same traffic.
+        #[arg(long, value_name = "BYTES")]
+        flow_window: Option<usize>,
+        /// Stamp every timestamp with this many milliseconds since the Unix
+        /// epoch instead of the clock's time; may be negative.
+        #[arg(long, value_name = "MILLIS", allow_hyphen_values = true)]
+        fixed_time: Option<i64>,
+        /// Serve every connection the same service, so players on separate
+        /// connections share rooms and trades. Matchmaking always shares.
+        #[arg(long)]
+        shared_state: bool,
+        #[command(flatten)]
+        limits: e2e_rpc_test::ReaderLimits,
+        #[command(flatten)]
+        socket: transport::SocketArgs,
+        #[command(flatten)]
+        tls: tls::TlsArgs,
+    },
+    /// Serve one peer over stdin/stdout, e.g. when spawned by an orchestrator.
+    #[cfg(feature = "net")]
+    ServerStdio {
+        #[arg(long, default_value = "game_world")]
+        schema: String,
+        /// Exchange a version hello before RPC traffic; both peers must agree.
+        #[arg(long)]
+        protocol_version: Option<u32>,
+        /// Start every outgoing message with a first segment of N words.
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        first_segment_words: Option<u32>,
+        /// Answer a bootstrap method with an exception of this kind. Repeatable.
+        #[arg(long = "fail", value_name = "METHOD=KIND", value_parser = faults::parse_fault)]
+        faults: Vec<faults::Fault>,
+        /// Serve no bootstrap capability.
+        #[arg(long)]
+        no_bootstrap: bool,
+        /// Flow control window for streaming calls, in bytes (default 65536).
+        #[arg(long, value_name = "BYTES")]
+        flow_window: Option<usize>,
+        /// Stamp every timestamp with this many milliseconds since the epoch.
+        #[arg(long, value_name = "MILLIS", allow_hyphen_values = true)]
+        fixed_time: Option<i64>,
+        #[command(flatten)]
+        limits: e2e_rpc_test::ReaderLimits,
+    },
+    /// Serve canned responses from a fixtures directory instead of the real impls.
+    #[cfg(feature = "net")]
+    Mock {
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        #[arg(long, default_value_t = 4003)]
+        port: u16,
+        /// Directory of `<Interface>.<method>.bin` results and `.err` exception texts.
+        #[arg(long)]
+        fixtures: std::path::PathBuf,
+        #[arg(long, value_enum, default_value_t = transport::Transport::Tcp)]
+        transport: transport::Transport,
+        /// Pipe name for `--transport named-pipe`, e.g. `\\.\pipe\capnp-e2e`.
+        #[arg(long)]
+        name: Option<String>,
+        /// Socket path for `--transport unix`, e.g. `/tmp/e2e.sock`.
+        #[arg(long)]
+        path: Option<std::path::PathBuf>,
+        /// Exchange a version hello before RPC traffic; both peers must agree.
+        #[arg(long)]
+        protocol_version: Option<u32>,
     },
+    #[cfg(feature = "net")]
     Client {
         #[arg(long, default_value = "127.0.0.1")]
         host: String,
@@ -44,26 +125,641 @@ enum Mode {
         port: u16,
         #[arg(long, default_value = "game_world")]
         schema: String,
+        #[arg(long, value_enum, default_value_t = transport::Transport::Tcp)]
+        transport: transport::Transport,
+        /// Pipe name for `--transport named-pipe`, e.g. `\\.\pipe\capnp-e2e`.
+        #[arg(long)]
+        name: Option<String>,
+        /// Socket path for `--transport unix`, e.g. `/tmp/e2e.sock`.
+        #[arg(long)]
+        path: Option<std::path::PathBuf>,
+        /// Write captured frames and a failure record for each failing test here.
+        #[arg(long)]
+        artifacts_dir: Option<std::path::PathBuf>,
+        /// Run each selected test this many times.
+        #[arg(long, default_value_t = 1)]
+        repeat: u32,
+        /// Exchange a version hello before RPC traffic; both peers must agree.
+        #[arg(long)]
+        protocol_version: Option<u32>,
+        /// Print the planned tests and target without connecting.
+        #[arg(long)]
+        dry_run: bool,
+        /// Print `<schema>\t<test>` for every test of every schema, as
+        /// narrowed by `--filter`, and exit without connecting.
+        #[arg(long, conflicts_with = "dry_run")]
+        list_tests: bool,
+        /// Only check that this method fails with an exception of this kind,
+        /// against a server run with the matching `--fail`. Repeatable.
+        #[arg(long = "expect-fail", value_name = "METHOD=KIND", value_parser = faults::parse_fault)]
+        faults: Vec<faults::Fault>,
+        /// Only check that a server run with `--no-bootstrap` fails the
+        /// bootstrap capability's calls with an exception instead of hanging.
+        #[arg(long)]
+        expect_no_bootstrap: bool,
+        /// Call the bootstrap capability's `ping` this often while the suite
+        /// runs, and report the round trips and whether every ping was
+        /// answered. Unlike `--keepalive-secs`, this exercises the RPC layer.
+        #[arg(long, value_name = "SECS")]
+        rpc_keepalive_secs: Option<u64>,
+        /// Flow control window for streaming calls, in bytes (default 65536).
+        /// Match the peer's so both sides hold back the same traffic.
+        #[arg(long, value_name = "BYTES")]
+        flow_window: Option<usize>,
+        /// Expect every timestamp to be this, against a server run with the
+        /// same `--fixed-time`.
+        #[arg(long, value_name = "MILLIS", allow_hyphen_values = true)]
+        fixed_time: Option<i64>,
+        /// Also run the big list tests, which send a million-element
+        /// List(UInt64) and a hundred-thousand-element struct list through
+        /// `ListService.echo`; they are skipped otherwise.
+        #[arg(long)]
+        big: bool,
+        /// Run only the tests whose names contain this, or match it as a
+        /// glob when it has `*` or `?`; the TAP plan counts only those.
+        #[arg(long, value_name = "PATTERN", value_parser = client::TestFilter::parse)]
+        filter: Option<client::TestFilter>,
+        /// Stop at the first failing test with a TAP `Bail out!` instead of
+        /// running the rest against a peer that may be in a bad state.
+        #[arg(long)]
+        fail_fast: bool,
+        /// Fail a test as a timeout once it has run this long, so a hung
+        /// peer costs one test instead of stalling the run.
+        #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+        test_timeout_secs: Option<u64>,
+        /// Rerun a failing test up to this many times; one that then passes
+        /// is reported ok with a `# RETRIED` comment and listed at the end.
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        retries: u32,
+        #[command(flatten)]
+        report: report::ReportArgs,
+        #[command(flatten)]
+        limits: e2e_rpc_test::ReaderLimits,
+        #[command(flatten)]
+        dial: transport::DialArgs,
+        #[command(flatten)]
+        socket: transport::SocketArgs,
+        #[command(flatten)]
+        tls: tls::TlsArgs,
+    },
+    /// Run a suite over stdin/stdout against a piped peer; TAP goes to stderr.
+    #[cfg(feature = "net")]
+    ClientStdio {
+        #[arg(long, default_value = "game_world")]
+        schema: String,
+        /// Write captured frames and a failure record for each failing test here.
+        #[arg(long)]
+        artifacts_dir: Option<std::path::PathBuf>,
+        /// Run each selected test this many times.
+        #[arg(long, default_value_t = 1)]
+        repeat: u32,
+        /// Exchange a version hello before RPC traffic; both peers must agree.
+        #[arg(long)]
+        protocol_version: Option<u32>,
+        /// Flow control window for streaming calls, in bytes (default 65536).
+        #[arg(long, value_name = "BYTES")]
+        flow_window: Option<usize>,
+        /// Run only the tests whose names contain this, or match it as a glob.
+        #[arg(long, value_name = "PATTERN", value_parser = client::TestFilter::parse)]
+        filter: Option<client::TestFilter>,
+        /// Stop at the first failing test with a TAP `Bail out!`.
+        #[arg(long)]
+        fail_fast: bool,
+        /// Fail a test as a timeout once it has run this long.
+        #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+        test_timeout_secs: Option<u64>,
+        /// Rerun a failing test up to this many times.
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        retries: u32,
+        #[command(flatten)]
+        report: report::ReportArgs,
+        #[command(flatten)]
+        limits: e2e_rpc_test::ReaderLimits,
+    },
+    /// Connect, bootstrap and make one cheap call; prints a one-line JSON result.
+    #[cfg(feature = "net")]
+    Health {
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        #[arg(long, default_value_t = 4003)]
+        port: u16,
+        #[arg(long, default_value = "game_world")]
+        schema: String,
+        #[arg(long, default_value_t = 5000)]
+        timeout_ms: u64,
+        /// Exchange a version hello before RPC traffic; both peers must agree.
+        #[arg(long)]
+        protocol_version: Option<u32>,
+    },
+    /// Connect to a server and issue RPCs from a small command language on stdin.
+    #[cfg(feature = "net")]
+    Repl {
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        #[arg(long, default_value_t = 4003)]
+        port: u16,
+        #[arg(long, default_value = "game_world")]
+        schema: String,
+        #[arg(long, value_enum, default_value_t = transport::Transport::Tcp)]
+        transport: transport::Transport,
+        /// Pipe name for `--transport named-pipe`, e.g. `\\.\pipe\capnp-e2e`.
+        #[arg(long)]
+        name: Option<String>,
+        /// Socket path for `--transport unix`, e.g. `/tmp/e2e.sock`.
+        #[arg(long)]
+        path: Option<std::path::PathBuf>,
+        /// Exchange a version hello before RPC traffic; both peers must agree.
+        #[arg(long)]
+        protocol_version: Option<u32>,
+    },
+    /// Play one vat of the three-party handoff topology: the provider (A)
+    /// serves chat, the introducer (C) hands A's room to the recipient (B).
+    #[cfg(feature = "net")]
+    Vat {
+        #[arg(long, value_enum)]
+        role: vat::Role,
+        /// Where the provider or introducer listens.
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        #[arg(long, default_value_t = 4003)]
+        port: u16,
+        /// The vat to connect to: A for the introducer, C for the recipient.
+        #[arg(long, default_value = "127.0.0.1")]
+        peer_host: String,
+        #[arg(long)]
+        peer_port: Option<u16>,
+    },
+    /// Run the cross matrix against external Zig server and client binaries.
+    #[cfg(feature = "net")]
+    Orchestrate {
+        #[arg(long)]
+        zig_server: std::path::PathBuf,
+        /// Argument for the Zig server, placed before `--host/--port/--schema`; repeatable.
+        #[arg(long = "zig-server-arg", allow_hyphen_values = true)]
+        zig_server_args: Vec<String>,
+        #[arg(long)]
+        zig_client: std::path::PathBuf,
+        /// Argument for the Zig client, placed before `--host/--port/--schema`; repeatable.
+        #[arg(long = "zig-client-arg", allow_hyphen_values = true)]
+        zig_client_args: Vec<String>,
+        /// Schema to cover; repeatable, every schema the Zig peers implement
+        /// when omitted.
+        #[arg(long = "schema")]
+        schemas: Vec<String>,
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        /// Server and client output for every case is written here.
+        #[arg(long, default_value = "orchestrate-logs")]
+        log_dir: std::path::PathBuf,
+        #[arg(long, default_value_t = 60_000)]
+        startup_timeout_ms: u64,
+        #[arg(long, default_value_t = 20_000)]
+        case_timeout_ms: u64,
+        /// Print the planned pairings and peer commands without spawning anything.
+        #[arg(long)]
+        dry_run: bool,
     },
+    /// Write the golden message corpus, one framed message per file.
+    Encode {
+        #[arg(long)]
+        out_dir: std::path::PathBuf,
+        /// Use the packed encoding instead of plain stream framing.
+        #[arg(long)]
+        packed: bool,
+        /// Spread each message over segments behind far pointers, written
+        /// as `<schema>.<message>.far.bin` or `.double_far.bin`.
+        #[arg(long, value_enum, default_value_t = corpus::Layout::Flat)]
+        layout: corpus::Layout,
+        /// Write only the big list samples, `lists.big_longs` and
+        /// `lists.big_points`, which the usual corpus leaves out.
+        #[arg(long)]
+        big: bool,
+    },
+    /// Check `<schema>.<message>.bin` files written by another encoder
+    /// against the golden corpus; reports TAP.
+    Verify {
+        #[arg(long)]
+        in_dir: std::path::PathBuf,
+        /// Read the files as packed messages.
+        #[arg(long)]
+        packed: bool,
+        /// Check the `.far.bin` or `.double_far.bin` files instead, and that
+        /// their roots sit behind that kind of far pointer.
+        #[arg(long, value_enum, default_value_t = corpus::Layout::Flat)]
+        layout: corpus::Layout,
+        /// Check only the big list samples written by `encode --big`.
+        #[arg(long)]
+        big: bool,
+    },
+    /// Canonicalize each `<schema>.<message>.bin` and byte-compare it with the
+    /// `<schema>.<message>.canonical.bin` produced by another implementation.
+    Canonicalize {
+        #[arg(long)]
+        in_dir: std::path::PathBuf,
+        /// Read the input messages as packed.
+        #[arg(long)]
+        packed: bool,
+    },
+    /// Read framed messages from stdin and write a deep copy of each to stdout.
+    Echo {
+        /// Read and write the packed encoding.
+        #[arg(long)]
+        packed: bool,
+    },
+    /// Write deliberately malformed segment tables and a `manifest.json`
+    /// giving the reason each must be rejected.
+    GenBadFrames {
+        #[arg(long)]
+        out_dir: std::path::PathBuf,
+    },
+    /// Convert the corpus in `--in-dir` between binary and the capnp JSON
+    /// conventions, writing `<schema>.<message>.json` or `.bin` to `--out-dir`.
+    Json {
+        #[arg(long)]
+        in_dir: std::path::PathBuf,
+        #[arg(long)]
+        out_dir: std::path::PathBuf,
+        /// Format to convert to; the input is the other one.
+        #[arg(long, value_enum, default_value_t = corpus::Format::Json)]
+        to: corpus::Format,
+        /// The binary messages are packed.
+        #[arg(long)]
+        packed: bool,
+    },
+    /// Run the schema compiler and print each node's ID, field offsets and
+    /// discriminant position from its `CodeGeneratorRequest` as JSON.
+    SchemaDump {
+        /// Schema compiler to run.
+        #[arg(long, default_value = "capnp")]
+        capnp: std::path::PathBuf,
+        /// Directory the schema files and their imports live in.
+        #[arg(long, default_value = schema_dump::SCHEMA_DIR)]
+        schema_dir: std::path::PathBuf,
+        /// Schema file to compile, relative to `--schema-dir`. Repeatable;
+        /// defaults to every schema the harness is built from.
+        #[arg(long = "file")]
+        files: Vec<std::path::PathBuf>,
+        /// Write the JSON here instead of stdout.
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+    },
+}
+
+fn main() {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            // --help and --version also arrive here and are not failures.
+            let code = if e.use_stderr() {
+                error::exit::CONFIG
+            } else {
+                error::exit::PASS
+            };
+            std::process::exit(code);
+        }
+    };
+
+    // The default panic hook has already printed the message by the time this returns.
+    let code = match std::panic::catch_unwind(|| run(cli)) {
+        Ok(Ok(())) => error::exit::PASS,
+        Ok(Err(e)) => {
+            eprintln!("Error: {}", e);
+            e.code.exit_code()
+        }
+        Err(_) => ErrorCode::Internal.exit_code(),
+    };
+    std::process::exit(code);
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cli = Cli::parse();
+/// Runs `future` to completion on a current-thread runtime inside a `LocalSet`.
+#[cfg(feature = "net")]
+fn block_on(
+    future: impl std::future::Future<Output = Result<(), TestError>>,
+) -> Result<(), TestError> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| TestError::new(ErrorCode::Internal, format!("tokio runtime: {}", e)))?;
+    let local = tokio::task::LocalSet::new();
+    let result = local.block_on(&rt, future);
+    drop(local);
+    // A stdin read parked on a blocking thread (stdio modes) never finishes on
+    // its own, so don't wait for it.
+    rt.shutdown_background();
+    result
+}
 
+fn run(cli: Cli) -> Result<(), TestError> {
     match cli.mode {
-        Mode::Server { host, port, schema } => {
-            let rt = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()?;
-            let local = tokio::task::LocalSet::new();
-            local.block_on(&rt, server::run(&host, port, &schema))?;
+        #[cfg(feature = "net")]
+        Mode::Server {
+            host,
+            port,
+            schema,
+            transport,
+            name,
+            path,
+            protocol_version,
+            first_segment_words,
+            faults,
+            no_bootstrap,
+            flow_window,
+            fixed_time,
+            shared_state,
+            limits,
+            socket,
+            tls,
+        } => {
+            let endpoint = transport::Endpoint::new(transport, host, port, name, path)?;
+            let tls = tls::acceptor(&tls)?;
+            let options = server::ServeOptions {
+                first_segment_words,
+                limits,
+                flow_window,
+                faults,
+                no_bootstrap,
+                fixed_time,
+                shared_state,
+            };
+            block_on(server::run(
+                &endpoint,
+                &schema,
+                protocol_version,
+                tls,
+                &socket,
+                options,
+            ))?;
+        }
+        #[cfg(feature = "net")]
+        Mode::ServerStdio {
+            schema,
+            protocol_version,
+            first_segment_words,
+            faults,
+            no_bootstrap,
+            flow_window,
+            fixed_time,
+            limits,
+        } => {
+            let options = server::ServeOptions {
+                first_segment_words,
+                limits,
+                flow_window,
+                faults,
+                no_bootstrap,
+                fixed_time,
+                shared_state: false,
+            };
+            block_on(server::serve_stdio(&schema, protocol_version, options))?;
+        }
+        #[cfg(feature = "net")]
+        Mode::Mock {
+            host,
+            port,
+            fixtures,
+            transport,
+            name,
+            path,
+            protocol_version,
+        } => {
+            let endpoint = transport::Endpoint::new(transport, host, port, name, path)?;
+            block_on(mock::run(&endpoint, &fixtures, protocol_version))?;
+        }
+        #[cfg(feature = "net")]
+        Mode::Client {
+            host,
+            port,
+            schema,
+            transport,
+            name,
+            path,
+            artifacts_dir,
+            repeat,
+            protocol_version,
+            dry_run,
+            list_tests,
+            faults,
+            expect_no_bootstrap,
+            rpc_keepalive_secs,
+            flow_window,
+            fixed_time,
+            big,
+            filter,
+            fail_fast,
+            test_timeout_secs,
+            retries,
+            report,
+            limits,
+            dial,
+            socket,
+            tls,
+        } => {
+            if list_tests {
+                let opts = client::RunOptions {
+                    protocol_version,
+                    keepalive: rpc_keepalive_secs.map(std::time::Duration::from_secs),
+                    filter,
+                    ..Default::default()
+                };
+                return client::list_tests(&opts, std::io::stdout().lock());
+            }
+            let endpoint = transport::Endpoint::new(transport, host, port, name, path)?;
+            let opts = client::RunOptions {
+                artifacts_dir,
+                repeat,
+                protocol_version,
+                faults,
+                expect_no_bootstrap,
+                keepalive: rpc_keepalive_secs.map(std::time::Duration::from_secs),
+                fixed_time,
+                big,
+                report,
+                filter,
+                fail_fast,
+                test_timeout: test_timeout_secs.map(std::time::Duration::from_secs),
+                retries,
+                connect: client::ConnectOptions {
+                    dial,
+                    socket,
+                    tls: tls::connector(&tls, &endpoint)?,
+                    limits,
+                    flow_window,
+                },
+            };
+            if dry_run {
+                client::dry_run(&endpoint, &schema, &opts)?;
+            } else {
+                block_on(client::run(&endpoint, &schema, &opts))?;
+            }
+        }
+        #[cfg(feature = "net")]
+        Mode::ClientStdio {
+            schema,
+            artifacts_dir,
+            repeat,
+            protocol_version,
+            flow_window,
+            filter,
+            fail_fast,
+            test_timeout_secs,
+            retries,
+            report,
+            limits,
+        } => {
+            let opts = client::RunOptions {
+                artifacts_dir,
+                repeat,
+                protocol_version,
+                faults: Vec::new(),
+                expect_no_bootstrap: false,
+                keepalive: None,
+                fixed_time: None,
+                big: false,
+                report,
+                filter,
+                fail_fast,
+                test_timeout: test_timeout_secs.map(std::time::Duration::from_secs),
+                retries,
+                connect: client::ConnectOptions {
+                    limits,
+                    flow_window,
+                    ..Default::default()
+                },
+            };
+            block_on(client::run_stdio(&schema, &opts))?;
+        }
+        #[cfg(feature = "net")]
+        Mode::Health {
+            host,
+            port,
+            schema,
+            timeout_ms,
+            protocol_version,
+        } => {
+            block_on(client::health(
+                &host,
+                port,
+                &schema,
+                timeout_ms,
+                protocol_version,
+            ))?;
+        }
+        #[cfg(feature = "net")]
+        Mode::Repl {
+            host,
+            port,
+            schema,
+            transport,
+            name,
+            path,
+            protocol_version,
+        } => {
+            let endpoint = transport::Endpoint::new(transport, host, port, name, path)?;
+            block_on(repl::run(&endpoint, &schema, protocol_version))?;
+        }
+        #[cfg(feature = "net")]
+        Mode::Vat {
+            role,
+            host,
+            port,
+            peer_host,
+            peer_port,
+        } => {
+            let listen = transport::Endpoint::Tcp { host, port };
+            let peer = peer_port.map(|port| transport::Endpoint::Tcp {
+                host: peer_host,
+                port,
+            });
+            block_on(vat::run(role, &listen, peer.as_ref()))?;
+        }
+        #[cfg(feature = "net")]
+        Mode::Orchestrate {
+            zig_server,
+            zig_server_args,
+            zig_client,
+            zig_client_args,
+            schemas,
+            host,
+            log_dir,
+            startup_timeout_ms,
+            case_timeout_ms,
+            dry_run,
+        } => {
+            let schemas = if schemas.is_empty() {
+                e2e_rpc_test::MATRIX_SCHEMAS.map(String::from).to_vec()
+            } else {
+                schemas
+            };
+            let opts = orchestrate::OrchestrateOptions {
+                zig_server: orchestrate::Peer {
+                    program: zig_server,
+                    args: zig_server_args,
+                },
+                zig_client: orchestrate::Peer {
+                    program: zig_client,
+                    args: zig_client_args,
+                },
+                schemas,
+                host,
+                log_dir,
+                startup_timeout: std::time::Duration::from_millis(startup_timeout_ms),
+                case_timeout: std::time::Duration::from_millis(case_timeout_ms),
+                dry_run,
+            };
+            block_on(orchestrate::run(&opts))?;
+        }
+        Mode::Encode {
+            out_dir,
+            packed,
+            layout,
+            big,
+        } => {
+            if big {
+                corpus::encode_big(&out_dir, packed, layout)?
+            } else {
+                corpus::encode(&out_dir, packed, layout)?
+            }
+        }
+        Mode::Verify {
+            in_dir,
+            packed,
+            layout,
+            big,
+        } => {
+            if big {
+                corpus::verify_big(&in_dir, packed, layout)?
+            } else {
+                corpus::verify(&in_dir, packed, layout)?
+            }
+        }
+        Mode::Canonicalize { in_dir, packed } => corpus::canonicalize(&in_dir, packed)?,
+        Mode::Echo { packed } => {
+            echo::run(std::io::stdin().lock(), std::io::stdout().lock(), packed)?;
         }
-        Mode::Client { host, port, schema } => {
-            let rt = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()?;
-            let local = tokio::task::LocalSet::new();
-            local.block_on(&rt, client::run(&host, port, &schema))?;
+        Mode::GenBadFrames { out_dir } => bad_frames::generate(&out_dir)?,
+        Mode::Json {
+            in_dir,
+            out_dir,
+            to,
+            packed,
+        } => corpus::convert_json(&in_dir, &out_dir, to, packed)?,
+        Mode::SchemaDump {
+            capnp,
+            schema_dir,
+            files,
+            out,
+        } => {
+            let request = schema_dump::compile(&capnp, &schema_dir, &files)?;
+            let mut text = serde_json::to_string_pretty(&schema_dump::dump_bytes(&request)?)
+                .map_err(|e| TestError::new(ErrorCode::Internal, e.to_string()))?;
+            text.push('\n');
+            match out {
+                Some(path) => std::fs::write(&path, text).map_err(|e| {
+                    TestError::new(ErrorCode::ConfigError, format!("{}: {}", path.display(), e))
+                })?,
+                None => print!("{}", text),
+            }
         }
     }
 
@@ -0,0 +1,226 @@
+//! Fixture-driven stand-in for the reference server.
+//!
+//! Every call is answered from `<fixtures>/<Interface>.<method>.bin`, a
+//! serialized results struct, or fails with the text of
+//! `<Interface>.<method>.err`. Methods without a fixture are unimplemented.
+//! Fixtures cannot carry capabilities, so capability results are filled with
+//! another mock client answering from the same fixtures.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use capnp::capability::{DispatchCallResult, FromClientHook, FromServer, Params, Promise, Results};
+use capnp::message::ReaderOptions;
+use capnp::private::capability::ClientHook;
+use capnp::serialize::OwnedSegments;
+use capnp::{any_pointer, message};
+
+use crate::chat_capnp::chat_service;
+use crate::error::{ErrorCode, TestError};
+use crate::inventory_capnp::inventory_service;
+use crate::matchmaking_capnp::matchmaking_service;
+use crate::transport::{Endpoint, Listener};
+
+enum Fixture {
+    Response(message::Reader<OwnedSegments>),
+    Error(String),
+}
+
+/// Fixtures keyed by (interface ID, method ordinal).
+type Fixtures = HashMap<(u64, u16), Fixture>;
+
+pub async fn run(
+    endpoint: &Endpoint,
+    fixtures_dir: &Path,
+    protocol_version: Option<u32>,
+) -> Result<(), TestError> {
+    let fixtures = load_fixtures(fixtures_dir)?;
+    let listener = Listener::bind(endpoint).await.map_err(|e| {
+        TestError::new(
+            ErrorCode::ConfigError,
+            format!("cannot listen on {}: {}", endpoint, e),
+        )
+    })?;
+    println!("READY");
+    serve_fixtures(listener, fixtures, protocol_version).await
+}
+
+/// Serves the fixtures in `fixtures_dir` on an already bound listener.
+pub async fn serve(
+    listener: Listener,
+    fixtures_dir: &Path,
+    protocol_version: Option<u32>,
+) -> Result<(), TestError> {
+    let fixtures = load_fixtures(fixtures_dir)?;
+    serve_fixtures(listener, fixtures, protocol_version).await
+}
+
+async fn serve_fixtures(
+    listener: Listener,
+    fixtures: Fixtures,
+    protocol_version: Option<u32>,
+) -> Result<(), TestError> {
+    let fixtures = Rc::new(fixtures);
+    crate::server::serve_with(listener, protocol_version, None, Default::default(), || {
+        mock_client(fixtures.clone()).client
+    })
+    .await
+}
+
+fn load_fixtures(dir: &Path) -> Result<Fixtures, TestError> {
+    let config_error = |path: &Path, e: &dyn std::fmt::Display| {
+        TestError::new(ErrorCode::ConfigError, format!("{}: {}", path.display(), e))
+    };
+    let entries = std::fs::read_dir(dir).map_err(|e| config_error(dir, &e))?;
+
+    let mut fixtures = Fixtures::new();
+    for entry in entries {
+        let path = entry.map_err(|e| config_error(dir, &e))?.path();
+        let (Some(stem), Some(ext)) = (
+            path.file_stem().and_then(|s| s.to_str()),
+            path.extension().and_then(|s| s.to_str()),
+        ) else {
+            continue;
+        };
+        if ext != "bin" && ext != "err" {
+            continue;
+        }
+        let Some(&(interface_id, method_id, _)) =
+            crate::METHODS.iter().find(|(_, _, name)| *name == stem)
+        else {
+            return Err(config_error(&path, &"no such interface method"));
+        };
+        let fixture = if ext == "bin" {
+            let file = std::fs::File::open(&path).map_err(|e| config_error(&path, &e))?;
+            let message =
+                capnp::serialize::read_message(std::io::BufReader::new(file), ReaderOptions::new())
+                    .map_err(|e| config_error(&path, &e))?;
+            Fixture::Response(message)
+        } else {
+            let text = std::fs::read_to_string(&path).map_err(|e| config_error(&path, &e))?;
+            Fixture::Error(text.trim().to_string())
+        };
+        if fixtures
+            .insert((interface_id, method_id), fixture)
+            .is_some()
+        {
+            return Err(config_error(&path, &"both .bin and .err fixtures present"));
+        }
+    }
+    eprintln!(
+        "mock: loaded {} fixtures from {}",
+        fixtures.len(),
+        dir.display()
+    );
+    Ok(fixtures)
+}
+
+struct MockServer {
+    fixtures: Rc<Fixtures>,
+}
+
+/// Untyped client for a [`MockServer`]; cast to whichever typed client a result needs.
+struct MockClient {
+    client: capnp::capability::Client,
+}
+
+impl FromClientHook for MockClient {
+    fn new(hook: Box<dyn ClientHook>) -> Self {
+        Self {
+            client: capnp::capability::Client::new(hook),
+        }
+    }
+
+    fn into_client_hook(self) -> Box<dyn ClientHook> {
+        self.client.hook
+    }
+
+    fn as_client_hook(&self) -> &dyn ClientHook {
+        &*self.client.hook
+    }
+}
+
+struct MockDispatch(MockServer);
+
+impl std::ops::Deref for MockDispatch {
+    type Target = MockServer;
+
+    fn deref(&self) -> &MockServer {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for MockDispatch {
+    fn deref_mut(&mut self) -> &mut MockServer {
+        &mut self.0
+    }
+}
+
+impl FromServer<MockServer> for MockClient {
+    type Dispatch = MockDispatch;
+
+    fn from_server(server: MockServer) -> MockDispatch {
+        MockDispatch(server)
+    }
+}
+
+fn mock_client(fixtures: Rc<Fixtures>) -> MockClient {
+    capnp_rpc::new_client(MockServer { fixtures })
+}
+
+impl capnp::capability::Server for MockDispatch {
+    fn dispatch_call(
+        &mut self,
+        interface_id: u64,
+        method_id: u16,
+        _params: Params<any_pointer::Owned>,
+        mut results: Results<any_pointer::Owned>,
+    ) -> DispatchCallResult {
+        let name = crate::METHODS
+            .iter()
+            .find(|(id, ordinal, _)| *id == interface_id && *ordinal == method_id)
+            .map_or("unknown method", |(_, _, name)| name);
+        let promise = match self.fixtures.get(&(interface_id, method_id)) {
+            None => Promise::err(capnp::Error::unimplemented(format!(
+                "no fixture for {}",
+                name
+            ))),
+            Some(Fixture::Error(text)) => Promise::err(capnp::Error::failed(text.clone())),
+            Some(Fixture::Response(message)) => {
+                match respond(name, message, results.get(), &self.fixtures) {
+                    Ok(()) => Promise::ok(()),
+                    Err(e) => Promise::err(e),
+                }
+            }
+        };
+        DispatchCallResult::new(promise, false)
+    }
+}
+
+/// Copies the fixture into the results and points every capability field of
+/// `name`'s results at a fresh mock client.
+fn respond(
+    name: &str,
+    message: &message::Reader<OwnedSegments>,
+    mut results: any_pointer::Builder,
+    fixtures: &Rc<Fixtures>,
+) -> capnp::Result<()> {
+    results.set_as(message.get_root::<any_pointer::Reader>()?)?;
+    match name {
+        "ChatService.createRoom" => results
+            .get_as::<chat_service::create_room_results::Builder>()?
+            .set_room(mock_client(fixtures.clone()).cast_to()),
+        "ChatService.joinRoom" => results
+            .get_as::<chat_service::join_room_results::Builder>()?
+            .set_room(mock_client(fixtures.clone()).cast_to()),
+        "InventoryService.startTrade" => results
+            .get_as::<inventory_service::start_trade_results::Builder>()?
+            .set_session(mock_client(fixtures.clone()).cast_to()),
+        "MatchmakingService.findMatch" => results
+            .get_as::<matchmaking_service::find_match_results::Builder>()?
+            .set_controller(mock_client(fixtures.clone()).cast_to()),
+        _ => {}
+    }
+    Ok(())
+}
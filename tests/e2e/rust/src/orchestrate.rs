@@ -0,0 +1,354 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+
+use crate::error::{ErrorCode, TestError};
+use crate::report::TapReporter;
+
+/// An executable plus the arguments placed before `--host/--port/--schema`.
+#[derive(Clone, Debug)]
+pub struct Peer {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+}
+
+pub struct OrchestrateOptions {
+    pub zig_server: Peer,
+    pub zig_client: Peer,
+    pub schemas: Vec<String>,
+    pub host: String,
+    pub log_dir: PathBuf,
+    pub startup_timeout: Duration,
+    pub case_timeout: Duration,
+    /// Print the planned pairings and peer commands without spawning anything.
+    pub dry_run: bool,
+}
+
+/// One client/server pairing for one schema.
+struct Pairing<'a> {
+    desc: String,
+    /// Log file stem, `<client label>-<schema>`.
+    stem: String,
+    client: &'a Peer,
+    server: &'a Peer,
+    schema: &'a str,
+}
+
+/// Runs Rust client ↔ Zig server and Zig client ↔ Rust server for every schema,
+/// reporting one TAP entry per pairing.
+pub async fn run(opts: &OrchestrateOptions) -> Result<(), TestError> {
+    for schema in &opts.schemas {
+        crate::check_schema(schema)?;
+    }
+    let exe =
+        std::env::current_exe().map_err(|e| TestError::new(ErrorCode::Internal, e.to_string()))?;
+    let rust_server = Peer {
+        program: exe.clone(),
+        // Each pairing is one client run, whose two-connection checks need
+        // rooms and trades shared.
+        args: vec!["server".to_string(), "--shared-state".to_string()],
+    };
+    let rust_client = Peer {
+        program: exe,
+        args: vec!["client".to_string()],
+    };
+    let plan = plan(opts, &rust_client, &rust_server);
+    if opts.dry_run {
+        return dry_run(opts, &plan);
+    }
+    std::fs::create_dir_all(&opts.log_dir).map_err(|e| {
+        TestError::new(
+            ErrorCode::ConfigError,
+            format!("create {}: {}", opts.log_dir.display(), e),
+        )
+    })?;
+
+    let mut tap = TapReporter::new(plan.len() as u32);
+    for pairing in &plan {
+        let result = run_case(
+            opts,
+            pairing.client,
+            pairing.server,
+            pairing.schema,
+            &pairing.stem,
+        )
+        .await;
+        tap.pass_or_fail(&pairing.desc, result);
+    }
+    tap.done()
+}
+
+/// Rust client -> Zig server, then Zig client -> Rust server, for every schema.
+fn plan<'a>(
+    opts: &'a OrchestrateOptions,
+    rust_client: &'a Peer,
+    rust_server: &'a Peer,
+) -> Vec<Pairing<'a>> {
+    let mut plan = Vec::new();
+    for schema in &opts.schemas {
+        let pairings = [
+            ("rust-client", rust_client, "zig-server", &opts.zig_server),
+            ("zig-client", &opts.zig_client, "rust-server", rust_server),
+        ];
+        for (client_label, client, server_label, server) in pairings {
+            plan.push(Pairing {
+                desc: format!("{} -> {}: {}", client_label, server_label, schema),
+                stem: format!("{}-{}", client_label, schema),
+                client,
+                server,
+                schema,
+            });
+        }
+    }
+    plan
+}
+
+fn dry_run(opts: &OrchestrateOptions, plan: &[Pairing]) -> Result<(), TestError> {
+    let mut tap = TapReporter::new(plan.len() as u32);
+    tap.comment(&format!("host: {}", opts.host));
+    tap.comment(&format!("log dir: {}", opts.log_dir.display()));
+    tap.comment(&format!(
+        "timeouts: startup {} ms, case {} ms",
+        opts.startup_timeout.as_millis(),
+        opts.case_timeout.as_millis()
+    ));
+    for pairing in plan {
+        tap.comment(&format!(
+            "{}: server `{}`, client `{}`",
+            pairing.desc,
+            command_line(pairing.server, &opts.host, pairing.schema),
+            command_line(pairing.client, &opts.host, pairing.schema)
+        ));
+    }
+    for pairing in plan {
+        tap.skip(&pairing.desc, "dry run");
+    }
+    tap.done()
+}
+
+/// The command `command` would build, with the port left symbolic.
+fn command_line(peer: &Peer, host: &str, schema: &str) -> String {
+    let mut parts = vec![peer.program.display().to_string()];
+    parts.extend(peer.args.iter().cloned());
+    parts.extend([
+        "--host".to_string(),
+        host.to_string(),
+        "--port".to_string(),
+        "<port>".to_string(),
+        "--schema".to_string(),
+        schema.to_string(),
+    ]);
+    parts.join(" ")
+}
+
+fn free_port(host: &str) -> Result<u16, TestError> {
+    std::net::TcpListener::bind((host, 0))
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .map_err(|e| TestError::new(ErrorCode::ConnectFailed, format!("reserve port: {}", e)))
+}
+
+fn spawn_failed(peer: &Peer, e: std::io::Error) -> TestError {
+    TestError::new(
+        ErrorCode::ConfigError,
+        format!("spawn {}: {}", peer.program.display(), e),
+    )
+}
+
+fn command(peer: &Peer, host: &str, port: u16, schema: &str) -> Command {
+    let mut cmd = Command::new(&peer.program);
+    cmd.args(&peer.args)
+        .args([
+            "--host",
+            host,
+            "--port",
+            &port.to_string(),
+            "--schema",
+            schema,
+        ])
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    cmd
+}
+
+async fn run_case(
+    opts: &OrchestrateOptions,
+    client: &Peer,
+    server: &Peer,
+    schema: &str,
+    stem: &str,
+) -> Result<(), TestError> {
+    let port = free_port(&opts.host)?;
+    let server_log = opts.log_dir.join(format!("{}.server.log", stem));
+    let client_log = opts.log_dir.join(format!("{}.client.log", stem));
+
+    let mut server_child = start_server(server, &opts.host, port, schema, &server_log)?;
+    let result = async {
+        wait_ready(&mut server_child, &opts.host, port, opts.startup_timeout).await?;
+        run_client(
+            client,
+            &opts.host,
+            port,
+            schema,
+            &client_log,
+            opts.case_timeout,
+        )
+        .await
+    }
+    .await;
+
+    let _ = server_child.child.kill().await;
+    result
+}
+
+struct RunningServer {
+    child: Child,
+    /// Set once the server prints READY on stdout.
+    ready: Arc<AtomicBool>,
+}
+
+/// Starts the server with stdout copied into its log so READY can be detected.
+fn start_server(
+    peer: &Peer,
+    host: &str,
+    port: u16,
+    schema: &str,
+    log: &Path,
+) -> Result<RunningServer, TestError> {
+    let log_failed = |e: std::io::Error| {
+        TestError::new(
+            ErrorCode::ConnectFailed,
+            format!("{}: {}", log.display(), e),
+        )
+    };
+    let file = File::create(log).map_err(log_failed)?;
+    let stderr = file.try_clone().map_err(log_failed)?;
+
+    let mut child = command(peer, host, port, schema)
+        .stdout(Stdio::piped())
+        .stderr(stderr)
+        .spawn()
+        .map_err(|e| spawn_failed(peer, e))?;
+
+    let ready = Arc::new(AtomicBool::new(false));
+    if let Some(stdout) = child.stdout.take() {
+        let ready = ready.clone();
+        let mut file = tokio::fs::File::from_std(file);
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line.trim() == "READY" {
+                    ready.store(true, Ordering::Relaxed);
+                }
+                let _ = file.write_all(line.as_bytes()).await;
+                let _ = file.write_all(b"\n").await;
+            }
+        });
+    }
+    Ok(RunningServer { child, ready })
+}
+
+/// Waits until the server prints READY or accepts a connection.
+async fn wait_ready(
+    server: &mut RunningServer,
+    host: &str,
+    port: u16,
+    timeout: Duration,
+) -> Result<(), TestError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if server.ready.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        if let Ok(Some(status)) = server.child.try_wait() {
+            return Err(TestError::new(
+                ErrorCode::ConnectFailed,
+                format!("server exited before becoming ready ({})", status),
+            ));
+        }
+        if TcpStream::connect((host, port)).await.is_ok() {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(TestError::new(
+                ErrorCode::Timeout,
+                format!("server not ready after {} ms", timeout.as_millis()),
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+async fn run_client(
+    peer: &Peer,
+    host: &str,
+    port: u16,
+    schema: &str,
+    log: &Path,
+    timeout: Duration,
+) -> Result<(), TestError> {
+    let log_failed = |e: std::io::Error| {
+        TestError::new(
+            ErrorCode::ConnectFailed,
+            format!("{}: {}", log.display(), e),
+        )
+    };
+    let file = File::create(log).map_err(log_failed)?;
+    let stderr = file.try_clone().map_err(log_failed)?;
+
+    let mut child = command(peer, host, port, schema)
+        .stdout(file)
+        .stderr(stderr)
+        .spawn()
+        .map_err(|e| spawn_failed(peer, e))?;
+
+    let status = match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(status) => status.map_err(|e| spawn_failed(peer, e))?,
+        Err(_) => {
+            let _ = child.kill().await;
+            return Err(TestError::new(
+                ErrorCode::Timeout,
+                format!(
+                    "client timed out after {} ms; log: {}",
+                    timeout.as_millis(),
+                    log.display()
+                ),
+            ));
+        }
+    };
+    if status.success() {
+        return Ok(());
+    }
+
+    let (passed, failed) = tap_counts(log);
+    let code = match status.code() {
+        Some(3) => ErrorCode::ConnectFailed,
+        Some(6) => ErrorCode::Timeout,
+        _ => ErrorCode::AssertionFailed,
+    };
+    Err(TestError::new(
+        code,
+        format!(
+            "client exited with {} (ok={} not ok={}); log: {}",
+            status,
+            passed,
+            failed,
+            log.display()
+        ),
+    ))
+}
+
+/// Counts TAP result lines in a captured client log.
+fn tap_counts(log: &Path) -> (usize, usize) {
+    let text = std::fs::read_to_string(log).unwrap_or_default();
+    let passed = text.lines().filter(|l| l.starts_with("ok ")).count();
+    let failed = text.lines().filter(|l| l.starts_with("not ok ")).count();
+    (passed, failed)
+}
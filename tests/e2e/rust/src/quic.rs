@@ -0,0 +1,198 @@
+//! Experimental QUIC transport (`--transport quic`); needs the `quic` feature.
+//!
+//! Each QUIC connection carries the two-party protocol on one bidirectional
+//! stream, opened by the client. QUIC always encrypts, so the certificates come
+//! from `--tls/--cert/--key/--ca` and no second TLS layer is added.
+
+pub use imp::{bind, connect, QuicListener};
+
+#[cfg(feature = "quic")]
+mod imp {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+    use tokio::sync::mpsc;
+
+    use crate::tls::{Acceptor, Connector};
+    use crate::transport::{BoxedIo, Listener};
+
+    /// How long a dropped stream keeps its connection open for the peer to
+    /// acknowledge the last writes.
+    const LINGER: Duration = Duration::from_secs(5);
+
+    /// One bidirectional stream; the streams keep their connection open.
+    struct QuicStream {
+        recv: quinn::RecvStream,
+        send: quinn::SendStream,
+        endpoint: quinn::Endpoint,
+    }
+
+    impl Drop for QuicStream {
+        fn drop(&mut self) {
+            // Closing the connection discards unsent data, so hold it open
+            // until the peer has everything, as a TCP close would.
+            let _ = self.send.finish();
+            let stopped = self.send.stopped();
+            let endpoint = self.endpoint.clone();
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                runtime.spawn(async move {
+                    let _ = tokio::time::timeout(LINGER, stopped).await;
+                    drop(endpoint);
+                });
+            }
+        }
+    }
+
+    impl AsyncRead for QuicStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            AsyncRead::poll_read(Pin::new(&mut self.recv), cx, buf)
+        }
+    }
+
+    impl AsyncWrite for QuicStream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            AsyncWrite::poll_write(Pin::new(&mut self.send), cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            AsyncWrite::poll_flush(Pin::new(&mut self.send), cx)
+        }
+
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            AsyncWrite::poll_shutdown(Pin::new(&mut self.send), cx)
+        }
+    }
+
+    pub struct QuicListener {
+        endpoint: quinn::Endpoint,
+        streams: mpsc::UnboundedReceiver<QuicStream>,
+    }
+
+    impl QuicListener {
+        pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+            self.endpoint.local_addr()
+        }
+
+        pub async fn accept(&mut self) -> std::io::Result<BoxedIo> {
+            match self.streams.recv().await {
+                Some(stream) => Ok(Box::new(stream)),
+                None => Err(std::io::Error::other("QUIC endpoint closed")),
+            }
+        }
+    }
+
+    impl Drop for QuicListener {
+        fn drop(&mut self) {
+            self.endpoint.close(0u32.into(), b"");
+        }
+    }
+
+    pub async fn bind(addr: SocketAddr, acceptor: &Acceptor) -> std::io::Result<Listener> {
+        let crypto =
+            QuicServerConfig::try_from(acceptor.server_config()).map_err(std::io::Error::other)?;
+        let config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        let endpoint = quinn::Endpoint::server(config, addr)?;
+        let (tx, streams) = mpsc::unbounded_channel();
+        tokio::spawn(accept_loop(endpoint.clone(), tx));
+        Ok(Listener::Quic(QuicListener { endpoint, streams }))
+    }
+
+    /// Finishes each handshake on its own task so a slow peer can't hold up
+    /// the others.
+    async fn accept_loop(endpoint: quinn::Endpoint, tx: mpsc::UnboundedSender<QuicStream>) {
+        while let Some(incoming) = endpoint.accept().await {
+            let endpoint = endpoint.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let stream = async {
+                    let connection = incoming.await?;
+                    let (send, recv) = connection.accept_bi().await?;
+                    Ok::<_, quinn::ConnectionError>(QuicStream {
+                        recv,
+                        send,
+                        endpoint,
+                    })
+                };
+                match stream.await {
+                    Ok(stream) => {
+                        let _ = tx.send(stream);
+                    }
+                    Err(e) => eprintln!("QUIC error: {}", e),
+                }
+            });
+        }
+    }
+
+    pub async fn connect(addr: SocketAddr, connector: &Connector) -> std::io::Result<BoxedIo> {
+        let crypto =
+            QuicClientConfig::try_from(connector.client_config()).map_err(std::io::Error::other)?;
+        let local: SocketAddr = if addr.is_ipv6() {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        };
+        let mut endpoint = quinn::Endpoint::client(local)?;
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+        let connection = endpoint
+            .connect(addr, &connector.server_name())
+            .map_err(std::io::Error::other)?
+            .await?;
+        let (send, recv) = connection.open_bi().await?;
+        Ok(Box::new(QuicStream {
+            recv,
+            send,
+            endpoint,
+        }))
+    }
+}
+
+#[cfg(not(feature = "quic"))]
+mod imp {
+    use std::net::SocketAddr;
+
+    use crate::tls::{Acceptor, Connector};
+    use crate::transport::{BoxedIo, Listener};
+
+    fn unsupported() -> std::io::Error {
+        std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "quic transport requires a build with the `quic` feature",
+        )
+    }
+
+    pub enum QuicListener {}
+
+    impl QuicListener {
+        pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+            match *self {}
+        }
+
+        pub async fn accept(&mut self) -> std::io::Result<BoxedIo> {
+            match *self {}
+        }
+    }
+
+    pub async fn bind(_addr: SocketAddr, _acceptor: &Acceptor) -> std::io::Result<Listener> {
+        Err(unsupported())
+    }
+
+    pub async fn connect(_addr: SocketAddr, _connector: &Connector) -> std::io::Result<BoxedIo> {
+        Err(unsupported())
+    }
+}
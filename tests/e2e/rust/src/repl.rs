@@ -0,0 +1,559 @@
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::str::FromStr;
+
+use capnp::introspect::{Introspect, TypeVariant};
+use capnp_rpc::rpc_twoparty_capnp;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::chat_capnp::{chat_room, chat_service};
+use crate::client::{connect, normalize_schema_name, ConnectOptions};
+use crate::error::{ErrorCode, TestError};
+use crate::game_types_capnp::{player_info, Faction, Rarity};
+use crate::game_world_capnp::{game_world, EntityKind};
+use crate::inventory_capnp::{inventory_service, trade_session};
+use crate::matchmaking_capnp::{match_controller, matchmaking_service, GameMode};
+use crate::transport::Endpoint;
+
+const HELP: &str = "\
+game_world:  spawn name=X [kind=Monster faction=Horde x= y= z= max_health=]
+             world.get ID | world.move ID X Y [Z] | world.damage ID AMOUNT
+             world.despawn ID | world.query [X Y RADIUS] [kind=K | faction=F]
+chat:        chat.create NAME [topic=T] | chat.join NAME [player=ID]
+             chat.send TEXT | chat.emote TEXT | chat.history [LIMIT]
+             chat.info | chat.leave | chat.rooms | chat.whisper TO TEXT [from=ID]
+inventory:   inv.get PLAYER | inv.add PLAYER name=X [rarity= level= qty= item=]
+             inv.remove PLAYER SLOT [qty=N] | inv.filter PLAYER RARITY
+             inv.trade FROM TO | trade.offer SLOT... | trade.accept
+             trade.confirm | trade.cancel | trade.state
+matchmaking: mm.enqueue PLAYER [mode=Duel] | mm.dequeue TICKET
+             mm.find PLAYER [mode=Duel] | mm.stats [MODE]
+             match.info | match.ready PLAYER
+help | quit";
+
+fn usage(text: impl Into<String>) -> TestError {
+    TestError::new(ErrorCode::ConfigError, text)
+}
+
+// ---------------------------------------------------------------------------
+// Command parsing
+// ---------------------------------------------------------------------------
+
+/// One input line: `name positional... key=value...`. Double quotes group words.
+struct Command {
+    name: String,
+    args: Vec<String>,
+    opts: HashMap<String, String>,
+}
+
+impl Command {
+    fn parse(line: &str) -> Option<Self> {
+        let mut words = Vec::new();
+        let mut current = String::new();
+        let mut quoted = false;
+        let mut pending = false;
+        for c in line.chars() {
+            match c {
+                '"' => {
+                    quoted = !quoted;
+                    pending = true;
+                }
+                c if c.is_whitespace() && !quoted => {
+                    if pending {
+                        words.push(std::mem::take(&mut current));
+                        pending = false;
+                    }
+                }
+                c => {
+                    current.push(c);
+                    pending = true;
+                }
+            }
+        }
+        if pending {
+            words.push(current);
+        }
+
+        let mut words = words.into_iter();
+        let name = words.next()?;
+        let mut args = Vec::new();
+        let mut opts = HashMap::new();
+        for word in words {
+            match word.split_once('=') {
+                Some((key, value)) => {
+                    opts.insert(key.to_string(), value.to_string());
+                }
+                None => args.push(word),
+            }
+        }
+        Some(Self { name, args, opts })
+    }
+
+    fn arg<T: FromStr>(&self, idx: usize, what: &str) -> Result<T, TestError> {
+        let text = self
+            .args
+            .get(idx)
+            .ok_or_else(|| usage(format!("{}: missing {}", self.name, what)))?;
+        text.parse()
+            .map_err(|_| usage(format!("{}: bad {} {:?}", self.name, what, text)))
+    }
+
+    fn arg_or<T: FromStr>(&self, idx: usize, what: &str, default: T) -> Result<T, TestError> {
+        match self.args.get(idx) {
+            Some(_) => self.arg(idx, what),
+            None => Ok(default),
+        }
+    }
+
+    fn opt<T: FromStr>(&self, key: &str, default: T) -> Result<T, TestError> {
+        match self.opts.get(key) {
+            Some(text) => text
+                .parse()
+                .map_err(|_| usage(format!("{}: bad {}={:?}", self.name, key, text))),
+            None => Ok(default),
+        }
+    }
+
+    fn opt_enum<E>(&self, key: &str, default: E) -> Result<E, TestError>
+    where
+        E: Introspect + TryFrom<u16>,
+    {
+        match self.opts.get(key) {
+            Some(text) => parse_enum(text),
+            None => Ok(default),
+        }
+    }
+
+    /// Positional arguments from `idx` on, joined back into one string.
+    fn rest(&self, idx: usize, what: &str) -> Result<String, TestError> {
+        if self.args.len() <= idx {
+            return Err(usage(format!("{}: missing {}", self.name, what)));
+        }
+        Ok(self.args[idx..].join(" "))
+    }
+}
+
+/// Resolves an enumerant by its schema name, ignoring case (`monster`, `Monster`).
+fn parse_enum<E>(text: &str) -> Result<E, TestError>
+where
+    E: Introspect + TryFrom<u16>,
+{
+    let TypeVariant::Enum(raw) = E::introspect().which() else {
+        return Err(TestError::new(ErrorCode::Internal, "not an enum type"));
+    };
+    let schema = capnp::schema::EnumSchema::new(raw);
+    let mut names = Vec::new();
+    for enumerant in schema.get_enumerants()? {
+        let name = enumerant.get_proto().get_name()?.to_str()?;
+        if name.eq_ignore_ascii_case(text) {
+            return E::try_from(enumerant.get_ordinal())
+                .map_err(|_| TestError::new(ErrorCode::DecodeError, "enumerant out of range"));
+        }
+        names.push(name);
+    }
+    Err(usage(format!(
+        "unknown value {:?}; expected one of {}",
+        text,
+        names.join(", ")
+    )))
+}
+
+fn set_player(mut builder: player_info::Builder<'_>, id: u64) {
+    builder.reborrow().init_id().set_id(id);
+    builder.reborrow().set_name(format!("Player_{}", id));
+    builder.reborrow().set_faction(Faction::Alliance);
+    builder.set_level(1);
+}
+
+// ---------------------------------------------------------------------------
+// Sessions
+// ---------------------------------------------------------------------------
+
+/// The bootstrap capability plus any capabilities handed out by earlier commands.
+enum Session {
+    GameWorld(game_world::Client),
+    Chat {
+        service: chat_service::Client,
+        room: Option<chat_room::Client>,
+    },
+    Inventory {
+        service: inventory_service::Client,
+        trade: Option<trade_session::Client>,
+    },
+    Matchmaking {
+        service: matchmaking_service::Client,
+        controller: Option<match_controller::Client>,
+    },
+}
+
+fn no_cap(what: &str, how: &str) -> TestError {
+    usage(format!("no {}; use {} first", what, how))
+}
+
+fn wrong_schema(cmd: &Command) -> TestError {
+    usage(format!(
+        "{}: not available for this schema; try `help`",
+        cmd.name
+    ))
+}
+
+impl Session {
+    async fn execute(&mut self, cmd: &Command) -> Result<(), TestError> {
+        match self {
+            Session::GameWorld(gw) => game_world_command(gw, cmd).await,
+            Session::Chat { service, room } => chat_command(service, room, cmd).await,
+            Session::Inventory { service, trade } => inventory_command(service, trade, cmd).await,
+            Session::Matchmaking {
+                service,
+                controller,
+            } => matchmaking_command(service, controller, cmd).await,
+        }
+    }
+}
+
+async fn game_world_command(gw: &game_world::Client, cmd: &Command) -> Result<(), TestError> {
+    match cmd.name.as_str() {
+        "spawn" | "world.spawn" => {
+            let name = cmd
+                .opts
+                .get("name")
+                .ok_or_else(|| usage("spawn: missing name="))?;
+            let mut req = gw.spawn_entity_request();
+            let mut r = req.get().init_request();
+            r.set_name(name.as_str());
+            r.set_kind(cmd.opt_enum("kind", EntityKind::Npc)?);
+            r.set_faction(cmd.opt_enum("faction", Faction::Neutral)?);
+            r.set_max_health(cmd.opt("max_health", 100)?);
+            let mut pos = r.init_position();
+            pos.set_x(cmd.opt("x", 0.0)?);
+            pos.set_y(cmd.opt("y", 0.0)?);
+            pos.set_z(cmd.opt("z", 0.0)?);
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "world.get" => {
+            let mut req = gw.get_entity_request();
+            req.get().init_id().set_id(cmd.arg(0, "entity id")?);
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "world.move" => {
+            let mut req = gw.move_entity_request();
+            req.get().init_id().set_id(cmd.arg(0, "entity id")?);
+            let mut pos = req.get().init_new_position();
+            pos.set_x(cmd.arg(1, "x")?);
+            pos.set_y(cmd.arg(2, "y")?);
+            pos.set_z(cmd.arg_or(3, "z", 0.0)?);
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "world.damage" => {
+            let mut req = gw.damage_entity_request();
+            req.get().init_id().set_id(cmd.arg(0, "entity id")?);
+            req.get().set_amount(cmd.arg(1, "amount")?);
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "world.despawn" => {
+            let mut req = gw.despawn_entity_request();
+            req.get().init_id().set_id(cmd.arg(0, "entity id")?);
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "world.query" => {
+            let mut req = gw.query_area_request();
+            let mut query = req.get().init_query();
+            query.set_radius(cmd.arg_or(2, "radius", 1000.0)?);
+            let mut center = query.reborrow().init_center();
+            center.set_x(cmd.arg_or(0, "x", 0.0)?);
+            center.set_y(cmd.arg_or(1, "y", 0.0)?);
+            let mut filter = query.init_filter();
+            if cmd.opts.contains_key("kind") {
+                filter.set_by_kind(cmd.opt_enum("kind", EntityKind::Npc)?);
+            } else if cmd.opts.contains_key("faction") {
+                filter.set_by_faction(cmd.opt_enum("faction", Faction::Neutral)?);
+            } else {
+                filter.set_all(());
+            }
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        _ => return Err(wrong_schema(cmd)),
+    }
+    Ok(())
+}
+
+async fn chat_command(
+    service: &chat_service::Client,
+    room: &mut Option<chat_room::Client>,
+    cmd: &Command,
+) -> Result<(), TestError> {
+    let current = |room: &Option<chat_room::Client>| {
+        room.clone()
+            .ok_or_else(|| no_cap("joined room", "chat.join"))
+    };
+    match cmd.name.as_str() {
+        "chat.create" => {
+            let mut req = service.create_room_request();
+            req.get()
+                .set_name(cmd.arg::<String>(0, "room name")?.as_str());
+            req.get()
+                .set_topic(cmd.opts.get("topic").map_or("", String::as_str));
+            let resp = req.send().promise.await?;
+            println!("{:?}", resp.get()?);
+        }
+        "chat.join" => {
+            let mut req = service.join_room_request();
+            req.get()
+                .set_name(cmd.arg::<String>(0, "room name")?.as_str());
+            set_player(req.get().init_player(), cmd.opt("player", 1)?);
+            let resp = req.send().promise.await?;
+            println!("{:?}", resp.get()?);
+            *room = Some(resp.get()?.get_room()?);
+        }
+        "chat.send" => {
+            let mut req = current(room)?.send_message_request();
+            req.get().set_content(cmd.rest(0, "message")?.as_str());
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "chat.emote" => {
+            let mut req = current(room)?.send_emote_request();
+            req.get().set_content(cmd.rest(0, "emote")?.as_str());
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "chat.history" => {
+            let mut req = current(room)?.get_history_request();
+            req.get().set_limit(cmd.arg_or(0, "limit", 20)?);
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "chat.info" => {
+            let req = current(room)?.get_info_request();
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "chat.leave" => {
+            let req = current(room)?.leave_request();
+            println!("{:?}", req.send().promise.await?.get()?);
+            *room = None;
+        }
+        "chat.rooms" => {
+            let req = service.list_rooms_request();
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "chat.whisper" => {
+            let mut req = service.whisper_request();
+            set_player(req.get().init_from(), cmd.opt("from", 1)?);
+            req.get().init_to().set_id(cmd.arg(0, "recipient id")?);
+            req.get().set_content(cmd.rest(1, "message")?.as_str());
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        _ => return Err(wrong_schema(cmd)),
+    }
+    Ok(())
+}
+
+async fn inventory_command(
+    service: &inventory_service::Client,
+    trade: &mut Option<trade_session::Client>,
+    cmd: &Command,
+) -> Result<(), TestError> {
+    let current = |trade: &Option<trade_session::Client>| {
+        trade
+            .clone()
+            .ok_or_else(|| no_cap("trade session", "inv.trade"))
+    };
+    match cmd.name.as_str() {
+        "inv.get" => {
+            let mut req = service.get_inventory_request();
+            req.get().init_player().set_id(cmd.arg(0, "player id")?);
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "inv.add" => {
+            let name = cmd
+                .opts
+                .get("name")
+                .ok_or_else(|| usage("inv.add: missing name="))?;
+            let mut req = service.add_item_request();
+            req.get().init_player().set_id(cmd.arg(0, "player id")?);
+            req.get().set_quantity(cmd.opt("qty", 1)?);
+            let mut item = req.get().init_item();
+            item.reborrow().init_id().set_id(cmd.opt("item", 1)?);
+            item.set_name(name.as_str());
+            item.set_rarity(cmd.opt_enum("rarity", Rarity::Common)?);
+            item.set_level(cmd.opt("level", 1)?);
+            item.set_stack_size(cmd.opt("stack", 1)?);
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "inv.remove" => {
+            let mut req = service.remove_item_request();
+            req.get().init_player().set_id(cmd.arg(0, "player id")?);
+            req.get().set_slot_index(cmd.arg(1, "slot")?);
+            req.get().set_quantity(cmd.opt("qty", 1)?);
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "inv.filter" => {
+            let mut req = service.filter_by_rarity_request();
+            req.get().init_player().set_id(cmd.arg(0, "player id")?);
+            let rarity = cmd.args.get(1).map_or("common", String::as_str);
+            req.get().set_min_rarity(parse_enum(rarity)?);
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "inv.trade" => {
+            let mut req = service.start_trade_request();
+            req.get()
+                .init_initiator()
+                .set_id(cmd.arg(0, "initiator id")?);
+            req.get().init_target().set_id(cmd.arg(1, "target id")?);
+            let resp = req.send().promise.await?;
+            println!("{:?}", resp.get()?);
+            *trade = Some(resp.get()?.get_session()?);
+        }
+        "trade.offer" => {
+            let slots = (0..cmd.args.len())
+                .map(|i| cmd.arg::<u16>(i, "slot"))
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut req = current(trade)?.offer_items_request();
+            let mut list = req.get().init_slots(slots.len() as u32);
+            for (i, slot) in slots.iter().enumerate() {
+                list.set(i as u32, *slot);
+            }
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "trade.accept" => {
+            let req = current(trade)?.accept_request();
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "trade.confirm" => {
+            let req = current(trade)?.confirm_request();
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "trade.cancel" => {
+            let req = current(trade)?.cancel_request();
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "trade.state" => {
+            let req = current(trade)?.get_state_request();
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        _ => return Err(wrong_schema(cmd)),
+    }
+    Ok(())
+}
+
+async fn matchmaking_command(
+    service: &matchmaking_service::Client,
+    controller: &mut Option<match_controller::Client>,
+    cmd: &Command,
+) -> Result<(), TestError> {
+    let current = |controller: &Option<match_controller::Client>| {
+        controller.clone().ok_or_else(|| no_cap("match", "mm.find"))
+    };
+    match cmd.name.as_str() {
+        "mm.enqueue" => {
+            let mut req = service.enqueue_request();
+            set_player(req.get().init_player(), cmd.arg(0, "player id")?);
+            req.get().set_mode(cmd.opt_enum("mode", GameMode::Duel)?);
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "mm.dequeue" => {
+            let mut req = service.dequeue_request();
+            req.get().set_ticket_id(cmd.arg(0, "ticket id")?);
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "mm.find" => {
+            let mut req = service.find_match_request();
+            set_player(req.get().init_player(), cmd.arg(0, "player id")?);
+            req.get().set_mode(cmd.opt_enum("mode", GameMode::Duel)?);
+            let resp = req.send().promise.await?;
+            println!("{:?}", resp.get()?);
+            *controller = Some(resp.get()?.get_controller()?);
+        }
+        "mm.stats" => {
+            let mut req = service.get_queue_stats_request();
+            let mode = cmd.args.first().map_or("duel", String::as_str);
+            req.get().set_mode(parse_enum(mode)?);
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "match.info" => {
+            let req = current(controller)?.get_info_request();
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        "match.ready" => {
+            let mut req = current(controller)?.signal_ready_request();
+            req.get().init_player().set_id(cmd.arg(0, "player id")?);
+            println!("{:?}", req.send().promise.await?.get()?);
+        }
+        _ => return Err(wrong_schema(cmd)),
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Entry point
+// ---------------------------------------------------------------------------
+
+/// Connects to a server and executes commands read from stdin until EOF or `quit`.
+pub async fn run(
+    endpoint: &Endpoint,
+    schema: &str,
+    protocol_version: Option<u32>,
+) -> Result<(), TestError> {
+    crate::check_schema(schema)?;
+    let mut rpc_system =
+        connect(endpoint, None, protocol_version, &ConnectOptions::default()).await?;
+    let side = rpc_twoparty_capnp::Side::Server;
+    let mut session = match normalize_schema_name(schema) {
+        "game_world" => Session::GameWorld(rpc_system.bootstrap(side)),
+        "chat" => Session::Chat {
+            service: rpc_system.bootstrap(side),
+            room: None,
+        },
+        "inventory" => Session::Inventory {
+            service: rpc_system.bootstrap(side),
+            trade: None,
+        },
+        "matchmaking" => Session::Matchmaking {
+            service: rpc_system.bootstrap(side),
+            controller: None,
+        },
+        other => {
+            return Err(TestError::new(
+                ErrorCode::ConfigError,
+                format!("the repl has no commands for {}", other),
+            ))
+        }
+    };
+    tokio::task::spawn_local(rpc_system);
+
+    let interactive = std::io::stdin().is_terminal();
+    if interactive {
+        println!(
+            "connected to {} ({}); type `help` for commands",
+            endpoint, schema
+        );
+    }
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        if interactive {
+            print!("> ");
+            let _ = std::io::stdout().flush();
+        }
+        let Some(line) = lines
+            .next_line()
+            .await
+            .map_err(|e| TestError::new(ErrorCode::Internal, format!("stdin: {}", e)))?
+        else {
+            break;
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some(cmd) = Command::parse(line) else {
+            continue;
+        };
+        match cmd.name.as_str() {
+            "quit" | "exit" => break,
+            "help" => println!("{}", HELP),
+            _ => {
+                if let Err(e) = session.execute(&cmd).await {
+                    println!("error: {}", e);
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,475 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "net")]
+use crate::artifacts::Artifacts;
+use crate::error::{ErrorCode, TestError};
+
+// Output errors are ignored: a closed stdout must not abort the remaining tests.
+macro_rules! emit {
+    ($reporter:ident, $($arg:tt)*) => {
+        let _ = writeln!($reporter.out, $($arg)*);
+    };
+}
+
+/// Emits TAP version 14 for one run, tracking the outcome for the exit code.
+pub struct TapReporter<W: Write = io::Stdout> {
+    out: W,
+    test_num: u32,
+    total: u32,
+    failures: u32,
+    first_failure: Option<ErrorCode>,
+    #[cfg(feature = "net")]
+    artifacts: Option<Artifacts>,
+    report: Option<Report>,
+}
+
+impl TapReporter {
+    pub fn new(total: u32) -> Self {
+        Self::with_writer(io::stdout(), total)
+    }
+}
+
+impl<W: Write> TapReporter<W> {
+    pub fn with_writer(out: W, total: u32) -> Self {
+        let mut reporter = Self {
+            out,
+            test_num: 0,
+            total,
+            failures: 0,
+            first_failure: None,
+            #[cfg(feature = "net")]
+            artifacts: None,
+            report: None,
+        };
+        emit!(reporter, "TAP version 14");
+        emit!(reporter, "1..{}", total);
+        reporter
+    }
+
+    #[cfg(feature = "net")]
+    pub(crate) fn set_artifacts(&mut self, artifacts: Artifacts) {
+        self.artifacts = Some(artifacts);
+    }
+
+    /// Keeps `stats`, a snapshot of the peer's DebugStats, for the failure
+    /// record of the next test reported.
+    #[cfg(feature = "net")]
+    pub(crate) fn set_server_stats(&mut self, stats: String) {
+        if let Some(artifacts) = &mut self.artifacts {
+            artifacts.set_server_stats(stats);
+        }
+    }
+
+    /// Also records every result in `report`, written out by `done`.
+    pub fn set_report(&mut self, report: Report) {
+        self.report = Some(report);
+    }
+
+    fn record(&mut self, desc: &str, outcome: Outcome) {
+        if let Some(report) = &mut self.report {
+            report.record(desc, outcome);
+        }
+    }
+
+    pub fn ok(&mut self, desc: &str) {
+        self.test_num += 1;
+        emit!(self, "ok {} - {}", self.test_num, desc);
+        self.record(desc, Outcome::Passed);
+        #[cfg(feature = "net")]
+        if let Some(artifacts) = &mut self.artifacts {
+            artifacts.advance();
+        }
+    }
+
+    /// Reports a test that passed on its `attempt`th run after failing the
+    /// ones before, with a `# RETRIED` comment so the flake stays visible.
+    pub fn ok_retried(&mut self, desc: &str, attempt: u32) {
+        self.test_num += 1;
+        emit!(
+            self,
+            "ok {} - {} # RETRIED passed on attempt {}",
+            self.test_num,
+            desc,
+            attempt
+        );
+        self.record(desc, Outcome::Passed);
+        #[cfg(feature = "net")]
+        if let Some(artifacts) = &mut self.artifacts {
+            artifacts.advance();
+        }
+    }
+
+    pub fn not_ok(&mut self, desc: &str, err: &TestError) {
+        self.test_num += 1;
+        self.failures += 1;
+        self.first_failure.get_or_insert(err.code);
+        emit!(self, "not ok {} - {}", self.test_num, desc);
+        emit!(self, "  ---");
+        emit!(self, "  message: {}", yaml_quote(&err.message));
+        emit!(self, "  code: {}", err.code);
+        if let Some(dump) = &err.dump {
+            emit!(self, "  struct: |");
+            for line in dump.lines() {
+                emit!(self, "    {}", line);
+            }
+        }
+        emit!(self, "  ...");
+        self.record(desc, Outcome::Failed(err.clone()));
+        #[cfg(feature = "net")]
+        if let Some(artifacts) = &mut self.artifacts {
+            let mut fields = vec![
+                ("message", err.message.clone()),
+                ("code", err.code.to_string()),
+            ];
+            if let Some(dump) = &err.dump {
+                fields.push(("struct", dump.clone()));
+            }
+            artifacts.record_failure(self.test_num, desc, &fields);
+        }
+    }
+
+    /// Reports a test that was not run; skips never fail the run.
+    pub fn skip(&mut self, desc: &str, reason: &str) {
+        self.test_num += 1;
+        emit!(self, "ok {} - {} # SKIP {}", self.test_num, desc, reason);
+        self.record(desc, Outcome::Skipped(reason.to_string()));
+        #[cfg(feature = "net")]
+        if let Some(artifacts) = &mut self.artifacts {
+            artifacts.advance();
+        }
+    }
+
+    /// Reports a check for behavior the stack does not have yet; a failing
+    /// TODO never fails the run, and a passing one is reported for removal.
+    pub fn todo(&mut self, desc: &str, reason: &str, result: Result<(), TestError>) {
+        self.test_num += 1;
+        match result {
+            Ok(()) => {
+                emit!(self, "ok {} - {} # TODO {}", self.test_num, desc, reason);
+                self.record(desc, Outcome::Passed);
+            }
+            Err(err) => {
+                emit!(
+                    self,
+                    "not ok {} - {} # TODO {}",
+                    self.test_num,
+                    desc,
+                    reason
+                );
+                emit!(self, "  ---");
+                emit!(self, "  message: {}", yaml_quote(&err.message));
+                emit!(self, "  ...");
+                self.record(
+                    desc,
+                    Outcome::Skipped(format!("TODO {}: {}", reason, err.message)),
+                );
+            }
+        }
+        #[cfg(feature = "net")]
+        if let Some(artifacts) = &mut self.artifacts {
+            artifacts.advance();
+        }
+    }
+
+    /// Writes a `# ` diagnostic line.
+    pub fn comment(&mut self, text: &str) {
+        emit!(self, "# {}", text);
+    }
+
+    pub fn pass_or_fail(&mut self, desc: &str, result: Result<(), TestError>) {
+        match result {
+            Ok(()) => self.ok(desc),
+            Err(e) => self.not_ok(desc, &e),
+        }
+    }
+
+    /// Whether any test has failed so far; failing TODOs do not count.
+    pub fn failed(&self) -> bool {
+        self.failures > 0
+    }
+
+    /// Ends the run early with `Bail out!` and writes the report, if any.
+    /// Tests left unrun go unreported, and the run fails with the code of its
+    /// first failing test.
+    pub fn bail_out(mut self, reason: &str) -> Result<(), TestError> {
+        emit!(self, "Bail out! {}", reason);
+        let _ = self.out.flush();
+        let written = self.report.as_ref().map_or(Ok(()), Report::write);
+        match self.first_failure {
+            None => written,
+            Some(code) => Err(TestError::new(code, format!("bailed out: {}", reason))),
+        }
+    }
+
+    /// Finishes the run and writes the report, if any; a failed run carries
+    /// the code of its first failing test.
+    pub fn done(mut self) -> Result<(), TestError> {
+        let _ = self.out.flush();
+        if self.test_num < self.total {
+            eprintln!(
+                "Warning: only ran {} of {} planned tests",
+                self.test_num, self.total
+            );
+        }
+        let written = self.report.as_ref().map_or(Ok(()), Report::write);
+        match self.first_failure {
+            None => written,
+            Some(code) => Err(TestError::new(
+                code,
+                format!("{} of {} tests failed", self.failures, self.test_num),
+            )),
+        }
+    }
+}
+
+/// Aborts a run before any test is planned, e.g. when the peer was built from
+/// a different schema revision.
+pub fn bail_out<W: Write>(mut out: W, reason: &str) {
+    let _ = writeln!(out, "TAP version 14");
+    let _ = writeln!(out, "Bail out! {}", reason);
+    let _ = out.flush();
+}
+
+/// Formats `--report` writes alongside TAP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    /// JUnit XML: one `<testsuite>` with a `<testcase>` per result.
+    Junit,
+    /// One JSON object per result, written as each test finishes.
+    Jsonl,
+}
+
+/// Where a client run writes its results for CI.
+#[derive(Clone, Debug, Default, clap::Args)]
+pub struct ReportArgs {
+    /// Also write the results in this format to `--report-path`.
+    #[arg(long, value_enum, requires = "report_path")]
+    pub report: Option<ReportFormat>,
+    /// File for `--report`; replaced if it exists. A path like `/dev/fd/3`
+    /// keeps the stream apart from TAP on stdout and stderr.
+    #[arg(long, value_name = "PATH", requires = "report")]
+    pub report_path: Option<PathBuf>,
+}
+
+impl ReportArgs {
+    /// A report for the suite `suite`, if one was asked for. A streamed
+    /// format's file is created here, before any test runs.
+    pub fn open(&self, suite: &str) -> Result<Option<Report>, TestError> {
+        let (Some(format), Some(path)) = (self.report, &self.report_path) else {
+            return Ok(None);
+        };
+        let stream = match format {
+            ReportFormat::Junit => None,
+            ReportFormat::Jsonl => {
+                Some(std::fs::File::create(path).map_err(|e| report_error(path, e))?)
+            }
+        };
+        Ok(Some(Report {
+            format,
+            path: path.clone(),
+            suite: suite.to_string(),
+            results: Vec::new(),
+            since: Instant::now(),
+            stream,
+        }))
+    }
+}
+
+/// Every result of one run in a machine-readable format, written at the end
+/// or, for JSON lines, as each arrives. A result's time runs from the one
+/// before it, or from when the report was opened, so for suite tests it is
+/// the test's own run.
+pub struct Report {
+    format: ReportFormat,
+    path: PathBuf,
+    suite: String,
+    results: Vec<TestResult>,
+    since: Instant,
+    /// Where JSON lines go as results arrive.
+    stream: Option<std::fs::File>,
+}
+
+struct TestResult {
+    name: String,
+    time: Duration,
+    outcome: Outcome,
+}
+
+enum Outcome {
+    Passed,
+    Failed(TestError),
+    Skipped(String),
+}
+
+impl Report {
+    fn record(&mut self, name: &str, outcome: Outcome) {
+        let now = Instant::now();
+        self.results.push(TestResult {
+            name: name.to_string(),
+            time: now - self.since,
+            outcome,
+        });
+        self.since = now;
+        if let Some(stream) = &mut self.stream {
+            let line = json_line(
+                &self.suite,
+                self.results.len(),
+                &self.results[self.results.len() - 1],
+            );
+            // Like TAP, a closed stream must not abort the remaining tests.
+            let _ = writeln!(stream, "{}", line);
+        }
+    }
+
+    fn write(&self) -> Result<(), TestError> {
+        match self.format {
+            ReportFormat::Junit => {
+                std::fs::write(&self.path, self.junit()).map_err(|e| report_error(&self.path, e))
+            }
+            ReportFormat::Jsonl => Ok(()),
+        }
+    }
+
+    fn junit(&self) -> String {
+        use std::fmt::Write as _;
+
+        let failures = self
+            .results
+            .iter()
+            .filter(|r| matches!(r.outcome, Outcome::Failed(_)))
+            .count();
+        let skipped = self
+            .results
+            .iter()
+            .filter(|r| matches!(r.outcome, Outcome::Skipped(_)))
+            .count();
+        let time: Duration = self.results.iter().map(|r| r.time).sum();
+        let totals = format!(
+            "tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{:.3}\"",
+            self.results.len(),
+            failures,
+            skipped,
+            time.as_secs_f64()
+        );
+        let suite = xml_attr(&self.suite);
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(xml, "<testsuites name=\"e2e-rpc-test\" {}>", totals);
+        let _ = writeln!(xml, "  <testsuite name=\"{}\" {}>", suite, totals);
+        for result in &self.results {
+            let _ = write!(
+                xml,
+                "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+                suite,
+                xml_attr(&result.name),
+                result.time.as_secs_f64()
+            );
+            match &result.outcome {
+                Outcome::Passed => xml.push_str("/>\n"),
+                Outcome::Failed(err) => {
+                    let mut body = err.message.clone();
+                    if let Some(dump) = &err.dump {
+                        body.push('\n');
+                        body.push_str(dump);
+                    }
+                    let _ = writeln!(
+                        xml,
+                        ">\n      <failure message=\"{}\" type=\"{}\">{}</failure>",
+                        xml_attr(&err.message),
+                        err.code,
+                        xml_escape(&body)
+                    );
+                    xml.push_str("    </testcase>\n");
+                }
+                Outcome::Skipped(reason) => {
+                    let _ = writeln!(xml, ">\n      <skipped message=\"{}\"/>", xml_attr(reason));
+                    xml.push_str("    </testcase>\n");
+                }
+            }
+        }
+        xml.push_str("  </testsuite>\n</testsuites>\n");
+        xml
+    }
+}
+
+/// `{"suite", "test", "name", "status", "duration_ms", ...}` for the `test`th
+/// result: failures add `code`, `message` and `struct`, skips a `reason`.
+fn json_line(suite: &str, test: usize, result: &TestResult) -> serde_json::Value {
+    let status = match result.outcome {
+        Outcome::Passed => "pass",
+        Outcome::Failed(_) => "fail",
+        Outcome::Skipped(_) => "skip",
+    };
+    let mut line = serde_json::json!({
+        "suite": suite,
+        "test": test,
+        "name": result.name,
+        "status": status,
+        "duration_ms": result.time.as_micros() as f64 / 1000.0,
+    });
+    match &result.outcome {
+        Outcome::Passed => {}
+        Outcome::Failed(err) => {
+            line["code"] = err.code.as_str().into();
+            line["message"] = err.message.as_str().into();
+            if let Some(dump) = &err.dump {
+                line["struct"] = dump.as_str().into();
+            }
+        }
+        Outcome::Skipped(reason) => line["reason"] = reason.as_str().into(),
+    }
+    line
+}
+
+fn report_error(path: &std::path::Path, e: io::Error) -> TestError {
+    TestError::new(
+        ErrorCode::ConfigError,
+        format!("report {}: {}", path.display(), e),
+    )
+}
+
+/// `text` as XML character data. Control characters XML 1.0 cannot hold at
+/// all become U+FFFD.
+fn xml_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if c < ' ' => out.push('\u{fffd}'),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// `text` as a double-quoted YAML scalar, so colons, quotes, `#` and line
+/// breaks in a message cannot change the diagnostic block's structure.
+fn yaml_quote(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' || c == '\u{7f}' => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `text` as an XML attribute value, keeping its line breaks.
+fn xml_attr(text: &str) -> String {
+    xml_escape(text).replace('\n', "&#10;")
+}
@@ -0,0 +1,226 @@
+//! The schema compiler's layout decisions as JSON, for the Zig code generator
+//! to diff its own against.
+//!
+//! `capnp compile -o-` writes the `CodeGeneratorRequest` every plugin gets;
+//! this reads it back and prints, per node in ID order, the struct sizes,
+//! each field's offset and discriminant value, and where the union tag sits;
+//! keys that do not apply, like a non-member's discriminant, are left out.
+//! Offsets are as the schema gives them: a slot's in units of its own type's
+//! size, a discriminant's in 16-bit units from the start of the data section.
+
+use std::path::{Path, PathBuf};
+
+use capnp::message::ReaderOptions;
+use capnp::schema_capnp::{code_generator_request, field, node, type_};
+use serde_json::{json, Value};
+
+use crate::error::{ErrorCode, TestError};
+
+/// The schema directory of the tree this harness was built from.
+pub const SCHEMA_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../schemas");
+
+/// build.rs's main request plus `rpc.capnp`: every schema that can share one
+/// request. The later revisions reuse their files' IDs and are left out.
+pub const FILES: [&str; 26] = [
+    "capnp/persistent.capnp",
+    "game_types.capnp",
+    "game_world.capnp",
+    "chat.capnp",
+    "inventory.capnp",
+    "matchmaking.capnp",
+    "nesting.capnp",
+    "defaults.capnp",
+    "lists.capnp",
+    "ordering.capnp",
+    "telemetry.capnp",
+    "registry.capnp",
+    "debug.capnp",
+    "linked.capnp",
+    "generics.capnp",
+    "envelope.capnp",
+    "spells.capnp",
+    "forecast.capnp",
+    "evolution.capnp",
+    "annotated.capnp",
+    "widestruct.capnp",
+    "commands.capnp",
+    "market/market.capnp",
+    "market/shared/price.capnp",
+    "deep.capnp",
+    "capnp/rpc.capnp",
+];
+
+/// Runs `capnp` over `files` (relative to `schema_dir`; every file in
+/// [`FILES`] when empty) and returns the framed `CodeGeneratorRequest`.
+pub fn compile(capnp: &Path, schema_dir: &Path, files: &[PathBuf]) -> Result<Vec<u8>, TestError> {
+    let files: Vec<PathBuf> = if files.is_empty() {
+        FILES.iter().map(|file| schema_dir.join(file)).collect()
+    } else {
+        files.iter().map(|file| schema_dir.join(file)).collect()
+    };
+    let output = std::process::Command::new(capnp)
+        .arg("compile")
+        .arg("-o-")
+        .arg(format!("--src-prefix={}", schema_dir.display()))
+        .arg(format!("--import-path={}", schema_dir.display()))
+        .args(&files)
+        .output()
+        .map_err(|e| {
+            TestError::new(
+                ErrorCode::ConfigError,
+                format!("spawn {}: {}", capnp.display(), e),
+            )
+        })?;
+    if !output.status.success() {
+        return Err(TestError::new(
+            ErrorCode::ConfigError,
+            format!(
+                "{} compile failed ({}): {}",
+                capnp.display(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// Reads a framed `CodeGeneratorRequest` and renders it with [`dump`].
+pub fn dump_bytes(bytes: &[u8]) -> Result<Value, TestError> {
+    // The request covers every schema at once and is our own compiler's output.
+    let mut options = ReaderOptions::new();
+    options.traversal_limit_in_words(None);
+    let message = capnp::serialize::read_message(bytes, options)?;
+    Ok(dump(message.get_root()?)?)
+}
+
+/// `{"files": [...], "nodes": [...]}` for `request`, nodes sorted by ID.
+pub fn dump(request: code_generator_request::Reader<'_>) -> capnp::Result<Value> {
+    let mut files = Vec::new();
+    for file in request.get_requested_files()? {
+        files.push(json!({
+            "id": hex_id(file.get_id()),
+            "name": file.get_filename()?.to_str()?,
+        }));
+    }
+
+    let mut nodes = Vec::new();
+    for node in request.get_nodes()? {
+        nodes.push((node.get_id(), dump_node(node)?));
+    }
+    nodes.sort_by_key(|(id, _)| *id);
+    let nodes: Vec<Value> = nodes.into_iter().map(|(_, node)| node).collect();
+
+    Ok(json!({ "files": files, "nodes": nodes }))
+}
+
+fn dump_node(node: node::Reader<'_>) -> capnp::Result<Value> {
+    let mut out = json!({
+        "id": hex_id(node.get_id()),
+        "displayName": node.get_display_name()?.to_str()?,
+        "scopeId": hex_id(node.get_scope_id()),
+    });
+    match node.which()? {
+        node::File(()) => out["kind"] = json!("file"),
+        node::Struct(s) => {
+            out["kind"] = json!("struct");
+            out["dataWordCount"] = json!(s.get_data_word_count());
+            out["pointerCount"] = json!(s.get_pointer_count());
+            out["isGroup"] = json!(s.get_is_group());
+            if s.get_discriminant_count() > 0 {
+                out["discriminantCount"] = json!(s.get_discriminant_count());
+                out["discriminantOffset"] = json!(s.get_discriminant_offset());
+            }
+            let mut fields = Vec::new();
+            for f in s.get_fields()? {
+                fields.push(dump_field(f)?);
+            }
+            out["fields"] = json!(fields);
+        }
+        node::Enum(e) => {
+            out["kind"] = json!("enum");
+            let mut enumerants = Vec::new();
+            for enumerant in e.get_enumerants()? {
+                enumerants.push(json!({
+                    "name": enumerant.get_name()?.to_str()?,
+                    "codeOrder": enumerant.get_code_order(),
+                }));
+            }
+            out["enumerants"] = json!(enumerants);
+        }
+        node::Interface(i) => {
+            out["kind"] = json!("interface");
+            let mut methods = Vec::new();
+            for (ordinal, method) in i.get_methods()?.iter().enumerate() {
+                methods.push(json!({
+                    "name": method.get_name()?.to_str()?,
+                    "ordinal": ordinal,
+                    "paramStructType": hex_id(method.get_param_struct_type()),
+                    "resultStructType": hex_id(method.get_result_struct_type()),
+                }));
+            }
+            out["methods"] = json!(methods);
+        }
+        node::Const(c) => {
+            out["kind"] = json!("const");
+            out["type"] = json!(type_name(c.get_type()?)?);
+        }
+        node::Annotation(a) => {
+            out["kind"] = json!("annotation");
+            out["type"] = json!(type_name(a.get_type()?)?);
+        }
+    }
+    Ok(out)
+}
+
+/// A field's offset and type, or the group it stands for, with its
+/// discriminant value when it is a union member.
+fn dump_field(f: field::Reader<'_>) -> capnp::Result<Value> {
+    let mut out = json!({
+        "name": f.get_name()?.to_str()?,
+        "codeOrder": f.get_code_order(),
+    });
+    if f.get_discriminant_value() != field::NO_DISCRIMINANT {
+        out["discriminantValue"] = json!(f.get_discriminant_value());
+    }
+    match f.which()? {
+        field::Slot(slot) => {
+            out["offset"] = json!(slot.get_offset());
+            out["type"] = json!(type_name(slot.get_type()?)?);
+            out["hadExplicitDefault"] = json!(slot.get_had_explicit_default());
+        }
+        field::Group(group) => out["group"] = json!(hex_id(group.get_type_id())),
+    }
+    Ok(out)
+}
+
+/// The schema-language name of `ty`, with struct, enum and interface types
+/// given by ID, e.g. `List(Struct(0x…))`.
+fn type_name(ty: type_::Reader<'_>) -> capnp::Result<String> {
+    Ok(match ty.which()? {
+        type_::Void(()) => "Void".to_string(),
+        type_::Bool(()) => "Bool".to_string(),
+        type_::Int8(()) => "Int8".to_string(),
+        type_::Int16(()) => "Int16".to_string(),
+        type_::Int32(()) => "Int32".to_string(),
+        type_::Int64(()) => "Int64".to_string(),
+        type_::Uint8(()) => "UInt8".to_string(),
+        type_::Uint16(()) => "UInt16".to_string(),
+        type_::Uint32(()) => "UInt32".to_string(),
+        type_::Uint64(()) => "UInt64".to_string(),
+        type_::Float32(()) => "Float32".to_string(),
+        type_::Float64(()) => "Float64".to_string(),
+        type_::Text(()) => "Text".to_string(),
+        type_::Data(()) => "Data".to_string(),
+        type_::List(list) => format!("List({})", type_name(list.get_element_type()?)?),
+        type_::Enum(e) => format!("Enum({})", hex_id(e.get_type_id())),
+        type_::Struct(s) => format!("Struct({})", hex_id(s.get_type_id())),
+        type_::Interface(i) => format!("Interface({})", hex_id(i.get_type_id())),
+        type_::AnyPointer(_) => "AnyPointer".to_string(),
+    })
+}
+
+/// IDs as strings: JSON numbers lose the low bits of most of them.
+fn hex_id(id: u64) -> String {
+    format!("{:#018x}", id)
+}
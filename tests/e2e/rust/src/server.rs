@@ -1,26 +1,84 @@
-use std::collections::HashMap;
-use std::net::ToSocketAddrs;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::marker::PhantomData;
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
-use capnp::capability::Promise;
+use capnp::capability::{FromClientHook, Promise};
+use capnp::traits::HasTypeId;
+use capnp_rpc::rpc_twoparty_capnp::Side;
 use capnp_rpc::{pry, rpc_twoparty_capnp, twoparty, RpcSystem};
-use futures::AsyncReadExt;
-use tokio::net::TcpListener;
+use futures::{AsyncReadExt, FutureExt, TryFutureExt};
 use tokio_util::compat::TokioAsyncReadCompatExt;
 
-use crate::chat_capnp::{chat_room, chat_service};
+use crate::annotated_capnp::{entry, ledger};
+use crate::chat_capnp::{chat_room, chat_service, message_listener, moderated_chat_room};
+use crate::commands_capnp::{command, command_service};
+use crate::debug_capnp::debug_stats;
+use crate::deep_capnp::{deep_service, tree_node, Shape};
+use crate::defaults_capnp::{defaults_service, settings, Quality};
+use crate::envelope_capnp::{counter, envelope, envelope_service, PayloadKind};
+use crate::error::{ErrorCode, TestError};
+use crate::evolution_capnp::{profile, profile_service, summary};
+use crate::faults::{self, Fault};
+use crate::forecast_capnp::{forecast, forecast_service, reading, Weather};
 use crate::game_types_capnp::{Faction, Rarity, StatusCode};
-use crate::game_world_capnp::{area_query, game_world, EntityKind};
-use crate::inventory_capnp::{inventory_service, trade_session, TradeState};
-use crate::matchmaking_capnp::{match_controller, matchmaking_service, GameMode, MatchState};
+use crate::game_world_capnp::{area_observer, area_query, entity_handle, game_world, EntityKind};
+use crate::generics_capnp::{generics_service, labeled, pair, repository};
+use crate::inventory_capnp::{inventory_listener, inventory_service, trade_session, TradeState};
+use crate::linked_capnp::{linked_list, node};
+use crate::lists_capnp::{blob_set, list_service, nested_lists, point, sized_lists};
+use crate::market_capnp::market;
+use crate::matchmaking_capnp::{
+    match_controller, matchmaking_service, queue_listener, GameMode, MatchState,
+};
+use crate::nesting_capnp::nesting_service;
+use crate::ordering_capnp::{call_order, ordering_service};
+use crate::persistent_capnp::persistent;
+use crate::registry_capnp::service_registry;
+use crate::spells_capnp::{spell_effect, spell_service};
+use crate::telemetry_capnp::{telemetry_service, telemetry_sink};
+use crate::tls::Acceptor;
+use crate::transport::{BoxedIo, Endpoint, Listener, SocketArgs};
 
-fn now_millis() -> i64 {
+pub(crate) fn now_millis() -> i64 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap()
         .as_millis() as i64
 }
 
+/// The clock's time for a `ping` reply, clamped to zero rather than
+/// wrapping should it read before the epoch.
+pub(crate) fn ping_millis() -> u64 {
+    u64::try_from(now_millis()).unwrap_or(0)
+}
+
+/// The `ping` method every bootstrap interface shares: echoes the nonce and
+/// adds the server's clock time.
+macro_rules! ping_method {
+    ($interface:ident) => {
+        fn ping(
+            &mut self,
+            params: $interface::PingParams,
+            mut results: $interface::PingResults,
+        ) -> Promise<(), capnp::Error> {
+            let nonce = pry!(params.get()).get_nonce();
+            let mut r = results.get();
+            r.set_nonce(nonce);
+            r.set_server_time_millis($crate::server::ping_millis());
+            Promise::ok(())
+        }
+    };
+}
+pub(crate) use ping_method;
+
+/// The time to stamp a `Timestamp` with: `fixed_time` if the server was
+/// started with one, otherwise the clock.
+fn timestamp_millis(fixed_time: Option<i64>) -> i64 {
+    fixed_time.unwrap_or_else(now_millis)
+}
+
 // ---------------------------------------------------------------------------
 // Shared data structures
 // ---------------------------------------------------------------------------
@@ -54,6 +112,95 @@ fn build_player_info(
     builder.set_level(p.level);
 }
 
+/// `Persistent` as ChatRoom and MatchController extend it: SturdyRefs are
+/// opaque tokens and owners are names.
+/// Spelled out, since the generated `persistent::SaveParams` alias lists its
+/// type parameters in a different order than the interface does.
+type SaveParams = capnp::capability::Params<
+    persistent::save_params::Owned<capnp::data::Owned, capnp::text::Owned>,
+>;
+type SaveResults = capnp::capability::Results<
+    persistent::save_results::Owned<capnp::data::Owned, capnp::text::Owned>,
+>;
+
+/// SturdyRef tokens minted by `Persistent.save`, each with what it restores
+/// and the owner it was sealed for, if any. Tokens outlive the connection
+/// that saved them, but not the server.
+struct SturdyRefs<T> {
+    refs: HashMap<Vec<u8>, (T, Option<String>)>,
+    keys: std::collections::hash_map::RandomState,
+    next: u64,
+}
+
+impl<T> Default for SturdyRefs<T> {
+    fn default() -> Self {
+        Self {
+            refs: HashMap::new(),
+            keys: Default::default(),
+            next: 0,
+        }
+    }
+}
+
+impl<T: Clone> SturdyRefs<T> {
+    /// Saves `target` under a fresh token: a counter, so tokens never repeat,
+    /// followed by a keyed hash of it, so they cannot be guessed.
+    fn save(&mut self, target: T, owner: Option<String>) -> Vec<u8> {
+        use std::hash::BuildHasher;
+        self.next += 1;
+        let mut token = self.next.to_le_bytes().to_vec();
+        token.extend(self.keys.hash_one(self.next).to_le_bytes());
+        self.refs.insert(token.clone(), (target, owner));
+        token
+    }
+
+    /// What `token` was saved for, if `owner` may restore it.
+    fn restore(&self, token: &[u8], owner: &str) -> Result<T, capnp::Error> {
+        match self.refs.get(token) {
+            None => Err(capnp::Error::failed("unknown sturdy ref".to_string())),
+            Some((_, Some(sealed))) if sealed != owner => Err(capnp::Error::failed(format!(
+                "sturdy ref is sealed for another owner than {:?}",
+                owner
+            ))),
+            Some((target, _)) => Ok(target.clone()),
+        }
+    }
+}
+
+/// The owner `save` was asked to seal the ref for; unset or empty leaves it
+/// unsealed.
+fn seal_for(params: &SaveParams) -> Result<Option<String>, capnp::Error> {
+    let p = params.get()?;
+    if !p.has_seal_for() {
+        return Ok(None);
+    }
+    let owner = p.get_seal_for()?.to_string()?;
+    Ok(Some(owner).filter(|owner| !owner.is_empty()))
+}
+
+/// Fills `list` with one element per item, each built by `build` as the root
+/// of a message of its own and then copied in. capnp-rust has no orphanage,
+/// so this is its nearest to building orphans and adopting them, the pattern
+/// the Zig builder API has to match; tests/adopt.rs checks the list encodes
+/// the same as one whose elements were initialized in place.
+pub fn adopt_elements<T, I>(
+    list: &mut capnp::struct_list::Builder<'_, T>,
+    items: &[I],
+    build: impl Fn(T::Builder<'_>, &I),
+) -> capnp::Result<()>
+where
+    T: capnp::traits::OwnedStruct,
+    for<'b> T::Builder<'b>: capnp::traits::FromPointerBuilder<'b>,
+    for<'b> T::Reader<'b>: capnp::traits::FromPointerReader<'b>,
+{
+    for (i, item) in items.iter().enumerate() {
+        let mut orphan = capnp::message::Builder::new_default();
+        build(orphan.init_root(), item);
+        list.set_with_caveats(i as u32, orphan.get_root_as_reader()?)?;
+    }
+    Ok(())
+}
+
 // ---------------------------------------------------------------------------
 // GameWorld implementation
 // ---------------------------------------------------------------------------
@@ -84,8 +231,62 @@ fn set_entity(builder: &mut crate::game_world_capnp::entity::Builder<'_>, e: &En
     builder.set_alive(e.alive);
 }
 
+/// An AreaQuery read out of its message, kept to match entities against.
+struct Area {
+    center: [f32; 3],
+    radius: f32,
+    filter: AreaFilter,
+}
+
+enum AreaFilter {
+    All,
+    ByKind(EntityKind),
+    ByFaction(Faction),
+}
+
+impl Area {
+    fn read(q: area_query::Reader<'_>) -> Result<Self, capnp::Error> {
+        let c = q.get_center()?;
+        let filter = match q.get_filter().which()? {
+            area_query::filter::Which::All(()) => AreaFilter::All,
+            area_query::filter::Which::ByKind(k) => AreaFilter::ByKind(k?),
+            area_query::filter::Which::ByFaction(f) => AreaFilter::ByFaction(f?),
+        };
+        Ok(Self {
+            center: [c.get_x(), c.get_y(), c.get_z()],
+            radius: q.get_radius(),
+            filter,
+        })
+    }
+
+    /// Whether `e`, standing at `position`, is inside the area and passes the filter.
+    fn contains(&self, e: &EntityData, position: [f32; 3]) -> bool {
+        let dx = position[0] - self.center[0];
+        let dy = position[1] - self.center[1];
+        let dz = position[2] - self.center[2];
+        if (dx * dx + dy * dy + dz * dz).sqrt() > self.radius {
+            return false;
+        }
+        match self.filter {
+            AreaFilter::All => true,
+            AreaFilter::ByKind(k) => k == e.kind,
+            AreaFilter::ByFaction(f) => f == e.faction,
+        }
+    }
+}
+
+/// Observers watching an area, each with the id it was registered under. Kept
+/// out of `GameWorldState`, since capabilities are not `Send`.
+#[derive(Default)]
+struct AreaWatchers {
+    observers: Vec<(u64, Area, area_observer::Client)>,
+    next_id: u64,
+}
+
+#[derive(Clone)]
 struct GameWorldImpl {
     state: Arc<Mutex<GameWorldState>>,
+    watchers: Rc<RefCell<AreaWatchers>>,
 }
 
 struct GameWorldState {
@@ -100,10 +301,73 @@ impl GameWorldImpl {
                 next_id: 1,
                 entities: HashMap::new(),
             })),
+            watchers: Default::default(),
+        }
+    }
+
+    /// Tells every observer whose area `e` is in, or was in before a move from
+    /// `moved_from`, about the change without waiting for them, dropping any
+    /// whose call fails.
+    fn notify(&self, e: &EntityData, moved_from: Option<[f32; 3]>) {
+        let watchers = self.watchers.borrow();
+        for (id, area, observer) in &watchers.observers {
+            let touched = area.contains(e, e.position)
+                || moved_from.is_some_and(|from| area.contains(e, from));
+            if !touched {
+                continue;
+            }
+            let promise = if moved_from.is_some() {
+                let mut req = observer.entity_moved_request();
+                set_entity(&mut req.get().init_entity(), e);
+                req.send().promise.map(|r| r.map(drop)).boxed_local()
+            } else {
+                let mut req = observer.entity_spawned_request();
+                set_entity(&mut req.get().init_entity(), e);
+                req.send().promise.map(|r| r.map(drop)).boxed_local()
+            };
+            let (watchers, id) = (self.watchers.clone(), *id);
+            tokio::task::spawn_local(promise.map(move |result| {
+                if result.is_err() {
+                    watchers
+                        .borrow_mut()
+                        .observers
+                        .retain(|(other, _, _)| *other != id);
+                }
+            }));
+        }
+    }
+
+    /// Moves entity `id` to `position` and reports the move to observers;
+    /// `None` if there is no such entity.
+    fn move_to(&self, id: u64, position: [f32; 3]) -> Option<EntityData> {
+        let mut st = self.state.lock().unwrap();
+        let e = st.entities.get_mut(&id)?;
+        let from = std::mem::replace(&mut e.position, position);
+        let e = e.clone();
+        drop(st);
+        self.notify(&e, Some(from));
+        Some(e)
+    }
+
+    /// Deals `amount` damage to entity `id`, returning it updated and whether
+    /// this killed it; `None` if there is no such entity.
+    fn damage(&self, id: u64, amount: i32) -> Option<(EntityData, bool)> {
+        let mut st = self.state.lock().unwrap();
+        let e = st.entities.get_mut(&id)?;
+        e.health -= amount;
+        let killed = e.health <= 0;
+        if killed {
+            e.alive = false;
+            e.health = 0;
         }
+        Some((e.clone(), killed))
     }
 }
 
+fn read_position(p: crate::game_types_capnp::position::Reader<'_>) -> [f32; 3] {
+    [p.get_x(), p.get_y(), p.get_z()]
+}
+
 impl game_world::Server for GameWorldImpl {
     fn spawn_entity(
         &mut self,
@@ -113,8 +377,7 @@ impl game_world::Server for GameWorldImpl {
         let req = pry!(pry!(params.get()).get_request());
         let kind = pry!(req.get_kind());
         let name = pry!(req.get_name()).to_string().unwrap_or_default();
-        let p = pry!(req.get_position());
-        let position = [p.get_x(), p.get_y(), p.get_z()];
+        let position = read_position(pry!(req.get_position()));
         let fac = pry!(req.get_faction());
         let max_health = req.get_max_health();
 
@@ -132,10 +395,16 @@ impl game_world::Server for GameWorldImpl {
             alive: true,
         };
         st.entities.insert(id, entity.clone());
+        drop(st);
+        self.notify(&entity, None);
 
         let mut r = results.get();
         set_entity(&mut r.reborrow().init_entity(), &entity);
         r.set_status(StatusCode::Ok);
+        r.set_handle(capnp_rpc::new_client(EntityHandleImpl {
+            world: self.clone(),
+            id,
+        }));
         Promise::ok(())
     }
 
@@ -178,14 +447,10 @@ impl game_world::Server for GameWorldImpl {
     ) -> Promise<(), capnp::Error> {
         let p = pry!(params.get());
         let id = pry!(p.get_id()).get_id();
-        let np = pry!(p.get_new_position());
-        let pos = [np.get_x(), np.get_y(), np.get_z()];
+        let pos = read_position(pry!(p.get_new_position()));
 
-        let mut st = self.state.lock().unwrap();
         let mut r = results.get();
-        if let Some(e) = st.entities.get_mut(&id) {
-            e.position = pos;
-            let e = e.clone();
+        if let Some(e) = self.move_to(id, pos) {
             set_entity(&mut r.reborrow().init_entity(), &e);
             r.set_status(StatusCode::Ok);
         } else {
@@ -201,18 +466,9 @@ impl game_world::Server for GameWorldImpl {
     ) -> Promise<(), capnp::Error> {
         let p = pry!(params.get());
         let id = pry!(p.get_id()).get_id();
-        let amount = p.get_amount();
 
-        let mut st = self.state.lock().unwrap();
         let mut r = results.get();
-        if let Some(e) = st.entities.get_mut(&id) {
-            e.health -= amount;
-            let killed = e.health <= 0;
-            if killed {
-                e.alive = false;
-                e.health = 0;
-            }
-            let e = e.clone();
+        if let Some((e, killed)) = self.damage(id, p.get_amount()) {
             set_entity(&mut r.reborrow().init_entity(), &e);
             r.set_killed(killed);
             r.set_status(StatusCode::Ok);
@@ -227,59 +483,109 @@ impl game_world::Server for GameWorldImpl {
         params: game_world::QueryAreaParams,
         mut results: game_world::QueryAreaResults,
     ) -> Promise<(), capnp::Error> {
-        let q = pry!(pry!(params.get()).get_query());
-        let c = pry!(q.get_center());
-        let (cx, cy, cz) = (c.get_x(), c.get_y(), c.get_z());
-        let radius = q.get_radius();
-        let filter = pry!(q.get_filter().which());
-
-        // Pre-extract filter values before the loop
-        enum Filter {
-            All,
-            ByKind(EntityKind),
-            ByFaction(Faction),
-        }
-        let filter = match filter {
-            area_query::filter::Which::All(()) => Filter::All,
-            area_query::filter::Which::ByKind(k) => Filter::ByKind(pry!(k)),
-            area_query::filter::Which::ByFaction(f) => Filter::ByFaction(pry!(f)),
-        };
+        let area = pry!(Area::read(pry!(pry!(params.get()).get_query())));
 
         let st = self.state.lock().unwrap();
-        let mut matched: Vec<EntityData> = Vec::new();
-
-        for e in st.entities.values() {
-            let dx = e.position[0] - cx;
-            let dy = e.position[1] - cy;
-            let dz = e.position[2] - cz;
-            if (dx * dx + dy * dy + dz * dz).sqrt() > radius {
-                continue;
-            }
-            match &filter {
-                Filter::All => {}
-                Filter::ByKind(k) => {
-                    if *k != e.kind {
-                        continue;
-                    }
-                }
-                Filter::ByFaction(f) => {
-                    if *f != e.faction {
-                        continue;
-                    }
-                }
-            }
-            matched.push(e.clone());
-        }
+        let matched: Vec<EntityData> = st
+            .entities
+            .values()
+            .filter(|e| area.contains(e, e.position))
+            .cloned()
+            .collect();
 
         let count = matched.len() as u32;
         let mut r = results.get();
         let mut list = r.reborrow().init_entities(count);
-        for (i, e) in matched.iter().enumerate() {
-            set_entity(&mut list.reborrow().get(i as u32), e);
-        }
+        pry!(adopt_elements(&mut list, &matched, |mut entity, e| {
+            set_entity(&mut entity, e)
+        }));
         r.set_count(count);
         Promise::ok(())
     }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: game_world::GetSchemaFingerprintParams,
+        mut results: game_world::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(game_world);
+
+    fn watch_area(
+        &mut self,
+        params: game_world::WatchAreaParams,
+        mut results: game_world::WatchAreaResults,
+    ) -> Promise<(), capnp::Error> {
+        let p = pry!(params.get());
+        let area = pry!(Area::read(pry!(p.get_query())));
+        let observer = pry!(p.get_observer());
+        let mut watchers = self.watchers.borrow_mut();
+        let id = watchers.next_id;
+        watchers.next_id += 1;
+        watchers.observers.push((id, area, observer));
+        results.get().set_status(StatusCode::Ok);
+        Promise::ok(())
+    }
+}
+
+/// One entity of a `GameWorldImpl`, handed out by spawnEntity.
+struct EntityHandleImpl {
+    world: GameWorldImpl,
+    id: u64,
+}
+
+impl entity_handle::Server for EntityHandleImpl {
+    fn move_(
+        &mut self,
+        params: entity_handle::MoveParams,
+        mut results: entity_handle::MoveResults,
+    ) -> Promise<(), capnp::Error> {
+        let pos = read_position(pry!(pry!(params.get()).get_new_position()));
+        let mut r = results.get();
+        if let Some(e) = self.world.move_to(self.id, pos) {
+            set_entity(&mut r.reborrow().init_entity(), &e);
+            r.set_status(StatusCode::Ok);
+        } else {
+            r.set_status(StatusCode::NotFound);
+        }
+        Promise::ok(())
+    }
+
+    fn damage(
+        &mut self,
+        params: entity_handle::DamageParams,
+        mut results: entity_handle::DamageResults,
+    ) -> Promise<(), capnp::Error> {
+        let amount = pry!(params.get()).get_amount();
+        let mut r = results.get();
+        if let Some((e, killed)) = self.world.damage(self.id, amount) {
+            set_entity(&mut r.reborrow().init_entity(), &e);
+            r.set_killed(killed);
+            r.set_status(StatusCode::Ok);
+        } else {
+            r.set_status(StatusCode::NotFound);
+        }
+        Promise::ok(())
+    }
+
+    fn get_snapshot(
+        &mut self,
+        _params: entity_handle::GetSnapshotParams,
+        mut results: entity_handle::GetSnapshotResults,
+    ) -> Promise<(), capnp::Error> {
+        let st = self.world.state.lock().unwrap();
+        let mut r = results.get();
+        if let Some(e) = st.entities.get(&self.id) {
+            set_entity(&mut r.reborrow().init_entity(), e);
+            r.set_status(StatusCode::Ok);
+        } else {
+            r.set_status(StatusCode::NotFound);
+        }
+        Promise::ok(())
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -321,17 +627,102 @@ struct ChatRoomData {
     topic: String,
     messages: Vec<ChatMessageData>,
     member_count: u32,
+    /// Player id behind each joinRoom membership still held.
+    members: Vec<u64>,
+    /// Players a moderator kicked who have not joined again since.
+    kicked: HashSet<u64>,
 }
 
 struct ChatState {
     rooms: HashMap<String, ChatRoomData>,
     next_room_id: u64,
+    /// Saved room memberships: the room name and the member it speaks for.
+    saved_rooms: SturdyRefs<(String, PlayerInfoData)>,
+    /// Stamps every message with this time instead of the clock's.
+    fixed_time: Option<i64>,
+}
+
+impl ChatState {
+    /// Adds an empty room and returns its id.
+    fn add_room(&mut self, name: &str, topic: &str) -> u64 {
+        let id = self.next_room_id;
+        self.next_room_id += 1;
+        self.rooms.insert(
+            name.to_string(),
+            ChatRoomData {
+                id,
+                name: name.to_string(),
+                topic: topic.to_string(),
+                messages: Vec::new(),
+                member_count: 0,
+                members: Vec::new(),
+                kicked: HashSet::new(),
+            },
+        );
+        id
+    }
+}
+
+/// Room `getDefaultRoom` joins, created the first time anyone asks for it.
+const DEFAULT_ROOM: &str = "lobby";
+
+/// Listeners subscribed to each room, by room name, each with the id it was
+/// subscribed under. Kept out of `ChatState`, since capabilities are not `Send`.
+#[derive(Default)]
+struct ChatListeners {
+    rooms: HashMap<String, Vec<(u64, message_listener::Client)>>,
+    next_id: u64,
 }
 
 struct ChatRoomImpl {
     room_name: String,
     player: PlayerInfoData,
     state: Arc<Mutex<ChatState>>,
+    listeners: Rc<RefCell<ChatListeners>>,
+    /// Method ordinal and content of every call, in arrival order.
+    arrivals: Vec<(u16, String)>,
+}
+
+/// Every room a connection's chat service has handed out, so one sent back
+/// can be looked up as the server object behind it.
+type IssuedRooms = capnp_rpc::CapabilityServerSet<ChatRoomImpl, chat_room::Client>;
+
+/// The moderated rooms among them, handed out as the subtype.
+type IssuedModeratedRooms =
+    capnp_rpc::CapabilityServerSet<ChatRoomImpl, moderated_chat_room::Client>;
+
+impl ChatRoomImpl {
+    fn arrive(&mut self, method_id: u16, content: &str) {
+        self.arrivals.push((method_id, content.to_string()));
+    }
+
+    fn write_info(&self, mut info: crate::chat_capnp::room_info::Builder<'_>) {
+        let st = self.state.lock().unwrap();
+        if let Some(room) = st.rooms.get(&self.room_name) {
+            info.reborrow().init_id().set_id(room.id);
+            info.reborrow().set_name(&room.name);
+            info.reborrow().set_member_count(room.member_count);
+            info.set_topic(&room.topic);
+        }
+    }
+
+    /// Sends `msg` to every listener subscribed to this room without waiting
+    /// for them, unsubscribing any whose call fails.
+    fn broadcast(&self, msg: &ChatMessageData) {
+        let listeners = self.listeners.borrow();
+        for (id, listener) in listeners.rooms.get(&self.room_name).into_iter().flatten() {
+            let mut req = listener.on_message_request();
+            build_chat_message(&mut req.get().init_message(), msg);
+            let (listeners, room_name, id) = (self.listeners.clone(), self.room_name.clone(), *id);
+            tokio::task::spawn_local(req.send().promise.map(move |result| {
+                if result.is_err() {
+                    if let Some(room) = listeners.borrow_mut().rooms.get_mut(&room_name) {
+                        room.retain(|(other, _)| *other != id);
+                    }
+                }
+            }));
+        }
+    }
 }
 
 impl chat_room::Server for ChatRoomImpl {
@@ -343,16 +734,22 @@ impl chat_room::Server for ChatRoomImpl {
         let content = pry!(pry!(params.get()).get_content())
             .to_string()
             .unwrap_or_default();
+        self.arrive(0, &content);
         let msg = ChatMessageData {
             sender: self.player.clone(),
             content,
-            timestamp_millis: now_millis(),
+            timestamp_millis: timestamp_millis(self.state.lock().unwrap().fixed_time),
             is_emote: false,
             whisper_target: None,
         };
         let mut st = self.state.lock().unwrap();
         if let Some(room) = st.rooms.get_mut(&self.room_name) {
+            if room.kicked.contains(&self.player.id) {
+                results.get().set_status(StatusCode::PermissionDenied);
+                return Promise::ok(());
+            }
             room.messages.push(msg.clone());
+            self.broadcast(&msg);
             let mut r = results.get();
             build_chat_message(&mut r.reborrow().init_message(), &msg);
             r.set_status(StatusCode::Ok);
@@ -370,16 +767,22 @@ impl chat_room::Server for ChatRoomImpl {
         let content = pry!(pry!(params.get()).get_content())
             .to_string()
             .unwrap_or_default();
+        self.arrive(1, &content);
         let msg = ChatMessageData {
             sender: self.player.clone(),
             content,
-            timestamp_millis: now_millis(),
+            timestamp_millis: timestamp_millis(self.state.lock().unwrap().fixed_time),
             is_emote: true,
             whisper_target: None,
         };
         let mut st = self.state.lock().unwrap();
         if let Some(room) = st.rooms.get_mut(&self.room_name) {
+            if room.kicked.contains(&self.player.id) {
+                results.get().set_status(StatusCode::PermissionDenied);
+                return Promise::ok(());
+            }
             room.messages.push(msg.clone());
+            self.broadcast(&msg);
             let mut r = results.get();
             build_chat_message(&mut r.reborrow().init_message(), &msg);
             r.set_status(StatusCode::Ok);
@@ -394,6 +797,7 @@ impl chat_room::Server for ChatRoomImpl {
         params: chat_room::GetHistoryParams,
         mut results: chat_room::GetHistoryResults,
     ) -> Promise<(), capnp::Error> {
+        self.arrive(2, "");
         let limit = pry!(params.get()).get_limit() as usize;
         let st = self.state.lock().unwrap();
         if let Some(room) = st.rooms.get(&self.room_name) {
@@ -406,9 +810,9 @@ impl chat_room::Server for ChatRoomImpl {
                 .cloned()
                 .collect();
             let mut list = results.get().init_messages(msgs.len() as u32);
-            for (i, msg) in msgs.iter().enumerate() {
-                build_chat_message(&mut list.reborrow().get(i as u32), msg);
-            }
+            pry!(adopt_elements(&mut list, &msgs, |mut message, msg| {
+                build_chat_message(&mut message, msg)
+            }));
         }
         Promise::ok(())
     }
@@ -418,14 +822,8 @@ impl chat_room::Server for ChatRoomImpl {
         _params: chat_room::GetInfoParams,
         mut results: chat_room::GetInfoResults,
     ) -> Promise<(), capnp::Error> {
-        let st = self.state.lock().unwrap();
-        if let Some(room) = st.rooms.get(&self.room_name) {
-            let mut info = results.get().init_info();
-            info.reborrow().init_id().set_id(room.id);
-            info.reborrow().set_name(&room.name);
-            info.reborrow().set_member_count(room.member_count);
-            info.set_topic(&room.topic);
-        }
+        self.arrive(3, "");
+        self.write_info(results.get().init_info());
         Promise::ok(())
     }
 
@@ -434,28 +832,142 @@ impl chat_room::Server for ChatRoomImpl {
         _params: chat_room::LeaveParams,
         mut results: chat_room::LeaveResults,
     ) -> Promise<(), capnp::Error> {
+        self.arrive(4, "");
         let mut st = self.state.lock().unwrap();
         if let Some(room) = st.rooms.get_mut(&self.room_name) {
             room.member_count = room.member_count.saturating_sub(1);
+            if let Some(at) = room.members.iter().position(|&id| id == self.player.id) {
+                room.members.remove(at);
+            }
             results.get().set_status(StatusCode::Ok);
         } else {
             results.get().set_status(StatusCode::NotFound);
         }
         Promise::ok(())
     }
+
+    fn subscribe(
+        &mut self,
+        params: chat_room::SubscribeParams,
+        mut results: chat_room::SubscribeResults,
+    ) -> Promise<(), capnp::Error> {
+        self.arrive(5, "");
+        let listener = pry!(pry!(params.get()).get_listener());
+        if !self
+            .state
+            .lock()
+            .unwrap()
+            .rooms
+            .contains_key(&self.room_name)
+        {
+            results.get().set_status(StatusCode::NotFound);
+            return Promise::ok(());
+        }
+        let mut listeners = self.listeners.borrow_mut();
+        let id = listeners.next_id;
+        listeners.next_id += 1;
+        listeners
+            .rooms
+            .entry(self.room_name.clone())
+            .or_default()
+            .push((id, listener));
+        results.get().set_status(StatusCode::Ok);
+        Promise::ok(())
+    }
+
+    fn get_arrivals(
+        &mut self,
+        _params: chat_room::GetArrivalsParams,
+        mut results: chat_room::GetArrivalsResults,
+    ) -> Promise<(), capnp::Error> {
+        let mut list = results.get().init_arrivals(self.arrivals.len() as u32);
+        for (i, (method_id, content)) in self.arrivals.iter().enumerate() {
+            let mut arrival = list.reborrow().get(i as u32);
+            arrival.set_method_id(*method_id);
+            arrival.set_content(content);
+        }
+        Promise::ok(())
+    }
+}
+
+impl moderated_chat_room::Server for ChatRoomImpl {
+    fn kick(
+        &mut self,
+        params: moderated_chat_room::KickParams,
+        mut results: moderated_chat_room::KickResults,
+    ) -> Promise<(), capnp::Error> {
+        let player = pry!(pry!(params.get()).get_player()).get_id();
+        let mut st = self.state.lock().unwrap();
+        let status = match st.rooms.get_mut(&self.room_name) {
+            Some(room) if room.members.contains(&player) => {
+                let before = room.members.len();
+                room.members.retain(|&id| id != player);
+                let removed = (before - room.members.len()) as u32;
+                room.member_count = room.member_count.saturating_sub(removed);
+                room.kicked.insert(player);
+                StatusCode::Ok
+            }
+            _ => StatusCode::NotFound,
+        };
+        results.get().set_status(status);
+        Promise::ok(())
+    }
+
+    fn set_topic(
+        &mut self,
+        params: moderated_chat_room::SetTopicParams,
+        mut results: moderated_chat_room::SetTopicResults,
+    ) -> Promise<(), capnp::Error> {
+        let topic = pry!(pry!(params.get()).get_topic())
+            .to_string()
+            .unwrap_or_default();
+        let status = match self.state.lock().unwrap().rooms.get_mut(&self.room_name) {
+            Some(room) => {
+                room.topic = topic;
+                StatusCode::Ok
+            }
+            None => StatusCode::NotFound,
+        };
+        let mut r = results.get();
+        self.write_info(r.reborrow().init_info());
+        r.set_status(status);
+        Promise::ok(())
+    }
+}
+
+impl persistent::Server<capnp::data::Owned, capnp::text::Owned> for ChatRoomImpl {
+    fn save(&mut self, params: SaveParams, mut results: SaveResults) -> Promise<(), capnp::Error> {
+        let owner = pry!(seal_for(&params));
+        let token = self
+            .state
+            .lock()
+            .unwrap()
+            .saved_rooms
+            .save((self.room_name.clone(), self.player.clone()), owner);
+        pry!(results.get().set_sturdy_ref(&token[..]));
+        Promise::ok(())
+    }
 }
 
 struct ChatServiceImpl {
     state: Arc<Mutex<ChatState>>,
+    listeners: Rc<RefCell<ChatListeners>>,
+    issued: Rc<RefCell<IssuedRooms>>,
+    issued_moderated: Rc<RefCell<IssuedModeratedRooms>>,
 }
 
 impl ChatServiceImpl {
-    fn new() -> Self {
+    fn new(fixed_time: Option<i64>) -> Self {
         Self {
             state: Arc::new(Mutex::new(ChatState {
                 rooms: HashMap::new(),
                 next_room_id: 1,
+                saved_rooms: Default::default(),
+                fixed_time,
             })),
+            listeners: Default::default(),
+            issued: Rc::new(RefCell::new(IssuedRooms::new())),
+            issued_moderated: Rc::new(RefCell::new(IssuedModeratedRooms::new())),
         }
     }
 }
@@ -469,24 +981,14 @@ impl chat_service::Server for ChatServiceImpl {
         let p = pry!(params.get());
         let name = pry!(p.get_name()).to_string().unwrap_or_default();
         let topic = pry!(p.get_topic()).to_string().unwrap_or_default();
+        let moderated = p.get_moderated();
 
         let mut st = self.state.lock().unwrap();
         if st.rooms.contains_key(&name) {
             results.get().set_status(StatusCode::AlreadyExists);
             return Promise::ok(());
         }
-        let id = st.next_room_id;
-        st.next_room_id += 1;
-        st.rooms.insert(
-            name.clone(),
-            ChatRoomData {
-                id,
-                name: name.clone(),
-                topic: topic.clone(),
-                messages: Vec::new(),
-                member_count: 0,
-            },
-        );
+        let id = st.add_room(&name, &topic);
 
         let room_impl = ChatRoomImpl {
             room_name: name.clone(),
@@ -497,8 +999,16 @@ impl chat_service::Server for ChatServiceImpl {
                 level: 0,
             },
             state: self.state.clone(),
+            listeners: self.listeners.clone(),
+            arrivals: Vec::new(),
+        };
+        let room_client: chat_room::Client = if moderated {
+            let room: moderated_chat_room::Client =
+                self.issued_moderated.borrow_mut().new_client(room_impl);
+            room.cast_to()
+        } else {
+            self.issued.borrow_mut().new_client(room_impl)
         };
-        let room_client: chat_room::Client = capnp_rpc::new_client(room_impl);
 
         let mut r = results.get();
         r.reborrow().set_room(room_client);
@@ -524,12 +1034,16 @@ impl chat_service::Server for ChatServiceImpl {
         let mut r = results.get();
         if let Some(room) = st.rooms.get_mut(&name) {
             room.member_count += 1;
+            room.members.push(player.id);
+            room.kicked.remove(&player.id);
             let room_impl = ChatRoomImpl {
                 room_name: name.clone(),
                 player,
                 state: self.state.clone(),
+                listeners: self.listeners.clone(),
+                arrivals: Vec::new(),
             };
-            r.set_room(capnp_rpc::new_client(room_impl));
+            r.set_room(self.issued.borrow_mut().new_client(room_impl));
             r.set_status(StatusCode::Ok);
         } else {
             r.set_status(StatusCode::NotFound);
@@ -555,6 +1069,39 @@ impl chat_service::Server for ChatServiceImpl {
         Promise::ok(())
     }
 
+    fn get_default_room(
+        &mut self,
+        params: chat_service::GetDefaultRoomParams,
+        mut results: chat_service::GetDefaultRoomResults,
+    ) -> Promise<(), capnp::Error> {
+        let player = pry!(pry!(params.get()).get_player());
+        {
+            let mut st = self.state.lock().unwrap();
+            if !st.rooms.contains_key(DEFAULT_ROOM) {
+                st.add_room(DEFAULT_ROOM, "Default room");
+            }
+        }
+
+        // Forward to joinRoom on a second capability over the same rooms and
+        // copy its results across; both methods share a results layout. This
+        // is not a tail call: capnp-rpc has none for a local target, so no
+        // Return.takeFromOtherQuestion goes on the wire.
+        let rooms: chat_service::Client = capnp_rpc::new_client(ChatServiceImpl {
+            state: self.state.clone(),
+            listeners: self.listeners.clone(),
+            issued: self.issued.clone(),
+            issued_moderated: self.issued_moderated.clone(),
+        });
+        let mut req = rooms.join_room_request();
+        req.get().set_name(DEFAULT_ROOM);
+        pry!(req.get().set_player(player));
+        let joined = req.send().promise;
+        Promise::from_future(async move {
+            let response = joined.await?;
+            results.hook.get()?.set_as(response.hook.get()?)
+        })
+    }
+
     fn whisper(
         &mut self,
         params: chat_service::WhisperParams,
@@ -568,7 +1115,7 @@ impl chat_service::Server for ChatServiceImpl {
         let msg = ChatMessageData {
             sender: from,
             content,
-            timestamp_millis: now_millis(),
+            timestamp_millis: timestamp_millis(self.state.lock().unwrap().fixed_time),
             is_emote: false,
             whisper_target: Some(to_id),
         };
@@ -577,27 +1124,148 @@ impl chat_service::Server for ChatServiceImpl {
         r.set_status(StatusCode::Ok);
         Promise::ok(())
     }
-}
 
-// ---------------------------------------------------------------------------
-// Inventory implementation
-// ---------------------------------------------------------------------------
+    fn describe_room(
+        &mut self,
+        params: chat_service::DescribeRoomParams,
+        mut results: chat_service::DescribeRoomResults,
+    ) -> Promise<(), capnp::Error> {
+        let room = pry!(pry!(params.get()).get_room());
+        let issued = self.issued.clone();
+        let issued_moderated = self.issued_moderated.clone();
+        Promise::from_future(async move {
+            // A room of ours comes back as a receiverHosted descriptor, which
+            // resolves to the server object itself: read it directly, without
+            // a call that could loop back through the peer.
+            let room = capnp::capability::get_resolved_cap(room).await;
+            if let Some(server) = issued.borrow().get_local_server_of_resolved(&room) {
+                server.borrow().server.write_info(results.get().init_info());
+                return Ok(());
+            }
+            let room: moderated_chat_room::Client = room.cast_to();
+            let Some(server) = issued_moderated
+                .borrow()
+                .get_local_server_of_resolved(&room)
+            else {
+                return Err(capnp::Error::failed(
+                    "room was not issued by this chat service".to_string(),
+                ));
+            };
+            server.borrow().server.write_info(results.get().init_info());
+            Ok(())
+        })
+    }
 
-#[derive(Clone)]
-struct InventorySlotData {
-    slot_index: u16,
-    item_id: u64,
-    item_name: String,
-    item_rarity: Rarity,
-    item_level: u16,
-    quantity: u32,
-}
+    fn whisper_via(
+        &mut self,
+        params: chat_service::WhisperViaParams,
+        mut results: chat_service::WhisperViaResults,
+    ) -> Promise<(), capnp::Error> {
+        let p = pry!(params.get());
+        let to_id = pry!(p.get_to()).get_id();
+        let mut req = pry!(p.get_room()).send_message_request();
+        req.get().set_content(pry!(p.get_content()));
+        let sent = req.send().promise;
+        Promise::from_future(async move {
+            let response = sent.await?;
+            let response = response.get()?;
+            let mut r = results.get();
+            r.set_message(response.get_message()?)?;
+            r.reborrow()
+                .get_message()?
+                .init_kind()
+                .init_whisper()
+                .set_id(to_id);
+            r.set_status(response.get_status()?);
+            Ok(())
+        })
+    }
 
-fn build_inventory_slot(
-    builder: &mut crate::inventory_capnp::inventory_slot::Builder<'_>,
-    s: &InventorySlotData,
-) {
-    builder.reborrow().set_slot_index(s.slot_index);
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: chat_service::GetSchemaFingerprintParams,
+        mut results: chat_service::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(chat_service);
+
+    fn restore_room(
+        &mut self,
+        params: chat_service::RestoreRoomParams,
+        mut results: chat_service::RestoreRoomResults,
+    ) -> Promise<(), capnp::Error> {
+        let p = pry!(params.get());
+        let owner = pry!(pry!(p.get_owner()).to_str());
+        let st = self.state.lock().unwrap();
+        let (room_name, player) = pry!(st.saved_rooms.restore(pry!(p.get_sturdy_ref()), owner));
+        if !st.rooms.contains_key(&room_name) {
+            return Promise::err(capnp::Error::failed(format!(
+                "room {:?} no longer exists",
+                room_name
+            )));
+        }
+        let room = self.issued.borrow_mut().new_client(ChatRoomImpl {
+            room_name,
+            player,
+            state: self.state.clone(),
+            listeners: self.listeners.clone(),
+            arrivals: Vec::new(),
+        });
+        results.get().set_room(room);
+        Promise::ok(())
+    }
+
+    fn get_all_rooms(
+        &mut self,
+        _params: chat_service::GetAllRoomsParams,
+        mut results: chat_service::GetAllRoomsResults,
+    ) -> Promise<(), capnp::Error> {
+        let st = self.state.lock().unwrap();
+        let mut rooms: Vec<_> = st.rooms.values().collect();
+        rooms.sort_by_key(|room| room.id);
+        let mut list = results.get().init_rooms(rooms.len() as u32);
+        for (i, room) in rooms.iter().enumerate() {
+            let client: chat_room::Client = self.issued.borrow_mut().new_client(ChatRoomImpl {
+                room_name: room.name.clone(),
+                player: PlayerInfoData {
+                    id: 0,
+                    name: "system".into(),
+                    faction: Faction::Neutral,
+                    level: 0,
+                },
+                state: self.state.clone(),
+                listeners: self.listeners.clone(),
+                arrivals: Vec::new(),
+            });
+            list.set(i as u32, client.client.hook);
+        }
+        Promise::ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Inventory implementation
+// ---------------------------------------------------------------------------
+
+#[derive(Clone)]
+struct InventorySlotData {
+    slot_index: u16,
+    item_id: u64,
+    item_name: String,
+    item_rarity: Rarity,
+    item_level: u16,
+    quantity: u32,
+    icon: Option<Vec<u8>>,
+}
+
+fn build_inventory_slot(
+    builder: &mut crate::inventory_capnp::inventory_slot::Builder<'_>,
+    s: &InventorySlotData,
+) {
+    builder.reborrow().set_slot_index(s.slot_index);
     let mut item = builder.reborrow().init_item();
     item.reborrow().init_id().set_id(s.item_id);
     item.reborrow().set_name(&s.item_name);
@@ -605,6 +1273,9 @@ fn build_inventory_slot(
     item.reborrow().set_level(s.item_level);
     item.set_stack_size(s.quantity);
     builder.set_quantity(s.quantity);
+    if let Some(icon) = &s.icon {
+        builder.set_icon(icon);
+    }
 }
 
 fn rarity_rank(r: Rarity) -> u8 {
@@ -619,10 +1290,24 @@ fn rarity_rank(r: Rarity) -> u8 {
 
 struct InventoryState {
     inventories: HashMap<u64, Vec<InventorySlotData>>,
+    /// Every trade started on any connection and still held by a session,
+    /// or not yet finished, by trade id.
+    trades: HashMap<u64, Trade>,
+    next_trade_id: u64,
+}
+
+/// Listeners subscribed to each player's inventory, by player id, each with
+/// the id it was subscribed under. Kept out of `InventoryState`, since
+/// capabilities are not `Send`.
+#[derive(Default)]
+struct InventoryListeners {
+    players: HashMap<u64, Vec<(u64, inventory_listener::Client)>>,
+    next_id: u64,
 }
 
 struct InventoryServiceImpl {
     state: Arc<Mutex<InventoryState>>,
+    listeners: Rc<RefCell<InventoryListeners>>,
 }
 
 impl InventoryServiceImpl {
@@ -630,7 +1315,29 @@ impl InventoryServiceImpl {
         Self {
             state: Arc::new(Mutex::new(InventoryState {
                 inventories: HashMap::new(),
+                trades: HashMap::new(),
+                next_trade_id: 1,
             })),
+            listeners: Default::default(),
+        }
+    }
+
+    /// Makes the call `send` builds on every listener subscribed to `player`
+    /// without waiting for them, unsubscribing any whose call fails.
+    fn notify<F>(&self, player: u64, send: impl Fn(&inventory_listener::Client) -> F)
+    where
+        F: std::future::Future<Output = Result<(), capnp::Error>> + 'static,
+    {
+        let listeners = self.listeners.borrow();
+        for (id, listener) in listeners.players.get(&player).into_iter().flatten() {
+            let (listeners, id) = (self.listeners.clone(), *id);
+            tokio::task::spawn_local(send(listener).map(move |result| {
+                if result.is_err() {
+                    if let Some(subscribed) = listeners.borrow_mut().players.get_mut(&player) {
+                        subscribed.retain(|(other, _)| *other != id);
+                    }
+                }
+            }));
         }
     }
 }
@@ -679,12 +1386,29 @@ impl inventory_service::Server for InventoryServiceImpl {
             item_rarity: pry!(item.get_rarity()),
             item_level: item.get_level(),
             quantity,
+            icon: if p.has_icon() {
+                Some(pry!(p.get_icon()).to_vec())
+            } else {
+                None
+            },
         };
 
         let mut st = self.state.lock().unwrap();
         let slots = st.inventories.entry(player_id).or_default();
-        slot_data.slot_index = slots.len() as u16;
+        slot_data.slot_index = match next_slot_index(slots) {
+            Ok(index) => index,
+            Err(status) => {
+                results.get().set_status(status);
+                return Promise::ok(());
+            }
+        };
         slots.push(slot_data.clone());
+        drop(st);
+        self.notify(player_id, |listener| {
+            let mut req = listener.item_added_request();
+            build_inventory_slot(&mut req.get().init_slot(), &slot_data);
+            req.send().promise.map(|r| r.map(drop))
+        });
 
         let mut r = results.get();
         build_inventory_slot(&mut r.reborrow().init_slot(), &slot_data);
@@ -707,9 +1431,18 @@ impl inventory_service::Server for InventoryServiceImpl {
             if let Some(slot) = slots.iter_mut().find(|s| s.slot_index == slot_index) {
                 if slot.quantity >= quantity {
                     slot.quantity -= quantity;
-                    if slot.quantity == 0 {
+                    let remaining = slot.quantity;
+                    if remaining == 0 {
                         slots.retain(|s| s.slot_index != slot_index);
                     }
+                    drop(st);
+                    self.notify(player_id, |listener| {
+                        let mut req = listener.item_removed_request();
+                        req.get().set_slot_index(slot_index);
+                        req.get().set_quantity(quantity);
+                        req.get().set_remaining(remaining);
+                        req.send().promise.map(|r| r.map(drop))
+                    });
                     results.get().set_status(StatusCode::Ok);
                 } else {
                     results.get().set_status(StatusCode::InvalidArgument);
@@ -732,21 +1465,21 @@ impl inventory_service::Server for InventoryServiceImpl {
         let initiator = pry!(p.get_initiator()).get_id();
         let target = pry!(p.get_target()).get_id();
 
+        let mut st = self.state.lock().unwrap();
+        let trade_id = st.next_trade_id;
+        st.next_trade_id += 1;
+        st.trades.insert(trade_id, Trade::new(initiator, target));
+        drop(st);
         let session = TradeSessionImpl {
-            _initiator: initiator,
-            _target: target,
-            state: Arc::new(Mutex::new(TradeSessionState {
-                trade_state: TradeState::Proposing,
-                offered_slots: Vec::new(),
-                other_offered_slots: Vec::new(),
-                accepted: false,
-                other_accepted: false,
-            })),
+            state: self.state.clone(),
+            trade_id,
+            side: INITIATOR,
         };
         let client: trade_session::Client = capnp_rpc::new_client(session);
         let mut r = results.get();
         r.set_session(client);
         r.set_status(StatusCode::Ok);
+        r.set_trade_id(trade_id);
         Promise::ok(())
     }
 
@@ -779,28 +1512,220 @@ impl inventory_service::Server for InventoryServiceImpl {
         }
         Promise::ok(())
     }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: inventory_service::GetSchemaFingerprintParams,
+        mut results: inventory_service::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(inventory_service);
+
+    fn subscribe(
+        &mut self,
+        params: inventory_service::SubscribeParams,
+        mut results: inventory_service::SubscribeResults,
+    ) -> Promise<(), capnp::Error> {
+        let p = pry!(params.get());
+        let player_id = pry!(p.get_player()).get_id();
+        let listener = pry!(p.get_listener());
+        let mut listeners = self.listeners.borrow_mut();
+        let id = listeners.next_id;
+        listeners.next_id += 1;
+        listeners
+            .players
+            .entry(player_id)
+            .or_default()
+            .push((id, listener));
+        results.get().set_status(StatusCode::Ok);
+        Promise::ok(())
+    }
+
+    fn join_trade(
+        &mut self,
+        params: inventory_service::JoinTradeParams,
+        mut results: inventory_service::JoinTradeResults,
+    ) -> Promise<(), capnp::Error> {
+        let p = pry!(params.get());
+        let trade_id = p.get_trade_id();
+        let player_id = pry!(p.get_player()).get_id();
+        let mut st = self.state.lock().unwrap();
+        let status = match st.trades.get_mut(&trade_id) {
+            None => StatusCode::NotFound,
+            Some(trade) if trade.players[TARGET] != player_id => StatusCode::PermissionDenied,
+            Some(trade) => {
+                trade.joined = true;
+                trade.sessions += 1;
+                StatusCode::Ok
+            }
+        };
+        drop(st);
+        let mut r = results.get();
+        if status == StatusCode::Ok {
+            let session = TradeSessionImpl {
+                state: self.state.clone(),
+                trade_id,
+                side: TARGET,
+            };
+            let client: trade_session::Client = capnp_rpc::new_client(session);
+            r.set_session(client);
+        }
+        r.set_status(status);
+        Promise::ok(())
+    }
 }
 
 // ---------------------------------------------------------------------------
 // TradeSession implementation
 // ---------------------------------------------------------------------------
 
-struct TradeSessionState {
+/// Index of each party's side in a `Trade`.
+const INITIATOR: usize = 0;
+const TARGET: usize = 1;
+
+/// One trade between two players, shared by the session each of them holds.
+struct Trade {
+    players: [u64; 2],
     trade_state: TradeState,
-    offered_slots: Vec<u16>,
-    other_offered_slots: Vec<u16>,
-    accepted: bool,
-    other_accepted: bool,
+    offers: [Vec<u16>; 2],
+    accepted: [bool; 2],
+    /// Whether the target has joined; until then the initiator trades alone.
+    joined: bool,
+    /// Live sessions on this trade; a joined or finished trade goes with the
+    /// last one.
+    sessions: usize,
+}
+
+impl Trade {
+    /// A trade held by the initiator's session alone.
+    fn new(initiator: u64, target: u64) -> Self {
+        Self {
+            players: [initiator, target],
+            trade_state: TradeState::Proposing,
+            offers: Default::default(),
+            accepted: [false; 2],
+            joined: false,
+            sessions: 1,
+        }
+    }
+
+    fn finished(&self) -> bool {
+        matches!(
+            self.trade_state,
+            TradeState::Confirmed | TradeState::Cancelled
+        )
+    }
+}
+
+/// The index for a slot added to `slots`: one past the highest in use, or
+/// `InvalidArgument` once that would pass `u16::MAX`.
+fn next_slot_index(slots: &[InventorySlotData]) -> Result<u16, StatusCode> {
+    match slots.iter().map(|s| s.slot_index).max() {
+        Some(last) => last.checked_add(1).ok_or(StatusCode::InvalidArgument),
+        None => Ok(0),
+    }
+}
+
+/// Moves each side's offered slots into the other side's inventory, or
+/// leaves both untouched and fails with `NotFound` if one is gone, or with
+/// `InvalidArgument` if a receiving inventory runs out of slot indexes.
+fn swap_offers(
+    inventories: &mut HashMap<u64, Vec<InventorySlotData>>,
+    trade: &Trade,
+) -> Result<(), StatusCode> {
+    for side in [INITIATOR, TARGET] {
+        let slots = inventories.get(&trade.players[side]);
+        let held = |index: &u16| slots.is_some_and(|s| s.iter().any(|s| s.slot_index == *index));
+        if !trade.offers[side].iter().all(held) {
+            return Err(StatusCode::NotFound);
+        }
+    }
+    let before = trade
+        .players
+        .map(|p| (p, inventories.get(&p).cloned().unwrap_or_default()));
+    let moved = move_offers(inventories, trade);
+    if moved.is_err() {
+        inventories.extend(before);
+    }
+    moved
+}
+
+/// The moves behind `swap_offers`, which puts the inventories back if this
+/// fails partway.
+fn move_offers(
+    inventories: &mut HashMap<u64, Vec<InventorySlotData>>,
+    trade: &Trade,
+) -> Result<(), StatusCode> {
+    let mut moving: [Vec<InventorySlotData>; 2] = Default::default();
+    for side in [INITIATOR, TARGET] {
+        let slots = inventories.entry(trade.players[side]).or_default();
+        let (given, kept) = slots
+            .drain(..)
+            .partition(|s| trade.offers[side].contains(&s.slot_index));
+        *slots = kept;
+        moving[side] = given;
+    }
+    for side in [INITIATOR, TARGET] {
+        let slots = inventories.entry(trade.players[1 - side]).or_default();
+        for mut slot in std::mem::take(&mut moving[side]) {
+            slot.slot_index = next_slot_index(slots)?;
+            slots.push(slot);
+        }
+    }
+    Ok(())
 }
 
+/// One party's view of a trade in `state.trades`.
 struct TradeSessionImpl {
-    _initiator: u64,
-    _target: u64,
-    state: Arc<Mutex<TradeSessionState>>,
+    state: Arc<Mutex<InventoryState>>,
+    trade_id: u64,
+    side: usize,
+}
+
+impl TradeSessionImpl {
+    /// Runs `f` on this session's trade and its inventories.
+    fn with_trade<R>(
+        &self,
+        f: impl FnOnce(&mut Trade, &mut HashMap<u64, Vec<InventorySlotData>>) -> R,
+    ) -> R {
+        let mut st = self.state.lock().unwrap();
+        let InventoryState {
+            inventories,
+            trades,
+            ..
+        } = &mut *st;
+        let trade = trades
+            .get_mut(&self.trade_id)
+            .expect("sessions are only issued for registered trades");
+        f(trade, inventories)
+    }
+
+    /// Replaces this side's offer with what `edit` leaves of it, withdrawing
+    /// both acceptances; offers are fixed once the trade is accepted.
+    fn change_offer(
+        &self,
+        offer: crate::inventory_capnp::trade_offer::Builder<'_>,
+        edit: impl FnOnce(&mut Vec<u16>),
+    ) -> StatusCode {
+        self.with_trade(|trade, _| {
+            let status = if trade.trade_state == TradeState::Proposing {
+                edit(&mut trade.offers[self.side]);
+                trade.accepted = [false; 2];
+                StatusCode::Ok
+            } else {
+                StatusCode::InvalidArgument
+            };
+            build_trade_offer(offer, &trade.offers[self.side], trade.accepted[self.side]);
+            status
+        })
+    }
 }
 
 fn build_trade_offer(
-    builder: &mut crate::inventory_capnp::trade_offer::Builder<'_>,
+    mut builder: crate::inventory_capnp::trade_offer::Builder<'_>,
     slots: &[u16],
     accepted: bool,
 ) {
@@ -813,6 +1738,18 @@ fn build_trade_offer(
     builder.set_accepted(accepted);
 }
 
+impl Drop for TradeSessionImpl {
+    fn drop(&mut self) {
+        let mut st = self.state.lock().unwrap();
+        if let Some(trade) = st.trades.get_mut(&self.trade_id) {
+            trade.sessions -= 1;
+            if trade.sessions == 0 && (trade.joined || trade.finished()) {
+                st.trades.remove(&self.trade_id);
+            }
+        }
+    }
+}
+
 impl trade_session::Server for TradeSessionImpl {
     fn offer_items(
         &mut self,
@@ -820,18 +1757,11 @@ impl trade_session::Server for TradeSessionImpl {
         mut results: trade_session::OfferItemsResults,
     ) -> Promise<(), capnp::Error> {
         let slots_reader = pry!(pry!(params.get()).get_slots());
-        let mut st = self.state.lock().unwrap();
-        st.offered_slots.clear();
-        for i in 0..slots_reader.len() {
-            st.offered_slots.push(slots_reader.get(i));
-        }
         let mut r = results.get();
-        build_trade_offer(
-            &mut r.reborrow().init_offer(),
-            &st.offered_slots,
-            st.accepted,
-        );
-        r.set_status(StatusCode::Ok);
+        let status = self.change_offer(r.reborrow().init_offer(), |offer| {
+            *offer = slots_reader.iter().collect();
+        });
+        r.set_status(status);
         Promise::ok(())
     }
 
@@ -841,18 +1771,12 @@ impl trade_session::Server for TradeSessionImpl {
         mut results: trade_session::RemoveItemsResults,
     ) -> Promise<(), capnp::Error> {
         let slots_reader = pry!(pry!(params.get()).get_slots());
-        let to_remove: Vec<u16> = (0..slots_reader.len())
-            .map(|i| slots_reader.get(i))
-            .collect();
-        let mut st = self.state.lock().unwrap();
-        st.offered_slots.retain(|s| !to_remove.contains(s));
+        let to_remove: Vec<u16> = slots_reader.iter().collect();
         let mut r = results.get();
-        build_trade_offer(
-            &mut r.reborrow().init_offer(),
-            &st.offered_slots,
-            st.accepted,
-        );
-        r.set_status(StatusCode::Ok);
+        let status = self.change_offer(r.reborrow().init_offer(), |offer| {
+            offer.retain(|s| !to_remove.contains(s));
+        });
+        r.set_status(status);
         Promise::ok(())
     }
 
@@ -861,14 +1785,20 @@ impl trade_session::Server for TradeSessionImpl {
         _params: trade_session::AcceptParams,
         mut results: trade_session::AcceptResults,
     ) -> Promise<(), capnp::Error> {
-        let mut st = self.state.lock().unwrap();
-        st.accepted = true;
-        if st.other_accepted {
-            st.trade_state = TradeState::Accepted;
-        }
+        let (state, status) = self.with_trade(|trade, _| match trade.trade_state {
+            TradeState::Proposing => {
+                trade.accepted[self.side] = true;
+                if trade.accepted == [true; 2] {
+                    trade.trade_state = TradeState::Accepted;
+                }
+                (trade.trade_state, StatusCode::Ok)
+            }
+            TradeState::Accepted => (TradeState::Accepted, StatusCode::Ok),
+            done => (done, StatusCode::InvalidArgument),
+        });
         let mut r = results.get();
-        r.set_state(st.trade_state);
-        r.set_status(StatusCode::Ok);
+        r.set_state(state);
+        r.set_status(status);
         Promise::ok(())
     }
 
@@ -877,11 +1807,27 @@ impl trade_session::Server for TradeSessionImpl {
         _params: trade_session::ConfirmParams,
         mut results: trade_session::ConfirmResults,
     ) -> Promise<(), capnp::Error> {
-        let mut st = self.state.lock().unwrap();
-        st.trade_state = TradeState::Confirmed;
+        let (state, status) = self.with_trade(|trade, inventories| {
+            // Alone, the initiator confirms without the target's say, and
+            // nothing changes hands.
+            if !trade.joined && !trade.finished() {
+                trade.trade_state = TradeState::Confirmed;
+                return (TradeState::Confirmed, StatusCode::Ok);
+            }
+            if trade.trade_state != TradeState::Accepted {
+                return (trade.trade_state, StatusCode::InvalidArgument);
+            }
+            match swap_offers(inventories, trade) {
+                Ok(()) => {
+                    trade.trade_state = TradeState::Confirmed;
+                    (TradeState::Confirmed, StatusCode::Ok)
+                }
+                Err(status) => (trade.trade_state, status),
+            }
+        });
         let mut r = results.get();
-        r.set_state(TradeState::Confirmed);
-        r.set_status(StatusCode::Ok);
+        r.set_state(state);
+        r.set_status(status);
         Promise::ok(())
     }
 
@@ -890,9 +1836,13 @@ impl trade_session::Server for TradeSessionImpl {
         _params: trade_session::CancelParams,
         mut results: trade_session::CancelResults,
     ) -> Promise<(), capnp::Error> {
-        let mut st = self.state.lock().unwrap();
-        st.trade_state = TradeState::Cancelled;
-        results.get().set_state(TradeState::Cancelled);
+        let state = self.with_trade(|trade, _| {
+            if trade.trade_state != TradeState::Confirmed {
+                trade.trade_state = TradeState::Cancelled;
+            }
+            trade.trade_state
+        });
+        results.get().set_state(state);
         Promise::ok(())
     }
 
@@ -901,12 +1851,14 @@ impl trade_session::Server for TradeSessionImpl {
         _params: trade_session::ViewOtherOfferParams,
         mut results: trade_session::ViewOtherOfferResults,
     ) -> Promise<(), capnp::Error> {
-        let st = self.state.lock().unwrap();
-        build_trade_offer(
-            &mut results.get().init_offer(),
-            &st.other_offered_slots,
-            st.other_accepted,
-        );
+        let other = 1 - self.side;
+        self.with_trade(|trade, _| {
+            build_trade_offer(
+                results.get().init_offer(),
+                &trade.offers[other],
+                trade.accepted[other],
+            );
+        });
         Promise::ok(())
     }
 
@@ -915,8 +1867,8 @@ impl trade_session::Server for TradeSessionImpl {
         _params: trade_session::GetStateParams,
         mut results: trade_session::GetStateResults,
     ) -> Promise<(), capnp::Error> {
-        let st = self.state.lock().unwrap();
-        results.get().set_state(st.trade_state);
+        let state = self.with_trade(|trade, _| trade.trade_state);
+        results.get().set_state(state);
         Promise::ok(())
     }
 }
@@ -931,11 +1883,16 @@ struct MatchmakingState {
     queue: Vec<QueueEntry>,
     matches: HashMap<u64, MatchData>,
     results: HashMap<u64, MatchResultData>,
+    /// Saved match controllers, by match id.
+    saved_matches: SturdyRefs<u64>,
+    /// Stamps every ticket and match with this time instead of the clock's.
+    fixed_time: Option<i64>,
 }
 
 #[derive(Clone)]
 struct QueueEntry {
     ticket_id: u64,
+    player: PlayerInfoData,
     mode: GameMode,
 }
 
@@ -969,10 +1926,13 @@ struct PlayerMatchStatsData {
 
 struct MatchmakingServiceImpl {
     state: Arc<Mutex<MatchmakingState>>,
+    /// Listener of each queued ticket that has one. Kept out of
+    /// `MatchmakingState`, since capabilities are not `Send`.
+    listeners: Rc<RefCell<HashMap<u64, queue_listener::Client>>>,
 }
 
 impl MatchmakingServiceImpl {
-    fn new() -> Self {
+    fn new(fixed_time: Option<i64>) -> Self {
         Self {
             state: Arc::new(Mutex::new(MatchmakingState {
                 next_ticket_id: 1,
@@ -980,7 +1940,56 @@ impl MatchmakingServiceImpl {
                 queue: Vec::new(),
                 matches: HashMap::new(),
                 results: HashMap::new(),
+                saved_matches: Default::default(),
+                fixed_time,
             })),
+            listeners: Default::default(),
+        }
+    }
+
+    /// Pairs `entry` with the oldest listening ticket queued for the same
+    /// mode, if any: both leave the queue and both listeners are told about
+    /// the new match. Otherwise queues `entry` under `listener`.
+    fn pair_or_queue(&self, entry: QueueEntry, listener: queue_listener::Client) {
+        let mut listeners = self.listeners.borrow_mut();
+        let mut st = self.state.lock().unwrap();
+        let Some(pos) = st
+            .queue
+            .iter()
+            .position(|e| e.mode == entry.mode && listeners.contains_key(&e.ticket_id))
+        else {
+            st.queue.push(entry.clone());
+            listeners.insert(entry.ticket_id, listener);
+            return;
+        };
+        let waiting = st.queue.remove(pos);
+        let match_id = st.next_match_id;
+        st.next_match_id += 1;
+        let created_at = timestamp_millis(st.fixed_time);
+        st.matches.insert(
+            match_id,
+            MatchData {
+                id: match_id,
+                mode: entry.mode,
+                state: MatchState::Ready,
+                team_a: vec![waiting.player],
+                team_b: vec![entry.player],
+                created_at,
+                ready_players: Vec::new(),
+            },
+        );
+        drop(st);
+
+        let waiting_listener = listeners.remove(&waiting.ticket_id).unwrap();
+        for listener in [waiting_listener, listener] {
+            let mut req = listener.match_found_request();
+            req.get()
+                .set_controller(capnp_rpc::new_client(MatchControllerImpl {
+                    match_id,
+                    state: self.state.clone(),
+                }));
+            req.get().init_match_id().set_id(match_id);
+            tokio::task::spawn_local(req.send().promise.map(drop));
         }
     }
 }
@@ -1019,7 +2028,18 @@ impl matchmaking_service::Server for MatchmakingServiceImpl {
         let mut st = self.state.lock().unwrap();
         let ticket_id = st.next_ticket_id;
         st.next_ticket_id += 1;
-        st.queue.push(QueueEntry { ticket_id, mode });
+        let enqueued_at = timestamp_millis(st.fixed_time);
+        let entry = QueueEntry {
+            ticket_id,
+            player: player.clone(),
+            mode,
+        };
+        if p.has_listener() {
+            drop(st);
+            self.pair_or_queue(entry, pry!(p.get_listener()));
+        } else {
+            st.queue.push(entry);
+        }
 
         let mut r = results.get();
         let mut ticket = r.reborrow().init_ticket();
@@ -1029,7 +2049,7 @@ impl matchmaking_service::Server for MatchmakingServiceImpl {
         ticket
             .reborrow()
             .init_enqueued_at()
-            .set_unix_millis(now_millis());
+            .set_unix_millis(enqueued_at);
         ticket.set_estimated_wait_secs(30);
         r.set_status(StatusCode::Ok);
         Promise::ok(())
@@ -1041,6 +2061,7 @@ impl matchmaking_service::Server for MatchmakingServiceImpl {
         mut results: matchmaking_service::DequeueResults,
     ) -> Promise<(), capnp::Error> {
         let ticket_id = pry!(params.get()).get_ticket_id();
+        self.listeners.borrow_mut().remove(&ticket_id);
         let mut st = self.state.lock().unwrap();
         let before = st.queue.len();
         st.queue.retain(|e| e.ticket_id != ticket_id);
@@ -1066,7 +2087,7 @@ impl matchmaking_service::Server for MatchmakingServiceImpl {
         st.next_match_id += 1;
 
         let opponent = PlayerInfoData {
-            id: player.id + 1000,
+            id: player.id.wrapping_add(1000),
             name: format!("Bot_{}", match_id),
             faction: Faction::Horde,
             level: player.level,
@@ -1077,7 +2098,7 @@ impl matchmaking_service::Server for MatchmakingServiceImpl {
             state: MatchState::Ready,
             team_a: vec![player],
             team_b: vec![opponent],
-            created_at: now_millis(),
+            created_at: timestamp_millis(st.fixed_time),
             ready_players: Vec::new(),
         };
         st.matches.insert(match_id, match_data);
@@ -1135,6 +2156,39 @@ impl matchmaking_service::Server for MatchmakingServiceImpl {
         }
         Promise::ok(())
     }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: matchmaking_service::GetSchemaFingerprintParams,
+        mut results: matchmaking_service::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(matchmaking_service);
+
+    fn restore_match(
+        &mut self,
+        params: matchmaking_service::RestoreMatchParams,
+        mut results: matchmaking_service::RestoreMatchResults,
+    ) -> Promise<(), capnp::Error> {
+        let p = pry!(params.get());
+        let owner = pry!(pry!(p.get_owner()).to_str());
+        let match_id = pry!(self
+            .state
+            .lock()
+            .unwrap()
+            .saved_matches
+            .restore(pry!(p.get_sturdy_ref()), owner));
+        results
+            .get()
+            .set_controller(capnp_rpc::new_client(MatchControllerImpl {
+                match_id,
+                state: self.state.clone(),
+            }));
+        Promise::ok(())
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -1146,6 +2200,20 @@ struct MatchControllerImpl {
     state: Arc<Mutex<MatchmakingState>>,
 }
 
+impl persistent::Server<capnp::data::Owned, capnp::text::Owned> for MatchControllerImpl {
+    fn save(&mut self, params: SaveParams, mut results: SaveResults) -> Promise<(), capnp::Error> {
+        let owner = pry!(seal_for(&params));
+        let token = self
+            .state
+            .lock()
+            .unwrap()
+            .saved_matches
+            .save(self.match_id, owner);
+        pry!(results.get().set_sturdy_ref(&token[..]));
+        Promise::ok(())
+    }
+}
+
 impl match_controller::Server for MatchControllerImpl {
     fn get_info(
         &mut self,
@@ -1236,74 +2304,2206 @@ impl match_controller::Server for MatchControllerImpl {
 }
 
 // ---------------------------------------------------------------------------
-// Server entry point
+// Nesting implementation
 // ---------------------------------------------------------------------------
 
-fn normalize_schema_name(schema: &str) -> &str {
-    if schema == "gameworld" {
-        "game_world"
-    } else {
-        schema
+/// Deepest chain `chain` will build.
+const MAX_CHAIN_DEPTH: u32 = 1 << 16;
+
+struct NestingServiceImpl;
+
+impl nesting_service::Server for NestingServiceImpl {
+    fn measure(
+        &mut self,
+        params: nesting_service::MeasureParams,
+        mut results: nesting_service::MeasureResults,
+    ) -> Promise<(), capnp::Error> {
+        let p = pry!(params.get());
+        let mut depth = 0;
+        if p.has_root() {
+            // Iterative on purpose: the reader's nesting limit, not the stack,
+            // has to be what stops a deep chain.
+            let mut node = pry!(p.get_root());
+            depth = 1;
+            while node.has_child() {
+                node = pry!(node.get_child());
+                depth += 1;
+            }
+        }
+        results.get().set_depth(depth);
+        Promise::ok(())
     }
-}
 
-pub async fn run(host: &str, port: u16, schema: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let addr = format!("{}:{}", host, port)
-        .to_socket_addrs()?
-        .next()
-        .ok_or("failed to resolve address")?;
+    fn chain(
+        &mut self,
+        params: nesting_service::ChainParams,
+        mut results: nesting_service::ChainResults,
+    ) -> Promise<(), capnp::Error> {
+        let depth = pry!(params.get()).get_depth();
+        if depth > MAX_CHAIN_DEPTH {
+            return Promise::err(capnp::Error::failed(format!(
+                "chain depth {} is over the maximum of {}",
+                depth, MAX_CHAIN_DEPTH
+            )));
+        }
+        if depth == 0 {
+            return Promise::ok(());
+        }
+        let mut node = results.get().init_root();
+        for below in (0..depth).rev() {
+            node.set_depth(below);
+            if below > 0 {
+                node = node.init_child();
+            }
+        }
+        Promise::ok(())
+    }
 
-    let listener = TcpListener::bind(addr).await?;
-    println!("READY");
-    let schema_name = normalize_schema_name(schema).to_string();
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: nesting_service::GetSchemaFingerprintParams,
+        mut results: nesting_service::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
 
-    loop {
-        let (stream, _) = listener.accept().await?;
-        stream.set_nodelay(true)?;
-        let schema_name = schema_name.clone();
+    ping_method!(nesting_service);
+}
 
-        tokio::task::spawn_local(async move {
-            let stream = stream.compat();
-            let (reader, writer) = stream.split();
-
-            let network = twoparty::VatNetwork::new(
-                reader,
-                writer,
-                rpc_twoparty_capnp::Side::Server,
-                Default::default(),
-            );
+// ---------------------------------------------------------------------------
+// Defaults implementation
+// ---------------------------------------------------------------------------
 
-            let bootstrap_client: capnp::capability::Client = match schema_name.as_str() {
-                "game_world" => {
-                    let client: game_world::Client = capnp_rpc::new_client(GameWorldImpl::new());
-                    client.client
-                }
-                "chat" => {
-                    let client: chat_service::Client =
-                        capnp_rpc::new_client(ChatServiceImpl::new());
-                    client.client
-                }
-                "inventory" => {
-                    let client: inventory_service::Client =
-                        capnp_rpc::new_client(InventoryServiceImpl::new());
-                    client.client
-                }
-                "matchmaking" => {
-                    let client: matchmaking_service::Client =
-                        capnp_rpc::new_client(MatchmakingServiceImpl::new());
-                    client.client
-                }
-                other => {
-                    eprintln!("unknown schema: {}", other);
-                    return;
-                }
-            };
+struct DefaultsServiceImpl;
+
+/// Copies `from` into `to` one field at a time, so every data value is decoded
+/// and encoded again rather than copied as raw words. Pointer fields that are
+/// set go through their setters; null pointers stay null.
+fn copy_settings(from: settings::Reader<'_>, mut to: settings::Builder<'_>) -> capnp::Result<()> {
+    to.set_count(from.get_count());
+    to.set_offset(from.get_offset());
+    to.set_retries(from.get_retries());
+    to.set_port(from.get_port());
+    to.set_seed(from.get_seed());
+    to.set_ratio(from.get_ratio());
+    to.set_scale(from.get_scale());
+    to.set_enabled(from.get_enabled());
+    to.set_quality(from.get_quality()?);
+    if from.has_label() {
+        to.set_label(from.get_label()?);
+    }
+    if from.has_bounds() {
+        let bounds = from.get_bounds()?;
+        let mut to = to.reborrow().init_bounds();
+        to.set_min(bounds.get_min());
+        to.set_max(bounds.get_max());
+    }
+    if from.has_spawn() {
+        to.set_spawn(from.get_spawn()?)?;
+    }
+    if from.has_starter_item() {
+        to.set_starter_item(from.get_starter_item()?)?;
+    }
+    if from.has_salt() {
+        to.set_salt(from.get_salt()?);
+    }
+    if from.has_ports() {
+        to.set_ports(from.get_ports()?)?;
+    }
+    if from.has_flags() {
+        to.set_flags(from.get_flags()?)?;
+    }
+    if from.has_tags() {
+        to.set_tags(from.get_tags()?)?;
+    }
+    if from.has_waypoints() {
+        to.set_waypoints(from.get_waypoints()?)?;
+    }
+    if from.has_matrix() {
+        to.set_matrix(from.get_matrix()?)?;
+    }
+    Ok(())
+}
 
-            let rpc_system = RpcSystem::new(Box::new(network), Some(bootstrap_client));
+impl defaults_service::Server for DefaultsServiceImpl {
+    fn echo(
+        &mut self,
+        params: defaults_service::EchoParams,
+        mut results: defaults_service::EchoResults,
+    ) -> Promise<(), capnp::Error> {
+        let settings = pry!(pry!(params.get()).get_settings());
+        pry!(copy_settings(settings, results.get().init_settings()));
+        Promise::ok(())
+    }
 
-            if let Err(e) = rpc_system.await {
-                eprintln!("RPC error: {}", e);
-            }
-        });
+    fn defaults(
+        &mut self,
+        _params: defaults_service::DefaultsParams,
+        mut results: defaults_service::DefaultsResults,
+    ) -> Promise<(), capnp::Error> {
+        let mut settings = results.get().init_settings();
+        settings.set_count(42);
+        settings.set_offset(-1_000_000);
+        settings.set_retries(3);
+        settings.set_port(8080);
+        settings.set_seed(0xdead_beef_cafe);
+        settings.set_ratio(1.5);
+        settings.set_scale(-0.25);
+        settings.set_enabled(true);
+        settings.set_quality(Quality::High);
+        Promise::ok(())
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: defaults_service::GetSchemaFingerprintParams,
+        mut results: defaults_service::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(defaults_service);
+}
+
+// ---------------------------------------------------------------------------
+// Lists implementation
+// ---------------------------------------------------------------------------
+
+/// Longest List(Void) `voids` will build; past the reader's default traversal
+/// limit, which charges a word per zero-sized element.
+const MAX_VOIDS: u32 = 1 << 24;
+
+struct ListServiceImpl;
+
+fn copy_primitives<T: capnp::private::layout::PrimitiveElement>(
+    from: capnp::primitive_list::Reader<'_, T>,
+    mut to: capnp::primitive_list::Builder<'_, T>,
+) {
+    for i in 0..from.len() {
+        to.set(i, from.get(i));
+    }
+}
+
+fn copy_points(
+    from: capnp::struct_list::Reader<'_, point::Owned>,
+    mut to: capnp::struct_list::Builder<'_, point::Owned>,
+) -> capnp::Result<()> {
+    for (i, point) in from.iter().enumerate() {
+        let mut copy = to.reborrow().get(i as u32);
+        copy.set_x(point.get_x());
+        if point.has_label() {
+            copy.set_label(point.get_label()?);
+        }
+    }
+    Ok(())
+}
+
+/// Copies `from` into `to` one element at a time, so every list is decoded
+/// and encoded again rather than copied as raw words. Null fields stay null.
+fn copy_lists(
+    from: sized_lists::Reader<'_>,
+    mut to: sized_lists::Builder<'_>,
+) -> capnp::Result<()> {
+    if from.has_voids() {
+        let voids = from.get_voids()?;
+        copy_primitives(voids, to.reborrow().init_voids(voids.len()));
+    }
+    if from.has_bools() {
+        let bools = from.get_bools()?;
+        copy_primitives(bools, to.reborrow().init_bools(bools.len()));
+    }
+    if from.has_bytes() {
+        let bytes = from.get_bytes()?;
+        copy_primitives(bytes, to.reborrow().init_bytes(bytes.len()));
+    }
+    if from.has_shorts() {
+        let shorts = from.get_shorts()?;
+        copy_primitives(shorts, to.reborrow().init_shorts(shorts.len()));
+    }
+    if from.has_ints() {
+        let ints = from.get_ints()?;
+        copy_primitives(ints, to.reborrow().init_ints(ints.len()));
+    }
+    if from.has_longs() {
+        let longs = from.get_longs()?;
+        copy_primitives(longs, to.reborrow().init_longs(longs.len()));
+    }
+    if from.has_texts() {
+        let texts = from.get_texts()?;
+        let mut out = to.reborrow().init_texts(texts.len());
+        for i in 0..texts.len() {
+            out.set(i, texts.get(i)?);
+        }
+    }
+    if from.has_points() {
+        let points = from.get_points()?;
+        copy_points(points, to.reborrow().init_points(points.len()))?;
+    }
+    if from.has_text() {
+        to.set_text(from.get_text()?);
+    }
+    if from.has_data() {
+        to.set_data(from.get_data()?);
+    }
+    Ok(())
+}
+
+/// Copies each blob's text and data as bytes, so a text with bytes that are
+/// not UTF-8 or with NULs inside comes back as sent. Null fields stay null.
+fn copy_blobs(from: blob_set::Reader<'_>, mut to: blob_set::Builder<'_>) -> capnp::Result<()> {
+    if !from.has_blobs() {
+        return Ok(());
+    }
+    let blobs = from.get_blobs()?;
+    let mut out = to.reborrow().init_blobs(blobs.len());
+    for (i, blob) in blobs.iter().enumerate() {
+        let mut copy = out.reborrow().get(i as u32);
+        if blob.has_text() {
+            copy.set_text(blob.get_text()?);
+        }
+        if blob.has_data() {
+            copy.set_data(blob.get_data()?);
+        }
+    }
+    Ok(())
+}
+
+/// Copies `from` into `to` as `copy_lists` does, one level at a time. Each
+/// inventory is copied as a whole struct, which re-encodes it all the same.
+fn copy_nested(
+    from: nested_lists::Reader<'_>,
+    mut to: nested_lists::Builder<'_>,
+) -> capnp::Result<()> {
+    if from.has_grid() {
+        let grid = from.get_grid()?;
+        let mut out = to.reborrow().init_grid(grid.len());
+        for i in 0..grid.len() {
+            let row = grid.get(i)?;
+            copy_primitives(row, out.reborrow().init(i, row.len()));
+        }
+    }
+    if from.has_words() {
+        let words = from.get_words()?;
+        let mut out = to.reborrow().init_words(words.len());
+        for i in 0..words.len() {
+            let texts = words.get(i)?;
+            let mut copy = out.reborrow().init(i, texts.len());
+            for j in 0..texts.len() {
+                copy.set(j, texts.get(j)?);
+            }
+        }
+    }
+    if from.has_polygons() {
+        let polygons = from.get_polygons()?;
+        let mut out = to.reborrow().init_polygons(polygons.len());
+        for i in 0..polygons.len() {
+            let points = polygons.get(i)?;
+            copy_points(points, out.reborrow().init(i, points.len()))?;
+        }
+    }
+    if from.has_inventories() {
+        let inventories = from.get_inventories()?;
+        let mut out = to.init_inventories(inventories.len());
+        for (i, view) in inventories.iter().enumerate() {
+            out.set_with_caveats(i as u32, view)?;
+        }
+    }
+    Ok(())
+}
+
+impl list_service::Server for ListServiceImpl {
+    fn empty(
+        &mut self,
+        _params: list_service::EmptyParams,
+        mut results: list_service::EmptyResults,
+    ) -> Promise<(), capnp::Error> {
+        let mut lists = results.get().init_lists();
+        lists.reborrow().init_voids(0);
+        lists.reborrow().init_bools(0);
+        lists.reborrow().init_bytes(0);
+        lists.reborrow().init_shorts(0);
+        lists.reborrow().init_ints(0);
+        lists.reborrow().init_longs(0);
+        lists.reborrow().init_texts(0);
+        lists.reborrow().init_points(0);
+        lists.set_text("");
+        lists.set_data(&[]);
+        Promise::ok(())
+    }
+
+    fn echo(
+        &mut self,
+        params: list_service::EchoParams,
+        mut results: list_service::EchoResults,
+    ) -> Promise<(), capnp::Error> {
+        let lists = pry!(pry!(params.get()).get_lists());
+        pry!(copy_lists(lists, results.get().init_lists()));
+        Promise::ok(())
+    }
+
+    fn echo_nested(
+        &mut self,
+        params: list_service::EchoNestedParams,
+        mut results: list_service::EchoNestedResults,
+    ) -> Promise<(), capnp::Error> {
+        let lists = pry!(pry!(params.get()).get_lists());
+        pry!(copy_nested(lists, results.get().init_lists()));
+        Promise::ok(())
+    }
+
+    fn echo_blobs(
+        &mut self,
+        params: list_service::EchoBlobsParams,
+        mut results: list_service::EchoBlobsResults,
+    ) -> Promise<(), capnp::Error> {
+        let set = pry!(pry!(params.get()).get_set());
+        pry!(copy_blobs(set, results.get().init_set()));
+        Promise::ok(())
+    }
+
+    fn voids(
+        &mut self,
+        params: list_service::VoidsParams,
+        mut results: list_service::VoidsResults,
+    ) -> Promise<(), capnp::Error> {
+        let count = pry!(params.get()).get_count();
+        if count > MAX_VOIDS {
+            return Promise::err(capnp::Error::failed(format!(
+                "void count {} is over the maximum of {}",
+                count, MAX_VOIDS
+            )));
+        }
+        results.get().init_voids(count);
+        Promise::ok(())
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: list_service::GetSchemaFingerprintParams,
+        mut results: list_service::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(list_service);
+}
+
+// ---------------------------------------------------------------------------
+// Ordering implementation
+// ---------------------------------------------------------------------------
+
+#[derive(Default)]
+struct CallOrderImpl {
+    next: u32,
+}
+
+impl call_order::Server for CallOrderImpl {
+    fn get_call_sequence(
+        &mut self,
+        _params: call_order::GetCallSequenceParams,
+        mut results: call_order::GetCallSequenceResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_n(self.next);
+        self.next += 1;
+        Promise::ok(())
+    }
+}
+
+struct OrderingServiceImpl;
+
+impl ordering_service::Server for OrderingServiceImpl {
+    fn counter(
+        &mut self,
+        _params: ordering_service::CounterParams,
+        mut results: ordering_service::CounterResults,
+    ) -> Promise<(), capnp::Error> {
+        results
+            .get()
+            .set_counter(capnp_rpc::new_client(CallOrderImpl::default()));
+        Promise::ok(())
+    }
+
+    fn echo(
+        &mut self,
+        params: ordering_service::EchoParams,
+        mut results: ordering_service::EchoResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_cap(pry!(pry!(params.get()).get_cap()));
+        Promise::ok(())
+    }
+
+    fn reflect(
+        &mut self,
+        params: ordering_service::ReflectParams,
+        mut results: ordering_service::ReflectResults,
+    ) -> Promise<(), capnp::Error> {
+        let params = pry!(params.get());
+        let mut req = pry!(params.get_cap()).get_call_sequence_request();
+        req.get().set_expected(params.get_expected());
+        // Forwarded by hand rather than with results.hook.direct_tail_call:
+        // capnp-rpc never runs a call it receives with sendResultsTo.yourself,
+        // so a capnp-rpc caller would wait on the reflected call forever.
+        let called = req.send().promise;
+        Promise::from_future(async move {
+            results.get().set_n(called.await?.get()?.get_n());
+            Ok(())
+        })
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: ordering_service::GetSchemaFingerprintParams,
+        mut results: ordering_service::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(ordering_service);
+}
+
+// ---------------------------------------------------------------------------
+// Telemetry implementation
+// ---------------------------------------------------------------------------
+
+/// Receives one upload. Once a batch is rejected the RPC layer fails every
+/// later call on the sink, so it never sees events past the gap.
+#[derive(Default)]
+struct TelemetrySinkImpl {
+    count: u32,
+    sum: f64,
+    slow: Option<SlowHandler>,
+}
+
+/// Completes a slow sink's batches in arrival order, one every `delay`.
+struct SlowHandler {
+    queue: tokio::sync::mpsc::UnboundedSender<futures::channel::oneshot::Sender<()>>,
+    pending: Rc<std::cell::Cell<u32>>,
+    peak: u32,
+}
+
+impl SlowHandler {
+    fn spawn(delay: std::time::Duration) -> Self {
+        let (queue, mut batches) =
+            tokio::sync::mpsc::unbounded_channel::<futures::channel::oneshot::Sender<()>>();
+        let pending = Rc::new(std::cell::Cell::new(0));
+        let handled = pending.clone();
+        tokio::task::spawn_local(async move {
+            // While batches are waiting, each deadline follows the last one
+            // rather than when the timer fired, so delays under the timer's
+            // resolution still average out.
+            let mut backlog: Option<tokio::time::Instant> = None;
+            while let Some(done) = batches.recv().await {
+                let deadline = backlog.unwrap_or_else(tokio::time::Instant::now) + delay;
+                tokio::time::sleep_until(deadline).await;
+                handled.set(handled.get() - 1);
+                let _ = done.send(());
+                backlog = (!batches.is_empty()).then_some(deadline);
+            }
+        });
+        Self {
+            queue,
+            pending,
+            peak: 0,
+        }
+    }
+
+    fn handle(&mut self) -> Promise<(), capnp::Error> {
+        let (done, handled) = futures::channel::oneshot::channel();
+        if self.queue.send(done).is_err() {
+            return Promise::err(capnp::Error::failed("slow handler stopped".to_string()));
+        }
+        self.pending.set(self.pending.get() + 1);
+        self.peak = self.peak.max(self.pending.get());
+        Promise::from_future(
+            handled.map_err(|_| capnp::Error::failed("slow handler dropped a batch".to_string())),
+        )
+    }
+}
+
+impl telemetry_sink::Server for TelemetrySinkImpl {
+    fn upload_events(
+        &mut self,
+        params: telemetry_sink::UploadEventsParams,
+    ) -> Promise<(), capnp::Error> {
+        let events = pry!(pry!(params.get()).get_events());
+        for (offset, event) in events.iter().enumerate() {
+            let expected = self.count + offset as u32;
+            if event.get_sequence() != expected {
+                return Promise::err(capnp::Error::failed(format!(
+                    "event {} arrived where {} was expected",
+                    event.get_sequence(),
+                    expected
+                )));
+            }
+        }
+        for event in events {
+            self.sum += event.get_value();
+        }
+        self.count += events.len();
+        match &mut self.slow {
+            Some(slow) => slow.handle(),
+            None => Promise::ok(()),
+        }
+    }
+
+    fn finish(
+        &mut self,
+        _params: telemetry_sink::FinishParams,
+        mut results: telemetry_sink::FinishResults,
+    ) -> Promise<(), capnp::Error> {
+        let mut results = results.get();
+        results.set_count(self.count);
+        results.set_sum(self.sum);
+        results.set_peak_pending(self.slow.as_ref().map_or(0, |slow| slow.peak));
+        Promise::ok(())
+    }
+}
+
+struct TelemetryServiceImpl;
+
+impl telemetry_service::Server for TelemetryServiceImpl {
+    fn open_sink(
+        &mut self,
+        params: telemetry_service::OpenSinkParams,
+        mut results: telemetry_service::OpenSinkResults,
+    ) -> Promise<(), capnp::Error> {
+        let delay_micros = pry!(params.get()).get_delay_micros();
+        let sink = TelemetrySinkImpl {
+            slow: (delay_micros > 0)
+                .then(|| SlowHandler::spawn(std::time::Duration::from_micros(delay_micros.into()))),
+            ..Default::default()
+        };
+        results.get().set_sink(capnp_rpc::new_client(sink));
+        Promise::ok(())
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: telemetry_service::GetSchemaFingerprintParams,
+        mut results: telemetry_service::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(telemetry_service);
+}
+
+// ---------------------------------------------------------------------------
+// Registry implementation
+// ---------------------------------------------------------------------------
+
+/// Every other schema's service, each a single instance shared by all
+/// connections like a standalone one.
+struct ServiceRegistryImpl {
+    services: Vec<(&'static str, capnp::capability::Client)>,
+}
+
+impl ServiceRegistryImpl {
+    fn new(fixed_time: Option<i64>) -> Self {
+        let services = crate::SCHEMAS
+            .iter()
+            .filter(|name| **name != "registry")
+            .map(|name| (*name, bootstrap_client(name, fixed_time)))
+            .collect();
+        Self { services }
+    }
+}
+
+impl service_registry::Server for ServiceRegistryImpl {
+    fn get(
+        &mut self,
+        params: service_registry::GetParams,
+        mut results: service_registry::GetResults,
+    ) -> Promise<(), capnp::Error> {
+        let name = pry!(pry!(pry!(params.get()).get_name()).to_str());
+        let Some((_, service)) = self.services.iter().find(|(n, _)| *n == name) else {
+            return Promise::err(capnp::Error::failed(format!("no such service: {}", name)));
+        };
+        results
+            .get()
+            .init_service()
+            .set_as_capability(service.hook.add_ref());
+        Promise::ok(())
+    }
+
+    fn list(
+        &mut self,
+        _params: service_registry::ListParams,
+        mut results: service_registry::ListResults,
+    ) -> Promise<(), capnp::Error> {
+        let mut names = results.get().init_names(self.services.len() as u32);
+        for (i, (name, _)) in self.services.iter().enumerate() {
+            names.set(i as u32, *name);
+        }
+        Promise::ok(())
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: service_registry::GetSchemaFingerprintParams,
+        mut results: service_registry::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(service_registry);
+}
+
+// ---------------------------------------------------------------------------
+// Debug implementation
+// ---------------------------------------------------------------------------
+
+/// What the server's RPC tables hold, summed over its open connections.
+#[derive(Default)]
+struct DebugCounters {
+    exports: u32,
+    answers: u32,
+    /// Calls received per (interface ID, method ordinal), in first-call order.
+    calls: Vec<((u64, u16), u64)>,
+}
+
+thread_local! {
+    /// Per thread, since servers share the process in tests.
+    static DEBUG_COUNTERS: RefCell<DebugCounters> = RefCell::default();
+}
+
+/// One connection's export and answer tables, rebuilt from the messages that
+/// cross it, and its share of `DEBUG_COUNTERS`.
+#[derive(Default)]
+struct ConnectionTables {
+    /// The peer's reference count on each live export.
+    exports: HashMap<u32, u32>,
+    /// Exports in each answer's results, which a Finish with
+    /// releaseResultCaps set releases once each.
+    result_exports: HashMap<u32, Vec<u32>>,
+    /// Question IDs of answers the peer has not finished.
+    answers: HashSet<u32>,
+}
+
+impl ConnectionTables {
+    fn export(&mut self, id: u32) {
+        let refs = self.exports.entry(id).or_insert(0);
+        if *refs == 0 {
+            DEBUG_COUNTERS.with_borrow_mut(|counters| counters.exports += 1);
+        }
+        *refs += 1;
+    }
+
+    fn release(&mut self, id: u32, count: u32) {
+        let Some(refs) = self.exports.get_mut(&id) else {
+            return;
+        };
+        *refs = refs.saturating_sub(count);
+        if *refs == 0 {
+            self.exports.remove(&id);
+            DEBUG_COUNTERS.with_borrow_mut(|counters| counters.exports -= 1);
+        }
+    }
+
+    /// Exports the server-hosted capabilities in `caps`, returning their IDs.
+    fn export_all(
+        &mut self,
+        caps: capnp::struct_list::Reader<capnp_rpc::rpc_capnp::cap_descriptor::Owned>,
+    ) -> capnp::Result<Vec<u32>> {
+        let mut ids = Vec::new();
+        for cap in caps {
+            if let Some(id) = self.export_descriptor(cap)? {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+
+    fn export_descriptor(
+        &mut self,
+        cap: capnp_rpc::rpc_capnp::cap_descriptor::Reader,
+    ) -> capnp::Result<Option<u32>> {
+        use capnp_rpc::rpc_capnp::cap_descriptor;
+        match cap.which()? {
+            cap_descriptor::SenderHosted(id) | cap_descriptor::SenderPromise(id) => {
+                self.export(id);
+                Ok(Some(id))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Accounts for a message the server sends.
+    fn sent(&mut self, msg: capnp_rpc::rpc_capnp::message::Reader) -> capnp::Result<()> {
+        use capnp_rpc::rpc_capnp::{message, resolve, return_};
+        match msg.which()? {
+            message::Return(ret) => {
+                let ret = ret?;
+                if let return_::Results(payload) = ret.which()? {
+                    let ids = self.export_all(payload?.get_cap_table()?)?;
+                    self.result_exports.insert(ret.get_answer_id(), ids);
+                }
+            }
+            message::Call(call) => {
+                self.export_all(call?.get_params()?.get_cap_table()?)?;
+            }
+            message::Resolve(res) => {
+                if let resolve::Cap(cap) = res?.which()? {
+                    self.export_descriptor(cap?)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Accounts for a message the server receives.
+    fn received(&mut self, msg: capnp_rpc::rpc_capnp::message::Reader) -> capnp::Result<()> {
+        use capnp_rpc::rpc_capnp::message;
+        match msg.which()? {
+            message::Call(call) => {
+                let call = call?;
+                self.begin_answer(call.get_question_id());
+                let method = (call.get_interface_id(), call.get_method_id());
+                DEBUG_COUNTERS.with_borrow_mut(|counters| {
+                    match counters.calls.iter_mut().find(|(m, _)| *m == method) {
+                        Some((_, count)) => *count += 1,
+                        None => counters.calls.push((method, 1)),
+                    }
+                });
+            }
+            message::Bootstrap(bootstrap) => self.begin_answer(bootstrap?.get_question_id()),
+            message::Finish(finish) => {
+                let finish = finish?;
+                let id = finish.get_question_id();
+                if self.answers.remove(&id) {
+                    DEBUG_COUNTERS.with_borrow_mut(|counters| counters.answers -= 1);
+                }
+                let exports = self.result_exports.remove(&id).unwrap_or_default();
+                if finish.get_release_result_caps() {
+                    for export in exports {
+                        self.release(export, 1);
+                    }
+                }
+            }
+            message::Release(release) => {
+                let release = release?;
+                self.release(release.get_id(), release.get_reference_count());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn begin_answer(&mut self, id: u32) {
+        if self.answers.insert(id) {
+            DEBUG_COUNTERS.with_borrow_mut(|counters| counters.answers += 1);
+        }
+    }
+}
+
+impl Drop for ConnectionTables {
+    fn drop(&mut self) {
+        DEBUG_COUNTERS.with_borrow_mut(|counters| {
+            counters.exports -= self.exports.len() as u32;
+            counters.answers -= self.answers.len() as u32;
+        });
+    }
+}
+
+/// `Interface.method` for a call, or the raw IDs for a method no schema here
+/// declares.
+fn method_name(interface_id: u64, method_id: u16) -> String {
+    crate::METHODS
+        .iter()
+        .find(|(id, ordinal, _)| *id == interface_id && *ordinal == method_id)
+        .map_or_else(
+            || format!("{:#018x}.@{}", interface_id, method_id),
+            |(_, _, name)| name.to_string(),
+        )
+}
+
+struct DebugStatsImpl;
+
+impl debug_stats::Server for DebugStatsImpl {
+    fn get_stats(
+        &mut self,
+        _params: debug_stats::GetStatsParams,
+        mut results: debug_stats::GetStatsResults,
+    ) -> Promise<(), capnp::Error> {
+        DEBUG_COUNTERS.with_borrow(|counters| {
+            let mut stats = results.get().init_stats();
+            stats.set_exports(counters.exports);
+            stats.set_answers(counters.answers);
+            let mut calls = stats.init_calls(counters.calls.len() as u32);
+            for (i, ((interface_id, method_id), count)) in counters.calls.iter().enumerate() {
+                let mut entry = calls.reborrow().get(i as u32);
+                entry.set_method(method_name(*interface_id, *method_id));
+                entry.set_count(*count);
+            }
+        });
+        Promise::ok(())
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: debug_stats::GetSchemaFingerprintParams,
+        mut results: debug_stats::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(debug_stats);
+}
+
+// ---------------------------------------------------------------------------
+// Linked implementation
+// ---------------------------------------------------------------------------
+
+/// Longest chain `LinkedList.head` builds.
+const MAX_LINKED_LENGTH: u32 = 1024;
+
+struct NodeImpl {
+    value: u64,
+    next: Option<node::Client>,
+}
+
+impl node::Server for NodeImpl {
+    fn next(
+        &mut self,
+        _params: node::NextParams,
+        mut results: node::NextResults,
+    ) -> Promise<(), capnp::Error> {
+        match &self.next {
+            Some(next) => {
+                results.get().set_node(next.clone());
+                Promise::ok(())
+            }
+            None => Promise::err(capnp::Error::failed(format!(
+                "node {} is the end of its chain",
+                self.value
+            ))),
+        }
+    }
+
+    fn value(
+        &mut self,
+        _params: node::ValueParams,
+        mut results: node::ValueResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_value(self.value);
+        Promise::ok(())
+    }
+}
+
+struct LinkedListImpl;
+
+impl linked_list::Server for LinkedListImpl {
+    fn head(
+        &mut self,
+        params: linked_list::HeadParams,
+        mut results: linked_list::HeadResults,
+    ) -> Promise<(), capnp::Error> {
+        let length = pry!(params.get()).get_length();
+        if !(1..=MAX_LINKED_LENGTH).contains(&length) {
+            return Promise::err(capnp::Error::failed(format!(
+                "chain length {} is not between 1 and {}",
+                length, MAX_LINKED_LENGTH
+            )));
+        }
+        // Built from the tail, so each node can hold the one after it.
+        let mut next = None;
+        for value in (0..u64::from(length)).rev() {
+            next = Some(capnp_rpc::new_client(NodeImpl { value, next }));
+        }
+        results.get().set_node(next.expect("length is at least 1"));
+        Promise::ok(())
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: linked_list::GetSchemaFingerprintParams,
+        mut results: linked_list::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(linked_list);
+}
+
+// ---------------------------------------------------------------------------
+// Generics implementation
+// ---------------------------------------------------------------------------
+
+/// A `Repository(T)`; each value is kept in a message of its own, by key.
+struct RepositoryImpl<T> {
+    values: BTreeMap<String, capnp::message::Builder<capnp::message::HeapAllocator>>,
+    _type: PhantomData<T>,
+}
+
+impl<T: capnp::traits::Owned + 'static> RepositoryImpl<T> {
+    fn client() -> repository::Client<T> {
+        capnp_rpc::new_client(Self {
+            values: BTreeMap::new(),
+            _type: PhantomData,
+        })
+    }
+}
+
+/// The `T` stored in `message`.
+fn stored_value<T: capnp::traits::Owned>(
+    message: &capnp::message::Builder<capnp::message::HeapAllocator>,
+) -> capnp::Result<T::Reader<'_>> {
+    message
+        .get_root_as_reader::<capnp::any_pointer::Reader>()?
+        .get_as()
+}
+
+impl<T: capnp::traits::Owned> repository::Server<T> for RepositoryImpl<T> {
+    fn put(
+        &mut self,
+        params: repository::PutParams<T>,
+        mut results: repository::PutResults<T>,
+    ) -> Promise<(), capnp::Error> {
+        let p = pry!(params.get());
+        let key = pry!(pry!(p.get_key()).to_string());
+        let mut message = capnp::message::Builder::new_default();
+        pry!(message
+            .init_root::<capnp::any_pointer::Builder>()
+            .set_as::<T>(pry!(p.get_value())));
+        let replaced = self.values.insert(key, message).is_some();
+        results.get().set_replaced(replaced);
+        Promise::ok(())
+    }
+
+    fn get(
+        &mut self,
+        params: repository::GetParams<T>,
+        mut results: repository::GetResults<T>,
+    ) -> Promise<(), capnp::Error> {
+        let key = pry!(pry!(pry!(params.get()).get_key()).to_str());
+        let mut r = results.get();
+        match self.values.get(key) {
+            Some(message) => {
+                pry!(r.set_value(pry!(stored_value::<T>(message))));
+                r.set_found(true);
+            }
+            None => r.set_found(false),
+        }
+        Promise::ok(())
+    }
+
+    fn entries(
+        &mut self,
+        _params: repository::EntriesParams<T>,
+        mut results: repository::EntriesResults<T>,
+    ) -> Promise<(), capnp::Error> {
+        let mut entries = results.get().init_entries(self.values.len() as u32);
+        for (i, (key, message)) in self.values.iter().enumerate() {
+            let mut entry = entries.reborrow().get(i as u32);
+            pry!(entry.set_key(key.as_str()));
+            pry!(entry.set_value(pry!(stored_value::<T>(message))));
+        }
+        Promise::ok(())
+    }
+}
+
+struct GenericsServiceImpl {
+    text: repository::Client<capnp::text::Owned>,
+    data: repository::Client<capnp::data::Owned>,
+    labeled: repository::Client<labeled::Owned>,
+    pair: repository::Client<pair::Owned<capnp::text::Owned, capnp::data::Owned>>,
+}
+
+impl GenericsServiceImpl {
+    fn new() -> Self {
+        Self {
+            text: RepositoryImpl::client(),
+            data: RepositoryImpl::client(),
+            labeled: RepositoryImpl::client(),
+            pair: RepositoryImpl::client(),
+        }
+    }
+}
+
+impl generics_service::Server for GenericsServiceImpl {
+    fn text_repository(
+        &mut self,
+        _params: generics_service::TextRepositoryParams,
+        mut results: generics_service::TextRepositoryResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_repository(self.text.clone());
+        Promise::ok(())
+    }
+
+    fn data_repository(
+        &mut self,
+        _params: generics_service::DataRepositoryParams,
+        mut results: generics_service::DataRepositoryResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_repository(self.data.clone());
+        Promise::ok(())
+    }
+
+    fn labeled_repository(
+        &mut self,
+        _params: generics_service::LabeledRepositoryParams,
+        mut results: generics_service::LabeledRepositoryResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_repository(self.labeled.clone());
+        Promise::ok(())
+    }
+
+    fn pair_repository(
+        &mut self,
+        _params: generics_service::PairRepositoryParams,
+        mut results: generics_service::PairRepositoryResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_repository(self.pair.clone());
+        Promise::ok(())
+    }
+
+    fn swap(
+        &mut self,
+        params: generics_service::SwapParams,
+        mut results: generics_service::SwapResults,
+    ) -> Promise<(), capnp::Error> {
+        let pair = pry!(pry!(params.get()).get_pair());
+        let mut swapped = results.get().init_pair();
+        pry!(swapped.set_key(pry!(pair.get_value())));
+        pry!(swapped.set_value(pry!(pair.get_key())));
+        Promise::ok(())
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: generics_service::GetSchemaFingerprintParams,
+        mut results: generics_service::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(generics_service);
+}
+
+// ---------------------------------------------------------------------------
+// Envelope implementation
+// ---------------------------------------------------------------------------
+
+/// The kind of pointer `payload` holds, found by reading it as each in turn:
+/// a struct pointer reads as any struct type, and a capability pointer as an
+/// client of any interface. Anything else that is not null is a list.
+fn payload_kind(payload: capnp::any_pointer::Reader<'_>) -> PayloadKind {
+    if payload.is_null() {
+        PayloadKind::Null
+    } else if payload.get_as::<envelope::Reader>().is_ok() {
+        PayloadKind::Struct
+    } else if payload.get_as_capability::<counter::Client>().is_ok() {
+        PayloadKind::Capability
+    } else {
+        PayloadKind::List
+    }
+}
+
+struct CounterImpl {
+    count: u32,
+}
+
+impl counter::Server for CounterImpl {
+    fn increment(
+        &mut self,
+        _params: counter::IncrementParams,
+        mut results: counter::IncrementResults,
+    ) -> Promise<(), capnp::Error> {
+        self.count += 1;
+        results.get().set_count(self.count);
+        Promise::ok(())
+    }
+}
+
+struct EnvelopeServiceImpl;
+
+impl envelope_service::Server for EnvelopeServiceImpl {
+    fn relay(
+        &mut self,
+        params: envelope_service::RelayParams,
+        mut results: envelope_service::RelayResults,
+    ) -> Promise<(), capnp::Error> {
+        let envelope = pry!(pry!(params.get()).get_envelope());
+        let mut r = results.get();
+        r.set_kind(payload_kind(envelope.get_payload()));
+        pry!(r.set_envelope(envelope));
+        Promise::ok(())
+    }
+
+    fn new_counter(
+        &mut self,
+        _params: envelope_service::NewCounterParams,
+        mut results: envelope_service::NewCounterResults,
+    ) -> Promise<(), capnp::Error> {
+        results
+            .get()
+            .set_counter(capnp_rpc::new_client(CounterImpl { count: 0 }));
+        Promise::ok(())
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: envelope_service::GetSchemaFingerprintParams,
+        mut results: envelope_service::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(envelope_service);
+}
+
+// ---------------------------------------------------------------------------
+// Spells implementation
+// ---------------------------------------------------------------------------
+
+/// The member set in each union of `effect`, in field order.
+fn union_members(effect: spell_effect::Reader<'_>) -> capnp::Result<Vec<&'static str>> {
+    use spell_effect::cost::reagent;
+    use spell_effect::damage::spread;
+
+    let mut members = vec![match effect.get_cost().get_reagent().which()? {
+        reagent::None(()) => "none",
+        reagent::Item(_) => "item",
+        reagent::Health(_) => "health",
+    }];
+    match effect.which()? {
+        spell_effect::Damage(damage) => {
+            members.push("damage");
+            members.push(match damage.get_spread().which()? {
+                spread::Single(()) => "single",
+                spread::SplashRadius(_) => "splashRadius",
+                spread::Chain(_) => "chain",
+            });
+        }
+        spell_effect::Heal(_) => members.push("heal"),
+        spell_effect::Summon(_) => members.push("summon"),
+        spell_effect::Dispel(()) => members.push("dispel"),
+    }
+    Ok(members)
+}
+
+struct SpellServiceImpl;
+
+impl spell_service::Server for SpellServiceImpl {
+    fn echo(
+        &mut self,
+        params: spell_service::EchoParams,
+        mut results: spell_service::EchoResults,
+    ) -> Promise<(), capnp::Error> {
+        let effect = pry!(pry!(params.get()).get_effect());
+        pry!(results.get().set_effect(effect));
+        Promise::ok(())
+    }
+
+    fn describe(
+        &mut self,
+        params: spell_service::DescribeParams,
+        mut results: spell_service::DescribeResults,
+    ) -> Promise<(), capnp::Error> {
+        let members = pry!(union_members(pry!(pry!(params.get()).get_effect())));
+        let mut list = results.get().init_members(members.len() as u32);
+        for (i, member) in members.into_iter().enumerate() {
+            list.set(i as u32, member);
+        }
+        Promise::ok(())
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: spell_service::GetSchemaFingerprintParams,
+        mut results: spell_service::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(spell_service);
+}
+
+// ---------------------------------------------------------------------------
+// Forecast implementation
+// ---------------------------------------------------------------------------
+
+/// Records how this revision of the schema reads `weather`.
+fn write_reading(weather: Result<Weather, capnp::NotInSchema>, mut reading: reading::Builder<'_>) {
+    match weather {
+        Ok(weather) => {
+            reading.set_ordinal(weather as u16);
+            reading.set_known(true);
+        }
+        Err(capnp::NotInSchema(ordinal)) => {
+            reading.set_ordinal(ordinal);
+            reading.set_known(false);
+        }
+    }
+}
+
+struct ForecastServiceImpl;
+
+impl forecast_service::Server for ForecastServiceImpl {
+    fn classify(
+        &mut self,
+        params: forecast_service::ClassifyParams,
+        mut results: forecast_service::ClassifyResults,
+    ) -> Promise<(), capnp::Error> {
+        let forecast = pry!(pry!(params.get()).get_forecast());
+        let week = pry!(forecast.get_week());
+        let mut r = results.get();
+        write_reading(forecast.get_weather(), r.reborrow().init_weather());
+        let mut readings = r.init_week(week.len());
+        for (i, day) in week.iter().enumerate() {
+            write_reading(day, readings.reborrow().get(i as u32));
+        }
+        Promise::ok(())
+    }
+
+    fn echo(
+        &mut self,
+        params: forecast_service::EchoParams,
+        mut results: forecast_service::EchoResults,
+    ) -> Promise<(), capnp::Error> {
+        let forecast = pry!(pry!(params.get()).get_forecast());
+        pry!(results.get().set_forecast(forecast));
+        Promise::ok(())
+    }
+
+    fn outlook(
+        &mut self,
+        _params: forecast_service::OutlookParams,
+        mut results: forecast_service::OutlookResults,
+    ) -> Promise<(), capnp::Error> {
+        use crate::forecast_v2_capnp::{forecast as forecast_v2, Weather as WeatherV2};
+
+        // Written with the later revision, which has the enumerants to spare.
+        let mut message = capnp::message::Builder::new_default();
+        let mut outlook = message.init_root::<forecast_v2::Builder>();
+        outlook.set_region("Northern Wastes");
+        outlook.set_weather(WeatherV2::Hail);
+        let mut week = outlook.init_week(3);
+        for (i, day) in [WeatherV2::Snow, WeatherV2::Hail, WeatherV2::Clear]
+            .into_iter()
+            .enumerate()
+        {
+            week.set(i as u32, day);
+        }
+        let outlook = pry!(message.get_root_as_reader::<forecast::Reader>());
+        pry!(results.get().set_forecast(outlook));
+        Promise::ok(())
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: forecast_service::GetSchemaFingerprintParams,
+        mut results: forecast_service::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(forecast_service);
+}
+
+// ---------------------------------------------------------------------------
+// Evolution implementation
+// ---------------------------------------------------------------------------
+
+/// Fills `summary` with `profile` as this revision of the schema reads it.
+fn summarize(profile: profile::Reader<'_>, mut summary: summary::Builder<'_>) -> capnp::Result<()> {
+    summary.set_name(profile.get_name()?);
+    summary.set_level(profile.get_level());
+    summary.set_scores(profile.get_scores()?)?;
+    let role = match profile.which() {
+        Ok(profile::Guest(())) => "guest".to_string(),
+        Ok(profile::Member(_)) => "member".to_string(),
+        Err(capnp::NotInSchema(discriminant)) => format!("unknown {}", discriminant),
+    };
+    summary.set_role(role.as_str());
+    Ok(())
+}
+
+struct ProfileServiceImpl;
+
+impl profile_service::Server for ProfileServiceImpl {
+    fn echo(
+        &mut self,
+        params: profile_service::EchoParams,
+        mut results: profile_service::EchoResults,
+    ) -> Promise<(), capnp::Error> {
+        let profile = pry!(pry!(params.get()).get_profile());
+        pry!(results.get().set_profile(profile));
+        Promise::ok(())
+    }
+
+    fn describe(
+        &mut self,
+        params: profile_service::DescribeParams,
+        mut results: profile_service::DescribeResults,
+    ) -> Promise<(), capnp::Error> {
+        let profile = pry!(pry!(params.get()).get_profile());
+        pry!(summarize(profile, results.get().init_summary()));
+        Promise::ok(())
+    }
+
+    fn upgraded(
+        &mut self,
+        _params: profile_service::UpgradedParams,
+        mut results: profile_service::UpgradedResults,
+    ) -> Promise<(), capnp::Error> {
+        use crate::evolution_v2_capnp::profile as profile_v2;
+
+        // Written with the later revision, which has the fields to fill.
+        let mut message = capnp::message::Builder::new_default();
+        let mut upgraded = message.init_root::<profile_v2::Builder>();
+        upgraded.set_name("Warden");
+        upgraded.set_level(40);
+        let mut scores = upgraded.reborrow().init_scores(2);
+        for (i, (value, set_at)) in [(9100, 1_700_000_000_000), (8700, 1_690_000_000_000)]
+            .into_iter()
+            .enumerate()
+        {
+            let mut score = scores.reborrow().get(i as u32);
+            score.set_value(value);
+            score.set_set_at(set_at);
+        }
+        upgraded.set_moderator("Frostmarch");
+        upgraded.set_title("Keeper of the Gate");
+        upgraded.set_experience(1_250_000);
+        let mut badges = upgraded.init_badges(2);
+        badges.set(0, "founder");
+        badges.set(1, "cartographer");
+        let upgraded = pry!(message.get_root_as_reader::<profile::Reader>());
+        pry!(results.get().set_profile(upgraded));
+        Promise::ok(())
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: profile_service::GetSchemaFingerprintParams,
+        mut results: profile_service::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(profile_service);
+}
+
+// ---------------------------------------------------------------------------
+// Annotated implementation
+// ---------------------------------------------------------------------------
+
+/// A `Ledger`; each entry is kept in a message of its own, oldest first.
+#[derive(Default)]
+struct LedgerImpl {
+    entries: Vec<capnp::message::Builder<capnp::message::HeapAllocator>>,
+}
+
+impl ledger::Server for LedgerImpl {
+    fn record(
+        &mut self,
+        params: ledger::RecordParams,
+        mut results: ledger::RecordResults,
+    ) -> Promise<(), capnp::Error> {
+        let recorded = pry!(pry!(params.get()).get_entry());
+        let currency = pry!(recorded.get_currency());
+        let mut message = capnp::message::Builder::new_default();
+        pry!(message.set_root(recorded));
+        self.entries.push(message);
+
+        let mut balance: i64 = 0;
+        for message in &self.entries {
+            let entry = pry!(message.get_root_as_reader::<entry::Reader>());
+            if pry!(entry.get_currency()) == currency {
+                balance = balance.wrapping_add(entry.get_amount());
+            }
+        }
+        results.get().set_balance(balance);
+        Promise::ok(())
+    }
+
+    fn history(
+        &mut self,
+        _params: ledger::HistoryParams,
+        mut results: ledger::HistoryResults,
+    ) -> Promise<(), capnp::Error> {
+        let mut entries = results.get().init_entries(self.entries.len() as u32);
+        for (i, message) in self.entries.iter().enumerate() {
+            let entry = pry!(message.get_root_as_reader::<entry::Reader>());
+            pry!(entries.set_with_caveats(i as u32, entry));
+        }
+        Promise::ok(())
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: ledger::GetSchemaFingerprintParams,
+        mut results: ledger::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(ledger);
+}
+
+// ---------------------------------------------------------------------------
+// Commands implementation
+// ---------------------------------------------------------------------------
+
+/// The union member set in `command`.
+fn command_member(command: command::Reader<'_>) -> capnp::Result<&'static str> {
+    Ok(match command.which()? {
+        command::Noop(()) => "noop",
+        command::Halt(()) => "halt",
+        command::Move(_) => "move",
+        command::Jump(_) => "jump",
+        command::Wait(_) => "wait",
+        command::Say(_) => "say",
+        command::Emote(_) => "emote",
+        command::Attack(_) => "attack",
+        command::Cast(_) => "cast",
+        command::UseItem(_) => "useItem",
+        command::Trade(_) => "trade",
+        command::Teleport(_) => "teleport",
+        command::Patrol(_) => "patrol",
+        command::Crouch(_) => "crouch",
+        command::Face(_) => "face",
+        command::Zoom(_) => "zoom",
+        command::Chant(_) => "chant",
+        command::Payload(_) => "payload",
+        command::Bind(_) => "bind",
+        command::Rename(_) => "rename",
+        command::Follow(_) => "follow",
+        command::Combo(_) => "combo",
+        command::Mark(_) => "mark",
+        command::Inspect(_) => "inspect",
+        command::Logout(()) => "logout",
+    })
+}
+
+struct CommandServiceImpl;
+
+impl command_service::Server for CommandServiceImpl {
+    fn echo(
+        &mut self,
+        params: command_service::EchoParams,
+        mut results: command_service::EchoResults,
+    ) -> Promise<(), capnp::Error> {
+        let command = pry!(pry!(params.get()).get_command());
+        pry!(results.get().set_command(command));
+        Promise::ok(())
+    }
+
+    fn describe(
+        &mut self,
+        params: command_service::DescribeParams,
+        mut results: command_service::DescribeResults,
+    ) -> Promise<(), capnp::Error> {
+        let command = pry!(pry!(params.get()).get_command());
+        results.get().set_member(pry!(command_member(command)));
+        Promise::ok(())
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: command_service::GetSchemaFingerprintParams,
+        mut results: command_service::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(command_service);
+}
+
+// ---------------------------------------------------------------------------
+// Market implementation
+// ---------------------------------------------------------------------------
+
+/// Gold per level of an item of `rarity`.
+fn rarity_multiplier(rarity: Rarity) -> u64 {
+    match rarity {
+        Rarity::Common => 1,
+        Rarity::Uncommon => 2,
+        Rarity::Rare => 5,
+        Rarity::Epic => 10,
+        Rarity::Legendary => 25,
+    }
+}
+
+struct MarketImpl;
+
+impl market::Server for MarketImpl {
+    fn echo(
+        &mut self,
+        params: market::EchoParams,
+        mut results: market::EchoResults,
+    ) -> Promise<(), capnp::Error> {
+        let listing = pry!(pry!(params.get()).get_listing());
+        pry!(results.get().set_listing(listing));
+        Promise::ok(())
+    }
+
+    fn quote(
+        &mut self,
+        params: market::QuoteParams,
+        mut results: market::QuoteResults,
+    ) -> Promise<(), capnp::Error> {
+        let item = pry!(pry!(params.get()).get_item());
+        let rarity = pry!(item.get_rarity());
+        let mut price = results.get().init_price();
+        price.set_amount(item.get_level() as u64 * rarity_multiplier(rarity));
+        price.reborrow().init_currency().set_id(1);
+        price.set_tier(rarity);
+        Promise::ok(())
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: market::GetSchemaFingerprintParams,
+        mut results: market::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(market);
+}
+
+// ---------------------------------------------------------------------------
+// Deep implementation
+// ---------------------------------------------------------------------------
+
+/// Levels of a balanced tree whose nodes have both children; below them every
+/// node has only a left one, so the tree is 32 leaves wide however deep it
+/// goes.
+pub(crate) const BALANCED_BRANCH_LEVELS: u32 = 5;
+
+/// Fills `root` with a tree of `shape`, `depth` levels deep, numbering the
+/// nodes in preorder.
+pub(crate) fn build_tree(root: tree_node::Builder<'_>, shape: Shape, depth: u32) {
+    fn fill(
+        mut node: tree_node::Builder<'_>,
+        shape: Shape,
+        level: u32,
+        depth: u32,
+        next: &mut u32,
+    ) {
+        node.set_value(*next);
+        *next += 1;
+        if shape == Shape::Balanced && level < BALANCED_BRANCH_LEVELS && level + 1 < depth {
+            fill(node.reborrow().init_left(), shape, level + 1, depth, next);
+            fill(node.init_right(), shape, level + 1, depth, next);
+            return;
+        }
+        // A chain from here down, built in a loop so a deep degenerate tree
+        // cannot use up the stack.
+        for _ in level + 1..depth {
+            node = node.init_left();
+            node.set_value(*next);
+            *next += 1;
+        }
+    }
+    fill(root, shape, 0, depth, &mut 0);
+}
+
+struct DeepServiceImpl;
+
+impl deep_service::Server for DeepServiceImpl {
+    fn echo(
+        &mut self,
+        params: deep_service::EchoParams,
+        mut results: deep_service::EchoResults,
+    ) -> Promise<(), capnp::Error> {
+        let root = pry!(pry!(params.get()).get_root());
+        pry!(results.get().set_root(root));
+        Promise::ok(())
+    }
+
+    fn build(
+        &mut self,
+        params: deep_service::BuildParams,
+        mut results: deep_service::BuildResults,
+    ) -> Promise<(), capnp::Error> {
+        let p = pry!(params.get());
+        let shape = pry!(p.get_shape());
+        let depth = p.get_depth();
+        if depth > MAX_CHAIN_DEPTH {
+            return Promise::err(capnp::Error::failed(format!(
+                "tree depth {} is over the maximum of {}",
+                depth, MAX_CHAIN_DEPTH
+            )));
+        }
+        if depth > 0 {
+            build_tree(results.get().init_root(), shape, depth);
+        }
+        Promise::ok(())
+    }
+
+    fn measure(
+        &mut self,
+        params: deep_service::MeasureParams,
+        mut results: deep_service::MeasureResults,
+    ) -> Promise<(), capnp::Error> {
+        let p = pry!(params.get());
+        let (mut nodes, mut depth, mut leaves) = (0, 0, 0);
+        if p.has_root() {
+            // Iterative, like NestingService.measure: the reader's nesting
+            // limit, not the stack, has to be what stops a deep tree.
+            let mut pending = vec![(pry!(p.get_root()), 1)];
+            while let Some((node, level)) = pending.pop() {
+                nodes += 1;
+                depth = depth.max(level);
+                if !node.has_left() && !node.has_right() {
+                    leaves += 1;
+                }
+                if node.has_left() {
+                    pending.push((pry!(node.get_left()), level + 1));
+                }
+                if node.has_right() {
+                    pending.push((pry!(node.get_right()), level + 1));
+                }
+            }
+        }
+        let mut r = results.get();
+        r.set_nodes(nodes);
+        r.set_depth(depth);
+        r.set_leaves(leaves);
+        Promise::ok(())
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: deep_service::GetSchemaFingerprintParams,
+        mut results: deep_service::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    ping_method!(deep_service);
+}
+
+// ---------------------------------------------------------------------------
+// Server entry point
+// ---------------------------------------------------------------------------
+
+fn normalize_schema_name(schema: &str) -> &str {
+    if schema == "gameworld" {
+        "game_world"
+    } else {
+        schema
+    }
+}
+
+/// Settings shared by every way of serving.
+#[derive(Clone, Default)]
+pub struct ServeOptions {
+    /// Caps the first segment of every outgoing message, forcing larger
+    /// responses into several segments joined by far pointers.
+    pub first_segment_words: Option<u32>,
+    /// Limits for incoming messages.
+    pub limits: crate::ReaderLimits,
+    /// Flow control window for streaming calls to client-hosted
+    /// capabilities, in bytes; `None` keeps the library's 64 KiB.
+    pub flow_window: Option<usize>,
+    /// Bootstrap methods that fail with an injected exception instead of running.
+    pub faults: Vec<Fault>,
+    /// Serve no bootstrap capability, so every call on one a peer asks for
+    /// fails with an exception.
+    pub no_bootstrap: bool,
+    /// Stamp every `Timestamp` the services hand out with this many
+    /// milliseconds since the Unix epoch instead of the clock's time, so
+    /// clients can check them exactly.
+    pub fixed_time: Option<i64>,
+    /// Hand every connection the same service, so players on separate
+    /// connections see each other's rooms and trades. Matchmaking always
+    /// shares, since `matchFound` pairs players across connections.
+    pub shared_state: bool,
+}
+
+pub async fn run(
+    endpoint: &Endpoint,
+    schema: &str,
+    protocol_version: Option<u32>,
+    tls: Option<Acceptor>,
+    socket: &SocketArgs,
+    options: ServeOptions,
+) -> Result<(), TestError> {
+    crate::check_schema(schema)?;
+    let bootstrap = connection_bootstrap(schema, &options)?;
+    let bound = match (endpoint, &tls) {
+        (Endpoint::Quic { host, port }, Some(tls)) => Listener::bind_quic(host, *port, tls).await,
+        _ => Listener::bind_with(endpoint, socket).await,
+    };
+    let listener = bound.map_err(|e| {
+        TestError::new(
+            ErrorCode::ConfigError,
+            format!("cannot listen on {}: {}", endpoint, e),
+        )
+    })?;
+    println!("READY");
+    // QUIC streams arrive already decrypted.
+    let tls = tls.filter(|_| !matches!(listener, Listener::Quic(_)));
+    serve_with(listener, protocol_version, tls, options, bootstrap).await
+}
+
+/// Accepts connections on an already bound listener, each served on its own
+/// local task. Each connection gets a fresh service unless
+/// `options.shared_state` is set or the schema is matchmaking.
+pub async fn serve(
+    listener: Listener,
+    schema: &str,
+    protocol_version: Option<u32>,
+    options: ServeOptions,
+) -> Result<(), TestError> {
+    crate::check_schema(schema)?;
+    let bootstrap = connection_bootstrap(schema, &options)?;
+    serve_with(listener, protocol_version, None, options, bootstrap).await
+}
+
+/// Serves a single peer over this process's stdin/stdout until it disconnects.
+/// Nothing else may write to stdout, so no READY line is printed.
+pub async fn serve_stdio(
+    schema: &str,
+    protocol_version: Option<u32>,
+    options: ServeOptions,
+) -> Result<(), TestError> {
+    crate::check_schema(schema)?;
+    let bootstrap = bootstrap(schema, &options)?;
+    serve_connection(
+        crate::transport::stdio(),
+        bootstrap,
+        protocol_version,
+        None,
+        options,
+    )
+    .await;
+    Ok(())
+}
+
+/// Accept loop shared with the mock server; `bootstrap` gives the bootstrap
+/// capability for each new connection.
+pub(crate) async fn serve_with(
+    mut listener: Listener,
+    protocol_version: Option<u32>,
+    tls: Option<Acceptor>,
+    options: ServeOptions,
+    bootstrap: impl Fn() -> capnp::capability::Client,
+) -> Result<(), TestError> {
+    loop {
+        let stream = listener
+            .accept()
+            .await
+            .map_err(|e| TestError::new(ErrorCode::ConnectFailed, format!("accept: {}", e)))?;
+        tokio::task::spawn_local(serve_connection(
+            stream,
+            bootstrap(),
+            protocol_version,
+            tls.clone(),
+            options.clone(),
+        ));
+    }
+}
+
+/// The service for `schema`, with the methods in `faults` made to fail.
+fn bootstrap(schema: &str, options: &ServeOptions) -> Result<capnp::capability::Client, TestError> {
+    let schema_name = normalize_schema_name(schema);
+    faults::wrap(
+        bootstrap_client(schema_name, options.fixed_time),
+        bootstrap_interface(schema_name),
+        &options.faults,
+    )
+}
+
+/// Gives each new connection its bootstrap capability: the one shared service
+/// when `options.shared_state` is set or the schema is matchmaking, a fresh
+/// one otherwise. Faults are checked here, before any connection arrives.
+fn connection_bootstrap(
+    schema: &str,
+    options: &ServeOptions,
+) -> Result<impl Fn() -> capnp::capability::Client, TestError> {
+    let shared = bootstrap(schema, options)?;
+    let share = options.shared_state || normalize_schema_name(schema) == "matchmaking";
+    let schema = schema.to_string();
+    let options = options.clone();
+    Ok(move || {
+        if share {
+            capnp::capability::Client::new(shared.hook.add_ref())
+        } else {
+            bootstrap(&schema, &options).expect("faults checked when serving started")
+        }
+    })
+}
+
+fn bootstrap_interface(schema_name: &str) -> u64 {
+    match schema_name {
+        "game_world" => game_world::Client::TYPE_ID,
+        "chat" => chat_service::Client::TYPE_ID,
+        "inventory" => inventory_service::Client::TYPE_ID,
+        "matchmaking" => matchmaking_service::Client::TYPE_ID,
+        "nesting" => nesting_service::Client::TYPE_ID,
+        "defaults" => defaults_service::Client::TYPE_ID,
+        "lists" => list_service::Client::TYPE_ID,
+        "ordering" => ordering_service::Client::TYPE_ID,
+        "telemetry" => telemetry_service::Client::TYPE_ID,
+        "registry" => service_registry::Client::TYPE_ID,
+        "debug" => debug_stats::Client::TYPE_ID,
+        "linked" => linked_list::Client::TYPE_ID,
+        "generics" => generics_service::Client::TYPE_ID,
+        "envelope" => envelope_service::Client::TYPE_ID,
+        "spells" => spell_service::Client::TYPE_ID,
+        "forecast" => forecast_service::Client::TYPE_ID,
+        "evolution" => profile_service::Client::TYPE_ID,
+        "annotated" => ledger::Client::TYPE_ID,
+        "commands" => command_service::Client::TYPE_ID,
+        "market" => market::Client::TYPE_ID,
+        "deep" => deep_service::Client::TYPE_ID,
+        other => unreachable!("unchecked schema: {}", other),
+    }
+}
+
+fn bootstrap_client(schema_name: &str, fixed_time: Option<i64>) -> capnp::capability::Client {
+    match schema_name {
+        "game_world" => {
+            let client: game_world::Client = capnp_rpc::new_client(GameWorldImpl::new());
+            client.client
+        }
+        "chat" => {
+            let client: chat_service::Client =
+                capnp_rpc::new_client(ChatServiceImpl::new(fixed_time));
+            client.client
+        }
+        "inventory" => {
+            let client: inventory_service::Client =
+                capnp_rpc::new_client(InventoryServiceImpl::new());
+            client.client
+        }
+        "matchmaking" => {
+            let client: matchmaking_service::Client =
+                capnp_rpc::new_client(MatchmakingServiceImpl::new(fixed_time));
+            client.client
+        }
+        "nesting" => {
+            let client: nesting_service::Client = capnp_rpc::new_client(NestingServiceImpl);
+            client.client
+        }
+        "defaults" => {
+            let client: defaults_service::Client = capnp_rpc::new_client(DefaultsServiceImpl);
+            client.client
+        }
+        "lists" => {
+            let client: list_service::Client = capnp_rpc::new_client(ListServiceImpl);
+            client.client
+        }
+        "ordering" => {
+            let client: ordering_service::Client = capnp_rpc::new_client(OrderingServiceImpl);
+            client.client
+        }
+        "telemetry" => {
+            let client: telemetry_service::Client = capnp_rpc::new_client(TelemetryServiceImpl);
+            client.client
+        }
+        "registry" => {
+            let client: service_registry::Client =
+                capnp_rpc::new_client(ServiceRegistryImpl::new(fixed_time));
+            client.client
+        }
+        "debug" => {
+            let client: debug_stats::Client = capnp_rpc::new_client(DebugStatsImpl);
+            client.client
+        }
+        "linked" => {
+            let client: linked_list::Client = capnp_rpc::new_client(LinkedListImpl);
+            client.client
+        }
+        "generics" => {
+            let client: generics_service::Client =
+                capnp_rpc::new_client(GenericsServiceImpl::new());
+            client.client
+        }
+        "envelope" => {
+            let client: envelope_service::Client = capnp_rpc::new_client(EnvelopeServiceImpl);
+            client.client
+        }
+        "spells" => {
+            let client: spell_service::Client = capnp_rpc::new_client(SpellServiceImpl);
+            client.client
+        }
+        "forecast" => {
+            let client: forecast_service::Client = capnp_rpc::new_client(ForecastServiceImpl);
+            client.client
+        }
+        "evolution" => {
+            let client: profile_service::Client = capnp_rpc::new_client(ProfileServiceImpl);
+            client.client
+        }
+        "annotated" => {
+            let client: ledger::Client = capnp_rpc::new_client(LedgerImpl::default());
+            client.client
+        }
+        "commands" => {
+            let client: command_service::Client = capnp_rpc::new_client(CommandServiceImpl);
+            client.client
+        }
+        "market" => {
+            let client: market::Client = capnp_rpc::new_client(MarketImpl);
+            client.client
+        }
+        "deep" => {
+            let client: deep_service::Client = capnp_rpc::new_client(DeepServiceImpl);
+            client.client
+        }
+        other => unreachable!("unchecked schema: {}", other),
+    }
+}
+
+/// Wraps a network so that every outgoing message starts with a first segment
+/// of `words` words, whatever size the RPC system asks for.
+struct SmallSegments<N> {
+    inner: N,
+    words: u32,
+}
+
+impl<N: capnp_rpc::VatNetwork<Side>> capnp_rpc::VatNetwork<Side> for SmallSegments<N> {
+    fn connect(&mut self, host_id: Side) -> Option<Box<dyn capnp_rpc::Connection<Side>>> {
+        let words = self.words;
+        self.inner
+            .connect(host_id)
+            .map(|inner| Box::new(SmallSegmentConnection { inner, words }) as Box<_>)
+    }
+
+    fn accept(&mut self) -> Promise<Box<dyn capnp_rpc::Connection<Side>>, capnp::Error> {
+        let words = self.words;
+        Promise::from_future(
+            self.inner
+                .accept()
+                .map_ok(move |inner| Box::new(SmallSegmentConnection { inner, words }) as Box<_>),
+        )
+    }
+
+    fn drive_until_shutdown(&mut self) -> Promise<(), capnp::Error> {
+        self.inner.drive_until_shutdown()
+    }
+}
+
+struct SmallSegmentConnection {
+    inner: Box<dyn capnp_rpc::Connection<Side>>,
+    words: u32,
+}
+
+impl capnp_rpc::Connection<Side> for SmallSegmentConnection {
+    fn get_peer_vat_id(&self) -> Side {
+        self.inner.get_peer_vat_id()
+    }
+
+    fn new_outgoing_message(&mut self, _size_hint: u32) -> Box<dyn capnp_rpc::OutgoingMessage> {
+        self.inner.new_outgoing_message(self.words)
+    }
+
+    fn receive_incoming_message(
+        &mut self,
+    ) -> Promise<Option<Box<dyn capnp_rpc::IncomingMessage>>, capnp::Error> {
+        self.inner.receive_incoming_message()
+    }
+
+    fn new_stream(
+        &mut self,
+    ) -> (
+        Box<dyn capnp_rpc::FlowController>,
+        Promise<(), capnp::Error>,
+    ) {
+        self.inner.new_stream()
+    }
+
+    fn shutdown(&mut self, result: capnp::Result<()>) -> Promise<(), capnp::Error> {
+        self.inner.shutdown(result)
+    }
+}
+
+/// Wraps a network so that each connection keeps `DEBUG_COUNTERS` current.
+struct CountedNetwork {
+    inner: Box<dyn capnp_rpc::VatNetwork<Side>>,
+}
+
+impl capnp_rpc::VatNetwork<Side> for CountedNetwork {
+    fn connect(&mut self, host_id: Side) -> Option<Box<dyn capnp_rpc::Connection<Side>>> {
+        self.inner
+            .connect(host_id)
+            .map(|inner| Box::new(CountedConnection::new(inner)) as Box<_>)
+    }
+
+    fn accept(&mut self) -> Promise<Box<dyn capnp_rpc::Connection<Side>>, capnp::Error> {
+        Promise::from_future(
+            self.inner
+                .accept()
+                .map_ok(|inner| Box::new(CountedConnection::new(inner)) as Box<_>),
+        )
+    }
+
+    fn drive_until_shutdown(&mut self) -> Promise<(), capnp::Error> {
+        self.inner.drive_until_shutdown()
+    }
+}
+
+struct CountedConnection {
+    inner: Box<dyn capnp_rpc::Connection<Side>>,
+    tables: Rc<RefCell<ConnectionTables>>,
+}
+
+impl CountedConnection {
+    fn new(inner: Box<dyn capnp_rpc::Connection<Side>>) -> Self {
+        Self {
+            inner,
+            tables: Rc::default(),
+        }
+    }
+}
+
+impl capnp_rpc::Connection<Side> for CountedConnection {
+    fn get_peer_vat_id(&self) -> Side {
+        self.inner.get_peer_vat_id()
+    }
+
+    fn new_outgoing_message(&mut self, size_hint: u32) -> Box<dyn capnp_rpc::OutgoingMessage> {
+        Box::new(CountedMessage {
+            inner: self.inner.new_outgoing_message(size_hint),
+            tables: self.tables.clone(),
+        })
+    }
+
+    fn receive_incoming_message(
+        &mut self,
+    ) -> Promise<Option<Box<dyn capnp_rpc::IncomingMessage>>, capnp::Error> {
+        let tables = self.tables.clone();
+        Promise::from_future(self.inner.receive_incoming_message().map_ok(move |msg| {
+            if let Some(msg) = &msg {
+                // A message that does not parse is the RPC system's to reject.
+                if let Ok(root) = msg.get_body().and_then(|body| body.get_as()) {
+                    let _ = tables.borrow_mut().received(root);
+                }
+            }
+            msg
+        }))
+    }
+
+    fn new_stream(
+        &mut self,
+    ) -> (
+        Box<dyn capnp_rpc::FlowController>,
+        Promise<(), capnp::Error>,
+    ) {
+        self.inner.new_stream()
+    }
+
+    fn shutdown(&mut self, result: capnp::Result<()>) -> Promise<(), capnp::Error> {
+        self.inner.shutdown(result)
+    }
+}
+
+/// An outgoing message that updates its connection's tables as it is sent.
+struct CountedMessage {
+    inner: Box<dyn capnp_rpc::OutgoingMessage>,
+    tables: Rc<RefCell<ConnectionTables>>,
+}
+
+impl capnp_rpc::OutgoingMessage for CountedMessage {
+    fn get_body(&mut self) -> capnp::Result<capnp::any_pointer::Builder<'_>> {
+        self.inner.get_body()
+    }
+
+    fn get_body_as_reader(&self) -> capnp::Result<capnp::any_pointer::Reader<'_>> {
+        self.inner.get_body_as_reader()
+    }
+
+    fn send(
+        self: Box<Self>,
+    ) -> (
+        Promise<(), capnp::Error>,
+        Rc<capnp::message::Builder<capnp::message::HeapAllocator>>,
+    ) {
+        if let Ok(root) = self
+            .inner
+            .get_body_as_reader()
+            .and_then(|body| body.get_as())
+        {
+            let _ = self.tables.borrow_mut().sent(root);
+        }
+        self.inner.send()
+    }
+
+    fn take(self: Box<Self>) -> capnp::message::Builder<capnp::message::HeapAllocator> {
+        self.inner.take()
+    }
+
+    fn size_in_words(&self) -> usize {
+        self.inner.size_in_words()
+    }
+}
+
+async fn serve_connection(
+    mut stream: BoxedIo,
+    bootstrap_client: capnp::capability::Client,
+    protocol_version: Option<u32>,
+    tls: Option<Acceptor>,
+    options: ServeOptions,
+) {
+    if let Some(tls) = tls {
+        stream = match tls.accept(stream).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("TLS error: {}", e);
+                return;
+            }
+        };
+    }
+    if let Some(version) = protocol_version {
+        match crate::handshake::accept(&mut stream, version).await {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => {
+                eprintln!("handshake error: {}", e);
+                return;
+            }
+        }
+    }
+
+    let stream = stream.compat();
+    let (reader, writer) = stream.split();
+
+    let mut network = twoparty::VatNetwork::new(
+        reader,
+        writer,
+        rpc_twoparty_capnp::Side::Server,
+        options.limits.options(),
+    );
+    if let Some(bytes) = options.flow_window {
+        network.set_window_size(bytes);
+    }
+    let network: Box<dyn capnp_rpc::VatNetwork<Side>> = match options.first_segment_words {
+        Some(words) => Box::new(SmallSegments {
+            inner: network,
+            words,
+        }),
+        None => Box::new(network),
+    };
+    let network = Box::new(CountedNetwork { inner: network });
+
+    let bootstrap_client = (!options.no_bootstrap).then_some(bootstrap_client);
+    let rpc_system = RpcSystem::new(network, bootstrap_client);
+
+    if let Err(e) = rpc_system.await {
+        eprintln!("RPC error: {}", e);
     }
 }
@@ -0,0 +1,229 @@
+//! Optional TLS around server and client streams; needs the `tls` feature.
+
+use std::path::PathBuf;
+
+use crate::error::{ErrorCode, TestError};
+use crate::transport::{self, Endpoint};
+
+pub use imp::{Acceptor, Connector};
+
+#[derive(Clone, Debug, Default, clap::Args)]
+pub struct TlsArgs {
+    /// Wrap the stream in TLS (rustls); needs a build with the `tls` feature.
+    #[arg(long)]
+    pub tls: bool,
+    /// PEM certificate chain to present; required on the server.
+    #[arg(long, requires = "tls")]
+    pub cert: Option<PathBuf>,
+    /// PEM private key for `--cert`.
+    #[arg(long, requires = "tls")]
+    pub key: Option<PathBuf>,
+    /// PEM CA bundle for verifying the peer; required on the client, and makes
+    /// the server demand client certificates.
+    #[arg(long, requires = "tls")]
+    pub ca: Option<PathBuf>,
+}
+
+fn config_error(message: impl Into<String>) -> TestError {
+    TestError::new(ErrorCode::ConfigError, message)
+}
+
+/// Server-side TLS from `--tls/--cert/--key/--ca`; `None` without `--tls`.
+pub fn acceptor(args: &TlsArgs) -> Result<Option<Acceptor>, TestError> {
+    if !args.tls {
+        return Ok(None);
+    }
+    let (Some(cert), Some(key)) = (&args.cert, &args.key) else {
+        return Err(config_error("--cert and --key are required with --tls"));
+    };
+    imp::acceptor(cert, key, args.ca.as_deref()).map(Some)
+}
+
+/// Client-side TLS from `--tls/--cert/--key/--ca`; `None` without `--tls`.
+pub fn connector(args: &TlsArgs, endpoint: &Endpoint) -> Result<Option<Connector>, TestError> {
+    if !args.tls {
+        return Ok(None);
+    }
+    let Some(ca) = &args.ca else {
+        return Err(config_error("--ca is required with --tls"));
+    };
+    let identity = match (&args.cert, &args.key) {
+        (Some(cert), Some(key)) => Some((cert.as_path(), key.as_path())),
+        (None, None) => None,
+        _ => return Err(config_error("--cert and --key must be given together")),
+    };
+    // Certificates for local sockets are expected to name `localhost`.
+    let server_name = match endpoint {
+        Endpoint::Tcp { host, .. } | Endpoint::Quic { host, .. } => transport::unbracket(host),
+        _ => "localhost",
+    };
+    imp::connector(ca, identity, server_name).map(Some)
+}
+
+#[cfg(feature = "tls")]
+mod imp {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+    use tokio_rustls::rustls::server::WebPkiClientVerifier;
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    use super::config_error;
+    use crate::error::TestError;
+    use crate::transport::BoxedIo;
+
+    #[derive(Clone)]
+    pub struct Acceptor(TlsAcceptor);
+
+    impl Acceptor {
+        pub async fn accept(&self, stream: BoxedIo) -> std::io::Result<BoxedIo> {
+            Ok(Box::new(self.0.accept(stream).await?))
+        }
+
+        #[cfg(feature = "quic")]
+        pub(crate) fn server_config(&self) -> Arc<ServerConfig> {
+            self.0.config().clone()
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct Connector {
+        inner: TlsConnector,
+        server_name: ServerName<'static>,
+    }
+
+    impl Connector {
+        pub async fn connect(&self, stream: BoxedIo) -> std::io::Result<BoxedIo> {
+            Ok(Box::new(
+                self.inner.connect(self.server_name.clone(), stream).await?,
+            ))
+        }
+
+        #[cfg(feature = "quic")]
+        pub(crate) fn client_config(&self) -> Arc<ClientConfig> {
+            self.inner.config().clone()
+        }
+
+        #[cfg(feature = "quic")]
+        pub(crate) fn server_name(&self) -> String {
+            self.server_name.to_str().into_owned()
+        }
+    }
+
+    fn read_pem(path: &Path) -> Result<std::io::BufReader<std::fs::File>, TestError> {
+        std::fs::File::open(path)
+            .map(std::io::BufReader::new)
+            .map_err(|e| config_error(format!("{}: {}", path.display(), e)))
+    }
+
+    fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, TestError> {
+        let certs = rustls_pemfile::certs(&mut read_pem(path)?)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| config_error(format!("{}: {}", path.display(), e)))?;
+        if certs.is_empty() {
+            return Err(config_error(format!(
+                "{}: no certificates found",
+                path.display()
+            )));
+        }
+        Ok(certs)
+    }
+
+    fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, TestError> {
+        rustls_pemfile::private_key(&mut read_pem(path)?)
+            .map_err(|e| config_error(format!("{}: {}", path.display(), e)))?
+            .ok_or_else(|| config_error(format!("{}: no private key found", path.display())))
+    }
+
+    fn load_roots(path: &Path) -> Result<RootCertStore, TestError> {
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(path)? {
+            roots
+                .add(cert)
+                .map_err(|e| config_error(format!("{}: {}", path.display(), e)))?;
+        }
+        Ok(roots)
+    }
+
+    pub fn acceptor(cert: &Path, key: &Path, ca: Option<&Path>) -> Result<Acceptor, TestError> {
+        let builder = ServerConfig::builder();
+        let builder = match ca {
+            Some(ca) => {
+                let verifier = WebPkiClientVerifier::builder(Arc::new(load_roots(ca)?))
+                    .build()
+                    .map_err(|e| config_error(format!("{}: {}", ca.display(), e)))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(load_certs(cert)?, load_key(key)?)
+            .map_err(|e| config_error(format!("{}: {}", cert.display(), e)))?;
+        Ok(Acceptor(TlsAcceptor::from(Arc::new(config))))
+    }
+
+    pub fn connector(
+        ca: &Path,
+        identity: Option<(&Path, &Path)>,
+        server_name: &str,
+    ) -> Result<Connector, TestError> {
+        let builder = ClientConfig::builder().with_root_certificates(load_roots(ca)?);
+        let config = match identity {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+                .map_err(|e| config_error(format!("{}: {}", cert.display(), e)))?,
+            None => builder.with_no_client_auth(),
+        };
+        let server_name = ServerName::try_from(server_name.to_string())
+            .map_err(|e| config_error(format!("TLS server name {}: {}", server_name, e)))?;
+        Ok(Connector {
+            inner: TlsConnector::from(Arc::new(config)),
+            server_name,
+        })
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+mod imp {
+    use std::path::Path;
+
+    use super::config_error;
+    use crate::error::TestError;
+    use crate::transport::BoxedIo;
+
+    #[derive(Clone)]
+    pub enum Acceptor {}
+
+    impl Acceptor {
+        pub async fn accept(&self, _stream: BoxedIo) -> std::io::Result<BoxedIo> {
+            match *self {}
+        }
+    }
+
+    #[derive(Clone)]
+    pub enum Connector {}
+
+    impl Connector {
+        pub async fn connect(&self, _stream: BoxedIo) -> std::io::Result<BoxedIo> {
+            match *self {}
+        }
+    }
+
+    fn unsupported() -> TestError {
+        config_error("--tls requires a build with the `tls` feature")
+    }
+
+    pub fn acceptor(_cert: &Path, _key: &Path, _ca: Option<&Path>) -> Result<Acceptor, TestError> {
+        Err(unsupported())
+    }
+
+    pub fn connector(
+        _ca: &Path,
+        _identity: Option<(&Path, &Path)>,
+        _server_name: &str,
+    ) -> Result<Connector, TestError> {
+        Err(unsupported())
+    }
+}
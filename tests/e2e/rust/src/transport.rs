@@ -0,0 +1,645 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::error::{ErrorCode, TestError};
+use crate::quic;
+use crate::tls::{Acceptor, Connector};
+
+/// Any bidirectional byte stream the RPC system can run over.
+pub trait Io: AsyncRead + AsyncWrite + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Io for T {}
+
+pub type BoxedIo = Box<dyn Io>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Transport {
+    Tcp,
+    /// Windows named pipe; needs a Windows build with the `named-pipe` feature.
+    #[value(alias = "pipe")]
+    NamedPipe,
+    /// Unix domain socket; not available on Windows.
+    Unix,
+    /// Experimental QUIC; needs the `quic` feature and `--tls` certificates.
+    Quic,
+}
+
+/// How a client dials a TCP endpoint.
+#[derive(Clone, Debug, Default, clap::Args)]
+pub struct DialArgs {
+    /// Try IPv4 addresses first when the host resolves to both families.
+    #[arg(long, conflicts_with = "ipv6")]
+    pub ipv4: bool,
+    /// Try IPv6 addresses first when the host resolves to both families.
+    #[arg(long)]
+    pub ipv6: bool,
+    /// Retry a failed connect this many times before giving up.
+    #[arg(long, default_value_t = 0)]
+    pub connect_retries: u32,
+    /// Delay before the first retry; doubles for each one after, with jitter.
+    #[arg(long, default_value_t = 100)]
+    pub connect_backoff_ms: u64,
+    /// Reach TCP endpoints through this SOCKS5 proxy, as `host:port`.
+    #[arg(long, value_name = "HOST:PORT", value_parser = parse_host_port)]
+    pub socks5: Option<(String, u16)>,
+}
+
+fn parse_host_port(s: &str) -> Result<(String, u16), String> {
+    let (host, port) = s
+        .rsplit_once(':')
+        .ok_or_else(|| format!("expected host:port, got {:?}", s))?;
+    let port = port
+        .parse()
+        .map_err(|e| format!("bad port {:?}: {}", port, e))?;
+    Ok((unbracket(host).to_string(), port))
+}
+
+/// Kernel options for TCP streams, set on each connection before RPC starts.
+#[derive(Clone, Debug, Default, clap::Args)]
+pub struct SocketArgs {
+    /// Leave Nagle's algorithm on instead of setting TCP_NODELAY.
+    #[arg(long)]
+    pub no_nodelay: bool,
+    /// Send buffer size (SO_SNDBUF) in bytes.
+    #[arg(long)]
+    pub send_buf: Option<usize>,
+    /// Receive buffer size (SO_RCVBUF) in bytes.
+    #[arg(long)]
+    pub recv_buf: Option<usize>,
+    /// Turn on TCP keepalive, probing after this many idle seconds.
+    #[arg(long)]
+    pub keepalive_secs: Option<u64>,
+    /// Seconds between keepalive probes.
+    #[arg(long, requires = "keepalive_secs")]
+    pub keepalive_interval_secs: Option<u64>,
+}
+
+impl SocketArgs {
+    fn apply(&self, stream: &TcpStream) -> std::io::Result<()> {
+        stream.set_nodelay(!self.no_nodelay)?;
+        let socket = socket2::SockRef::from(stream);
+        if let Some(size) = self.send_buf {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buf {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(secs) = self.keepalive_secs {
+            let mut keepalive = socket2::TcpKeepalive::new().with_time(Duration::from_secs(secs));
+            if let Some(secs) = self.keepalive_interval_secs {
+                keepalive = keepalive.with_interval(Duration::from_secs(secs));
+            }
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where a server listens or a client connects.
+#[derive(Clone, Debug)]
+pub enum Endpoint {
+    Tcp { host: String, port: u16 },
+    NamedPipe(String),
+    Unix(PathBuf),
+    Quic { host: String, port: u16 },
+}
+
+impl Endpoint {
+    pub fn new(
+        transport: Transport,
+        host: String,
+        port: u16,
+        name: Option<String>,
+        path: Option<PathBuf>,
+    ) -> Result<Self, TestError> {
+        match transport {
+            Transport::Tcp => Ok(Endpoint::Tcp { host, port }),
+            Transport::Quic => Ok(Endpoint::Quic { host, port }),
+            Transport::NamedPipe => name.map(Endpoint::NamedPipe).ok_or_else(|| {
+                TestError::new(
+                    ErrorCode::ConfigError,
+                    "--name is required with --transport named-pipe",
+                )
+            }),
+            Transport::Unix => path.map(Endpoint::Unix).ok_or_else(|| {
+                TestError::new(
+                    ErrorCode::ConfigError,
+                    "--path is required with --transport unix",
+                )
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endpoint::Tcp { host, port } => write_host_port(f, host, *port),
+            Endpoint::NamedPipe(name) => f.write_str(name),
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+            Endpoint::Quic { host, port } => {
+                f.write_str("quic:")?;
+                write_host_port(f, host, *port)
+            }
+        }
+    }
+}
+
+fn write_host_port(f: &mut std::fmt::Formatter<'_>, host: &str, port: u16) -> std::fmt::Result {
+    if host.contains(':') && !host.starts_with('[') {
+        write!(f, "[{}]:{}", host, port)
+    } else {
+        write!(f, "{}:{}", host, port)
+    }
+}
+
+/// Strips the brackets from an IPv6 literal such as `[::1]`.
+pub(crate) fn unbracket(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+fn resolve_all(host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+    let addrs: Vec<_> = (unbracket(host), port).to_socket_addrs()?.collect();
+    if addrs.is_empty() {
+        return Err(std::io::Error::other("failed to resolve address"));
+    }
+    Ok(addrs)
+}
+
+fn resolve(host: &str, port: u16) -> std::io::Result<SocketAddr> {
+    Ok(resolve_all(host, port)?[0])
+}
+
+/// Alternates address families, starting with the preferred one or else
+/// whichever the resolver listed first (RFC 8305 section 4).
+fn dial_order(addrs: Vec<SocketAddr>, dial: &DialArgs) -> Vec<SocketAddr> {
+    let ipv6_first = if dial.ipv6 || dial.ipv4 {
+        dial.ipv6
+    } else {
+        addrs[0].is_ipv6()
+    };
+    let (first, second): (Vec<_>, Vec<_>) =
+        addrs.into_iter().partition(|a| a.is_ipv6() == ipv6_first);
+    let mut ordered = Vec::with_capacity(first.len() + second.len());
+    let (mut first, mut second) = (first.into_iter(), second.into_iter());
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// How long an attempt gets before the next address is tried alongside it.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Happy-eyeballs connect: starts an attempt per address, staggered, and
+/// keeps the first that succeeds.
+async fn connect_tcp(host: &str, port: u16, dial: &DialArgs) -> std::io::Result<TcpStream> {
+    let mut addrs = dial_order(resolve_all(host, port)?, dial).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+    loop {
+        if let Some(addr) = addrs.next() {
+            attempts.push(TcpStream::connect(addr));
+        } else if attempts.is_empty() {
+            break;
+        }
+        tokio::select! {
+            Some(result) = attempts.next() => match result {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            },
+            _ = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if !addrs.as_slice().is_empty() => {}
+        }
+    }
+    Err(last_error.unwrap_or_else(|| std::io::Error::other("failed to resolve address")))
+}
+
+/// Upper bound on the delay between connect retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// The delay before retry `attempt` (from 0): `base` doubled per attempt up to
+/// [`MAX_BACKOFF`], then scaled by a random factor between 0.5 and 1 so
+/// clients started together don't retry in lockstep.
+fn backoff(base: Duration, attempt: u32) -> Duration {
+    let delay = base.saturating_mul(1 << attempt.min(16)).min(MAX_BACKOFF);
+    let jitter = RandomState::new().build_hasher().finish() % 1000;
+    delay / 2 + delay.mul_f64(jitter as f64 / 2000.0)
+}
+
+/// Connects, retrying failures `--connect-retries` times with backoff; a
+/// server can print READY a moment before it accepts. With `tls`, the stream
+/// is wrapped in TLS, or for QUIC, used for the QUIC handshake.
+pub async fn connect(
+    endpoint: &Endpoint,
+    dial: &DialArgs,
+    socket: &SocketArgs,
+    tls: Option<&Connector>,
+) -> std::io::Result<BoxedIo> {
+    let base = Duration::from_millis(dial.connect_backoff_ms);
+    let mut attempt = 0;
+    loop {
+        let result = match (endpoint, tls) {
+            (Endpoint::Quic { host, port }, Some(tls)) => {
+                let addr = dial_order(resolve_all(host, *port)?, dial)[0];
+                quic::connect(addr, tls).await
+            }
+            (_, Some(tls)) => match connect_once(endpoint, dial, socket).await {
+                Ok(stream) => tls.connect(stream).await,
+                Err(e) => Err(e),
+            },
+            (_, None) => connect_once(endpoint, dial, socket).await,
+        };
+        match result {
+            Err(e)
+                if attempt < dial.connect_retries
+                    && e.kind() != std::io::ErrorKind::Unsupported =>
+            {
+                let delay = backoff(base, attempt);
+                eprintln!(
+                    "connect to {} failed: {}; retrying in {} ms",
+                    endpoint,
+                    e,
+                    delay.as_millis()
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn connect_once(
+    endpoint: &Endpoint,
+    dial: &DialArgs,
+    socket: &SocketArgs,
+) -> std::io::Result<BoxedIo> {
+    match endpoint {
+        Endpoint::Tcp { host, port } => match &dial.socks5 {
+            Some((proxy_host, proxy_port)) => {
+                let mut stream = connect_tcp(proxy_host, *proxy_port, dial).await?;
+                socket.apply(&stream)?;
+                socks5::connect(&mut stream, unbracket(host), *port).await?;
+                Ok(Box::new(stream))
+            }
+            None => {
+                let stream = connect_tcp(host, *port, dial).await?;
+                socket.apply(&stream)?;
+                Ok(Box::new(stream))
+            }
+        },
+        Endpoint::NamedPipe(name) => pipe::connect(name).await,
+        Endpoint::Unix(path) => unix::connect(path).await,
+        Endpoint::Quic { .. } => Err(quic_needs_tls()),
+    }
+}
+
+fn quic_needs_tls() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "--transport quic requires --tls",
+    )
+}
+
+/// This process's stdin and stdout as one stream, for peers connected by pipes.
+pub fn stdio() -> BoxedIo {
+    Box::new(tokio::io::join(tokio::io::stdin(), tokio::io::stdout()))
+}
+
+/// Accepts incoming connections on an endpoint.
+pub enum Listener {
+    Tcp(TcpListener, SocketArgs),
+    #[cfg(all(windows, feature = "named-pipe"))]
+    NamedPipe(pipe::PipeListener),
+    #[cfg(unix)]
+    Unix(unix::SocketListener),
+    Quic(quic::QuicListener),
+}
+
+impl Listener {
+    pub async fn bind(endpoint: &Endpoint) -> std::io::Result<Self> {
+        Self::bind_with(endpoint, &SocketArgs::default()).await
+    }
+
+    /// Like [`Listener::bind`], with options for every accepted TCP stream.
+    pub async fn bind_with(endpoint: &Endpoint, socket: &SocketArgs) -> std::io::Result<Self> {
+        match endpoint {
+            Endpoint::Tcp { host, port } => Ok(Listener::Tcp(
+                TcpListener::bind(resolve(host, *port)?).await?,
+                socket.clone(),
+            )),
+            Endpoint::NamedPipe(name) => pipe::bind(name),
+            Endpoint::Unix(path) => unix::bind(path),
+            Endpoint::Quic { .. } => Err(quic_needs_tls()),
+        }
+    }
+
+    /// Binds a QUIC endpoint, which needs its TLS settings up front.
+    pub async fn bind_quic(host: &str, port: u16, tls: &Acceptor) -> std::io::Result<Self> {
+        quic::bind(resolve(host, port)?, tls).await
+    }
+
+    /// The endpoint clients should dial, with any ephemeral TCP port filled in.
+    pub fn local_endpoint(&self) -> std::io::Result<Endpoint> {
+        match self {
+            Listener::Tcp(listener, _) => {
+                let addr = listener.local_addr()?;
+                Ok(Endpoint::Tcp {
+                    host: addr.ip().to_string(),
+                    port: addr.port(),
+                })
+            }
+            #[cfg(all(windows, feature = "named-pipe"))]
+            Listener::NamedPipe(listener) => Ok(Endpoint::NamedPipe(listener.name().to_string())),
+            #[cfg(unix)]
+            Listener::Unix(listener) => Ok(Endpoint::Unix(listener.path().to_path_buf())),
+            Listener::Quic(listener) => {
+                let addr = listener.local_addr()?;
+                Ok(Endpoint::Quic {
+                    host: addr.ip().to_string(),
+                    port: addr.port(),
+                })
+            }
+        }
+    }
+
+    /// Waits for the next connection. A TCP connection whose socket options
+    /// cannot be applied is logged and dropped, and the wait goes on; only a
+    /// failing listener is an error.
+    pub async fn accept(&mut self) -> std::io::Result<BoxedIo> {
+        match self {
+            Listener::Tcp(listener, socket) => loop {
+                let (stream, peer) = listener.accept().await?;
+                match socket.apply(&stream) {
+                    Ok(()) => return Ok(Box::new(stream)),
+                    Err(e) => eprintln!("dropping {}: socket options: {}", peer, e),
+                }
+            },
+            #[cfg(all(windows, feature = "named-pipe"))]
+            Listener::NamedPipe(listener) => listener.accept().await,
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.accept().await,
+            Listener::Quic(listener) => listener.accept().await,
+        }
+    }
+}
+
+#[cfg(all(windows, feature = "named-pipe"))]
+mod pipe {
+    use std::time::Duration;
+
+    use tokio::net::windows::named_pipe::{ClientOptions, NamedPipeServer, ServerOptions};
+
+    use super::{BoxedIo, Listener};
+
+    /// All pipe instances are busy; the client should retry.
+    const ERROR_PIPE_BUSY: i32 = 231;
+
+    pub struct PipeListener {
+        name: String,
+        next: NamedPipeServer,
+    }
+
+    impl PipeListener {
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+
+        pub async fn accept(&mut self) -> std::io::Result<BoxedIo> {
+            self.next.connect().await?;
+            // Create the next instance before handing this one out so clients never
+            // see the pipe missing between connections.
+            let next = ServerOptions::new().create(&self.name)?;
+            Ok(Box::new(std::mem::replace(&mut self.next, next)))
+        }
+    }
+
+    pub fn bind(name: &str) -> std::io::Result<Listener> {
+        let next = ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(name)?;
+        Ok(Listener::NamedPipe(PipeListener {
+            name: name.to_string(),
+            next,
+        }))
+    }
+
+    pub async fn connect(name: &str) -> std::io::Result<BoxedIo> {
+        loop {
+            match ClientOptions::new().open(name) {
+                Ok(client) => return Ok(Box::new(client)),
+                Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) => {}
+                Err(e) => return Err(e),
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+#[cfg(not(all(windows, feature = "named-pipe")))]
+mod pipe {
+    use super::{BoxedIo, Listener};
+
+    fn unsupported() -> std::io::Error {
+        std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "named-pipe transport requires a Windows build with the `named-pipe` feature",
+        )
+    }
+
+    pub fn bind(_name: &str) -> std::io::Result<Listener> {
+        Err(unsupported())
+    }
+
+    pub async fn connect(_name: &str) -> std::io::Result<BoxedIo> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::path::{Path, PathBuf};
+
+    use tokio::net::{UnixListener, UnixStream};
+
+    use super::{BoxedIo, Listener};
+
+    /// Removes its socket file when dropped.
+    pub struct SocketListener {
+        path: PathBuf,
+        listener: UnixListener,
+    }
+
+    impl SocketListener {
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+
+        pub async fn accept(&mut self) -> std::io::Result<BoxedIo> {
+            let (stream, _) = self.listener.accept().await?;
+            Ok(Box::new(stream))
+        }
+    }
+
+    impl Drop for SocketListener {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    pub fn bind(path: &Path) -> std::io::Result<Listener> {
+        let listener = match UnixListener::bind(path) {
+            // A socket file nobody answers on is left over from a crashed run.
+            Err(e)
+                if e.kind() == std::io::ErrorKind::AddrInUse
+                    && std::os::unix::net::UnixStream::connect(path).is_err() =>
+            {
+                std::fs::remove_file(path)?;
+                UnixListener::bind(path)?
+            }
+            result => result?,
+        };
+        Ok(Listener::Unix(SocketListener {
+            path: path.to_path_buf(),
+            listener,
+        }))
+    }
+
+    pub async fn connect(path: &Path) -> std::io::Result<BoxedIo> {
+        Ok(Box::new(UnixStream::connect(path).await?))
+    }
+}
+
+#[cfg(not(unix))]
+mod unix {
+    use std::path::Path;
+
+    use super::{BoxedIo, Listener};
+
+    fn unsupported() -> std::io::Error {
+        std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "unix transport is not available on this platform",
+        )
+    }
+
+    pub fn bind(_path: &Path) -> std::io::Result<Listener> {
+        Err(unsupported())
+    }
+
+    pub async fn connect(_path: &Path) -> std::io::Result<BoxedIo> {
+        Err(unsupported())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// SOCKS5 (RFC 1928)
+// ---------------------------------------------------------------------------
+//
+// Only what a client behind a relay needs: no authentication, and a single
+// CONNECT whose target is sent as an IP literal or a name for the proxy to
+// resolve.
+
+mod socks5 {
+    use std::net::IpAddr;
+
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    const VERSION: u8 = 5;
+    const NO_AUTH: u8 = 0;
+    const CONNECT: u8 = 1;
+    const ATYP_IPV4: u8 = 1;
+    const ATYP_DOMAIN: u8 = 3;
+    const ATYP_IPV6: u8 = 4;
+
+    fn error(message: String) -> std::io::Error {
+        std::io::Error::other(format!("SOCKS5 proxy: {}", message))
+    }
+
+    fn reply_message(code: u8) -> &'static str {
+        match code {
+            1 => "general failure",
+            2 => "connection not allowed by ruleset",
+            3 => "network unreachable",
+            4 => "host unreachable",
+            5 => "connection refused",
+            6 => "TTL expired",
+            7 => "command not supported",
+            8 => "address type not supported",
+            _ => "unknown error",
+        }
+    }
+
+    /// Asks the proxy at the other end of `stream` to connect to `host:port`.
+    pub async fn connect<S>(stream: &mut S, host: &str, port: u16) -> std::io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        stream.write_all(&[VERSION, 1, NO_AUTH]).await?;
+        let mut choice = [0u8; 2];
+        stream.read_exact(&mut choice).await?;
+        if choice != [VERSION, NO_AUTH] {
+            return Err(error(format!(
+                "proxy requires authentication (method {})",
+                choice[1]
+            )));
+        }
+
+        let mut request = vec![VERSION, CONNECT, 0];
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                request.push(ATYP_IPV4);
+                request.extend_from_slice(&ip.octets());
+            }
+            Ok(IpAddr::V6(ip)) => {
+                request.push(ATYP_IPV6);
+                request.extend_from_slice(&ip.octets());
+            }
+            Err(_) => {
+                let len = u8::try_from(host.len())
+                    .map_err(|_| error(format!("host name too long: {}", host)))?;
+                request.push(ATYP_DOMAIN);
+                request.push(len);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+        stream.flush().await?;
+
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != VERSION {
+            return Err(error(format!("unexpected reply version {}", reply[0])));
+        }
+        if reply[1] != 0 {
+            return Err(error(format!(
+                "connect to {}:{} failed: {}",
+                host,
+                port,
+                reply_message(reply[1])
+            )));
+        }
+        // Skip the bound address the proxy reports; the stream is ready after it.
+        let addr_len = match reply[3] {
+            ATYP_IPV4 => 4,
+            ATYP_IPV6 => 16,
+            ATYP_DOMAIN => stream.read_u8().await? as usize,
+            other => return Err(error(format!("unknown address type {}", other))),
+        };
+        let mut bound = vec![0u8; addr_len + 2];
+        stream.read_exact(&mut bound).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+//! Raw RPC frames whose params are small on the wire but cost a reader more
+//! than its traversal limit to walk.
+//!
+//! Each case is three frames: a Bootstrap (question 0), a
+//! `MatchmakingService.findMatch` call on it (question 1), and a
+//! `MatchController.reportResult` call pipelined on the controller that
+//! `findMatch` returns (question 2). Only the `playerStats` list of the last
+//! call is crafted; a conforming server must fail question 2 with an
+//! exception, or abort the connection, instead of returning results.
+
+use capnp::message::{Builder, HeapAllocator, ReaderOptions};
+use capnp::traits::HasTypeId;
+use capnp_rpc::rpc_capnp::{message, return_};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::error::{ErrorCode, TestError};
+use crate::matchmaking_capnp::{match_controller, matchmaking_service, GameMode};
+
+/// Traversal limit readers use when none is configured.
+pub const DEFAULT_LIMIT_WORDS: u64 = 8 * 1024 * 1024;
+
+/// Largest traversal limit frames can be sized against: the crafted name
+/// grows with its square root, and stays near 512 KiB at this one.
+pub const MAX_LIMIT_WORDS: u64 = 1 << 32;
+
+/// Largest element count a list pointer can carry.
+const MAX_LIST_ELEMENTS: u64 = (1 << 29) - 1;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Case {
+    /// Every element's `player` points at the same PlayerInfo, whose name is
+    /// about √limit words, and there are twice that many elements: reading
+    /// each player reads the name again, for about twice the limit in total.
+    Aliased,
+    /// The list claims `limit + 1` elements of zero words each, which readers
+    /// must charge at one word per element even though none are present.
+    ZeroSized,
+}
+
+/// The three frames of `case`, sized against a limit of `limit_words`, which
+/// must be between 1 and [`MAX_LIMIT_WORDS`].
+pub fn frames(case: Case, limit_words: u64) -> Result<Vec<Vec<u8>>, TestError> {
+    if !(1..=MAX_LIMIT_WORDS).contains(&limit_words) {
+        return Err(TestError::new(
+            ErrorCode::ConfigError,
+            format!(
+                "traversal limit {} is outside 1..={} words",
+                limit_words, MAX_LIMIT_WORDS
+            ),
+        ));
+    }
+    Ok(vec![
+        bootstrap(),
+        find_match(),
+        report_result(case, limit_words)?,
+    ])
+}
+
+fn bootstrap() -> Vec<u8> {
+    let mut message = Builder::new_default();
+    message
+        .init_root::<message::Builder>()
+        .init_bootstrap()
+        .set_question_id(0);
+    capnp::serialize::write_message_to_words(&message)
+}
+
+fn find_match() -> Vec<u8> {
+    let mut message = Builder::new_default();
+    let mut call = message.init_root::<message::Builder>().init_call();
+    call.set_question_id(1);
+    call.reborrow()
+        .init_target()
+        .init_promised_answer()
+        .set_question_id(0);
+    call.set_interface_id(matchmaking_service::Client::TYPE_ID);
+    call.set_method_id(2);
+    let mut params = call
+        .init_params()
+        .init_content()
+        .init_as::<matchmaking_service::find_match_params::Builder>();
+    params.set_mode(GameMode::Duel);
+    params.init_player().set_name("traversal");
+    capnp::serialize::write_message_to_words(&message)
+}
+
+/// `limit_words` is in 1..=MAX_LIMIT_WORDS, so every size here fits its type.
+fn report_result(case: Case, limit_words: u64) -> Result<Vec<u8>, TestError> {
+    let name_words = limit_words.isqrt() + 1;
+    let count = match case {
+        Case::Aliased => 2 * name_words,
+        Case::ZeroSized => 0,
+    };
+    let too_big = || {
+        TestError::new(
+            ErrorCode::Internal,
+            format!("{:?} frame for {} words is too big", case, limit_words),
+        )
+    };
+    let words = u32::try_from(name_words + 4 * count + 64).map_err(|_| too_big())?;
+    let count = u32::try_from(count).map_err(|_| too_big())?;
+    // name_words is at least 1; one word of the name holds its NUL.
+    let name_bytes = usize::try_from(name_words * 8 - 1).map_err(|_| too_big())?;
+    let mut message = Builder::new(HeapAllocator::new().first_segment_words(words));
+    {
+        let mut call = message.init_root::<message::Builder>().init_call();
+        call.set_question_id(2);
+        let mut answer = call.reborrow().init_target().init_promised_answer();
+        answer.set_question_id(1);
+        // The controller is the first pointer of findMatch's results.
+        answer.init_transform(1).get(0).set_get_pointer_field(0);
+        call.set_interface_id(match_controller::Client::TYPE_ID);
+        call.set_method_id(2);
+        let mut result = call
+            .init_params()
+            .init_content()
+            .init_as::<match_controller::report_result_params::Builder>()
+            .init_result();
+        result.reborrow().init_match_id().set_id(1);
+        let mut stats = result.init_player_stats(count);
+        if count > 0 {
+            let name = "x".repeat(name_bytes);
+            stats.reborrow().get(0).init_player().set_name(&name[..]);
+        }
+    }
+
+    let segments = message.get_segments_for_output();
+    if segments.len() != 1 {
+        return Err(TestError::new(
+            ErrorCode::Internal,
+            format!(
+                "crafted {:?} message spilled into {} segments",
+                case,
+                segments.len()
+            ),
+        ));
+    }
+    let mut segment: Vec<u64> = segments[0]
+        .chunks_exact(8)
+        .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+
+    // Root Message -> call -> params -> content -> result -> playerStats.
+    let call = pointer(&segment, 0, 0);
+    let params = pointer(&segment, call, 1);
+    let content = pointer(&segment, params, 0);
+    let result = pointer(&segment, content, 0);
+    let stats = pointer(&segment, result, 1);
+    let tag = target(&segment, stats);
+    match case {
+        Case::Aliased => {
+            let data_words = (segment[tag] >> 32 & 0xffff) as usize;
+            let element_words = data_words + (segment[tag] >> 48) as usize;
+            // `player` is the first pointer after each element's data section.
+            let player = |i: u64| tag + 1 + i as usize * element_words + data_words;
+            let first = player(0);
+            for i in 1..u64::from(count) {
+                let at = player(i);
+                segment[at] = retarget(&segment, first, at);
+            }
+        }
+        Case::ZeroSized => {
+            // Zero data and pointer words; the count sits in the offset field.
+            segment[tag] = (limit_words + 1).min(MAX_LIST_ELEMENTS) << 2;
+        }
+    }
+
+    let mut bytes = Vec::with_capacity(8 + segment.len() * 8);
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes.extend_from_slice(&(segment.len() as u32).to_le_bytes());
+    for word in segment {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    Ok(bytes)
+}
+
+/// Word index a struct or list pointer at `at` points to.
+fn target(segment: &[u64], at: usize) -> usize {
+    let offset = (segment[at] as u32 as i32) >> 2;
+    (at as i64 + 1 + i64::from(offset)) as usize
+}
+
+/// Word index of pointer `index` of the struct the pointer at `at` points to.
+fn pointer(segment: &[u64], at: usize, index: usize) -> usize {
+    let data_words = (segment[at] >> 32 & 0xffff) as usize;
+    target(segment, at) + data_words + index
+}
+
+/// A copy of the pointer at `from`, rewritten to sit at `at`.
+fn retarget(segment: &[u64], from: usize, at: usize) -> u64 {
+    let offset = target(segment, from) as i64 - (at as i64 + 1);
+    (segment[from] & !0xffff_ffff) | u64::from(((offset as i32) << 2) as u32) | (segment[from] & 3)
+}
+
+/// Reads the `playerStats` of a crafted reportResult frame the way the
+/// reference server does, with the given traversal limit or none at all, and
+/// returns the total length of the player names.
+pub fn read_player_stats(frame: &[u8], limit_words: Option<u64>) -> capnp::Result<u64> {
+    let mut options = ReaderOptions::new();
+    options.traversal_limit_in_words(limit_words.map(|words| words as usize));
+    let message = capnp::serialize::read_message(frame, options)?;
+    let root = message.get_root::<message::Reader>()?;
+    let message::Call(call) = root.which()? else {
+        return Err(capnp::Error::failed("not a call".into()));
+    };
+    let params = call?
+        .get_params()?
+        .get_content()
+        .get_as::<match_controller::report_result_params::Reader>()?;
+    let mut total = 0;
+    for stats in params.get_result()?.get_player_stats()? {
+        total += stats.get_player()?.get_name()?.len() as u64;
+    }
+    Ok(total)
+}
+
+/// Writes `frames` to `stream` and waits for the answer to the last one.
+/// Returns why the peer refused it, or `None` if it sent results.
+pub async fn exchange<S>(stream: &mut S, frames: &[Vec<u8>]) -> Result<Option<String>, TestError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let io_failed = |e: std::io::Error| TestError::new(ErrorCode::ConnectFailed, e.to_string());
+    for frame in frames {
+        stream.write_all(frame).await.map_err(io_failed)?;
+    }
+    stream.flush().await.map_err(io_failed)?;
+    let Some(question) = u32::try_from(frames.len())
+        .ok()
+        .and_then(|n| n.checked_sub(1))
+    else {
+        return Err(TestError::new(ErrorCode::Internal, "no frames to exchange"));
+    };
+    loop {
+        let Some(frame) = read_frame(stream).await.map_err(io_failed)? else {
+            return Ok(Some("connection closed".to_string()));
+        };
+        let message = capnp::serialize::read_message(frame.as_slice(), Default::default())?;
+        match message.get_root::<message::Reader>()?.which()? {
+            message::Abort(exception) => {
+                return Ok(Some(format!(
+                    "abort: {}",
+                    exception?.get_reason()?.to_str()?
+                )));
+            }
+            message::Return(ret) => {
+                let ret = ret?;
+                if ret.get_answer_id() != question {
+                    continue;
+                }
+                return match ret.which()? {
+                    return_::Exception(exception) => Ok(Some(format!(
+                        "exception: {}",
+                        exception?.get_reason()?.to_str()?
+                    ))),
+                    return_::Results(_) => Ok(None),
+                    _ => Err(TestError::new(
+                        ErrorCode::AssertionFailed,
+                        "unexpected return variant for the crafted call",
+                    )),
+                };
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Reads one framed message, or `None` at a clean end of stream.
+async fn read_frame<S: AsyncRead + Unpin>(stream: &mut S) -> std::io::Result<Option<Vec<u8>>> {
+    let mut count = [0u8; 4];
+    match stream.read_exact(&mut count).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let segments = u32::from_le_bytes(count) as usize + 1;
+    // Sizes, then padding to a whole word.
+    let mut table = vec![0u8; (segments + 1 - segments % 2) * 4];
+    stream.read_exact(&mut table).await?;
+    let words: usize = table
+        .chunks_exact(4)
+        .take(segments)
+        .map(|size| u32::from_le_bytes(size.try_into().unwrap()) as usize)
+        .sum();
+    let mut frame = count.to_vec();
+    frame.extend_from_slice(&table);
+    let header = frame.len();
+    frame.resize(header + words * 8, 0);
+    stream.read_exact(&mut frame[header..]).await?;
+    Ok(Some(frame))
+}
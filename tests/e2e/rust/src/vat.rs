@@ -0,0 +1,293 @@
+//! Three-party handoff topology: a provider vat A, an introducer vat C and a
+//! recipient vat B, each normally its own process.
+//!
+//! A serves the chat service. C connects to A, has it mint a room, and serves
+//! a `ServiceRegistry` offering that room and A's chat service. B connects to
+//! C, takes the room and uses it. Under level 3 RPC, C would answer B with a
+//! ThirdPartyCapDescriptor and send A a Provide; B would connect to A, send
+//! the matching Accept and make its calls there. capnp-rpc stops at level 2,
+//! so C proxies every call instead, and the checks that need the handoff are
+//! reported as TAP TODOs until it does.
+
+use capnp::capability::{FromClientHook, Promise};
+use capnp_rpc::pry;
+use capnp_rpc::rpc_twoparty_capnp::Side;
+
+use crate::artifacts::WireLog;
+use crate::chat_capnp::{chat_room, chat_service};
+use crate::client::{self, ConnectOptions};
+use crate::error::{ErrorCode, TestError};
+use crate::faults::AnyClient;
+use crate::frames;
+use crate::registry_capnp::service_registry;
+use crate::report::TapReporter;
+use crate::server::{self, ServeOptions};
+use crate::transport::{Endpoint, Listener};
+
+/// Which vat of the topology a process plays.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Role {
+    /// Vat A: hosts the capability being handed off.
+    Provider,
+    /// Vat C: holds A's capability and introduces B to it.
+    Introducer,
+    /// Vat B: receives the capability from C and checks it.
+    Recipient,
+}
+
+/// Room C has A mint for the handoff.
+pub const HANDOFF_ROOM: &str = "handoff";
+
+pub const VAT_TESTS: [&str; 3] = [
+    "Three-party: room minted on A answers B through C",
+    "Three-party: C hands B a third-party capability",
+    "Three-party: B calls A directly after Accept",
+];
+
+/// Why the handoff checks are TODOs rather than failures.
+const NO_LEVEL_3: &str = "capnp-rpc has no level 3 (Provide/Accept)";
+
+/// Runs `role` on `listen`, connecting to `peer` (A for C, C for B).
+pub async fn run(role: Role, listen: &Endpoint, peer: Option<&Endpoint>) -> Result<(), TestError> {
+    let peer = || {
+        peer.ok_or_else(|| {
+            TestError::new(
+                ErrorCode::ConfigError,
+                format!("the {:?} vat needs --peer-host/--peer-port", role),
+            )
+        })
+    };
+    match role {
+        Role::Provider => {
+            server::run(
+                listen,
+                "chat",
+                None,
+                None,
+                &Default::default(),
+                ServeOptions::default(),
+            )
+            .await
+        }
+        Role::Introducer => {
+            let provider = peer()?;
+            let listener = bind(listen).await?;
+            println!("READY");
+            introduce(listener, provider).await
+        }
+        Role::Recipient => receive(peer()?).await,
+    }
+}
+
+async fn bind(endpoint: &Endpoint) -> Result<Listener, TestError> {
+    Listener::bind(endpoint).await.map_err(|e| {
+        TestError::new(
+            ErrorCode::ConfigError,
+            format!("cannot listen on {}: {}", endpoint, e),
+        )
+    })
+}
+
+/// Vat C: has the provider at `provider` mint the handoff room, then serves
+/// a registry offering it on `listener`.
+pub async fn introduce(listener: Listener, provider: &Endpoint) -> Result<(), TestError> {
+    let mut rpc_system = client::connect(provider, None, None, &ConnectOptions::default()).await?;
+    let chat: chat_service::Client = rpc_system.bootstrap(Side::Server);
+    tokio::task::spawn_local(rpc_system);
+
+    let mut req = chat.create_room_request();
+    req.get().set_name(HANDOFF_ROOM);
+    req.get().set_topic("Three-party handoff");
+    let resp = req.send().promise.await?;
+    let room = resp.get()?.get_room()?;
+
+    let introducer: service_registry::Client = capnp_rpc::new_client(IntroducerImpl {
+        services: vec![("room", room.client), ("chat", chat.client)],
+    });
+    server::serve_with(listener, None, None, ServeOptions::default(), || {
+        capnp::capability::Client::new(introducer.client.hook.add_ref())
+    })
+    .await
+}
+
+/// Offers the capabilities C holds from A, by name.
+struct IntroducerImpl {
+    services: Vec<(&'static str, capnp::capability::Client)>,
+}
+
+impl service_registry::Server for IntroducerImpl {
+    fn get(
+        &mut self,
+        params: service_registry::GetParams,
+        mut results: service_registry::GetResults,
+    ) -> Promise<(), capnp::Error> {
+        let name = pry!(pry!(pry!(params.get()).get_name()).to_str());
+        let Some((_, service)) = self.services.iter().find(|(n, _)| *n == name) else {
+            return Promise::err(capnp::Error::failed(format!("no such service: {}", name)));
+        };
+        // TODO: with level 3, hand out a ThirdPartyCapDescriptor here and
+        // Provide the capability to A for B to Accept.
+        results
+            .get()
+            .init_service()
+            .set_as_capability(service.hook.add_ref());
+        Promise::ok(())
+    }
+
+    fn list(
+        &mut self,
+        _params: service_registry::ListParams,
+        mut results: service_registry::ListResults,
+    ) -> Promise<(), capnp::Error> {
+        let mut names = results.get().init_names(self.services.len() as u32);
+        for (i, (name, _)) in self.services.iter().enumerate() {
+            names.set(i as u32, *name);
+        }
+        Promise::ok(())
+    }
+
+    fn get_schema_fingerprint(
+        &mut self,
+        _params: service_registry::GetSchemaFingerprintParams,
+        mut results: service_registry::GetSchemaFingerprintResults,
+    ) -> Promise<(), capnp::Error> {
+        results.get().set_fingerprint(crate::SCHEMA_FINGERPRINT);
+        Promise::ok(())
+    }
+
+    server::ping_method!(service_registry);
+}
+
+/// Vat B: takes the handoff room from the introducer at `introducer`, uses
+/// it, and reports the handoff checks as TAP.
+pub async fn receive(introducer: &Endpoint) -> Result<(), TestError> {
+    let log = WireLog::default();
+    let mut rpc_system = client::connect(
+        introducer,
+        Some(log.clone()),
+        None,
+        &ConnectOptions::default(),
+    )
+    .await?;
+    let registry: service_registry::Client = rpc_system.bootstrap(Side::Server);
+    tokio::task::spawn_local(rpc_system);
+
+    let mut tap = TapReporter::new(VAT_TESTS.len() as u32);
+    tap.comment(&format!("introducer: {}", introducer));
+    let used = use_room(&registry).await;
+    let handed_off = used.is_ok();
+    tap.pass_or_fail(VAT_TESTS[0], used);
+    if !handed_off {
+        for name in &VAT_TESTS[1..] {
+            tap.skip(name, "the room never reached B");
+        }
+        return tap.done();
+    }
+
+    let (sent, received) = log.bytes();
+    tap.todo(
+        VAT_TESTS[1],
+        NO_LEVEL_3,
+        check_third_party_descriptor(&received),
+    );
+    tap.todo(VAT_TESTS[2], NO_LEVEL_3, check_no_calls_to_c(&sent));
+    tap.done()
+}
+
+/// Sends a message through the room C hands out, then checks A recorded it.
+async fn use_room(registry: &service_registry::Client) -> Result<(), TestError> {
+    let room: chat_room::Client = registry_get(registry, "room").await?;
+    let mut req = room.send_message_request();
+    req.get().set_content("minted on A, used by B");
+    let resp = req.send().promise.await?;
+    let content = resp.get()?.get_message()?.get_content()?.to_string()?;
+    if content != "minted on A, used by B" {
+        return Err(TestError::new(
+            ErrorCode::AssertionFailed,
+            format!("message content: got {:?}", content),
+        ));
+    }
+
+    let chat: chat_service::Client = registry_get(registry, "chat").await?;
+    let resp = chat.list_rooms_request().send().promise.await?;
+    for room in resp.get()?.get_rooms()? {
+        if room.get_name()?.to_str()? == HANDOFF_ROOM {
+            return Ok(());
+        }
+    }
+    Err(TestError::new(
+        ErrorCode::AssertionFailed,
+        format!("A does not list the {:?} room", HANDOFF_ROOM),
+    ))
+}
+
+async fn registry_get<C: FromClientHook>(
+    registry: &service_registry::Client,
+    name: &str,
+) -> Result<C, TestError> {
+    let mut req = registry.get_request();
+    req.get().set_name(name);
+    let resp = req.send().promise.await?;
+    let service: AnyClient = resp.get()?.get_service().get_as_capability()?;
+    Ok(C::new(service.client.hook))
+}
+
+/// Calls `f` on every RPC message framed in `bytes`.
+fn for_each_message(
+    bytes: &[u8],
+    mut f: impl FnMut(crate::rpc_capnp::message::Reader<'_>) -> capnp::Result<()>,
+) -> Result<(), TestError> {
+    for frame in frames::split(bytes)? {
+        f(frame.root()?)?;
+    }
+    Ok(())
+}
+
+/// Checks some Return from C carried a `thirdPartyHosted` capability.
+fn check_third_party_descriptor(received: &[u8]) -> Result<(), TestError> {
+    use crate::rpc_capnp::{cap_descriptor, message, return_};
+    let mut found = false;
+    for_each_message(received, |msg| {
+        if let message::Return(ret) = msg.which()? {
+            if let return_::Results(payload) = ret?.which()? {
+                for cap in payload?.get_cap_table()? {
+                    if let cap_descriptor::ThirdPartyHosted(_) = cap.which()? {
+                        found = true;
+                    }
+                }
+            }
+        }
+        Ok(())
+    })?;
+    if found {
+        Ok(())
+    } else {
+        Err(TestError::new(
+            ErrorCode::AssertionFailed,
+            "C returned the room as its own capability, not a thirdPartyHosted one",
+        ))
+    }
+}
+
+/// Checks B sent C no ChatRoom calls, which after an Accept go to A.
+fn check_no_calls_to_c(sent: &[u8]) -> Result<(), TestError> {
+    use crate::rpc_capnp::message;
+    let mut calls = 0;
+    for_each_message(sent, |msg| {
+        if let message::Call(call) = msg.which()? {
+            if call?.get_interface_id() == <chat_room::Client as capnp::traits::HasTypeId>::TYPE_ID
+            {
+                calls += 1;
+            }
+        }
+        Ok(())
+    })?;
+    if calls == 0 {
+        Ok(())
+    } else {
+        Err(TestError::new(
+            ErrorCode::AssertionFailed,
+            format!("B sent {} ChatRoom call(s) through C", calls),
+        ))
+    }
+}
@@ -0,0 +1,91 @@
+//! Checks lists built from detached elements encode the same as lists whose
+//! elements were initialized in place.
+#![cfg(feature = "net")]
+
+use capnp::message::{Builder, HeapAllocator};
+use capnp::struct_list;
+use e2e_rpc_test::game_types_capnp::Faction;
+use e2e_rpc_test::game_world_capnp::{entity, EntityKind};
+use e2e_rpc_test::server::adopt_elements;
+
+struct Spawned {
+    id: u64,
+    name: &'static str,
+    position: [f32; 3],
+    health: i32,
+    alive: bool,
+}
+
+const SPAWNED: [Spawned; 3] = [
+    Spawned {
+        id: 1,
+        name: "TestHero",
+        position: [1.0, -2.5, 3.0],
+        health: 100,
+        alive: true,
+    },
+    // `alive` defaults to true, so false is stored XORed.
+    Spawned {
+        id: u64::MAX,
+        name: "",
+        position: [0.0; 3],
+        health: 0,
+        alive: false,
+    },
+    Spawned {
+        id: 7,
+        name: "Grünspan",
+        position: [f32::MAX, f32::MIN, -0.0],
+        health: -1,
+        alive: true,
+    },
+];
+
+fn build(mut builder: entity::Builder<'_>, e: &Spawned) {
+    builder.reborrow().init_id().set_id(e.id);
+    builder.reborrow().set_kind(EntityKind::Npc);
+    builder.reborrow().set_name(e.name);
+    let mut pos = builder.reborrow().init_position();
+    pos.set_x(e.position[0]);
+    pos.set_y(e.position[1]);
+    pos.set_z(e.position[2]);
+    builder.reborrow().set_health(e.health);
+    builder.reborrow().set_max_health(100);
+    builder.reborrow().set_faction(Faction::Neutral);
+    builder.set_alive(e.alive);
+}
+
+/// The root of `message` in canonical form, in one segment sized to fit.
+fn canonical_root(message: &Builder<HeapAllocator>) -> Vec<u8> {
+    let root: capnp::any_pointer::Reader = message.get_root_as_reader().unwrap();
+    let words = root.target_size().unwrap().word_count + 1;
+    let mut canonical = Builder::new(HeapAllocator::new().first_segment_words(words as u32));
+    canonical.set_root_canonical(root).unwrap();
+    canonical.get_segments_for_output()[0].to_vec()
+}
+
+fn adopted(items: &[Spawned]) -> Vec<u8> {
+    let mut message = Builder::new_default();
+    let mut list: struct_list::Builder<'_, entity::Owned> = message.initn_root(items.len() as u32);
+    adopt_elements(&mut list, items, build).unwrap();
+    canonical_root(&message)
+}
+
+fn in_place(items: &[Spawned]) -> Vec<u8> {
+    let mut message = Builder::new_default();
+    let mut list: struct_list::Builder<'_, entity::Owned> = message.initn_root(items.len() as u32);
+    for (i, item) in items.iter().enumerate() {
+        build(list.reborrow().get(i as u32), item);
+    }
+    canonical_root(&message)
+}
+
+#[test]
+fn adopted_elements_match_ones_built_in_place() {
+    assert_eq!(adopted(&SPAWNED), in_place(&SPAWNED));
+}
+
+#[test]
+fn adopting_nothing_leaves_an_empty_list() {
+    assert_eq!(adopted(&[]), in_place(&[]));
+}
@@ -0,0 +1,28 @@
+//! Checks the malformed framing written by `gen-bad-frames`.
+
+use e2e_rpc_test::bad_frames;
+
+#[test]
+fn every_bad_frame_is_written_and_listed() {
+    let dir = std::env::temp_dir().join(format!("e2e-bad-frames-{}", std::process::id()));
+    bad_frames::generate(&dir).unwrap();
+
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(dir.join("manifest.json")).unwrap()).unwrap();
+    let entries = manifest.as_array().unwrap();
+    assert_eq!(entries.len(), bad_frames::FRAMES.len());
+    for (frame, entry) in bad_frames::FRAMES.iter().zip(entries) {
+        let file = format!("{}.bin", frame.name);
+        assert_eq!(entry["file"], file.as_str());
+        assert_eq!(entry["reason"], frame.reason);
+        let bytes = std::fs::read(dir.join(&file)).unwrap();
+        assert_eq!(bytes, frame.bytes(), "{}", file);
+        assert_eq!(
+            entry["rust_error"].as_str(),
+            bad_frames::reference_error(&bytes).as_deref(),
+            "{}",
+            file
+        );
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,671 @@
+//! Checks the golden corpus written by `encode` and read back by `verify`
+//! and `canonicalize`, and its JSON form written by `json`.
+
+use std::path::PathBuf;
+
+use e2e_rpc_test::corpus::{self, Layout};
+use e2e_rpc_test::error::ErrorCode;
+
+fn out_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("e2e-corpus-{}-{}", name, std::process::id()))
+}
+
+#[test]
+fn encode_is_deterministic() {
+    let (first, second) = (out_dir("a"), out_dir("b"));
+    corpus::encode(&first, false, Layout::Flat).unwrap();
+    corpus::encode(&second, false, Layout::Flat).unwrap();
+    for sample in corpus::SAMPLES {
+        let file = format!("{}.bin", sample.name);
+        let bytes = std::fs::read(first.join(&file)).unwrap();
+        assert_eq!(
+            bytes,
+            std::fs::read(second.join(&file)).unwrap(),
+            "{}",
+            file
+        );
+        capnp::serialize::read_message(&mut bytes.as_slice(), Default::default())
+            .unwrap_or_else(|e| panic!("{}: {}", file, e));
+    }
+    std::fs::remove_dir_all(first).unwrap();
+    std::fs::remove_dir_all(second).unwrap();
+}
+
+#[test]
+fn verify_accepts_encoded_corpus() {
+    let dir = out_dir("verify");
+    corpus::encode(&dir, false, Layout::Flat).unwrap();
+    corpus::verify(&dir, false, Layout::Flat).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn verify_accepts_big_samples() {
+    let dir = out_dir("big");
+    corpus::encode_big(&dir, false, Layout::Flat).unwrap();
+    corpus::verify_big(&dir, false, Layout::Flat).unwrap();
+    let err = corpus::encode_big(&dir, false, Layout::Far).unwrap_err();
+    assert_eq!(err.code, ErrorCode::ConfigError);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn verify_reports_field_mismatches() {
+    let dir = out_dir("mismatch");
+    corpus::encode(&dir, false, Layout::Flat).unwrap();
+    std::fs::copy(
+        dir.join("chat.message_whisper.bin"),
+        dir.join("chat.message_normal.bin"),
+    )
+    .unwrap();
+    let err = corpus::verify(&dir, false, Layout::Flat).unwrap_err();
+    assert_eq!(err.code, ErrorCode::AssertionFailed, "{}", err);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn far_layouts_hold_the_same_messages() {
+    let dir = out_dir("far");
+    for layout in [Layout::Flat, Layout::Far, Layout::DoubleFar] {
+        corpus::encode(&dir, false, layout).unwrap();
+        corpus::verify(&dir, false, layout).unwrap();
+    }
+    let read = |file: &str| {
+        let bytes = std::fs::read(dir.join(file)).unwrap();
+        capnp::serialize::read_message(bytes.as_slice(), Default::default())
+            .unwrap_or_else(|e| panic!("{}: {}", file, e))
+    };
+    for sample in corpus::SAMPLES {
+        let flat = read(&Layout::Flat.file(sample.name));
+        for layout in [Layout::Far, Layout::DoubleFar] {
+            let file = layout.file(sample.name);
+            let message = read(&file);
+            assert_eq!(
+                message.canonicalize().unwrap(),
+                flat.canonicalize().unwrap(),
+                "{}",
+                file
+            );
+            assert!(message.into_segments().len() > 2, "{}", file);
+        }
+    }
+
+    // Single-far roots do not pass for double-far ones, or the reverse.
+    std::fs::copy(
+        dir.join(Layout::Far.file("chat.room_info")),
+        dir.join(Layout::DoubleFar.file("chat.room_info")),
+    )
+    .unwrap();
+    let err = corpus::verify(&dir, false, Layout::DoubleFar).unwrap_err();
+    assert_eq!(err.code, ErrorCode::AssertionFailed, "{}", err);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn text_samples_keep_raw_bytes() {
+    use e2e_rpc_test::lists_capnp::sized_lists;
+
+    let dir = out_dir("raw-text");
+    corpus::encode(&dir, false, Layout::Flat).unwrap();
+    let bytes = std::fs::read(dir.join(Layout::Flat.file("lists.text_invalid_utf8"))).unwrap();
+    let message = capnp::serialize::read_message(bytes.as_slice(), Default::default()).unwrap();
+    let lists = message.get_root::<sized_lists::Reader>().unwrap();
+    let text = lists.get_text().unwrap();
+    assert_eq!(text.as_bytes(), corpus::INVALID_UTF8_TEXT);
+    assert!(text.to_str().is_err());
+    let texts = lists.get_texts().unwrap();
+    for (i, bytes) in corpus::INVALID_UTF8.iter().enumerate() {
+        let text = texts.get(i as u32).unwrap();
+        assert_eq!(text.as_bytes(), *bytes);
+        assert!(text.to_str().is_err(), "{:02x?}", bytes);
+    }
+
+    let bytes = std::fs::read(dir.join(Layout::Flat.file("lists.text_embedded_nul"))).unwrap();
+    let message = capnp::serialize::read_message(bytes.as_slice(), Default::default()).unwrap();
+    let lists = message.get_root::<sized_lists::Reader>().unwrap();
+    assert_eq!(lists.get_text().unwrap().to_str().unwrap(), "before\0after");
+    assert_eq!(lists.get_data().unwrap(), corpus::EMBEDDED_NUL_TEXT);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn blob_text_carries_a_nul_its_data_does_not() {
+    use capnp::traits::IntoInternalStructReader;
+    use e2e_rpc_test::lists_capnp::blob_set;
+
+    let dir = out_dir("blobs");
+    corpus::encode(&dir, false, Layout::Flat).unwrap();
+    let bytes = std::fs::read(dir.join(Layout::Flat.file("lists.blobs"))).unwrap();
+    let message = capnp::serialize::read_message(bytes.as_slice(), Default::default()).unwrap();
+    let blobs = message
+        .get_root::<blob_set::Reader>()
+        .unwrap()
+        .get_blobs()
+        .unwrap();
+    assert_eq!(blobs.len() as usize, corpus::BLOBS.len());
+    for (blob, bytes) in blobs.iter().zip(corpus::BLOBS) {
+        assert_eq!(
+            corpus::check_blob(blob, bytes).unwrap(),
+            Vec::<String>::new(),
+            "{:02x?}",
+            bytes
+        );
+    }
+
+    // Without its terminator a text still reads as Data, but not as Text.
+    let mut message = capnp::message::Builder::new_default();
+    let blob = message
+        .init_root::<blob_set::Builder>()
+        .init_blobs(1)
+        .get(0);
+    let text = blob.init_text(3);
+    text.as_bytes_mut().copy_from_slice(b"abc");
+    let mut bytes = capnp::serialize::write_message_to_words(&message);
+    // The text is the last object, so its terminator is in the last word.
+    let nul = bytes.len() - 8 + 3;
+    assert_eq!(bytes[nul], 0);
+    bytes[nul] = b'!';
+    let message = capnp::serialize::read_message(bytes.as_slice(), Default::default()).unwrap();
+    let blob = message
+        .get_root::<blob_set::Reader>()
+        .unwrap()
+        .get_blobs()
+        .unwrap()
+        .get(0);
+    let wire = blob.into_internal_struct_reader().get_pointer_field(0);
+    assert_eq!(wire.get_data(None).unwrap(), b"abc!");
+    assert!(blob.get_text().is_err());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn default_fields_encode_as_zero() {
+    use capnp::traits::IntoInternalStructReader;
+    use e2e_rpc_test::defaults_capnp::settings;
+
+    let dir = out_dir("defaults");
+    corpus::encode(&dir, false, Layout::Flat).unwrap();
+    for (name, zero) in [
+        ("defaults.settings_default", true),
+        ("defaults.settings_zero", false),
+    ] {
+        let bytes = std::fs::read(dir.join(Layout::Flat.file(name))).unwrap();
+        let message = capnp::serialize::read_message(bytes.as_slice(), Default::default()).unwrap();
+        let settings = message.get_root::<settings::Reader>().unwrap();
+        let data = settings
+            .into_internal_struct_reader()
+            .get_data_section_as_blob();
+        assert_eq!(
+            data.iter().all(|&byte| byte == 0),
+            zero,
+            "{}: {:02x?}",
+            name,
+            data
+        );
+    }
+
+    let bytes = std::fs::read(dir.join(Layout::Flat.file("defaults.settings_default"))).unwrap();
+    let message = capnp::serialize::read_message(bytes.as_slice(), Default::default()).unwrap();
+    let settings = message.get_root::<settings::Reader>().unwrap();
+    assert!(!settings.has_label() && !settings.has_bounds());
+    assert_eq!(settings.get_label().unwrap(), "untitled");
+    assert_eq!(settings.get_bounds().unwrap().get_max(), 10);
+    assert!(!settings.has_spawn() && !settings.has_starter_item() && !settings.has_matrix());
+    assert_eq!(settings.get_spawn().unwrap().get_z(), 64.0);
+    let item = settings.get_starter_item().unwrap();
+    assert_eq!(item.get_name().unwrap(), "Rusty Sword");
+    assert_eq!(item.get_attributes().unwrap().get(1).get_value(), -1);
+    assert_eq!(settings.get_salt().unwrap(), [0xde, 0xad, 0xbe, 0xef]);
+    assert_eq!(settings.get_matrix().unwrap().get(2).unwrap().get(0), -3);
+
+    let bytes = std::fs::read(dir.join(Layout::Flat.file("defaults.settings_zero"))).unwrap();
+    let message = capnp::serialize::read_message(bytes.as_slice(), Default::default()).unwrap();
+    let settings = message.get_root::<settings::Reader>().unwrap();
+    assert_eq!(settings.get_spawn().unwrap().get_z(), 0.0);
+    assert_eq!(settings.get_salt().unwrap().len(), 0);
+    assert_eq!(settings.get_ports().unwrap().len(), 0);
+    assert_eq!(settings.get_matrix().unwrap().len(), 0);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn float_fields_xor_their_defaults_bit_for_bit() {
+    use capnp::traits::IntoInternalStructReader;
+    use e2e_rpc_test::defaults_capnp::settings;
+
+    let dir = out_dir("edge-floats");
+    corpus::encode(&dir, false, Layout::Flat).unwrap();
+    let bytes =
+        std::fs::read(dir.join(Layout::Flat.file("defaults.settings_edge_floats"))).unwrap();
+    let message = capnp::serialize::read_message(bytes.as_slice(), Default::default()).unwrap();
+    let settings = message.get_root::<settings::Reader>().unwrap();
+    assert_eq!(settings.get_ratio().to_bits(), (-0.0f64).to_bits());
+    assert_eq!(settings.get_scale().to_bits(), f32::NAN.to_bits());
+
+    // ratio is the Float64 at word 3 and scale the Float32 at offset 8 in
+    // 32-bit units, each holding value XOR default.
+    let data = settings
+        .into_internal_struct_reader()
+        .get_data_section_as_blob();
+    let ratio = u64::from_le_bytes(data[24..32].try_into().unwrap());
+    let scale = u32::from_le_bytes(data[32..36].try_into().unwrap());
+    assert_eq!(ratio, (-0.0f64).to_bits() ^ 1.5f64.to_bits());
+    assert_eq!(scale, f32::NAN.to_bits() ^ (-0.25f32).to_bits());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn spell_discriminants_sit_at_their_schema_offsets() {
+    use capnp::dynamic_value;
+    use capnp::traits::IntoInternalStructReader;
+    use e2e_rpc_test::spells_capnp::spell_effect;
+
+    let dir = out_dir("spells");
+    corpus::encode(&dir, false, Layout::Flat).unwrap();
+    // The discriminants of the effect, of `cost.reagent` and, for damage, of
+    // `damage.spread`: each member's position in its union.
+    let expected = [
+        ("spells.damage_single", 0, 0, Some(0)),
+        ("spells.damage_splash", 0, 2, Some(1)),
+        ("spells.damage_chain", 0, 1, Some(2)),
+        ("spells.heal", 1, 0, None),
+        ("spells.summon", 2, 1, None),
+        ("spells.dispel", 3, 2, None),
+    ];
+    // Byte offset of a union's discriminant in the data section its groups
+    // share with the root.
+    let offset = |union: dynamic_value::Reader<'_>| {
+        let schema = union
+            .downcast::<capnp::dynamic_struct::Reader>()
+            .get_schema();
+        match schema.get_proto().which().unwrap() {
+            capnp::schema_capnp::node::Struct(proto) => {
+                proto.get_discriminant_offset() as usize * 2
+            }
+            _ => unreachable!("unions are structs"),
+        }
+    };
+    let mut offsets = Vec::new();
+    for (name, effect_tag, reagent_tag, spread_tag) in expected {
+        let bytes = std::fs::read(dir.join(Layout::Flat.file(name))).unwrap();
+        let message = capnp::serialize::read_message(bytes.as_slice(), Default::default()).unwrap();
+        let effect = message.get_root::<spell_effect::Reader>().unwrap();
+        let data = effect
+            .into_internal_struct_reader()
+            .get_data_section_as_blob();
+        let tag = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+
+        let (root, reagent) = (
+            offset(effect.into()),
+            offset(effect.get_cost().get_reagent().into()),
+        );
+        assert_eq!(tag(root), effect_tag, "{} effect", name);
+        assert_eq!(tag(reagent), reagent_tag, "{} cost.reagent", name);
+        offsets.extend([root, reagent]);
+        if let spell_effect::Damage(damage) = effect.which().unwrap() {
+            let spread = offset(damage.get_spread().into());
+            assert_eq!(Some(tag(spread)), spread_tag, "{} damage.spread", name);
+            offsets.push(spread);
+        } else {
+            assert_eq!(spread_tag, None, "{} is not a damage effect", name);
+        }
+    }
+    offsets.sort();
+    offsets.dedup();
+    assert_eq!(offsets.len(), 3, "discriminants share a slot");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// The element size code and count of the list that pointer `index` of the
+/// root struct points to, and the byte offset of its first element, from a
+/// single-segment message file.
+fn root_list(bytes: &[u8], index: usize) -> (u64, usize, usize) {
+    assert_eq!(&bytes[..4], &[0; 4], "expected a single segment");
+    let words: Vec<u64> = bytes[8..]
+        .chunks_exact(8)
+        .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+    let target = |at: usize| (at as i64 + 1 + i64::from((words[at] as u32 as i32) >> 2)) as usize;
+    let at = target(0) + (words[0] >> 32 & 0xffff) as usize + index;
+    assert_eq!(words[at] & 3, 1, "pointer {} is not a list pointer", index);
+    let (size, count) = (words[at] >> 32 & 7, (words[at] >> 35) as usize);
+    (size, count, target(at) * 8 + 8)
+}
+
+#[test]
+fn bool_lists_pack_bits_lsb_first() {
+    let dir = out_dir("bools");
+    corpus::encode(&dir, false, Layout::Flat).unwrap();
+    type Pattern = fn(usize, usize) -> bool;
+    let patterns: [(&str, Pattern); 3] = [
+        ("lists.bools_alternating", |i, _| i % 2 == 0),
+        ("lists.bools_all", |_, _| true),
+        ("lists.bools_sparse", |i, len| i % 7 == 0 || i + 1 == len),
+    ];
+    for (name, bit) in patterns {
+        let bytes = std::fs::read(dir.join(Layout::Flat.file(name))).unwrap();
+        for (index, len) in [0usize, 1, 8, 9, 64, 65].into_iter().enumerate() {
+            // Eight elements to a byte, low bit first, zero-padded to a word.
+            let mut expected = vec![0u8; len.div_ceil(64) * 8];
+            for i in (0..len).filter(|&i| bit(i, len)) {
+                expected[i / 8] |= 1 << (i % 8);
+            }
+            let (size, count, start) = root_list(&bytes, index);
+            assert_eq!((size, count), (1, len), "{} list {}", name, index);
+            let actual = &bytes[start..start + expected.len()];
+            assert_eq!(actual, expected, "{} list {}", name, index);
+        }
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn empty_lists_carry_only_a_count() {
+    let dir = out_dir("empty-lists");
+    corpus::encode(&dir, false, Layout::Flat).unwrap();
+
+    let bytes = std::fs::read(dir.join(Layout::Flat.file("lists.empty"))).unwrap();
+    // Void, Bool, UInt8, UInt16, UInt32, UInt64, pointer and composite
+    // elements, then Text (its NUL terminator) and Data as byte lists.
+    let expected = [
+        (0, 0),
+        (1, 0),
+        (2, 0),
+        (3, 0),
+        (4, 0),
+        (5, 0),
+        (6, 0),
+        (7, 0),
+        (2, 1),
+        (2, 0),
+    ];
+    for (index, (size, count)) in expected.into_iter().enumerate() {
+        let (actual_size, actual_count, _) = root_list(&bytes, index);
+        assert_eq!(
+            (actual_size, actual_count),
+            (size, count),
+            "pointer {}",
+            index
+        );
+    }
+
+    let bytes = std::fs::read(dir.join(Layout::Flat.file("lists.voids"))).unwrap();
+    let (size, count, _) = root_list(&bytes, 0);
+    assert_eq!((size, count), (0, 100_000));
+    // Segment table, root pointer, the struct's data and pointer sections.
+    assert_eq!(bytes.len(), 8 + 8 + 10 * 8);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn composite_lists_lead_with_a_tag_word() {
+    let dir = out_dir("nested-lists");
+    corpus::encode(&dir, false, Layout::Flat).unwrap();
+    let bytes = std::fs::read(dir.join(Layout::Flat.file("lists.nested"))).unwrap();
+    let word = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+    // Element count, data words and pointers from the tag word at `at`.
+    let tag = |at: usize| {
+        let tag = word(at);
+        assert_eq!(tag & 3, 0, "tag word at byte {} is not struct-shaped", at);
+        (
+            (tag as u32 >> 2) as usize,
+            (tag >> 32 & 0xffff) as usize,
+            (tag >> 48) as usize,
+        )
+    };
+
+    // List(InventoryView): a word count, then one tag for all three views
+    // of one data word and two pointers each.
+    let (size, count, start) = root_list(&bytes, 3);
+    assert_eq!((size, count), (7, 9), "inventories");
+    assert_eq!(tag(start), (3, 1, 2), "inventories tag");
+
+    // List(List(Point)): every inner list has a tag of its own, the empty
+    // one included.
+    let (size, count, start) = root_list(&bytes, 2);
+    assert_eq!((size, count), (6, 3), "polygons");
+    for (i, points) in [3, 0, 1].into_iter().enumerate() {
+        let at = start + i * 8;
+        let pointer = word(at);
+        assert_eq!(pointer & 3, 1, "polygon {} is not a list pointer", i);
+        let target = (at as i64 + 8 + i64::from((pointer as u32 as i32) >> 2) * 8) as usize;
+        assert_eq!(
+            (pointer >> 32 & 7, (pointer >> 35) as usize),
+            (7, points * 2),
+            "polygon {}",
+            i
+        );
+        assert_eq!(tag(target), (points, 1, 1), "polygon {} tag", i);
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// The data and pointer sections of a single-segment message's root struct.
+fn root_sections(bytes: &[u8]) -> (&[u8], Vec<u64>) {
+    assert_eq!(&bytes[..4], &[0; 4], "expected a single segment");
+    let root = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+    assert_eq!(root & 3, 0, "root is not a struct pointer");
+    let (data_words, pointers) = ((root >> 32 & 0xffff) as usize, (root >> 48) as usize);
+    let start = 16 + ((root as u32 as i32) >> 2) as usize * 8;
+    let data = &bytes[start..start + data_words * 8];
+    let pointers = bytes[start + data_words * 8..][..pointers * 8]
+        .chunks_exact(8)
+        .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+    (data, pointers)
+}
+
+#[test]
+fn wide_struct_fields_sit_at_their_schema_offsets() {
+    let dir = out_dir("wide");
+    corpus::encode(&dir, false, Layout::Flat).unwrap();
+    let bytes = std::fs::read(dir.join(Layout::Flat.file("widestruct.full"))).unwrap();
+    let (data, pointers) = root_sections(&bytes);
+    assert_eq!((data.len(), pointers.len()), (27 * 8, 42));
+
+    // (ordinal, byte offset, width): mostly fields packed into holes an
+    // earlier, wider field left, some rounds after it. Every byte of field
+    // `@n` is `n + 1`.
+    let fields = [
+        (1, 8, 8),
+        (3, 1, 1),
+        (4, 2, 2),
+        (6, 4, 4),
+        (12, 20, 1),
+        (14, 22, 2),
+        (19, 21, 1),
+        (28, 42, 1),
+        (30, 52, 2),
+        (35, 43, 1),
+        (36, 54, 2),
+        (41, 76, 4),
+        (60, 94, 1),
+        (67, 122, 1),
+        (76, 123, 1),
+        (78, 126, 2),
+        (99, 174, 1),
+        (106, 208, 8),
+        (108, 175, 1),
+        (110, 194, 2),
+    ];
+    for (n, at, width) in fields {
+        assert_eq!(data[at..at + width], vec![n + 1; width], "field @{}", n);
+    }
+    // The fourteen bools: rounds 0 to 3 fill byte 0, the rest the low six
+    // bits of byte 95, the last hole left when round 4 starts.
+    assert_eq!((data[0], data[95]), (0xff, 0x3f), "bools");
+
+    // Each round's pointers: text, data, List(UInt16), List(Bool), text and
+    // a WideInner, as (element size, count) or, for the struct, (data
+    // words, pointers).
+    for (slot, pointer) in pointers.iter().enumerate() {
+        let n = slot / 6 * 16 + [2, 5, 8, 11, 13, 15][slot % 6];
+        let text = format!("text {}", n).len() + 1;
+        let expected = match slot % 6 {
+            0 | 4 => (1, 2, text),
+            1 => (1, 2, n % 4 + 1),
+            2 => (1, 3, 3),
+            3 => (1, 1, n % 7 + 1),
+            _ => (0, 1, 1),
+        };
+        let actual = match pointer & 3 {
+            1 => (1, pointer >> 32 & 7, (pointer >> 35) as usize),
+            kind => (kind, pointer >> 32 & 0xffff, (pointer >> 48) as usize),
+        };
+        assert_eq!(actual, expected, "pointer {} (field @{})", slot, n);
+    }
+
+    // With only the last round set, both sections keep their full size and
+    // everything before that round is zero.
+    let bytes = std::fs::read(dir.join(Layout::Flat.file("widestruct.last_round"))).unwrap();
+    let (data, pointers) = root_sections(&bytes);
+    assert_eq!((data.len(), pointers.len()), (27 * 8, 42));
+    assert_eq!((data[0], data[95], data[174]), (0, 0x30, 100));
+    assert!(pointers[..36].iter().all(|&pointer| pointer == 0));
+    assert!(pointers[36..].iter().all(|&pointer| pointer != 0));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn annotations_leave_the_layout_alone() {
+    let dir = out_dir("annotated");
+    corpus::encode(&dir, false, Layout::Flat).unwrap();
+    let annotated = std::fs::read(dir.join(Layout::Flat.file("annotated.entry"))).unwrap();
+    let plain = std::fs::read(dir.join(Layout::Flat.file("annotated.plain_entry"))).unwrap();
+    assert_eq!(annotated, plain);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn imports_resolve_to_one_file_however_spelled() {
+    use capnp::introspect::{Introspect, TypeVariant};
+    use capnp::schema::StructSchema;
+    use capnp::schema_capnp::{field, type_};
+    use capnp::traits::HasTypeId;
+    use e2e_rpc_test::game_types_capnp::{item, item_id, player_id};
+    use e2e_rpc_test::market_capnp::listing;
+    use e2e_rpc_test::price_capnp::price;
+
+    fn field_type_id<T: Introspect>(name: &str) -> u64 {
+        let TypeVariant::Struct(raw) = T::introspect().which() else {
+            panic!("not a struct");
+        };
+        let field = StructSchema::new(raw).get_field_by_name(name).unwrap();
+        let field::Slot(slot) = field.get_proto().which().unwrap() else {
+            panic!("{} is not a slot", name);
+        };
+        match slot.get_type().unwrap().which().unwrap() {
+            type_::Struct(s) => s.get_type_id(),
+            _ => panic!("{} is not a struct", name),
+        }
+    }
+
+    // `../game_types.capnp`, `/game_types.capnp` and `../../game_types.capnp`.
+    assert_eq!(
+        field_type_id::<listing::Owned>("item"),
+        item::Reader::TYPE_ID
+    );
+    assert_eq!(
+        field_type_id::<listing::Owned>("seller"),
+        player_id::Reader::TYPE_ID
+    );
+    assert_eq!(
+        field_type_id::<price::Owned>("currency"),
+        item_id::Reader::TYPE_ID
+    );
+}
+
+#[test]
+fn packed_corpus_unpacks_to_the_same_messages() {
+    let (plain, packed) = (out_dir("plain"), out_dir("packed"));
+    corpus::encode(&plain, false, Layout::Flat).unwrap();
+    corpus::encode(&packed, true, Layout::Flat).unwrap();
+    corpus::verify(&packed, true, Layout::Flat).unwrap();
+    for sample in corpus::SAMPLES {
+        let file = format!("{}.bin", sample.name);
+        let packed_bytes = std::fs::read(packed.join(&file)).unwrap();
+        let message = capnp::serialize_packed::read_message(
+            packed_bytes.as_slice(),
+            capnp::message::ReaderOptions::new(),
+        )
+        .unwrap();
+        let mut unpacked = Vec::new();
+        capnp::serialize::write_message_segments(&mut unpacked, &message.into_segments()).unwrap();
+        assert_eq!(
+            unpacked,
+            std::fs::read(plain.join(&file)).unwrap(),
+            "{}",
+            file
+        );
+    }
+    std::fs::remove_dir_all(plain).unwrap();
+    std::fs::remove_dir_all(packed).unwrap();
+}
+
+/// Writes the canonical form of every sample next to it, as the Zig
+/// canonicalizer would.
+fn write_canonical(dir: &std::path::Path) {
+    for sample in corpus::SAMPLES {
+        let bytes = std::fs::read(dir.join(format!("{}.bin", sample.name))).unwrap();
+        let message = capnp::serialize::read_message(bytes.as_slice(), Default::default()).unwrap();
+        let words = message.canonicalize().unwrap();
+        std::fs::write(
+            dir.join(format!("{}.canonical.bin", sample.name)),
+            capnp::Word::words_to_bytes(&words),
+        )
+        .unwrap();
+    }
+}
+
+#[test]
+fn canonicalize_matches_canonical_files() {
+    let dir = out_dir("canonical");
+    corpus::encode(&dir, false, Layout::Flat).unwrap();
+    write_canonical(&dir);
+    corpus::canonicalize(&dir, false).unwrap();
+
+    let path = dir.join("inventory.view.canonical.bin");
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes.push(0);
+    std::fs::write(&path, bytes).unwrap();
+    let err = corpus::canonicalize(&dir, false).unwrap_err();
+    assert_eq!(err.code, ErrorCode::AssertionFailed, "{}", err);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn json_round_trips_to_the_same_messages() {
+    let (bin, json, back) = (out_dir("json-bin"), out_dir("json"), out_dir("json-back"));
+    corpus::encode(&bin, false, Layout::Flat).unwrap();
+    corpus::convert_json(&bin, &json, corpus::Format::Json, false).unwrap();
+    corpus::convert_json(&json, &back, corpus::Format::Binary, false).unwrap();
+    for sample in corpus::SAMPLES {
+        if corpus::NOT_JSON.contains(&sample.name) {
+            assert!(!json.join(format!("{}.json", sample.name)).exists());
+            continue;
+        }
+        let file = format!("{}.bin", sample.name);
+        assert_eq!(
+            std::fs::read(back.join(&file)).unwrap(),
+            std::fs::read(bin.join(&file)).unwrap(),
+            "{}",
+            file
+        );
+    }
+    insta::assert_snapshot!(
+        "chat_message_whisper_json",
+        std::fs::read_to_string(json.join("chat.message_whisper.json")).unwrap()
+    );
+
+    std::fs::write(json.join("game_world.entity.json"), r#"{"kind": "dragon"}"#).unwrap();
+    let err = corpus::convert_json(&json, &back, corpus::Format::Binary, false).unwrap_err();
+    assert_eq!(err.code, ErrorCode::DecodeError, "{}", err);
+    assert!(
+        err.to_string().contains("kind: no enumerant named"),
+        "{}",
+        err
+    );
+    for dir in [bin, json, back] {
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,59 @@
+//! Pipes messages through `echo` in memory.
+
+use capnp::message::{Builder, HeapAllocator, ReaderOptions, ReaderSegments};
+use e2e_rpc_test::echo;
+use e2e_rpc_test::error::ErrorCode;
+use e2e_rpc_test::game_types_capnp::player_info;
+
+fn player(message: &mut Builder<HeapAllocator>) {
+    let mut player = message.init_root::<player_info::Builder<'_>>();
+    player.reborrow().init_id().set_id(42);
+    player.reborrow().set_name("Alice");
+    player.set_level(10);
+}
+
+#[test]
+fn echo_copies_every_message() {
+    let mut input = Vec::new();
+    let mut expected = Vec::new();
+    for _ in 0..3 {
+        let mut message = Builder::new_default();
+        player(&mut message);
+        capnp::serialize::write_message(&mut input, &message).unwrap();
+        capnp::serialize::write_message(&mut expected, &message).unwrap();
+    }
+    let mut output = Vec::new();
+    assert_eq!(echo::run(input.as_slice(), &mut output, false).unwrap(), 3);
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn echo_flattens_far_pointers() {
+    let mut message = Builder::new(HeapAllocator::new().first_segment_words(1));
+    player(&mut message);
+    assert!(message.get_segments_for_output().len() > 1);
+    let mut input = Vec::new();
+    capnp::serialize_packed::write_message(&mut input, &message).unwrap();
+
+    let mut output = Vec::new();
+    echo::run(input.as_slice(), &mut output, true).unwrap();
+    let copy =
+        capnp::serialize_packed::read_message(output.as_slice(), ReaderOptions::new()).unwrap();
+    let player = copy.get_root::<player_info::Reader<'_>>().unwrap();
+    assert_eq!(player.get_name().unwrap(), "Alice");
+    assert_eq!(player.get_id().unwrap().get_id(), 42);
+    assert_eq!(ReaderSegments::len(&copy.into_segments()), 1);
+}
+
+#[test]
+fn echo_rejects_truncated_input() {
+    let mut message = Builder::new_default();
+    player(&mut message);
+    let mut input = Vec::new();
+    capnp::serialize::write_message(&mut input, &message).unwrap();
+    input.truncate(input.len() - 1);
+
+    let err = echo::run(input.as_slice(), Vec::new(), false).unwrap_err();
+    assert_eq!(err.code, ErrorCode::DecodeError, "{}", err);
+    assert!(err.message.starts_with("message 0: "), "{}", err);
+}
@@ -0,0 +1,167 @@
+//! Injects failures into the reference server and checks each exception kind
+//! reaches the client unchanged.
+#![cfg(feature = "net")]
+
+use e2e_rpc_test::error::{ErrorCode, TestError};
+use e2e_rpc_test::faults::{self, Fault};
+use e2e_rpc_test::transport::{Endpoint, Listener};
+use e2e_rpc_test::{client, server};
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    tokio::task::LocalSet::new().block_on(&rt, future)
+}
+
+fn faults(specs: &[&str]) -> Vec<Fault> {
+    specs
+        .iter()
+        .map(|spec| faults::parse_fault(spec).unwrap())
+        .collect()
+}
+
+/// Serves `schema` with `faults` on an ephemeral port.
+async fn start(schema: &str, faults: Vec<Fault>) -> Endpoint {
+    let listener = Listener::bind(&Endpoint::Tcp {
+        host: "127.0.0.1".to_string(),
+        port: 0,
+    })
+    .await
+    .unwrap();
+    let endpoint = listener.local_endpoint().unwrap();
+    let schema = schema.to_string();
+    tokio::task::spawn_local(async move {
+        let options = server::ServeOptions {
+            faults,
+            ..Default::default()
+        };
+        if let Err(e) = server::serve(listener, &schema, None, options).await {
+            eprintln!("server error: {}", e);
+        }
+    });
+    endpoint
+}
+
+/// Runs the client's fault checks for `expected` against a server failing `injected`.
+fn check(schema: &str, injected: &[&str], expected: &[&str]) -> Result<(), TestError> {
+    block_on(async {
+        let endpoint = start(schema, faults(injected)).await;
+        let opts = client::RunOptions {
+            faults: faults(expected),
+            ..Default::default()
+        };
+        client::run(&endpoint, schema, &opts).await
+    })
+}
+
+#[test]
+fn every_kind_reaches_the_client() {
+    let specs = [
+        "GameWorld.spawnEntity=failed",
+        "GameWorld.getEntity=overloaded",
+        "GameWorld.moveEntity=disconnected",
+        "GameWorld.despawnEntity=unimplemented",
+    ];
+    check("game_world", &specs, &specs).unwrap();
+}
+
+#[test]
+fn mismatched_kind_fails() {
+    let err = check(
+        "game_world",
+        &["GameWorld.spawnEntity=overloaded"],
+        &["GameWorld.spawnEntity=failed"],
+    )
+    .unwrap_err();
+    assert_eq!(err.code, ErrorCode::AssertionFailed);
+}
+
+#[test]
+fn uninjected_method_fails() {
+    let err = check("chat", &[], &["ChatService.whisper=overloaded"]).unwrap_err();
+    assert_eq!(err.code, ErrorCode::AssertionFailed);
+}
+
+/// Calls the wrapper lets through still carry capabilities and pipeline.
+#[test]
+fn other_methods_still_work() {
+    let result = block_on(async {
+        let endpoint = start("chat", faults(&["ChatService.getSchemaFingerprint=failed"])).await;
+        client::run_one(
+            &endpoint,
+            "chat",
+            "ChatService.getDefaultRoom pipelines through the forwarded joinRoom",
+        )
+        .await
+    });
+    result.unwrap();
+}
+
+#[test]
+fn rejects_non_bootstrap_methods() {
+    let result = block_on(async {
+        let listener = Listener::bind(&Endpoint::Tcp {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+        })
+        .await
+        .unwrap();
+        let options = server::ServeOptions {
+            faults: faults(&["ChatRoom.sendMessage=failed"]),
+            ..Default::default()
+        };
+        server::serve(listener, "chat", None, options).await
+    });
+    assert_eq!(result.unwrap_err().code, ErrorCode::ConfigError);
+}
+
+#[test]
+fn rejects_bad_specs() {
+    assert!(faults::parse_fault("GameWorld.spawnEntity").is_err());
+    assert!(faults::parse_fault("GameWorld.teleport=failed").is_err());
+    assert!(faults::parse_fault("GameWorld.spawnEntity=permissionDenied").is_err());
+}
+
+/// Runs the client's no-bootstrap check against a server serving `schema`,
+/// with or without its bootstrap capability.
+fn check_no_bootstrap(schema: &str, no_bootstrap: bool) -> Result<(), TestError> {
+    block_on(async {
+        let listener = Listener::bind(&Endpoint::Tcp {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+        })
+        .await
+        .unwrap();
+        let endpoint = listener.local_endpoint().unwrap();
+        let server_schema = schema.to_string();
+        tokio::task::spawn_local(async move {
+            let options = server::ServeOptions {
+                no_bootstrap,
+                ..Default::default()
+            };
+            if let Err(e) = server::serve(listener, &server_schema, None, options).await {
+                eprintln!("server error: {}", e);
+            }
+        });
+        let opts = client::RunOptions {
+            expect_no_bootstrap: true,
+            ..Default::default()
+        };
+        client::run(&endpoint, schema, &opts).await
+    })
+}
+
+#[test]
+fn missing_bootstrap_fails_calls() {
+    for schema in ["game_world", "chat", "registry"] {
+        check_no_bootstrap(schema, true).unwrap();
+    }
+}
+
+#[test]
+fn served_bootstrap_fails_the_check() {
+    let err = check_no_bootstrap("chat", false).unwrap_err();
+    assert_eq!(err.code, ErrorCode::AssertionFailed);
+}
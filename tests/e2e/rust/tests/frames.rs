@@ -0,0 +1,74 @@
+//! Checks captured RPC frames read back through the harness's own `rpc.capnp`.
+
+use e2e_rpc_test::frames;
+use e2e_rpc_test::rpc_capnp::{exception, message};
+
+/// `build` as one framed RPC message.
+fn frame(build: impl FnOnce(message::Builder<'_>)) -> Vec<u8> {
+    let mut msg = capnp::message::Builder::new_default();
+    build(msg.init_root());
+    capnp::serialize::write_message_to_words(&msg)
+}
+
+#[test]
+fn split_reads_each_frame_and_describe_names_it() {
+    let mut bytes = frame(|m| m.init_bootstrap().set_question_id(0));
+    let call_at = bytes.len();
+    bytes.extend(frame(|m| {
+        let mut call = m.init_call();
+        call.set_question_id(1);
+        call.set_interface_id(0xdead_beef);
+        call.set_method_id(2);
+        call.init_target().init_promised_answer().set_question_id(0);
+    }));
+    bytes.extend(frame(|m| {
+        let mut ret = m.init_return();
+        ret.set_answer_id(1);
+        let mut e = ret.init_exception();
+        e.set_type(exception::Type::Overloaded);
+        e.set_reason("busy");
+    }));
+    bytes.extend(frame(|m| m.init_finish().set_question_id(1)));
+    // A frame cut off by the end of the capture.
+    let last = frame(|m| m.init_release().set_id(4));
+    bytes.extend(&last[..last.len() - 3]);
+
+    let frames = frames::split(&bytes).unwrap();
+    assert_eq!(frames.len(), 4);
+    assert_eq!(frames[1].offset, call_at);
+    let lines: Vec<String> = frames
+        .iter()
+        .map(|f| frames::describe(f.root().unwrap()).unwrap())
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            "Bootstrap question=0",
+            "Call question=1 target=answer(0) method=0x00000000deadbeef@2",
+            "Return answer=1 exception overloaded: busy",
+            "Finish question=1",
+        ]
+    );
+}
+
+#[test]
+fn split_rejects_a_frame_that_is_not_a_message() {
+    // A segment table with an impossible segment count.
+    let bytes = [0xff; 16];
+    assert!(frames::split(&bytes).is_err());
+}
+
+#[cfg(feature = "net")]
+#[test]
+fn schema_matches_the_rpc_stack() {
+    use capnp::traits::HasTypeId;
+
+    assert_eq!(
+        message::Reader::TYPE_ID,
+        capnp_rpc::rpc_capnp::message::Reader::TYPE_ID
+    );
+    assert_eq!(
+        exception::Reader::TYPE_ID,
+        capnp_rpc::rpc_capnp::exception::Reader::TYPE_ID
+    );
+}
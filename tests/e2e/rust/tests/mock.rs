@@ -0,0 +1,143 @@
+//! Drives the fixture-backed mock server through the generated clients.
+#![cfg(feature = "net")]
+
+use std::path::{Path, PathBuf};
+
+use capnp::message::Builder;
+use e2e_rpc_test::chat_capnp::{chat_room, chat_service};
+use e2e_rpc_test::error::ErrorCode;
+use e2e_rpc_test::game_types_capnp::StatusCode;
+use e2e_rpc_test::game_world_capnp::game_world;
+use e2e_rpc_test::mock;
+use e2e_rpc_test::transport::{Endpoint, Listener};
+
+fn fixtures_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("e2e-mock-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write_fixture<A: capnp::message::Allocator>(dir: &Path, method: &str, message: &Builder<A>) {
+    let mut bytes = Vec::new();
+    capnp::serialize::write_message(&mut bytes, message).unwrap();
+    std::fs::write(dir.join(format!("{}.bin", method)), bytes).unwrap();
+}
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    tokio::task::LocalSet::new().block_on(&rt, future)
+}
+
+/// Serves `dir` on an ephemeral port and bootstraps a client of type `C`.
+async fn connect<C: capnp::capability::FromClientHook>(dir: &Path) -> C {
+    let endpoint = Endpoint::Tcp {
+        host: "127.0.0.1".to_string(),
+        port: 0,
+    };
+    let listener = Listener::bind(&endpoint).await.unwrap();
+    let Endpoint::Tcp { port, .. } = listener.local_endpoint().unwrap() else {
+        unreachable!()
+    };
+    let dir = dir.to_path_buf();
+    tokio::task::spawn_local(async move {
+        if let Err(e) = mock::serve(listener, &dir, None).await {
+            eprintln!("mock error: {}", e);
+        }
+    });
+
+    let stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+        .await
+        .unwrap();
+    let (reader, writer) =
+        futures::AsyncReadExt::split(tokio_util::compat::TokioAsyncReadCompatExt::compat(stream));
+    let network = capnp_rpc::twoparty::VatNetwork::new(
+        reader,
+        writer,
+        capnp_rpc::rpc_twoparty_capnp::Side::Client,
+        Default::default(),
+    );
+    let mut rpc_system = capnp_rpc::RpcSystem::new(Box::new(network), None);
+    let client = rpc_system.bootstrap(capnp_rpc::rpc_twoparty_capnp::Side::Server);
+    tokio::task::spawn_local(rpc_system);
+    client
+}
+
+#[test]
+fn answers_from_fixtures() {
+    let dir = fixtures_dir("answers");
+    let mut message = Builder::new_default();
+    {
+        let mut results = message.init_root::<game_world::get_entity_results::Builder>();
+        let mut entity = results.reborrow().init_entity();
+        entity.reborrow().init_id().set_id(42);
+        entity.set_health(-5);
+        results.set_status(StatusCode::NotFound);
+    }
+    write_fixture(&dir, "GameWorld.getEntity", &message);
+    std::fs::write(dir.join("GameWorld.despawnEntity.err"), "despawn refused\n").unwrap();
+
+    block_on(async {
+        let gw: game_world::Client = connect(&dir).await;
+
+        let response = gw.get_entity_request().send().promise.await.unwrap();
+        let results = response.get().unwrap();
+        assert_eq!(results.get_entity().unwrap().get_id().unwrap().get_id(), 42);
+        assert_eq!(results.get_entity().unwrap().get_health(), -5);
+        assert_eq!(results.get_status().unwrap(), StatusCode::NotFound);
+
+        let err = gw
+            .despawn_entity_request()
+            .send()
+            .promise
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("despawn refused"), "{}", err);
+
+        let err = gw.move_entity_request().send().promise.await.err().unwrap();
+        assert_eq!(err.kind, capnp::ErrorKind::Unimplemented);
+    });
+}
+
+#[test]
+fn capability_results_are_mocked() {
+    let dir = fixtures_dir("caps");
+    let mut message = Builder::new_default();
+    message.init_root::<chat_service::create_room_results::Builder>();
+    write_fixture(&dir, "ChatService.createRoom", &message);
+    let mut message = Builder::new_default();
+    message
+        .init_root::<chat_room::get_info_results::Builder>()
+        .init_info()
+        .set_name("fixture-room");
+    write_fixture(&dir, "ChatRoom.getInfo", &message);
+
+    block_on(async {
+        let chat: chat_service::Client = connect(&dir).await;
+        let response = chat.create_room_request().send().promise.await.unwrap();
+        let room = response.get().unwrap().get_room().unwrap();
+        let info = room.get_info_request().send().promise.await.unwrap();
+        let name = info.get().unwrap().get_info().unwrap().get_name().unwrap();
+        assert_eq!(name.to_str().unwrap(), "fixture-room");
+    });
+}
+
+#[test]
+fn rejects_unknown_fixture_names() {
+    let dir = fixtures_dir("unknown");
+    std::fs::write(dir.join("GameWorld.teleport.err"), "nope").unwrap();
+
+    let result = block_on(async {
+        let endpoint = Endpoint::Tcp {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+        };
+        let listener = Listener::bind(&endpoint).await.unwrap();
+        mock::serve(listener, &dir, None).await
+    });
+    assert_eq!(result.unwrap_err().code, ErrorCode::ConfigError);
+}
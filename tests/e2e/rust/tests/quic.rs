@@ -0,0 +1,78 @@
+//! Runs a suite over the experimental QUIC transport, with the certificates in
+//! `tests/tls/`.
+#![cfg(feature = "quic")]
+
+use std::path::PathBuf;
+
+use e2e_rpc_test::client::{self, ConnectOptions, RunOptions};
+use e2e_rpc_test::server;
+use e2e_rpc_test::tls::{self, TlsArgs};
+use e2e_rpc_test::transport::Listener;
+
+fn cert(name: &str) -> Option<PathBuf> {
+    Some(
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/tls")
+            .join(name),
+    )
+}
+
+#[test]
+fn suite_runs_over_quic() {
+    let acceptor = tls::acceptor(&TlsArgs {
+        tls: true,
+        cert: cert("server.pem"),
+        key: cert("server.key"),
+        ca: None,
+    })
+    .unwrap()
+    .unwrap();
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let result = tokio::task::LocalSet::new().block_on(&rt, async {
+        let listener = Listener::bind_quic("127.0.0.1", 0, &acceptor)
+            .await
+            .unwrap();
+        let endpoint = listener.local_endpoint().unwrap();
+        tokio::task::spawn_local(async move {
+            if let Err(e) = server::serve(listener, "game_world", Some(1), Default::default()).await
+            {
+                eprintln!("server error: {}", e);
+            }
+        });
+
+        let connector = tls::connector(
+            &TlsArgs {
+                tls: true,
+                ca: cert("ca.pem"),
+                ..Default::default()
+            },
+            &endpoint,
+        )
+        .unwrap();
+        let opts = RunOptions {
+            artifacts_dir: None,
+            repeat: 1,
+            protocol_version: Some(1),
+            faults: Vec::new(),
+            expect_no_bootstrap: false,
+            keepalive: None,
+            fixed_time: None,
+            big: false,
+            report: Default::default(),
+            filter: None,
+            fail_fast: false,
+            test_timeout: None,
+            retries: 0,
+            connect: ConnectOptions {
+                tls: connector,
+                ..Default::default()
+            },
+        };
+        client::run(&endpoint, "game_world", &opts).await
+    });
+    result.unwrap();
+}
@@ -0,0 +1,335 @@
+//! Snapshot tests for the TAP reporter. Snapshots are stored as raw `.tap` files
+//! and compared byte-for-byte; review changes with `cargo insta review`.
+#![cfg(feature = "net")]
+
+use e2e_rpc_test::error::{ErrorCode, TestError};
+use e2e_rpc_test::game_types_capnp::{player_info, Faction};
+use e2e_rpc_test::report::{self, ReportArgs, ReportFormat, TapReporter};
+
+/// Runs `script` against a reporter planned for `total` tests and returns the
+/// emitted TAP together with the run outcome.
+fn render(total: u32, script: impl FnOnce(&mut TapReporter<&mut Vec<u8>>)) -> (Vec<u8>, String) {
+    let mut out = Vec::new();
+    let mut tap = TapReporter::with_writer(&mut out, total);
+    script(&mut tap);
+    let outcome = match tap.done() {
+        Ok(()) => "ok".to_string(),
+        Err(e) => e.to_string(),
+    };
+    (out, outcome)
+}
+
+#[test]
+fn all_passing() {
+    let (tap, outcome) = render(3, |tap| {
+        tap.ok("GameWorld.spawnEntity creates entity");
+        tap.pass_or_fail("GameWorld.getEntity retrieves entity", Ok(()));
+        tap.ok("GameWorld.queryArea finds entities");
+    });
+    assert_eq!(outcome, "ok");
+    insta::assert_binary_snapshot!(".tap", tap);
+}
+
+#[test]
+fn mixed_results() {
+    let (tap, outcome) = render(6, |tap| {
+        tap.ok("ChatService.createRoom creates room");
+        tap.not_ok(
+            "ChatRoom.sendMessage delivers",
+            &TestError::new(
+                ErrorCode::AssertionFailed,
+                "message text: expected \"hello\", got \"\"",
+            ),
+        );
+        tap.skip("ChatRoom.sendEmote works", "not supported by peer");
+        tap.comment("flaky: ChatRoom.getHistory returns messages failed 1/3 (33.3%)");
+        tap.pass_or_fail(
+            "ChatService.listRooms lists rooms",
+            Err(TestError::new(
+                ErrorCode::RpcException,
+                "Failed: remote exception: room not found",
+            )),
+        );
+        tap.ok("ChatService.whisper sends DM");
+        tap.pass_or_fail(
+            "ChatRoom.leave reduces members",
+            Err(TestError::new(
+                ErrorCode::Timeout,
+                "timed out after 5000 ms",
+            )),
+        );
+    });
+    assert_eq!(outcome, "3 of 6 tests failed [assertion-failed]");
+    insta::assert_binary_snapshot!(".tap", tap);
+}
+
+#[test]
+fn only_skips() {
+    let (tap, outcome) = render(2, |tap| {
+        tap.skip(
+            "Handshake: peers agree on protocol version",
+            "no --protocol-version",
+        );
+        tap.skip(
+            "Handshake: wrong protocol version is rejected",
+            "no --protocol-version",
+        );
+    });
+    assert_eq!(outcome, "ok");
+    insta::assert_binary_snapshot!(".tap", tap);
+}
+
+#[test]
+fn todos() {
+    let (tap, outcome) = render(3, |tap| {
+        tap.ok("Three-party: room minted on A answers B through C");
+        tap.todo(
+            "Three-party: C hands B a third-party capability",
+            "capnp-rpc has no level 3",
+            Err(TestError::new(
+                ErrorCode::AssertionFailed,
+                "no thirdPartyHosted descriptor from C",
+            )),
+        );
+        tap.todo(
+            "Three-party: B calls A directly after Accept",
+            "capnp-rpc has no level 3",
+            Ok(()),
+        );
+    });
+    assert_eq!(outcome, "ok");
+    insta::assert_binary_snapshot!(".tap", tap);
+}
+
+#[test]
+fn short_run() {
+    let (tap, outcome) = render(4, |tap| {
+        tap.ok("MatchmakingService.enqueue works");
+        tap.not_ok(
+            "MatchmakingService.dequeue works",
+            &TestError::new(
+                ErrorCode::ConnectFailed,
+                "Connection reset by peer (os error 104)",
+            ),
+        );
+    });
+    assert_eq!(outcome, "1 of 2 tests failed [connect-failed]");
+    insta::assert_binary_snapshot!(".tap", tap);
+}
+
+/// Messages carrying YAML syntax and line breaks stay one quoted scalar.
+#[test]
+fn message_with_yaml_syntax() {
+    let (tap, outcome) = render(1, |tap| {
+        tap.not_ok(
+            "ChatRoom.sendMessage delivers",
+            &TestError::new(
+                ErrorCode::AssertionFailed,
+                "content: expected \"# not a comment\", got:\n  - C:\\rooms\tlobby",
+            ),
+        );
+    });
+    assert_eq!(outcome, "1 of 1 tests failed [assertion-failed]");
+    insta::assert_binary_snapshot!(".tap", tap);
+}
+
+#[test]
+fn failure_with_struct_dump() {
+    let mut message = capnp::message::Builder::new_default();
+    let mut player = message.init_root::<player_info::Builder<'_>>();
+    player.reborrow().init_id().set_id(42);
+    player.reborrow().set_name("Alice");
+    player.reborrow().set_faction(Faction::Alliance);
+    player.set_level(10);
+    let player = player.into_reader();
+
+    let (tap, outcome) = render(1, |tap| {
+        tap.not_ok(
+            "ChatService.joinRoom works",
+            &TestError::new(ErrorCode::AssertionFailed, "level: expected 12, got 10")
+                .with_dump(player),
+        );
+    });
+    assert_eq!(outcome, "1 of 1 tests failed [assertion-failed]");
+    insta::assert_binary_snapshot!(".tap", tap);
+}
+
+#[test]
+fn bail_out() {
+    let mut tap = Vec::new();
+    report::bail_out(
+        &mut tap,
+        "schema fingerprint mismatch: local 0x0123456789abcdef, peer 0xfedcba9876543210",
+    );
+    insta::assert_binary_snapshot!(".tap", tap);
+}
+
+/// `xml` with every `time="…"` value replaced by `T`.
+fn strip_times(xml: &str) -> String {
+    let mut out = String::new();
+    let mut rest = xml;
+    while let Some(at) = rest.find("time=\"") {
+        out.push_str(&rest[..at]);
+        out.push_str("time=\"T");
+        rest = &rest[at + 6..];
+        rest = &rest[rest.find('"').unwrap()..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[test]
+fn junit_report() {
+    let path = std::env::temp_dir().join(format!("e2e-junit-{}.xml", std::process::id()));
+    let args = ReportArgs {
+        report: Some(ReportFormat::Junit),
+        report_path: Some(path.clone()),
+    };
+    let (_, outcome) = render(4, |tap| {
+        tap.set_report(args.open("chat").unwrap().unwrap());
+        tap.ok("ChatService.createRoom creates room");
+        tap.not_ok(
+            "ChatRoom.sendMessage delivers <text>",
+            &TestError::new(
+                ErrorCode::AssertionFailed,
+                "message text: expected \"hello\", got \"\"",
+            ),
+        );
+        tap.skip("ChatRoom.sendEmote works", "not supported by peer");
+        tap.todo(
+            "ChatRoom.history survives a restart",
+            "no persistence yet",
+            Err(TestError::new(ErrorCode::AssertionFailed, "history empty")),
+        );
+    });
+    assert_eq!(outcome, "1 of 4 tests failed [assertion-failed]");
+    let xml = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        strip_times(&xml),
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="e2e-rpc-test" tests="4" failures="1" errors="0" skipped="2" time="T">
+  <testsuite name="chat" tests="4" failures="1" errors="0" skipped="2" time="T">
+    <testcase classname="chat" name="ChatService.createRoom creates room" time="T"/>
+    <testcase classname="chat" name="ChatRoom.sendMessage delivers &lt;text&gt;" time="T">
+      <failure message="message text: expected &quot;hello&quot;, got &quot;&quot;" type="assertion-failed">message text: expected &quot;hello&quot;, got &quot;&quot;</failure>
+    </testcase>
+    <testcase classname="chat" name="ChatRoom.sendEmote works" time="T">
+      <skipped message="not supported by peer"/>
+    </testcase>
+    <testcase classname="chat" name="ChatRoom.history survives a restart" time="T">
+      <skipped message="TODO no persistence yet: history empty"/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+    );
+}
+
+#[test]
+fn jsonl_report() {
+    let path = std::env::temp_dir().join(format!("e2e-jsonl-{}.jsonl", std::process::id()));
+    let args = ReportArgs {
+        report: Some(ReportFormat::Jsonl),
+        report_path: Some(path.clone()),
+    };
+    let mut lines_before_done = 0;
+    let (_, outcome) = render(3, |tap| {
+        tap.set_report(args.open("lists").unwrap().unwrap());
+        tap.ok("ListService.echo keeps every element");
+        tap.not_ok(
+            "ListService.echoBlobs keeps Text and Data byte for byte",
+            &TestError::new(ErrorCode::AssertionFailed, "blob 2: text lost its NUL"),
+        );
+        tap.skip("ListService.echo big lists", "needs --big");
+        // Streamed, not held until the run ends.
+        lines_before_done = std::fs::read_to_string(&path).unwrap().lines().count();
+    });
+    assert_eq!(outcome, "1 of 3 tests failed [assertion-failed]");
+    assert_eq!(lines_before_done, 3);
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut lines: Vec<serde_json::Value> = text
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    for line in &mut lines {
+        assert!(line["duration_ms"].as_f64().unwrap() >= 0.0);
+        line.as_object_mut().unwrap().remove("duration_ms");
+    }
+    assert_eq!(
+        lines,
+        [
+            serde_json::json!({
+                "suite": "lists",
+                "test": 1,
+                "name": "ListService.echo keeps every element",
+                "status": "pass",
+            }),
+            serde_json::json!({
+                "suite": "lists",
+                "test": 2,
+                "name": "ListService.echoBlobs keeps Text and Data byte for byte",
+                "status": "fail",
+                "code": "assertion-failed",
+                "message": "blob 2: text lost its NUL",
+            }),
+            serde_json::json!({
+                "suite": "lists",
+                "test": 3,
+                "name": "ListService.echo big lists",
+                "status": "skip",
+                "reason": "needs --big",
+            }),
+        ]
+    );
+}
+
+#[test]
+fn bail_out_mid_run() {
+    let mut out = Vec::new();
+    let mut tap = TapReporter::with_writer(&mut out, 3);
+    tap.ok("Inventory.add stacks items");
+    tap.not_ok(
+        "Inventory.remove takes items",
+        &TestError::new(
+            ErrorCode::RpcException,
+            "Failed: remote exception: bad state",
+        ),
+    );
+    assert!(tap.failed());
+    let err = tap
+        .bail_out("--fail-fast: Inventory.remove takes items failed")
+        .unwrap_err();
+    assert_eq!(err.code, ErrorCode::RpcException);
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "TAP version 14\n\
+         1..3\n\
+         ok 1 - Inventory.add stacks items\n\
+         not ok 2 - Inventory.remove takes items\n  \
+         ---\n  \
+         message: Failed: remote exception: bad state\n  \
+         code: rpc-exception\n  \
+         ...\n\
+         Bail out! --fail-fast: Inventory.remove takes items failed\n"
+    );
+}
+
+#[test]
+fn retried_test_passes_with_a_directive() {
+    let mut out = Vec::new();
+    let mut tap = TapReporter::with_writer(&mut out, 2);
+    tap.ok("Inventory.add stacks items");
+    tap.ok_retried("Inventory.remove takes items", 3);
+    assert!(!tap.failed());
+    tap.done().unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "TAP version 14\n\
+         1..2\n\
+         ok 1 - Inventory.add stacks items\n\
+         ok 2 - Inventory.remove takes items # RETRIED passed on attempt 3\n"
+    );
+}
@@ -0,0 +1,122 @@
+//! Checks `schema-dump` output against the code capnpc-rust generated from
+//! the same schemas.
+
+use std::path::Path;
+
+use capnp::traits::HasTypeId;
+use e2e_rpc_test::schema_dump;
+use serde_json::{json, Value};
+
+fn dump() -> Value {
+    let request =
+        schema_dump::compile(Path::new("capnp"), Path::new(schema_dump::SCHEMA_DIR), &[]).unwrap();
+    schema_dump::dump_bytes(&request).unwrap()
+}
+
+fn node<'a>(dump: &'a Value, display_name: &str) -> &'a Value {
+    dump["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|node| node["displayName"] == display_name)
+        .unwrap_or_else(|| panic!("no node {}", display_name))
+}
+
+#[test]
+fn blob_fields_sit_where_generated_code_reads_them() {
+    let dump = dump();
+    let blob = node(&dump, "lists.capnp:Blob");
+    assert_eq!(
+        blob["id"],
+        format!("{:#018x}", e2e_rpc_test::lists_capnp::blob::Reader::TYPE_ID)
+    );
+    assert_eq!(blob["kind"], "struct");
+    assert_eq!(blob["dataWordCount"], 0);
+    assert_eq!(blob["pointerCount"], 2);
+    assert_eq!(
+        blob["fields"],
+        json!([
+            {"name": "text", "codeOrder": 0, "offset": 0, "type": "Text", "hadExplicitDefault": false},
+            {"name": "data", "codeOrder": 1, "offset": 1, "type": "Data", "hadExplicitDefault": false},
+        ])
+    );
+}
+
+#[test]
+fn annotations_leave_layout_alone() {
+    let dump = dump();
+    // Group node IDs differ between the twins; everything else must not.
+    let layout = |name: &str| {
+        let mut node = node(&dump, name).clone();
+        for key in ["id", "displayName", "scopeId"] {
+            node.as_object_mut().unwrap().remove(key);
+        }
+        for field in node["fields"].as_array_mut().unwrap() {
+            field.as_object_mut().unwrap().remove("group");
+        }
+        node
+    };
+    assert_eq!(
+        layout("annotated.capnp:Entry"),
+        layout("annotated.capnp:PlainEntry")
+    );
+    assert_eq!(
+        layout("annotated.capnp:Entry.source"),
+        layout("annotated.capnp:PlainEntry.source")
+    );
+
+    let source = node(&dump, "annotated.capnp:Entry.source");
+    assert_eq!(source["isGroup"], true);
+    assert_eq!(source["discriminantCount"], 3);
+    let discriminants: Vec<&Value> = source["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|field| &field["discriminantValue"])
+        .collect();
+    assert_eq!(discriminants, [&json!(0), &json!(1), &json!(2)]);
+}
+
+#[test]
+fn every_requested_file_is_listed() {
+    let dump = dump();
+    let names: Vec<&str> = dump["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| file["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, schema_dump::FILES);
+}
+
+#[test]
+fn schema_ids_match_the_compiler() {
+    let dump = dump();
+    let nodes = dump["nodes"].as_array().unwrap();
+    for file in e2e_rpc_test::SCHEMA_IDS {
+        let file_node = node(&dump, file.file);
+        assert_eq!(file_node["id"], format!("{:#018x}", file.id));
+        let prefix = format!("{}:", file.file);
+        let types: Vec<(String, &str)> = nodes
+            .iter()
+            .filter_map(|node| {
+                let name = node["displayName"].as_str()?.strip_prefix(&prefix)?;
+                Some((node["id"].as_str()?.to_string(), name))
+            })
+            .collect();
+        let expected: Vec<(String, &str)> = file
+            .types
+            .iter()
+            .map(|&(id, name)| (format!("{:#018x}", id), name))
+            .collect();
+        assert_eq!(types, expected, "{}", file.file);
+    }
+
+    let lists = e2e_rpc_test::SCHEMA_IDS
+        .iter()
+        .find(|file| file.file == "lists.capnp")
+        .unwrap();
+    assert!(lists
+        .types
+        .contains(&(e2e_rpc_test::lists_capnp::blob::Reader::TYPE_ID, "Blob")));
+}
@@ -0,0 +1,616 @@
+//! Runs every registered client test against an in-process reference server,
+//! one `#[test]` per case so `cargo nextest run` can schedule and retry them.
+#![cfg(feature = "net")]
+
+use e2e_rpc_test::transport::{Endpoint, Listener};
+use e2e_rpc_test::{client, server, ReaderLimits};
+
+/// Starts a fresh server for `schema` on an ephemeral port and runs one named test.
+fn run_case(schema: &str, name: &str) {
+    run_case_on(
+        Endpoint::Tcp {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+        },
+        schema,
+        name,
+        None,
+    );
+}
+
+fn run_case_on(endpoint: Endpoint, schema: &str, name: &str, first_segment_words: Option<u32>) {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let local = tokio::task::LocalSet::new();
+    let result = local.block_on(&rt, async {
+        let listener = Listener::bind(&endpoint).await.unwrap();
+        let endpoint = listener.local_endpoint().unwrap();
+        let schema_name = schema.to_string();
+        tokio::task::spawn_local(async move {
+            let options = server::ServeOptions {
+                first_segment_words,
+                ..Default::default()
+            };
+            if let Err(e) = server::serve(listener, &schema_name, None, options).await {
+                eprintln!("server error: {}", e);
+            }
+        });
+        client::run_one(&endpoint, schema, name).await
+    });
+    if let Err(e) = result {
+        panic!("{}: {}", name, e);
+    }
+}
+
+macro_rules! suite {
+    ($module:ident, $schema:literal, { $($test:ident => $name:literal,)* }) => {
+        mod $module {
+            $(
+                #[test]
+                fn $test() {
+                    super::run_case($schema, $name);
+                }
+            )*
+
+            #[test]
+            fn covers_registry() {
+                let wrapped: Vec<&str> = vec![$($name),*];
+                assert_eq!(e2e_rpc_test::client::test_names($schema).unwrap(), wrapped);
+            }
+        }
+    };
+}
+
+suite!(game_world, "game_world", {
+    spawn_entity => "GameWorld.spawnEntity creates entity",
+    get_entity => "GameWorld.getEntity retrieves entity",
+    move_entity => "GameWorld.moveEntity updates position",
+    damage_entity => "GameWorld.damageEntity reduces health",
+    damage_kill => "GameWorld.damageEntity can kill",
+    despawn_entity => "GameWorld.despawnEntity removes entity",
+    query_area => "GameWorld.queryArea finds entities",
+    watch_spawns => "GameWorld.watchArea reports spawns in the area",
+    watch_moves => "GameWorld.watchArea reports moves in order",
+    handle_move => "EntityHandle.move moves the entity",
+    handle_damage_kill => "EntityHandle.damage can kill",
+    handle_despawned => "EntityHandle.getSnapshot reports a despawned entity",
+    many_handles => "GameWorld keeps a handle per spawned entity",
+    reserved => "GameWorld.reserved fails as unimplemented",
+    entity_id_boundaries => "GameWorld keeps entity IDs distinct at the 64-bit boundary",
+    edge_float_positions => "GameWorld keeps special float positions bit for bit",
+    ping => "GameWorld.ping echoes its nonce",
+});
+
+suite!(chat, "chat", {
+    create_room => "ChatService.createRoom creates room",
+    join_and_send => "ChatRoom.sendMessage delivers",
+    send_emote => "ChatRoom.sendEmote works",
+    get_history => "ChatRoom.getHistory returns messages",
+    list_rooms => "ChatService.listRooms lists rooms",
+    whisper => "ChatService.whisper sends DM",
+    leave_room => "ChatRoom.leave reduces members",
+    subscribe => "ChatRoom.subscribe calls back into the client",
+    subscribe_order => "ChatRoom.subscribe delivers in send order",
+    pipelined_room_order => "ChatRoom keeps E-order across a pipelined joinRoom",
+    call_arrival_order => "ChatRoom receives unawaited calls in send order",
+    default_room => "ChatService.getDefaultRoom joins the lobby",
+    default_room_pipelined => "ChatService.getDefaultRoom pipelines through the forwarded joinRoom",
+    whisper_via_joined => "ChatService.whisperVia takes a room still pending from joinRoom",
+    whisper_via_default_room => "ChatService.whisperVia takes a room still pending from getDefaultRoom",
+    describe_room => "ChatService.describeRoom reads a room it handed out",
+    get_all_rooms => "ChatService.getAllRooms returns a working capability per room",
+    moderated_room => "ModeratedChatRoom takes ChatRoom and its own calls on one capability",
+    moderated_downcast => "ModeratedChatRoom calls on a plain ChatRoom are unimplemented",
+    text_multi_byte => "ChatRoom keeps multi-byte UTF-8 intact",
+    text_surrogate_adjacent => "ChatRoom keeps surrogate-adjacent code points intact",
+    text_combining => "ChatRoom keeps combining sequences intact",
+    text_max_length => "ChatRoom keeps maximum-length content intact",
+    message_timestamps => "ChatRoom stamps messages with the server's time",
+    ping => "ChatService.ping echoes its nonce",
+});
+
+suite!(inventory, "inventory", {
+    add_item => "InventoryService.addItem works",
+    get_inventory => "InventoryService.getInventory works",
+    remove_item => "InventoryService.removeItem works",
+    filter_by_rarity => "InventoryService.filterByRarity works",
+    start_trade => "InventoryService.startTrade works",
+    trade_flow => "TradeSession full flow",
+    icon_byte_values => "InventorySlot.icon keeps every byte value",
+    icon_nuls => "InventorySlot.icon keeps NUL-only data",
+    icon_large => "InventorySlot.icon keeps a 1 MiB blob",
+    subscribe => "InventoryService.subscribe reports adds and removals",
+    subscribe_per_player => "InventoryService.subscribe reports only the player's changes",
+    ping => "InventoryService.ping echoes its nonce",
+});
+
+suite!(matchmaking, "matchmaking", {
+    enqueue => "MatchmakingService.enqueue works",
+    dequeue => "MatchmakingService.dequeue works",
+    find_match => "MatchmakingService.findMatch works",
+    match_controller => "MatchController signalReady+getInfo",
+    queue_stats => "MatchmakingService.getQueueStats works",
+    player_id_boundaries => "MatchmakingService keeps player IDs intact at the 64-bit boundary",
+    match_id_boundaries => "MatchmakingService.getMatchResult misses boundary match IDs",
+    timestamps => "MatchmakingService stamps tickets and matches with the server's time",
+    ping => "MatchmakingService.ping echoes its nonce",
+});
+
+suite!(nesting, "nesting", {
+    measure_shallow => "NestingService.measure reads a shallow chain",
+    measure_deep => "NestingService.measure rejects a deep chain",
+    chain_shallow => "NestingService.chain returns a shallow chain",
+    chain_deep => "Client rejects a deep chain result",
+    ping => "NestingService.ping echoes its nonce",
+});
+
+suite!(defaults, "defaults", {
+    defaults => "DefaultsService.defaults encodes defaults as zero",
+    echo_defaults => "DefaultsService.echo keeps fields at their defaults",
+    echo_zeros => "DefaultsService.echo round-trips zeros",
+    echo_edge_floats => "DefaultsService.echo keeps special floats bit for bit",
+    ping => "DefaultsService.ping echoes its nonce",
+});
+
+suite!(lists, "lists", {
+    empty => "ListService.empty returns empty lists",
+    echo_empty => "ListService.echo keeps empty lists empty",
+    voids => "ListService.voids returns a long List(Void)",
+    echo_voids => "ListService.echo round-trips a long List(Void)",
+    echo_nested => "ListService.echoNested keeps ragged nested lists",
+    echo_nested_empty => "ListService.echoNested keeps lists of empty lists",
+    echo_invalid_utf8 => "ListService.echo keeps Text that is not UTF-8 byte for byte",
+    echo_embedded_nul => "ListService.echo keeps NULs embedded in Text",
+    echo_blobs => "ListService.echoBlobs keeps Text and Data byte for byte",
+    echo_big_longs => "ListService.echo keeps a million-element List(UInt64)",
+    echo_big_points => "ListService.echo keeps a hundred-thousand-element struct list",
+    ping => "ListService.ping echoes its nonce",
+});
+
+suite!(ordering, "ordering", {
+    e_order_remote => "CallOrder keeps E-order on a promised server capability",
+    e_order_embargo => "CallOrder keeps E-order through an embargo",
+    reflect_remote => "OrderingService.reflect forwards to a server capability",
+    ping => "OrderingService.ping echoes its nonce",
+    id_reuse => "Stress: reused question ids never mix up replies",
+    id_churn => "Stress: 200,000 pings on one connection never mix up replies",
+});
+
+suite!(telemetry, "telemetry", {
+    stream_upload => "TelemetrySink.uploadEvents completes under flow control",
+    stream_error => "TelemetrySink.uploadEvents fails the stream after an error",
+    stream_backpressure => "TelemetrySink.uploadEvents stays within the flow window of a slow sink",
+    ping => "TelemetryService.ping echoes its nonce",
+});
+
+suite!(registry, "registry", {
+    list => "ServiceRegistry.list names every service",
+    game_world => "ServiceRegistry.get serves game_world",
+    chat => "ServiceRegistry.get serves chat",
+    inventory => "ServiceRegistry.get serves inventory",
+    matchmaking => "ServiceRegistry.get serves matchmaking",
+    nesting => "ServiceRegistry.get serves nesting",
+    defaults => "ServiceRegistry.get serves defaults",
+    lists => "ServiceRegistry.get serves lists",
+    ordering => "ServiceRegistry.get serves ordering",
+    telemetry => "ServiceRegistry.get serves telemetry",
+    debug => "ServiceRegistry.get serves debug",
+    linked => "ServiceRegistry.get serves linked",
+    generics => "ServiceRegistry.get serves generics",
+    envelope => "ServiceRegistry.get serves envelope",
+    spells => "ServiceRegistry.get serves spells",
+    forecast => "ServiceRegistry.get serves forecast",
+    evolution => "ServiceRegistry.get serves evolution",
+    annotated => "ServiceRegistry.get serves annotated",
+    commands => "ServiceRegistry.get serves commands",
+    market => "ServiceRegistry.get serves market",
+    deep => "ServiceRegistry.get serves deep",
+    pipelined => "ServiceRegistry.get pipelines into the service",
+    pipelined_chain => "ServiceRegistry.get pipelines through two levels of capabilities",
+    unknown => "ServiceRegistry.get rejects unknown names",
+    release_rooms => "DebugStats: released ChatRooms leave no exports",
+    release_trade_sessions => "DebugStats: released TradeSessions leave no exports",
+    release_controllers => "DebugStats: released MatchControllers leave no exports",
+    stress_rooms => "DebugStats: 2000 concurrent ChatRooms are exported and released",
+    stress_trade_sessions => "DebugStats: 2000 concurrent TradeSessions are exported and released",
+    describe_room_locally => "DebugStats: ChatService.describeRoom makes no call on the room",
+    ping => "ServiceRegistry.ping echoes its nonce",
+});
+
+suite!(debug, "debug", {
+    call_counts => "DebugStats.getStats counts calls per method",
+    in_flight => "DebugStats.getStats counts its own call as an answer",
+    ping => "DebugStats.ping echoes its nonce",
+});
+
+suite!(linked, "linked", {
+    pipelined => "Node.next pipelines 100 levels deep",
+    end => "Node.next fails past the end of the chain",
+    empty => "LinkedList.head rejects an empty chain",
+    ping => "LinkedList.ping echoes its nonce",
+});
+
+suite!(generics, "generics", {
+    text_repository => "Repository(Text) puts, replaces and gets text",
+    data_repository => "Repository(Data) keeps every byte value",
+    labeled_repository => "Repository(Labeled) keeps struct values",
+    pair_repository => "Repository(Pair(Text, Data)) keeps generic struct values",
+    repository_entries => "Repository.entries lists Pair(Text, T) in key order",
+    swap => "GenericsService.swap turns a Pair around",
+    ping => "GenericsService.ping echoes its nonce",
+});
+
+suite!(envelope, "envelope", {
+    relay_struct => "EnvelopeService.relay keeps a struct payload",
+    relay_lists => "EnvelopeService.relay keeps list payloads",
+    relay_text_and_data => "EnvelopeService.relay keeps text and data payloads",
+    relay_null => "EnvelopeService.relay keeps a null payload",
+    relay_client_capability => "EnvelopeService.relay hands back a client capability payload",
+    relay_server_capability => "EnvelopeService.relay hands back a server capability payload",
+    relay_nested => "EnvelopeService.relay keeps a capability in a nested payload",
+    ping => "EnvelopeService.ping echoes its nonce",
+});
+
+suite!(spells, "spells", {
+    echo => "SpellService.echo keeps every corpus spell intact",
+    describe => "SpellService.describe reads the member of every nested union",
+    ping => "SpellService.ping echoes its nonce",
+});
+
+suite!(forecast, "forecast", {
+    classify_known => "ForecastService.classify knows this revision's enumerants",
+    classify_unknown => "ForecastService.classify reads later enumerants as unknown",
+    echo => "ForecastService.echo keeps enumerants it does not know",
+    outlook => "ForecastService.outlook returns enumerants this revision lacks",
+    ping => "ForecastService.ping echoes its nonce",
+});
+
+suite!(evolution, "evolution", {
+    echo_later => "ProfileService.echo keeps fields this revision lacks",
+    echo_earlier => "ProfileService.echo of an earlier profile reads with defaults",
+    describe_later => "ProfileService.describe reads the fields it knows of a later profile",
+    upgraded => "ProfileService.upgraded returns fields this revision lacks",
+    ping => "ProfileService.ping echoes its nonce",
+});
+
+suite!(annotated, "annotated", {
+    record => "Ledger.record returns the balance of each currency",
+    history => "Ledger.history returns entries as recorded",
+    ping => "Ledger.ping echoes its nonce",
+});
+
+suite!(commands, "commands", {
+    describe => "CommandService.describe names every union member",
+    echo => "CommandService.echo keeps every union member and its payload",
+    overwrite => "CommandService.describe reads the member set last",
+    ping => "CommandService.ping echoes its nonce",
+});
+
+suite!(market, "market", {
+    echo => "Market.echo keeps types from relative and absolute imports",
+    quote => "Market.quote prices every rarity",
+    ping => "Market.ping echoes its nonce",
+});
+
+suite!(deep, "deep", {
+    echo_balanced => "DeepService.echo keeps a balanced 32-deep tree",
+    echo_degenerate => "DeepService.echo keeps a degenerate 32-deep tree",
+    measure => "DeepService.measure reads 32-deep trees of both shapes",
+    build => "DeepService.build returns 32-deep trees of both shapes",
+    measure_over_limit => "DeepService.measure rejects trees over the nesting limit",
+    build_over_limit => "Client rejects built trees over the nesting limit",
+    ping => "DeepService.ping echoes its nonce",
+});
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_transport() {
+    let path = std::env::temp_dir().join(format!("e2e-suites-{}.sock", std::process::id()));
+    run_case_on(
+        Endpoint::Unix(path),
+        "chat",
+        "ChatService.whisper sends DM",
+        None,
+    );
+}
+
+/// Every case again, with each response split across one-word segments.
+#[test]
+fn multi_segment_responses() {
+    for schema in e2e_rpc_test::SCHEMAS {
+        for name in client::test_names(schema).unwrap() {
+            run_case_on(
+                Endpoint::Tcp {
+                    host: "127.0.0.1".to_string(),
+                    port: 0,
+                },
+                schema,
+                name,
+                Some(1),
+            );
+        }
+    }
+}
+
+/// A full run of `schema` with both sides held to the same reader `limits`.
+fn run_with_limits(schema: &str, limits: ReaderLimits) {
+    run_with(schema, limits, None);
+}
+
+/// A full run of `schema` with both sides set to the same reader `limits`
+/// and flow control window.
+fn run_with(schema: &str, limits: ReaderLimits, flow_window: Option<usize>) {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let local = tokio::task::LocalSet::new();
+    let result = local.block_on(&rt, async {
+        let listener = Listener::bind(&Endpoint::Tcp {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+        })
+        .await
+        .unwrap();
+        let endpoint = listener.local_endpoint().unwrap();
+        let options = server::ServeOptions {
+            limits,
+            flow_window,
+            shared_state: true,
+            ..Default::default()
+        };
+        let schema_name = schema.to_string();
+        tokio::task::spawn_local(async move {
+            if let Err(e) = server::serve(listener, &schema_name, None, options).await {
+                eprintln!("server error: {}", e);
+            }
+        });
+        let opts = client::RunOptions {
+            connect: client::ConnectOptions {
+                limits,
+                flow_window,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        client::run(&endpoint, schema, &opts).await
+    });
+    if let Err(e) = result {
+        panic!(
+            "{} with {:?}, window {:?}: {}",
+            schema, limits, flow_window, e
+        );
+    }
+}
+
+#[test]
+fn traversal_limit_default() {
+    run_with_limits("matchmaking", ReaderLimits::default());
+}
+
+#[test]
+fn traversal_limit_matched() {
+    run_with_limits(
+        "matchmaking",
+        ReaderLimits {
+            traversal_limit_words: Some(64 * 1024),
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+fn traversal_frames_reject_out_of_range_limits() {
+    use e2e_rpc_test::error::ErrorCode;
+    use e2e_rpc_test::traversal::{frames, Case, MAX_LIMIT_WORDS};
+
+    for limit in [0, MAX_LIMIT_WORDS + 1, u64::MAX] {
+        let err = frames(Case::Aliased, limit).unwrap_err();
+        assert_eq!(err.code, ErrorCode::ConfigError, "limit {}", limit);
+    }
+    for case in [Case::Aliased, Case::ZeroSized] {
+        assert_eq!(frames(case, 1).unwrap().len(), 3);
+        assert_eq!(frames(case, MAX_LIMIT_WORDS).unwrap().len(), 3);
+    }
+}
+
+/// A full run of `ordering`, which adds the Disembargo wire check on a
+/// connection of its own.
+#[test]
+fn ordering_full_run() {
+    run_with_limits("ordering", ReaderLimits::default());
+}
+
+/// A full run of `chat`, which adds the sturdy ref checks on connections of
+/// their own.
+#[test]
+fn chat_full_run() {
+    run_with_limits("chat", ReaderLimits::default());
+}
+
+/// A full run of `inventory`, which adds the trade checks, each party on a
+/// connection of its own.
+#[test]
+fn inventory_full_run() {
+    run_with_limits("inventory", ReaderLimits::default());
+}
+
+/// A full run of `registry`, which adds the pipelining round trip check on
+/// a connection of its own.
+#[test]
+fn registry_full_run() {
+    run_with_limits("registry", ReaderLimits::default());
+}
+
+/// A full `chat` run that pings every second, long enough for several.
+#[test]
+fn rpc_keepalive() {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let result = tokio::task::LocalSet::new().block_on(&rt, async {
+        let listener = Listener::bind(&Endpoint::Tcp {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+        })
+        .await
+        .unwrap();
+        let endpoint = listener.local_endpoint().unwrap();
+        let options = server::ServeOptions {
+            shared_state: true,
+            ..Default::default()
+        };
+        tokio::task::spawn_local(async move {
+            if let Err(e) = server::serve(listener, "chat", None, options).await {
+                eprintln!("server error: {}", e);
+            }
+        });
+        let opts = client::RunOptions {
+            keepalive: Some(std::time::Duration::from_secs(1)),
+            ..Default::default()
+        };
+        client::run(&endpoint, "chat", &opts).await
+    });
+    result.unwrap();
+}
+
+/// Full `chat` and `matchmaking` runs against servers stopped before the
+/// epoch, at it, and at the end of year 9999, so every timestamp is checked
+/// exactly.
+#[test]
+fn fixed_time_stamps() {
+    for fixed_time in [-86_400_000, 0, 253_402_300_799_999] {
+        for schema in ["chat", "matchmaking"] {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            let result = tokio::task::LocalSet::new().block_on(&rt, async {
+                let listener = Listener::bind(&Endpoint::Tcp {
+                    host: "127.0.0.1".to_string(),
+                    port: 0,
+                })
+                .await
+                .unwrap();
+                let endpoint = listener.local_endpoint().unwrap();
+                let options = server::ServeOptions {
+                    fixed_time: Some(fixed_time),
+                    shared_state: true,
+                    ..Default::default()
+                };
+                tokio::task::spawn_local(async move {
+                    if let Err(e) = server::serve(listener, schema, None, options).await {
+                        eprintln!("server error: {}", e);
+                    }
+                });
+                let opts = client::RunOptions {
+                    fixed_time: Some(fixed_time),
+                    ..Default::default()
+                };
+                client::run(&endpoint, schema, &opts).await
+            });
+            if let Err(e) = result {
+                panic!("{} at {}: {}", schema, fixed_time, e);
+            }
+        }
+    }
+}
+
+#[test]
+fn nesting_limit_matched() {
+    for depth in [16, 200] {
+        for schema in ["nesting", "deep"] {
+            run_with_limits(
+                schema,
+                ReaderLimits {
+                    nesting_limit: Some(depth),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+}
+
+/// The telemetry suite with a window a fraction of the default, which the
+/// slow sink's backlog has to stay within.
+#[test]
+fn flow_window_matched() {
+    run_with("telemetry", ReaderLimits::default(), Some(8 * 1024));
+}
+
+#[test]
+fn filter_patterns() {
+    let filter = |pattern: &str| client::TestFilter::parse(pattern).unwrap();
+    let name = "GameWorld.damageEntity can kill";
+    assert!(filter("damageEntity").matches(name));
+    assert!(!filter("damageentity").matches(name));
+    assert!(filter("GameWorld.*kill").matches(name));
+    assert!(filter("*Entity can ki??").matches(name));
+    assert!(!filter("*Entity").matches(name));
+    assert!(!filter("GameWorld.?amage*x").matches(name));
+    assert!(client::TestFilter::parse("").is_err());
+}
+
+/// A `game_world` run of the tests matching `filter`, whose earlier cases
+/// still run as setup.
+fn run_filtered(filter: &str) -> Result<(), e2e_rpc_test::error::TestError> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    tokio::task::LocalSet::new().block_on(&rt, async {
+        let listener = Listener::bind(&Endpoint::Tcp {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+        })
+        .await
+        .unwrap();
+        let endpoint = listener.local_endpoint().unwrap();
+        tokio::task::spawn_local(async move {
+            if let Err(e) = server::serve(listener, "game_world", None, Default::default()).await {
+                eprintln!("server error: {}", e);
+            }
+        });
+        let opts = client::RunOptions {
+            filter: Some(client::TestFilter::parse(filter).unwrap()),
+            ..Default::default()
+        };
+        client::run(&endpoint, "game_world", &opts).await
+    })
+}
+
+#[test]
+fn filtered_run() {
+    run_filtered("GameWorld.despawnEntity").unwrap();
+    run_filtered("GameWorld.*Entity *").unwrap();
+    let err = run_filtered("NoSuchService").unwrap_err();
+    assert_eq!(err.code, e2e_rpc_test::error::ErrorCode::ConfigError);
+}
+
+#[test]
+fn list_tests_covers_every_schema() {
+    let mut out = Vec::new();
+    client::list_tests(&client::RunOptions::default(), &mut out).unwrap();
+    let listed = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = listed.lines().collect();
+    for schema in e2e_rpc_test::SCHEMAS {
+        for name in client::test_names(schema).unwrap() {
+            let line = format!("{}\t{}", schema, name);
+            assert!(lines.contains(&line.as_str()), "{} not listed", line);
+        }
+    }
+
+    let mut out = Vec::new();
+    let opts = client::RunOptions {
+        filter: Some(client::TestFilter::parse("GameWorld.spawnEntity").unwrap()),
+        ..Default::default()
+    };
+    client::list_tests(&opts, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "game_world\tGameWorld.spawnEntity creates entity\n"
+    );
+}
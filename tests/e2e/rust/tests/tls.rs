@@ -0,0 +1,100 @@
+//! Runs a suite over TLS with client certificates, using the certificates in
+//! `tests/tls/` (regenerate with `tests/tls/gen.sh`).
+#![cfg(all(unix, feature = "tls"))]
+
+use std::path::PathBuf;
+
+use e2e_rpc_test::client::{self, ConnectOptions, RunOptions};
+use e2e_rpc_test::error::{ErrorCode, TestError};
+use e2e_rpc_test::server;
+use e2e_rpc_test::tls::{self, TlsArgs};
+use e2e_rpc_test::transport::Endpoint;
+
+fn cert(name: &str) -> Option<PathBuf> {
+    Some(
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/tls")
+            .join(name),
+    )
+}
+
+/// Serves `chat` over TLS on a fresh Unix socket and runs the whole suite
+/// with a client that trusts `client_ca`.
+fn run_chat(client_ca: &str) -> Result<(), TestError> {
+    let path =
+        std::env::temp_dir().join(format!("e2e-tls-{}-{}.sock", client_ca, std::process::id()));
+    let endpoint = Endpoint::Unix(path);
+    let acceptor = tls::acceptor(&TlsArgs {
+        tls: true,
+        cert: cert("server.pem"),
+        key: cert("server.key"),
+        ca: cert("ca.pem"),
+    })
+    .unwrap();
+    let connector = tls::connector(
+        &TlsArgs {
+            tls: true,
+            cert: cert("client.pem"),
+            key: cert("client.key"),
+            ca: cert(client_ca),
+        },
+        &endpoint,
+    )
+    .unwrap();
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    tokio::task::LocalSet::new().block_on(&rt, async {
+        let server_endpoint = endpoint.clone();
+        tokio::task::spawn_local(async move {
+            if let Err(e) = server::run(
+                &server_endpoint,
+                "chat",
+                None,
+                acceptor,
+                &Default::default(),
+                Default::default(),
+            )
+            .await
+            {
+                eprintln!("server error: {}", e);
+            }
+        });
+        // Binding happens on the server task's first poll.
+        tokio::task::yield_now().await;
+
+        let opts = RunOptions {
+            artifacts_dir: None,
+            repeat: 1,
+            protocol_version: None,
+            faults: Vec::new(),
+            expect_no_bootstrap: false,
+            keepalive: None,
+            fixed_time: None,
+            big: false,
+            report: Default::default(),
+            filter: None,
+            fail_fast: false,
+            test_timeout: None,
+            retries: 0,
+            connect: ConnectOptions {
+                tls: connector,
+                ..Default::default()
+            },
+        };
+        client::run(&endpoint, "chat", &opts).await
+    })
+}
+
+#[test]
+fn suite_runs_over_mutual_tls() {
+    run_chat("ca.pem").unwrap();
+}
+
+#[test]
+fn untrusted_server_certificate_fails_to_connect() {
+    let err = run_chat("other-ca.pem").unwrap_err();
+    assert_eq!(err.code, ErrorCode::ConnectFailed, "{}", err);
+}
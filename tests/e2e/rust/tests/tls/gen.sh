@@ -0,0 +1,27 @@
+#!/bin/sh
+# Regenerates the TLS test certificates (valid for 100 years).
+set -e
+cd "$(dirname "$0")"
+days=36500
+
+openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes -days $days \
+    -subj "/CN=e2e test CA" -keyout ca.key -out ca.pem \
+    -addext "basicConstraints=critical,CA:TRUE" -addext "keyUsage=critical,keyCertSign"
+openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes -days $days \
+    -subj "/CN=e2e other CA" -keyout other-ca.key -out other-ca.pem \
+    -addext "basicConstraints=critical,CA:TRUE" -addext "keyUsage=critical,keyCertSign"
+
+leaf() {
+    openssl req -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes \
+        -subj "/CN=$1" -keyout "$1.key" -out "$1.csr"
+    openssl x509 -req -in "$1.csr" -CA ca.pem -CAkey ca.key -CAcreateserial -days $days \
+        -out "$1.pem" -extfile /dev/stdin <<EXT
+basicConstraints=critical,CA:FALSE
+subjectAltName=DNS:localhost,IP:127.0.0.1
+extendedKeyUsage=$2
+EXT
+    rm "$1.csr"
+}
+leaf server serverAuth
+leaf client clientAuth
+rm -f ca.srl ca.key other-ca.key
@@ -0,0 +1,80 @@
+//! Runs the three-party handoff topology as three processes of the harness
+//! binary: provider A, introducer C and recipient B.
+#![cfg(feature = "net")]
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+
+const EXE: &str = env!("CARGO_BIN_EXE_e2e-rpc-test");
+
+/// A port nothing is listening on right now.
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Kills the vat when the test ends, however it ends.
+struct Vat(Child);
+
+impl Drop for Vat {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Starts a listening vat and waits for its READY line.
+fn start(args: &[&str]) -> Vat {
+    let mut child = Command::new(EXE)
+        .arg("vat")
+        .args(args)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(child.stdout.as_mut().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    assert_eq!(line.trim(), "READY", "vat {:?} did not start", args);
+    Vat(child)
+}
+
+#[test]
+fn room_minted_on_a_reaches_b_through_c() {
+    let (a, c) = (free_port().to_string(), free_port().to_string());
+    let _provider = start(&["--role", "provider", "--port", &a]);
+    let _introducer = start(&["--role", "introducer", "--port", &c, "--peer-port", &a]);
+    let output = Command::new(EXE)
+        .args(["vat", "--role", "recipient", "--peer-port", &c])
+        .output()
+        .unwrap();
+    let tap = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{}", tap);
+    assert!(
+        tap.contains("ok 1 - Three-party: room minted on A answers B through C\n"),
+        "{}",
+        tap
+    );
+    // The handoff itself waits on level 3 support; until then it stays TODO.
+    assert_eq!(tap.matches(" # TODO ").count(), 2, "{}", tap);
+}
+
+#[test]
+fn introducer_needs_a_peer() {
+    let status = Command::new(EXE)
+        .args([
+            "vat",
+            "--role",
+            "introducer",
+            "--port",
+            &free_port().to_string(),
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}